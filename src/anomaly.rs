@@ -3,6 +3,12 @@
 //! Provides both score-based detection (used by LZ77 and JSON paths) and
 //! a convenience wrapper for LZ77 `RecordAnalysis`.
 
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::opsfile::{invalid, read_f64, write_f64};
+use crate::quantile::{RunningStats, TDigest};
+use crate::scanner::{OpKind, ScanOp};
 use crate::scorer::RecordAnalysis;
 
 // ---------------------------------------------------------------------------
@@ -21,6 +27,51 @@ pub enum DetectionMethod {
     Top,
 }
 
+/// Which end of the distribution is flagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    /// Abnormally odd records: high scores, low coverage.
+    #[default]
+    High,
+    /// Suspiciously normal records: low scores, high coverage, such as
+    /// replayed or injected copies of a line that are too perfect.
+    Low,
+    /// Either end.
+    Both,
+}
+
+/// What [`DetectionMethod::Percentile`] and [`DetectionMethod::Top`] rank
+/// LZ77 records by; the other methods threshold their own signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RankMetric {
+    /// Anomaly score, highest first.
+    #[default]
+    Score,
+    /// Coverage, lowest first.
+    Coverage,
+    /// Literal (unmatched) bytes, most first.
+    LiteralBytes,
+}
+
+impl RankMetric {
+    /// The metric of `r`, signed so that higher is more anomalous.
+    pub fn key(self, r: &RecordAnalysis) -> f64 {
+        match self {
+            RankMetric::Score => r.anomaly_score,
+            RankMetric::Coverage => -r.coverage,
+            RankMetric::LiteralBytes => r.literal_bytes as f64,
+        }
+    }
+
+    /// A [`key`](Self::key) back in the metric's units.
+    fn value(self, key: f64) -> f64 {
+        match self {
+            RankMetric::Coverage => -key,
+            _ => key,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct AnomalyReport {
     pub total_records: usize,
@@ -29,9 +80,14 @@ pub struct AnomalyReport {
     pub mean_coverage: f64,
     pub median_coverage: f64,
     pub stdev_coverage: f64,
+    /// Threshold of the flagged tail (the high one under
+    /// [`Direction::Both`]).
     pub threshold: f64,
+    /// Threshold of the low tail under [`Direction::Both`].
+    pub low_threshold: Option<f64>,
     pub anomaly_count: usize,
-    /// Indices into the original records slice, sorted by score descending.
+    /// Indices into the original records slice, sorted by score descending
+    /// (low-tail records after, most normal first).
     pub anomaly_indices: Vec<usize>,
 }
 
@@ -62,7 +118,7 @@ pub(crate) fn median_of(vals: &[f64]) -> f64 {
     let mut sorted = vals.to_vec();
     sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
     let n = sorted.len();
-    if n % 2 == 0 {
+    if n.is_multiple_of(2) {
         (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
     } else {
         sorted[n / 2]
@@ -78,6 +134,334 @@ pub(crate) fn sample_stdev(vals: &[f64], m: f64) -> f64 {
     var.sqrt()
 }
 
+/// Each score's distance from the mean in sample standard deviations, so
+/// scores from different analyzers can be compared; all 0 when the scores
+/// do not vary.
+pub fn standard_scores(scores: &[f64]) -> Vec<f64> {
+    let m = mean(scores);
+    let sd = sample_stdev(scores, m);
+    scores.iter().map(|&s| if sd > 0.0 { (s - m) / sd } else { 0.0 }).collect()
+}
+
+// ---------------------------------------------------------------------------
+// Histogram
+// ---------------------------------------------------------------------------
+
+/// Equal-width histogram over `[lo, hi]`.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub lo: f64,
+    pub hi: f64,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    /// Bin `vals` into `bins` buckets spanning their min..max.
+    pub fn new(vals: &[f64], bins: usize) -> Self {
+        let lo = vals.iter().copied().fold(f64::INFINITY, f64::min);
+        let hi = vals.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        if lo > hi {
+            return Self::with_range(vals, bins, 0.0, 1.0);
+        }
+        Self::with_range(vals, bins, lo, hi)
+    }
+
+    /// Bin `vals` into `bins` buckets spanning `lo..hi`. Values outside the
+    /// range are clamped into the first or last bucket.
+    pub fn with_range(vals: &[f64], bins: usize, lo: f64, hi: f64) -> Self {
+        let bins = bins.max(1);
+        let width = hi - lo;
+        let mut counts = vec![0usize; bins];
+        for &v in vals {
+            let b = if width > 0.0 {
+                ((v - lo) / width * bins as f64).clamp(0.0, (bins - 1) as f64) as usize
+            } else {
+                0
+            };
+            counts[b] += 1;
+        }
+        Self { lo, hi, counts }
+    }
+
+    /// Lower and upper edge of bucket `i`.
+    pub fn bin_range(&self, i: usize) -> (f64, f64) {
+        let w = (self.hi - self.lo) / self.counts.len() as f64;
+        (self.lo + w * i as f64, self.lo + w * (i + 1) as f64)
+    }
+}
+
+/// Unicode block levels used by [`sparkline`], lowest first.
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Render `vals` in order as a one-line sparkline at most `width` chars wide.
+///
+/// When there are more values than columns, each column shows the maximum
+/// of its slice so isolated spikes stay visible. Levels are scaled between
+/// the lowest and highest column.
+pub fn sparkline(vals: &[f64], width: usize) -> String {
+    if vals.is_empty() || width == 0 {
+        return String::new();
+    }
+    let cols = width.min(vals.len());
+    let col_max: Vec<f64> = (0..cols)
+        .map(|c| {
+            let (a, b) = (c * vals.len() / cols, (c + 1) * vals.len() / cols);
+            vals[a..b].iter().copied().fold(f64::NEG_INFINITY, f64::max)
+        })
+        .collect();
+    let lo = col_max.iter().copied().fold(f64::INFINITY, f64::min);
+    let hi = col_max.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let top = (SPARK_LEVELS.len() - 1) as f64;
+    col_max
+        .iter()
+        .map(|&v| {
+            let level = if hi > lo { ((v - lo) / (hi - lo) * top).round() } else { 0.0 };
+            SPARK_LEVELS[level as usize]
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Offset heatmap
+// ---------------------------------------------------------------------------
+
+/// Statistics for one equal-width slice of the input's byte range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    /// Bytes in the segment covered by back-references.
+    pub backref_bytes: usize,
+    /// Records starting in the segment.
+    pub records: usize,
+    /// Anomalous records starting in the segment.
+    pub anomalies: usize,
+}
+
+impl Segment {
+    pub fn coverage(&self) -> f64 {
+        if self.end > self.start {
+            self.backref_bytes as f64 / (self.end - self.start) as f64
+        } else {
+            0.0
+        }
+    }
+
+    pub fn anomaly_density(&self) -> f64 {
+        if self.records == 0 {
+            return 0.0;
+        }
+        self.anomalies as f64 / self.records as f64
+    }
+}
+
+/// Split `0..len` into `n` equal segments and tally back-reference bytes
+/// from `ops` (split exactly at segment edges) plus records and anomalies
+/// by their start offset. Pass empty `ops` when coverage is not wanted.
+pub fn offset_heatmap(
+    len: usize,
+    n: usize,
+    ops: &[ScanOp],
+    record_offsets: &[usize],
+    anomaly_offsets: &[usize],
+) -> Vec<Segment> {
+    let n = n.clamp(1, len.max(1));
+    let edge = |i: usize| i * len / n;
+    let mut segs: Vec<Segment> = (0..n)
+        .map(|i| Segment { start: edge(i), end: edge(i + 1), backref_bytes: 0, records: 0, anomalies: 0 })
+        .collect();
+    let seg_of = |segs: &[Segment], pos: usize| segs.partition_point(|s| s.end <= pos).min(n - 1);
+
+    for op in ops.iter().filter(|o| o.kind == OpKind::Backref) {
+        let (mut pos, end) = (op.position, (op.position + op.length).min(len));
+        while pos < end {
+            let i = seg_of(&segs, pos);
+            let seg = &mut segs[i];
+            let stop = end.min(seg.end);
+            seg.backref_bytes += stop - pos;
+            pos = stop;
+        }
+    }
+    for &off in record_offsets.iter().filter(|&&o| o < len) {
+        let i = seg_of(&segs, off);
+        segs[i].records += 1;
+    }
+    for &off in anomaly_offsets.iter().filter(|&&o| o < len) {
+        let i = seg_of(&segs, off);
+        segs[i].anomalies += 1;
+    }
+    segs
+}
+
+// ---------------------------------------------------------------------------
+// Smoothing and blocks
+// ---------------------------------------------------------------------------
+
+/// Replace each score with the mean of the `window` scores centered on it
+/// (fewer at either end). Incidents span many adjacent records, so a run of
+/// moderately odd records then outscores a single spike.
+pub fn smooth_scores(scores: &[f64], window: usize) -> Vec<f64> {
+    let half = window / 2;
+    if half == 0 {
+        return scores.to_vec();
+    }
+    let mut prefix = Vec::with_capacity(scores.len() + 1);
+    prefix.push(0.0);
+    for &s in scores {
+        prefix.push(prefix.last().unwrap() + s);
+    }
+    (0..scores.len())
+        .map(|i| {
+            let (a, b) = (i.saturating_sub(half), (i + half + 1).min(scores.len()));
+            (prefix[b] - prefix[a]) / (b - a) as f64
+        })
+        .collect()
+}
+
+/// A run of flagged records close enough together to report as one entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnomalyBlock {
+    /// First and last flagged record, inclusive.
+    pub start: usize,
+    pub end: usize,
+    /// Flagged records in the block.
+    pub anomalies: usize,
+    /// The highest-scoring of them.
+    pub peak: usize,
+}
+
+/// Merge flagged record `indices` into blocks, starting a new block when
+/// the next one is more than `max_gap` records on (1: only adjacent records
+/// merge). Blocks are sorted by peak score descending; single records form
+/// blocks of their own.
+pub fn merge_blocks(indices: &[usize], scores: &[f64], max_gap: usize) -> Vec<AnomalyBlock> {
+    let mut sorted = indices.to_vec();
+    sorted.sort_unstable();
+    let mut blocks: Vec<AnomalyBlock> = Vec::new();
+    for i in sorted {
+        match blocks.last_mut() {
+            Some(b) if i - b.end <= max_gap.max(1) => {
+                b.end = i;
+                b.anomalies += 1;
+                if scores[i] > scores[b.peak] {
+                    b.peak = i;
+                }
+            }
+            _ => blocks.push(AnomalyBlock { start: i, end: i, anomalies: 1, peak: i }),
+        }
+    }
+    blocks.sort_by(|a, b| scores[b.peak].partial_cmp(&scores[a.peak]).unwrap());
+    blocks
+}
+
+// ---------------------------------------------------------------------------
+// Templates
+// ---------------------------------------------------------------------------
+
+/// How much one kind of record contributes to the anomalies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateStats {
+    pub template: String,
+    pub records: usize,
+    pub anomalies: usize,
+    /// Summed score of its flagged records.
+    pub contribution: f64,
+    /// Its share of the summed score of all flagged records.
+    pub share: f64,
+    /// Its highest-scoring flagged record.
+    pub peak: usize,
+}
+
+impl TemplateStats {
+    pub fn anomaly_rate(&self) -> f64 {
+        self.anomalies as f64 / self.records as f64
+    }
+}
+
+/// Tally records per template label (`templates` is aligned with `scores`)
+/// and rank the templates with anomalies by their contribution, descending:
+/// which kinds of lines are odd, rather than which lines.
+pub fn template_contributions(templates: &[String], scores: &[f64], anomaly_indices: &[usize]) -> Vec<TemplateStats> {
+    let mut records: HashMap<&str, usize> = HashMap::new();
+    for t in templates {
+        *records.entry(t.as_str()).or_default() += 1;
+    }
+    let mut flagged: HashMap<&str, (usize, f64, usize)> = HashMap::new();
+    for &i in anomaly_indices {
+        let f = flagged.entry(templates[i].as_str()).or_insert((0, 0.0, i));
+        f.0 += 1;
+        f.1 += scores[i];
+        if scores[i] > scores[f.2] {
+            f.2 = i;
+        }
+    }
+    let total: f64 = flagged.values().map(|f| f.1).sum();
+    let mut out: Vec<TemplateStats> = flagged
+        .into_iter()
+        .map(|(t, (anomalies, contribution, peak))| TemplateStats {
+            template: t.to_string(),
+            records: records[t],
+            anomalies,
+            contribution,
+            share: if total > 0.0 { contribution / total } else { 0.0 },
+            peak,
+        })
+        .collect();
+    out.sort_by(|a, b| {
+        b.contribution
+            .partial_cmp(&a.contribution)
+            .unwrap()
+            .then_with(|| b.anomalies.cmp(&a.anomalies))
+            .then_with(|| a.template.cmp(&b.template))
+    });
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Review sampling
+// ---------------------------------------------------------------------------
+
+/// Score strata of a review sample: deciles.
+pub const REVIEW_STRATA: usize = 10;
+
+/// Up to `n` records for a human to review, spread over the score deciles
+/// rather than taken from the top, so borderline and normal records are
+/// seen beside the extremes. Each decile gets an equal share; any remainder
+/// goes to deciles at even steps from the top, and the share a decile is
+/// too small to fill to the others, top first. Within a decile, records are picked at even steps through its scores, so the
+/// sample is the same on every run. Returns `(decile, record)` pairs,
+/// decile 1 the highest-scoring tenth, highest scores first.
+pub fn review_sample(scores: &[f64], n: usize) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]).then(a.cmp(&b)));
+    let bounds: Vec<usize> = (0..=REVIEW_STRATA).map(|d| d * order.len() / REVIEW_STRATA).collect();
+    let size = |d: usize| bounds[d + 1] - bounds[d];
+    let n = n.min(order.len());
+    let mut take = [n / REVIEW_STRATA; REVIEW_STRATA];
+    let extra = n % REVIEW_STRATA;
+    for k in 0..extra {
+        take[k * REVIEW_STRATA / extra] += 1;
+    }
+    for (d, t) in take.iter_mut().enumerate() {
+        *t = (*t).min(size(d));
+    }
+    let mut left = n - take.iter().sum::<usize>();
+    while left > 0 {
+        for (d, t) in take.iter_mut().enumerate() {
+            if left > 0 && *t < size(d) {
+                *t += 1;
+                left -= 1;
+            }
+        }
+    }
+    let mut out = Vec::new();
+    for (d, &m) in take.iter().enumerate() {
+        let stratum = &order[bounds[d]..bounds[d + 1]];
+        out.extend((0..m).map(|k| (d + 1, stratum[(2 * k + 1) * stratum.len() / (2 * m)])));
+    }
+    out
+}
+
 // ---------------------------------------------------------------------------
 // Core detection — works on raw score slices
 // ---------------------------------------------------------------------------
@@ -153,6 +537,186 @@ pub fn detect_indices(
     (threshold_used, idx)
 }
 
+/// The low tail of [`detect_indices`], records too normal rather than too
+/// odd: scores below mean - k*stdev, coverage above mean + k*stdev (at most
+/// 1), the bottom N% or the N lowest scores. Most normal first.
+fn detect_low_indices(
+    scores: &[f64],
+    coverages: Option<&[f64]>,
+    method: DetectionMethod,
+    threshold: Option<f64>,
+    top_n: Option<usize>,
+) -> (f64, Vec<usize>) {
+    if scores.is_empty() {
+        return (0.0, Vec::new());
+    }
+    let mut by_score: Vec<usize> = (0..scores.len()).collect();
+    by_score.sort_by(|&a, &b| scores[a].total_cmp(&scores[b]));
+    match method {
+        DetectionMethod::Score => {
+            let ms = mean(scores);
+            let t = threshold.unwrap_or(ms - 1.5 * sample_stdev(scores, ms));
+            by_score.retain(|&i| scores[i] <= t);
+            (t, by_score)
+        }
+        DetectionMethod::Coverage => {
+            let covs = coverages.unwrap_or(scores);
+            let mc = mean(covs);
+            let t = threshold.unwrap_or((mc + 1.5 * sample_stdev(covs, mc)).min(1.0));
+            let mut selected: Vec<usize> = (0..covs.len()).filter(|&i| covs[i] >= t).collect();
+            selected.sort_by(|&a, &b| covs[b].total_cmp(&covs[a]));
+            (t, selected)
+        }
+        DetectionMethod::Percentile => {
+            let pct = threshold.unwrap_or(0.05);
+            by_score.truncate(((scores.len() as f64 * pct).ceil() as usize).max(1));
+            (pct, by_score)
+        }
+        DetectionMethod::Top => {
+            by_score.truncate(top_n.unwrap_or(10));
+            let t = by_score.last().map_or(0.0, |&i| scores[i]);
+            (t, by_score)
+        }
+    }
+}
+
+/// [`detect_indices`] on the tail or tails `direction` asks for. Returns
+/// the high and low thresholds used (`None` for a tail not searched) and
+/// the indices: the high tail by score descending, then the low tail most
+/// normal first. Under [`Direction::Both`] a percentile or top-N applies to
+/// each tail, while a `threshold` for the score and coverage methods is the
+/// high tail's and the low tail keeps its default.
+pub fn detect_two_sided(
+    scores: &[f64],
+    coverages: Option<&[f64]>,
+    method: DetectionMethod,
+    direction: Direction,
+    threshold: Option<f64>,
+    top_n: Option<usize>,
+) -> (Option<f64>, Option<f64>, Vec<usize>) {
+    let high = (direction != Direction::Low).then(|| detect_indices(scores, coverages, method, threshold, top_n));
+    let low_threshold = match (direction, method) {
+        (Direction::Both, DetectionMethod::Score | DetectionMethod::Coverage) => None,
+        _ => threshold,
+    };
+    let low = (direction != Direction::High)
+        .then(|| detect_low_indices(scores, coverages, method, low_threshold, top_n));
+    let (high_t, mut idx) = high.map_or((None, Vec::new()), |(t, idx)| (Some(t), idx));
+    let low_t = low.map(|(t, low_idx)| {
+        let mut flagged = vec![false; scores.len()];
+        idx.iter().for_each(|&i| flagged[i] = true);
+        idx.extend(low_idx.into_iter().filter(|&i| !flagged[i]));
+        t
+    });
+    (high_t, low_t, idx)
+}
+
+// ---------------------------------------------------------------------------
+// Online detection — bounded memory, for scores that arrive one by one
+// ---------------------------------------------------------------------------
+
+/// The threshold [`detect_indices`] would use, kept up to date as values
+/// arrive instead of computed from the full slice: mean and deviation are
+/// running ([`RunningStats`]) and the percentile cut comes from a
+/// [`TDigest`]. Values are anomaly scores, or coverages for
+/// [`DetectionMethod::Coverage`].
+#[derive(Debug, Clone)]
+pub struct OnlineThreshold {
+    method: DetectionMethod,
+    threshold: Option<f64>,
+    stats: RunningStats,
+    digest: Option<TDigest>,
+}
+
+impl OnlineThreshold {
+    /// `threshold` as for [`detect_indices`]. `None` for
+    /// [`DetectionMethod::Top`], which ranks rather than cuts.
+    pub fn new(method: DetectionMethod, threshold: Option<f64>) -> Option<Self> {
+        let digest = match method {
+            DetectionMethod::Top => return None,
+            DetectionMethod::Percentile => Some(TDigest::default()),
+            DetectionMethod::Score | DetectionMethod::Coverage => None,
+        };
+        Some(Self { method, threshold, stats: RunningStats::default(), digest })
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.stats.add(value);
+        if let Some(d) = &mut self.digest {
+            d.add(value);
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.stats.count()
+    }
+
+    pub fn method(&self) -> DetectionMethod {
+        self.method
+    }
+
+    /// Current cut on values: flagged at or above it, or at or below it for
+    /// coverage.
+    pub fn cut(&self) -> f64 {
+        let (m, sd) = (self.stats.mean(), self.stats.stdev());
+        match self.method {
+            DetectionMethod::Score => self.threshold.unwrap_or(m + 1.5 * sd),
+            DetectionMethod::Coverage => self.threshold.unwrap_or((m - 1.5 * sd).max(0.0)),
+            DetectionMethod::Percentile => {
+                let pct = self.threshold.unwrap_or(0.05);
+                self.digest.as_ref().and_then(|d| d.quantile(1.0 - pct)).unwrap_or(f64::INFINITY)
+            }
+            DetectionMethod::Top => unreachable!("no online threshold for top"),
+        }
+    }
+
+    pub fn is_anomalous(&self, value: f64) -> bool {
+        match self.method {
+            DetectionMethod::Coverage => value <= self.cut(),
+            _ => value >= self.cut(),
+        }
+    }
+
+    /// Encoded as `u8 method` (0 score, 1 coverage, 2 percentile), `u8`
+    /// threshold present, `[f64 threshold]`, running stats, then the
+    /// t-digest for percentile.
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let method = match self.method {
+            DetectionMethod::Score => 0u8,
+            DetectionMethod::Coverage => 1,
+            DetectionMethod::Percentile => 2,
+            DetectionMethod::Top => unreachable!("no online threshold for top"),
+        };
+        w.write_all(&[method, self.threshold.is_some() as u8])?;
+        if let Some(t) = self.threshold {
+            write_f64(w, t)?;
+        }
+        self.stats.write(w)?;
+        match &self.digest {
+            Some(d) => d.write(w),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut head = [0u8; 2];
+        r.read_exact(&mut head)?;
+        let method = match head[0] {
+            0 => DetectionMethod::Score,
+            1 => DetectionMethod::Coverage,
+            2 => DetectionMethod::Percentile,
+            m => return Err(invalid(format!("unknown detection method {m}"))),
+        };
+        let threshold = if head[1] != 0 { Some(read_f64(r)?) } else { None };
+        let stats = RunningStats::read(r)?;
+        let digest = match method {
+            DetectionMethod::Percentile => Some(TDigest::read(r)?),
+            _ => None,
+        };
+        Ok(Self { method, threshold, stats, digest })
+    }
+}
+
 // ---------------------------------------------------------------------------
 // LZ77-specific convenience wrapper
 // ---------------------------------------------------------------------------
@@ -164,6 +728,22 @@ pub fn detect_anomalies(
     method: DetectionMethod,
     threshold: Option<f64>,
     top_n: Option<usize>,
+) -> AnomalyReport {
+    detect_anomalies_by(records, dict_entry_count, method, RankMetric::Score, Direction::High, threshold, top_n)
+}
+
+/// [`detect_anomalies`], with `Percentile` and `Top` ranking records by
+/// `metric`; the anomalies come most anomalous by it first, and the `Top`
+/// threshold is in its units (the coverage of the last record flagged,
+/// say). `direction` picks the tails flagged ([`detect_two_sided`]).
+pub fn detect_anomalies_by(
+    records: &[RecordAnalysis],
+    dict_entry_count: usize,
+    method: DetectionMethod,
+    metric: RankMetric,
+    direction: Direction,
+    threshold: Option<f64>,
+    top_n: Option<usize>,
 ) -> AnomalyReport {
     if records.is_empty() {
         return AnomalyReport {
@@ -174,6 +754,7 @@ pub fn detect_anomalies(
             median_coverage: 0.0,
             stdev_coverage: 0.0,
             threshold: 0.0,
+            low_threshold: None,
             anomaly_count: 0,
             anomaly_indices: Vec::new(),
         };
@@ -187,8 +768,16 @@ pub fn detect_anomalies(
     let median_cov = median_of(&coverages);
     let stdev_cov = sample_stdev(&coverages, mean_cov);
 
-    let (threshold_used, anomaly_idx) =
-        detect_indices(&scores, Some(&coverages), method, threshold, top_n);
+    let ranked = matches!(method, DetectionMethod::Percentile | DetectionMethod::Top);
+    let keys: Vec<f64> = match metric {
+        RankMetric::Score => Vec::new(),
+        m => records.iter().map(|r| m.key(r)).collect(),
+    };
+    let by = if ranked && !keys.is_empty() { &keys } else { &scores };
+    let (high, low, anomaly_idx) = detect_two_sided(by, Some(&coverages), method, direction, threshold, top_n);
+    let units = |t: f64| if method == DetectionMethod::Top { metric.value(t) } else { t };
+    let threshold_used = high.or(low).map_or(0.0, units);
+    let low_threshold = high.and(low).map(units);
 
     AnomalyReport {
         total_records: records.len(),
@@ -198,6 +787,7 @@ pub fn detect_anomalies(
         median_coverage: median_cov,
         stdev_coverage: stdev_cov,
         threshold: threshold_used,
+        low_threshold,
         anomaly_count: anomaly_idx.len(),
         anomaly_indices: anomaly_idx,
     }
@@ -211,11 +801,11 @@ pub fn detect_anomalies(
 mod tests {
     use super::*;
     use crate::dictionary::build_dictionary;
-    use crate::scanner::{scan, DEFAULT_WINDOW, MAX_MATCH, MIN_MATCH};
+    use crate::scanner::{scan, ScanParams};
     use crate::scorer::score_records;
 
     fn full_pipeline(data: &[u8], method: DetectionMethod, top_n: Option<usize>) -> AnomalyReport {
-        let ops = scan(data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(data, &ScanParams::default());
         let dict = build_dictionary(data, &ops, 1);
         let recs = score_records(data, &ops, &dict, b'\n');
         detect_anomalies(&recs, dict.len(), method, None, top_n)
@@ -228,6 +818,42 @@ mod tests {
         assert_eq!(r.anomaly_count, 0);
     }
 
+    #[test]
+    fn standard_scores_center_and_scale() {
+        let z = standard_scores(&[1.0, 2.0, 3.0]);
+        assert_eq!(z, [-1.0, 0.0, 1.0]);
+        assert_eq!(standard_scores(&[0.5, 0.5]), [0.0, 0.0]);
+        assert!(standard_scores(&[]).is_empty());
+    }
+
+    #[test]
+    fn histogram_bins_and_edges() {
+        let h = Histogram::with_range(&[0.0, 0.05, 0.5, 0.99, 1.0, 1.5], 10, 0.0, 1.0);
+        assert_eq!(h.counts.iter().sum::<usize>(), 6);
+        assert_eq!(h.counts[0], 2);
+        assert_eq!(h.counts[5], 1);
+        assert_eq!(h.counts[9], 3);
+        let (a, b) = h.bin_range(5);
+        assert!((a - 0.5).abs() < 1e-12 && (b - 0.6).abs() < 1e-12);
+
+        let flat = Histogram::new(&[3.0, 3.0], 4);
+        assert_eq!(flat.counts, vec![2, 0, 0, 0]);
+        assert_eq!(Histogram::new(&[], 3).counts, vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn sparkline_keeps_spikes() {
+        let mut vals = vec![0.1; 1000];
+        vals[777] = 0.9;
+        let line = sparkline(&vals, 10);
+        let chars: Vec<char> = line.chars().collect();
+        assert_eq!(chars.len(), 10);
+        assert_eq!(chars[7], '█');
+        assert!(chars.iter().enumerate().all(|(i, &c)| i == 7 || c == '▁'));
+        assert_eq!(sparkline(&[0.5, 0.5], 80), "▁▁");
+        assert_eq!(sparkline(&[], 80), "");
+    }
+
     #[test]
     fn top_n_returns_n() {
         let data: Vec<u8> = b"line data content here\n".repeat(30);
@@ -241,7 +867,7 @@ mod tests {
         data.extend_from_slice(b"KERNEL PANIC: fatal error 0xDEADBEEF segfault\n");
         data.extend_from_slice(&b"2026-02-16 app: normal operation completed\n".repeat(50));
 
-        let ops = scan(&data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(&data, &ScanParams::default());
         let dict = build_dictionary(&data, &ops, 1);
         let recs = score_records(&data, &ops, &dict, b'\n');
         let report = detect_anomalies(&recs, dict.len(), DetectionMethod::Top, None, Some(5));
@@ -262,7 +888,7 @@ mod tests {
         let mut data: Vec<u8> = b"aaa_repeated_data_content_here\n".repeat(40);
         data.extend_from_slice(b"first unique anomaly string!!\n");
         data.extend_from_slice(b"second completely different anomaly data here longer\n");
-        let ops = scan(&data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(&data, &ScanParams::default());
         let dict = build_dictionary(&data, &ops, 1);
         let recs = score_records(&data, &ops, &dict, b'\n');
         let report = detect_anomalies(&recs, dict.len(), DetectionMethod::Top, None, Some(10));
@@ -276,6 +902,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn percentile_and_top_rank_by_metric() {
+        let mut data: Vec<u8> = b"2026-02-16 app: normal operation completed\n".repeat(60);
+        data.extend_from_slice(b"2026-02-16 app: normal operation completed; KERNEL PANIC: fatal error 0xDEADBEEF segfault in worker 7\n");
+        data.extend_from_slice(&b"2026-02-16 app: normal operation completed\n".repeat(60));
+        data.extend_from_slice(b"zq\n");
+        let ops = scan(&data, &ScanParams::default());
+        let dict = build_dictionary(&data, &ops, 1);
+        let recs = score_records(&data, &ops, &dict, b'\n');
+
+        let by = |metric| {
+            detect_anomalies_by(&recs, dict.len(), DetectionMethod::Top, metric, Direction::High, None, Some(2))
+        };
+        let lit = by(RankMetric::LiteralBytes);
+        assert_eq!(recs[lit.anomaly_indices[0]].index, 60);
+        assert_eq!(lit.threshold, recs[lit.anomaly_indices[1]].literal_bytes as f64);
+        let cov = by(RankMetric::Coverage);
+        let covs: Vec<f64> = cov.anomaly_indices.iter().map(|&i| recs[i].coverage).collect();
+        assert!(covs[0] <= covs[1] && recs.iter().all(|r| r.coverage >= covs[0]));
+        assert_eq!(cov.threshold, covs[1]);
+        assert_eq!(by(RankMetric::Score).anomaly_indices, detect_anomalies(&recs, dict.len(),
+            DetectionMethod::Top, None, Some(2)).anomaly_indices);
+
+        let p = detect_anomalies_by(&recs, dict.len(), DetectionMethod::Percentile, RankMetric::LiteralBytes,
+            Direction::High, Some(0.01), None);
+        assert_eq!((p.threshold, p.anomaly_count), (0.01, 2));
+        assert_eq!(recs[p.anomaly_indices[0]].index, 60);
+    }
+
+    #[test]
+    fn low_tail_finds_too_normal_records() {
+        let scores = [0.5, 0.52, 0.48, 0.9, 0.5, 0.01, 0.49, 0.51, 0.5, 0.47];
+        let (high, low, idx) = detect_two_sided(&scores, None, DetectionMethod::Score, Direction::Low, None, None);
+        assert!(high.is_none() && low.unwrap() < 0.2);
+        assert_eq!(idx, [5]);
+        let (high, low, idx) = detect_two_sided(&scores, None, DetectionMethod::Score, Direction::Both, None, None);
+        assert!(high.is_some() && low.is_some());
+        assert_eq!(idx, [3, 5]);
+        assert_eq!(detect_two_sided(&scores, None, DetectionMethod::Top, Direction::Both, None, Some(2)).2, [3, 1, 5, 9]);
+        let (_, low, idx) = detect_two_sided(&scores, None, DetectionMethod::Percentile, Direction::Low, Some(0.2), None);
+        assert_eq!((low, idx), (Some(0.2), vec![5, 9]));
+        // High coverage is the normal end of the coverage method.
+        let covs = [0.8, 0.81, 0.79, 0.8, 1.0, 0.2, 0.8, 0.82, 0.78, 0.8];
+        let (high, low, idx) =
+            detect_two_sided(&scores, Some(&covs), DetectionMethod::Coverage, Direction::Both, None, None);
+        assert!(high.unwrap() < 0.5 && low.unwrap() <= 1.0);
+        assert_eq!(idx, [5, 4]);
+        // Top-N tails never repeat a record.
+        assert_eq!(detect_two_sided(&[0.1, 0.2], None, DetectionMethod::Top, Direction::Both, None, Some(2)).2, [1, 0]);
+    }
+
     #[test]
     fn detect_indices_basic() {
         let scores = vec![0.1, 0.9, 0.2, 0.8, 0.15];
@@ -284,4 +961,103 @@ mod tests {
         assert_eq!(idx[0], 1); // highest score
         assert_eq!(idx[1], 3); // second highest
     }
+
+    #[test]
+    fn smoothing_and_blocks_favor_runs() {
+        let mut scores = vec![0.1; 100];
+        scores[20] = 0.9;
+        for s in &mut scores[60..70] {
+            *s = 0.5;
+        }
+        let smooth = smooth_scores(&scores, 5);
+        assert!((smooth[20] - 0.26).abs() < 1e-9);
+        assert!((smooth[65] - 0.5).abs() < 1e-9);
+        assert!((smooth[0] - 0.1).abs() < 1e-9);
+        assert_eq!(smooth_scores(&scores, 1), scores);
+
+        let (_, flagged) = detect_indices(&smooth, None, DetectionMethod::Score, None, None);
+        assert!(flagged.contains(&65) && !flagged.contains(&20));
+
+        let blocks = merge_blocks(&[64, 2, 61, 3, 62, 60, 90], &scores, 2);
+        let found: Vec<_> = blocks.iter().map(|b| (b.start, b.end, b.anomalies)).collect();
+        assert_eq!(found, [(60, 64, 4), (2, 3, 2), (90, 90, 1)]);
+        assert_eq!(blocks[0].peak, 60);
+        assert_eq!(merge_blocks(&[60, 62], &scores, 1).len(), 2);
+    }
+
+    #[test]
+    fn templates_ranked_by_flagged_score() {
+        let templates: Vec<String> = ["a", "a", "b", "b", "b", "c", "a", "b"].iter().map(|t| t.to_string()).collect();
+        let scores = [0.9, 0.1, 0.4, 0.3, 0.3, 0.2, 0.8, 0.1];
+        let ranked = template_contributions(&templates, &scores, &[0, 2, 3, 4, 6]);
+        let found: Vec<_> = ranked.iter().map(|t| (t.template.as_str(), t.records, t.anomalies, t.peak)).collect();
+        assert_eq!(found, [("a", 3, 2, 0), ("b", 4, 3, 2)]);
+        assert!((ranked[0].contribution - 1.7).abs() < 1e-9);
+        assert!((ranked[0].share - 1.7 / 2.7).abs() < 1e-9);
+        assert!((ranked[1].anomaly_rate() - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn review_sample_spans_every_decile() {
+        let scores: Vec<f64> = (0..1000).map(|i| ((i * 7919) % 1000) as f64 / 1000.0).collect();
+        let sample = review_sample(&scores, 25);
+        assert_eq!(sample.len(), 25);
+        for d in 1..=REVIEW_STRATA {
+            let picked: Vec<f64> = sample.iter().filter(|s| s.0 == d).map(|s| scores[s.1]).collect();
+            assert_eq!(picked.len(), if d % 2 == 1 { 3 } else { 2 }, "decile {d}");
+            let (lo, hi) = (1.0 - d as f64 / 10.0, 1.0 - (d - 1) as f64 / 10.0);
+            assert!(picked.iter().all(|&s| s >= lo && s < hi), "decile {d}: {picked:?}");
+        }
+        assert!(sample.windows(2).all(|w| scores[w[0].1] >= scores[w[1].1]));
+        assert_eq!(review_sample(&scores, 25), sample);
+        let deciles: Vec<usize> = review_sample(&scores, 3).iter().map(|s| s.0).collect();
+        assert_eq!(deciles, [1, 4, 7]);
+
+        // Fewer records than deciles; more asked for than there are.
+        let few = review_sample(&[0.1, 0.9, 0.5], 10);
+        assert_eq!(few.iter().map(|s| s.1).collect::<Vec<_>>(), [1, 2, 0]);
+        assert!(review_sample(&[], 5).is_empty() && review_sample(&scores, 0).is_empty());
+    }
+
+    #[test]
+    fn online_threshold_matches_batch() {
+        let scores: Vec<f64> = (0..5000).map(|i| ((i * 7919) % 1000) as f64 / 1000.0).collect();
+        let mut score = OnlineThreshold::new(DetectionMethod::Score, None).unwrap();
+        let mut pct = OnlineThreshold::new(DetectionMethod::Percentile, Some(0.1)).unwrap();
+        for &s in &scores {
+            score.add(s);
+            pct.add(s);
+        }
+        let (t, batch) = detect_indices(&scores, None, DetectionMethod::Score, None, None);
+        assert!((score.cut() - t).abs() < 1e-9);
+        assert_eq!(scores.iter().filter(|&&s| score.is_anomalous(s)).count(), batch.len());
+
+        let flagged = scores.iter().filter(|&&s| pct.is_anomalous(s)).count();
+        assert!((flagged as i64 - 500).abs() <= 10, "{flagged}");
+        assert!(OnlineThreshold::new(DetectionMethod::Top, None).is_none());
+        assert!(!OnlineThreshold::new(DetectionMethod::Percentile, None).unwrap().is_anomalous(1.0));
+    }
+
+    #[test]
+    fn heatmap_locates_literal_region() {
+        let mut data: Vec<u8> = b"steady log line here\n".repeat(50);
+        let noise: Vec<u8> = (0..400u32).map(|i| b'a' + (i * 7919 % 26) as u8).collect();
+        data.extend_from_slice(&noise);
+        data.extend_from_slice(&b"steady log line here\n".repeat(50));
+        let ops = scan(&data, &ScanParams::default());
+        let offsets: Vec<usize> = (0..data.len()).step_by(21).collect();
+        let segs = offset_heatmap(data.len(), 10, &ops, &offsets, &[1050]);
+        let small = offset_heatmap(10, 3, &[], &[3, 9], &[]);
+        assert_eq!(small.iter().map(|s| s.records).collect::<Vec<_>>(), [0, 1, 1]);
+
+        assert_eq!(segs.len(), 10);
+        assert_eq!(segs[0].start, 0);
+        assert_eq!(segs[9].end, data.len());
+        assert_eq!(segs.iter().map(|s| s.records).sum::<usize>(), offsets.len());
+        let backref: usize = ops.iter().filter(|o| o.kind == OpKind::Backref).map(|o| o.length).sum();
+        assert_eq!(segs.iter().map(|s| s.backref_bytes).sum::<usize>(), backref);
+        let worst = segs.iter().min_by(|a, b| a.coverage().total_cmp(&b.coverage())).unwrap();
+        assert!(worst.start < 1050 + 400 && worst.end > 1050);
+        assert_eq!(segs.iter().filter(|s| s.anomalies > 0).count(), 1);
+    }
 }
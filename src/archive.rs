@@ -0,0 +1,325 @@
+//! Tar and zip input.
+//!
+//! Log bundles (support tickets, CI artifacts) usually arrive as a
+//! `.tar.gz` or `.zip` of several files. Regular files are extracted in
+//! archive order and analyzed as one concatenated input, and
+//! [`ArchiveMember`] keeps each file's name and byte range so reports can
+//! attribute records and anomalies to it.
+//!
+//! Supported: ustar/GNU/pax tar (long names via GNU `L` and pax `path`
+//! records), and zip with stored or deflated entries. Zip64 is not.
+
+use std::io::{self, Read};
+
+use flate2::read::DeflateDecoder;
+
+use crate::opsfile::invalid;
+
+const TAR_BLOCK: usize = 512;
+const ZIP_LOCAL: u32 = 0x0403_4b50;
+const ZIP_CENTRAL: u32 = 0x0201_4b50;
+const ZIP_END: u32 = 0x0605_4b50;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// A regular file extracted from an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// A file's place in the concatenated input built by [`concat_files`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveMember {
+    pub name: String,
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl ArchiveMember {
+    /// True if offset `pos` of the concatenated input falls in this file.
+    pub fn contains(&self, pos: usize) -> bool {
+        pos >= self.offset && pos < self.offset + self.len
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Detection
+// ---------------------------------------------------------------------------
+
+pub fn is_tar(data: &[u8]) -> bool {
+    data.len() >= TAR_BLOCK && data[257..262] == *b"ustar"
+}
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.len() >= 4 && le32(data, 0) == Some(ZIP_LOCAL)
+}
+
+/// Leading bytes [`is_archive`] needs to recognise an archive.
+pub const ARCHIVE_SNIFF_LEN: usize = TAR_BLOCK;
+
+pub fn is_archive(data: &[u8]) -> bool {
+    is_tar(data) || is_zip(data)
+}
+
+/// Regular files of a tar or zip archive, in archive order. Directories,
+/// links, and other special entries are skipped.
+pub fn read_archive(data: &[u8]) -> io::Result<Vec<ArchiveFile>> {
+    if is_tar(data) {
+        read_tar(data)
+    } else if is_zip(data) {
+        read_zip(data)
+    } else {
+        Err(invalid("not a tar or zip archive"))
+    }
+}
+
+/// Concatenate `files`, ending each with a newline so no record spans two
+/// files, and record where each one landed.
+pub fn concat_files(files: Vec<ArchiveFile>) -> (Vec<u8>, Vec<ArchiveMember>) {
+    let mut out = Vec::with_capacity(files.iter().map(|f| f.data.len() + 1).sum());
+    let mut members = Vec::with_capacity(files.len());
+    for f in files {
+        let offset = out.len();
+        out.extend_from_slice(&f.data);
+        if out.last().is_some_and(|&b| b != b'\n') {
+            out.push(b'\n');
+        }
+        members.push(ArchiveMember { name: f.name, offset, len: out.len() - offset });
+    }
+    (out, members)
+}
+
+/// Count how many of `offsets` fall in each member.
+pub fn archive_counts(members: &[ArchiveMember], offsets: &[usize]) -> Vec<usize> {
+    let mut counts = vec![0; members.len()];
+    for &pos in offsets {
+        let i = members.partition_point(|m| m.offset + m.len <= pos);
+        if members.get(i).is_some_and(|m| m.contains(pos)) {
+            counts[i] += 1;
+        }
+    }
+    counts
+}
+
+// ---------------------------------------------------------------------------
+// Tar
+// ---------------------------------------------------------------------------
+
+/// NUL-terminated header field as text.
+fn tar_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Octal size field, or GNU base-256 if the high bit is set.
+fn tar_size(field: &[u8]) -> io::Result<usize> {
+    if field[0] & 0x80 != 0 {
+        let n = field[1..].iter().fold(0u64, |n, &b| (n << 8) | b as u64);
+        return usize::try_from(n).map_err(|_| invalid("tar entry too large"));
+    }
+    let text = tar_str(field);
+    let text = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    usize::from_str_radix(text, 8).map_err(|_| invalid(format!("bad tar size field {text:?}")))
+}
+
+/// `path` from a pax extended header (`"<len> key=value\n"` records).
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .filter_map(|l| l.split_once(' ').map(|(_, kv)| kv))
+        .find_map(|kv| kv.strip_prefix("path=").map(str::to_string))
+}
+
+fn read_tar(data: &[u8]) -> io::Result<Vec<ArchiveFile>> {
+    let mut files = Vec::new();
+    let mut pos = 0;
+    let mut long_name: Option<String> = None;
+    while pos + TAR_BLOCK <= data.len() {
+        let h = &data[pos..pos + TAR_BLOCK];
+        if h.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = tar_size(&h[124..136])?;
+        let start = pos + TAR_BLOCK;
+        let body = data
+            .get(start..start + size)
+            .ok_or_else(|| invalid(format!("truncated tar entry at byte {pos}")))?;
+        pos = start + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+
+        let name = long_name.take().unwrap_or_else(|| {
+            let (prefix, name) = (tar_str(&h[345..500]), tar_str(&h[..100]));
+            if prefix.is_empty() { name } else { format!("{prefix}/{name}") }
+        });
+        match h[156] {
+            b'L' => long_name = Some(tar_str(body)),
+            b'x' => long_name = pax_path(body),
+            b'0' | 0 | b'7' => files.push(ArchiveFile { name, data: body.to_vec() }),
+            _ => {}
+        }
+    }
+    Ok(files)
+}
+
+// ---------------------------------------------------------------------------
+// Zip
+// ---------------------------------------------------------------------------
+
+fn le16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Entries are located through the central directory, since local headers
+/// of streamed archives carry no sizes.
+fn read_zip(data: &[u8]) -> io::Result<Vec<ArchiveFile>> {
+    let truncated = || invalid("truncated zip archive");
+    let end = (0..=data.len().saturating_sub(22))
+        .rev()
+        .take(0xffff + 22)
+        .find(|&i| le32(data, i) == Some(ZIP_END))
+        .ok_or_else(|| invalid("zip end of central directory not found"))?;
+    let count = le16(data, end + 10).ok_or_else(truncated)? as usize;
+    let mut at = le32(data, end + 16).ok_or_else(truncated)? as usize;
+
+    let mut files = Vec::with_capacity(count);
+    for _ in 0..count {
+        if le32(data, at) != Some(ZIP_CENTRAL) {
+            return Err(invalid(format!("bad zip central directory entry at byte {at}")));
+        }
+        let field = |off: usize| le32(data, at + off).ok_or_else(truncated);
+        let short = |off: usize| le16(data, at + off).ok_or_else(truncated);
+        let (method, csize, size) = (short(10)?, field(20)? as usize, field(24)? as usize);
+        let (name_len, extra_len, comment_len) = (short(28)? as usize, short(30)? as usize, short(32)? as usize);
+        let local = field(42)? as usize;
+        let name = data.get(at + 46..at + 46 + name_len).ok_or_else(truncated)?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        at += 46 + name_len + extra_len + comment_len;
+        if name.ends_with('/') {
+            continue;
+        }
+        if [csize, size, local].contains(&0xffff_ffff) {
+            return Err(invalid(format!("{name}: zip64 entries are not supported")));
+        }
+
+        if le32(data, local) != Some(ZIP_LOCAL) {
+            return Err(invalid(format!("{name}: bad zip local header at byte {local}")));
+        }
+        let skip = le16(data, local + 26).ok_or_else(truncated)? as usize
+            + le16(data, local + 28).ok_or_else(truncated)? as usize;
+        let start = local + 30 + skip;
+        let body = data.get(start..start + csize).ok_or_else(truncated)?;
+        let data = match method {
+            0 => body.to_vec(),
+            8 => {
+                let mut out = Vec::with_capacity(size);
+                DeflateDecoder::new(body).read_to_end(&mut out)?;
+                out
+            }
+            m => return Err(invalid(format!("{name}: unsupported zip compression method {m}"))),
+        };
+        files.push(ArchiveFile { name, data });
+    }
+    Ok(files)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn tar_entry(out: &mut Vec<u8>, name: &str, kind: u8, body: &[u8]) {
+        let mut h = [0u8; TAR_BLOCK];
+        h[..name.len()].copy_from_slice(name.as_bytes());
+        h[124..135].copy_from_slice(format!("{:011o}", body.len()).as_bytes());
+        h[156] = kind;
+        h[257..263].copy_from_slice(b"ustar\0");
+        out.extend_from_slice(&h);
+        out.extend_from_slice(body);
+        out.resize(out.len().div_ceil(TAR_BLOCK) * TAR_BLOCK, 0);
+    }
+
+    #[test]
+    fn reads_tar_files_and_long_names() {
+        let long = format!("logs/{}/app.log", "x".repeat(120));
+        let mut tar = Vec::new();
+        tar_entry(&mut tar, "logs/", b'5', b"");
+        tar_entry(&mut tar, "logs/syslog", b'0', b"boot ok\nkernel: oops\n");
+        tar_entry(&mut tar, "././@LongLink", b'L', format!("{long}\0").as_bytes());
+        tar_entry(&mut tar, "truncated-name", b'0', b"GET / 200");
+        tar.extend_from_slice(&[0; 2 * TAR_BLOCK]);
+        assert!(is_tar(&tar) && !is_zip(&tar));
+
+        let files = read_archive(&tar).unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["logs/syslog", long.as_str()]);
+        assert_eq!(files[1].data, b"GET / 200");
+
+        let (data, members) = concat_files(files);
+        assert_eq!(data, b"boot ok\nkernel: oops\nGET / 200\n");
+        assert_eq!((members[1].offset, members[1].len), (21, 10));
+        assert_eq!(archive_counts(&members, &[0, 8, 21, 31]), [2, 1]);
+        assert!(read_archive(&tar[..2 * TAR_BLOCK + 10]).is_err());
+    }
+
+    #[test]
+    fn reads_stored_and_deflated_zip_entries() {
+        let entries: [(&str, u16, &[u8]); 3] =
+            [("a.log", 0, b"one\ntwo\n"), ("dir/", 0, b""), ("b.log", 8, b"three\n")];
+        let (mut zip, mut central) = (Vec::new(), Vec::new());
+        for (name, method, body) in entries {
+            let stored = if method == 8 {
+                let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
+                e.write_all(body).unwrap();
+                e.finish().unwrap()
+            } else {
+                body.to_vec()
+            };
+            let local = zip.len() as u32;
+            zip.extend_from_slice(&ZIP_LOCAL.to_le_bytes());
+            zip.extend_from_slice(&[0; 22]);
+            zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            zip.extend_from_slice(&[0; 2]);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(&stored);
+
+            let mut c = [0u8; 46];
+            c[..4].copy_from_slice(&ZIP_CENTRAL.to_le_bytes());
+            c[10..12].copy_from_slice(&method.to_le_bytes());
+            c[20..24].copy_from_slice(&(stored.len() as u32).to_le_bytes());
+            c[24..28].copy_from_slice(&(body.len() as u32).to_le_bytes());
+            c[28..30].copy_from_slice(&(name.len() as u16).to_le_bytes());
+            c[42..46].copy_from_slice(&local.to_le_bytes());
+            central.extend_from_slice(&c);
+            central.extend_from_slice(name.as_bytes());
+        }
+        let mut end = [0u8; 22];
+        end[..4].copy_from_slice(&ZIP_END.to_le_bytes());
+        end[10..12].copy_from_slice(&3u16.to_le_bytes());
+        end[16..20].copy_from_slice(&(zip.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&central);
+        zip.extend_from_slice(&end);
+        assert!(is_zip(&zip) && is_archive(&zip));
+
+        let files = read_archive(&zip).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].name.as_str(), files[0].data.as_slice()), ("a.log", &b"one\ntwo\n"[..]));
+        assert_eq!((files[1].name.as_str(), files[1].data.as_slice()), ("b.log", &b"three\n"[..]));
+        assert!(read_archive(&zip[..zip.len() - 22]).is_err());
+    }
+}
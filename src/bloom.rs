@@ -0,0 +1,243 @@
+//! Known-normal record fingerprints.
+//!
+//! On stable logs most lines are variations of templates seen in earlier
+//! runs. Each record is reduced to a fingerprint (its bytes with digit runs
+//! collapsed, so counters, IDs, and timestamps do not matter, and, if the
+//! filter has a template vocabulary, words outside it masked; see
+//! [`Fingerprinter`]) and stored in a Bloom filter file. The vocabulary is
+//! saved with the filter so later runs fingerprint records the same way.
+//! Later runs drop records whose fingerprint is
+//! present before scanning, and score only never-seen-before lines. A
+//! false positive hides a new record, at the configured rate; a known
+//! record is never reported as unseen.
+//!
+//! Layout (integers little-endian):
+//!
+//! ```text
+//! magic    8 bytes   "BGTZBLOM"
+//! version  u16
+//! hashes   u32       probes per fingerprint
+//! items    u64       fingerprints inserted
+//! words    u64       bit array length in 64-bit words
+//! bits     words x u64
+//! vocab    template words (version 2+; see `Fingerprinter::write`)
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::opsfile::{invalid, read_u64};
+pub use crate::fingerprint::{fingerprint, Fingerprinter};
+use crate::records::{RecordIndex, RecordSubset};
+
+pub const BLOOM_MAGIC: &[u8; 8] = b"BGTZBLOM";
+pub const BLOOM_VERSION: u16 = 2;
+
+// ---------------------------------------------------------------------------
+// Fingerprints
+// ---------------------------------------------------------------------------
+
+/// Second, independent hash for double hashing (splitmix64 finalizer).
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// ---------------------------------------------------------------------------
+// Filter
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+    items: u64,
+    templates: Fingerprinter,
+}
+
+impl BloomFilter {
+    /// Filter sized for `capacity` fingerprints at false-positive rate
+    /// `fp_rate`.
+    pub fn with_rate(capacity: usize, fp_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let n = capacity.max(1) as f64;
+        let m = (-n * fp_rate.clamp(1e-12, 0.5).ln() / (ln2 * ln2)).ceil().max(64.0);
+        let words = (m as usize).div_ceil(64);
+        let hashes = ((words * 64) as f64 / n * ln2).round().clamp(1.0, 30.0) as u32;
+        Self { bits: vec![0; words], hashes, items: 0, templates: Fingerprinter::default() }
+    }
+
+    /// Fingerprint records with `templates` (an empty filter only: the
+    /// fingerprints already inserted would no longer match).
+    pub fn with_templates(mut self, templates: Fingerprinter) -> Self {
+        debug_assert_eq!(self.items, 0);
+        self.templates = templates;
+        self
+    }
+
+    pub fn templates(&self) -> &Fingerprinter {
+        &self.templates
+    }
+
+    /// Fingerprint of `record` under this filter's vocabulary.
+    pub fn fingerprint(&self, record: &[u8]) -> u64 {
+        self.templates.fingerprint(record)
+    }
+
+    fn probes(&self, fp: u64) -> impl Iterator<Item = usize> + '_ {
+        let m = self.bits.len() as u64 * 64;
+        let (h1, h2) = (fp, mix(fp) | 1);
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize)
+    }
+
+    /// Insert `fp`; returns true if it was not (apparently) present.
+    pub fn insert(&mut self, fp: u64) -> bool {
+        let probes: Vec<usize> = self.probes(fp).collect();
+        let mut new = false;
+        for p in probes {
+            let (w, b) = (p / 64, 1u64 << (p % 64));
+            new |= self.bits[w] & b == 0;
+            self.bits[w] |= b;
+        }
+        self.items += new as u64;
+        new
+    }
+
+    pub fn contains(&self, fp: u64) -> bool {
+        self.probes(fp).all(|p| self.bits[p / 64] & (1 << (p % 64)) != 0)
+    }
+
+    /// Distinct fingerprints inserted (up to false positives at insert time).
+    pub fn items(&self) -> u64 {
+        self.items
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.bits.len() * 8
+    }
+
+    /// False-positive rate at the current fill.
+    pub fn fp_rate(&self) -> f64 {
+        let set: u32 = self.bits.iter().map(|w| w.count_ones()).sum();
+        (set as f64 / (self.bits.len() * 64) as f64).powi(self.hashes as i32)
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(BLOOM_MAGIC)?;
+        w.write_all(&BLOOM_VERSION.to_le_bytes())?;
+        w.write_all(&self.hashes.to_le_bytes())?;
+        w.write_all(&self.items.to_le_bytes())?;
+        w.write_all(&(self.bits.len() as u64).to_le_bytes())?;
+        for word in &self.bits {
+            w.write_all(&word.to_le_bytes())?;
+        }
+        self.templates.write(&mut w)?;
+        w.flush()
+    }
+
+    pub fn read<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != BLOOM_MAGIC {
+            return Err(invalid("not a bgtzip known-fingerprint file"));
+        }
+        let mut head = [0u8; 6];
+        r.read_exact(&mut head)?;
+        let version = u16::from_le_bytes([head[0], head[1]]);
+        if version == 0 || version > BLOOM_VERSION {
+            return Err(invalid(format!(
+                "unsupported known-fingerprint version {version} (expected {BLOOM_VERSION})"
+            )));
+        }
+        let hashes = u32::from_le_bytes([head[2], head[3], head[4], head[5]]);
+        let items = read_u64(&mut r)?;
+        let words = read_u64(&mut r)? as usize;
+        if words == 0 || hashes == 0 {
+            return Err(invalid("empty known-fingerprint filter"));
+        }
+        let mut bits = Vec::with_capacity(words.min(1 << 24));
+        for _ in 0..words {
+            bits.push(read_u64(&mut r)?);
+        }
+        let templates = if version >= 2 { Fingerprinter::read(&mut r)? } else { Fingerprinter::default() };
+        Ok(Self { bits, hashes, items, templates })
+    }
+}
+
+pub fn save_filter(path: impl AsRef<Path>, f: &BloomFilter) -> io::Result<()> {
+    f.write(BufWriter::new(File::create(path)?))
+}
+
+pub fn load_filter(path: impl AsRef<Path>) -> io::Result<BloomFilter> {
+    BloomFilter::read(BufReader::new(File::open(path)?))
+}
+
+// ---------------------------------------------------------------------------
+// Filtering input
+// ---------------------------------------------------------------------------
+
+/// Keep only records whose fingerprint `filter` does not contain.
+pub fn unseen_records(data: &[u8], delimiter: u8, filter: &BloomFilter) -> RecordSubset {
+    let index = RecordIndex::new(data, delimiter);
+    let mut out = RecordSubset::new(index.len());
+    for (i, (off, len)) in index.iter().enumerate() {
+        let rec = &data[off..off + len];
+        if !filter.contains(filter.fingerprint(rec)) {
+            out.push(i, off, rec);
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_round_trip_and_rate() {
+        let mut f = BloomFilter::with_rate(1000, 0.01);
+        for i in 0..1000u64 {
+            f.insert(mix(i));
+        }
+        assert!((0..1000u64).all(|i| f.contains(mix(i))));
+        let fp = (1000..21000u64).filter(|&i| f.contains(mix(i))).count();
+        assert!(fp < 400, "{fp} false positives");
+        assert!(f.fp_rate() < 0.02);
+        assert!(!f.insert(mix(5)));
+
+        let mut buf = Vec::new();
+        f.write(&mut buf).unwrap();
+        assert_eq!(BloomFilter::read(buf.as_slice()).unwrap(), f);
+        assert!(BloomFilter::read(&buf[..20]).is_err());
+
+        // Version 1: no vocabulary.
+        let mut v1 = buf[..buf.len() - 1].to_vec();
+        v1[8] = 1;
+        assert_eq!(BloomFilter::read(v1.as_slice()).unwrap(), f);
+        let t = BloomFilter::with_rate(10, 0.01).with_templates(Fingerprinter::from_words([&b"user"[..]]));
+        let mut buf = Vec::new();
+        t.write(&mut buf).unwrap();
+        let back = BloomFilter::read(buf.as_slice()).unwrap();
+        assert_eq!(back.templates().words().collect::<Vec<_>>(), [b"user"]);
+        assert_eq!(back.fingerprint(b"user alice"), back.fingerprint(b"user bob"));
+    }
+
+    #[test]
+    fn unseen_keeps_new_records_and_maps_offsets() {
+        let mut f = BloomFilter::with_rate(100, 0.001);
+        f.insert(fingerprint(b"GET /health 200"));
+        let data = b"GET /health 200\nPANIC at 0x1f\nGET /health 200\nslow query 1200ms\n";
+        let u = unseen_records(data, b'\n', &f);
+        assert_eq!(u.total, 4);
+        assert_eq!(u.index, [1, 3]);
+        assert_eq!(u.data, b"PANIC at 0x1f\nslow query 1200ms\n");
+        assert_eq!(u.orig_offset(0), 16);
+        assert_eq!(u.orig_offset(14), 46);
+    }
+}
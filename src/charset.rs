@@ -0,0 +1,223 @@
+//! Input character set detection.
+//!
+//! Windows tooling often writes logs as UTF-16, with or without a byte
+//! order mark. Scanned as bytes, every other byte is a zero, so matches
+//! fragment and no line parses as JSON. Input is detected here and
+//! transcoded to UTF-8 before analysis; byte order marks are dropped.
+//! [`utf8_offsets`] maps the text's offsets back to the input's.
+//!
+//! Input that is not text at all (archives, images, core dumps) is flagged
+//! by [`looks_binary`], since splitting it on newlines yields arbitrary
+//! "records".
+
+use std::borrow::Cow;
+
+use crate::records::OffsetMap;
+
+/// Bytes sampled when guessing UTF-16 without a byte order mark.
+const SNIFF_LEN: usize = 4096;
+
+/// Bytes sampled when checking for binary content.
+const BINARY_SNIFF_LEN: usize = 64 * 1024;
+
+/// Share of control bytes at which input is considered binary. Text
+/// logs have next to none; compressed or random data has about 1/8.
+pub const BINARY_RATIO: f64 = 0.05;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// UTF-8 (or ASCII, or anything else passed through unchanged).
+    Utf8,
+    /// UTF-8 with a byte order mark.
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Charset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Charset::Utf8 => "UTF-8",
+            Charset::Utf8Bom => "UTF-8 with BOM",
+            Charset::Utf16Le => "UTF-16LE",
+            Charset::Utf16Be => "UTF-16BE",
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Detection
+// ---------------------------------------------------------------------------
+
+/// Detect the character set of `data` from its byte order mark, or, for
+/// UTF-16 without one, from zero bytes concentrated at odd (LE) or even
+/// (BE) positions, as in mostly-ASCII UTF-16 text.
+pub fn detect_charset(data: &[u8]) -> Charset {
+    if data.starts_with(&[0xef, 0xbb, 0xbf]) {
+        return Charset::Utf8Bom;
+    }
+    if data.starts_with(&[0xff, 0xfe]) {
+        return Charset::Utf16Le;
+    }
+    if data.starts_with(&[0xfe, 0xff]) {
+        return Charset::Utf16Be;
+    }
+    let pairs = data[..data.len().min(SNIFF_LEN)].chunks_exact(2);
+    let n = pairs.len();
+    if n < 2 {
+        return Charset::Utf8;
+    }
+    let (mut even, mut odd) = (0, 0);
+    for p in pairs {
+        even += (p[0] == 0) as usize;
+        odd += (p[1] == 0) as usize;
+    }
+    // Mostly-ASCII UTF-16 has a zero in nearly every pair, on one side only.
+    match (even * 10 < n, odd * 10 < n) {
+        (true, false) if odd * 10 >= n * 4 => Charset::Utf16Le,
+        (false, true) if even * 10 >= n * 4 => Charset::Utf16Be,
+        _ => Charset::Utf8,
+    }
+}
+
+/// `data` as UTF-8: UTF-16 is transcoded (invalid code units become
+/// U+FFFD), byte order marks are dropped, and UTF-8 is returned as is.
+pub fn decode_to_utf8(data: &[u8]) -> (Charset, Cow<'_, [u8]>) {
+    let charset = detect_charset(data);
+    let out = match charset {
+        Charset::Utf8 => Cow::Borrowed(data),
+        Charset::Utf8Bom => Cow::Borrowed(&data[3..]),
+        Charset::Utf16Le | Charset::Utf16Be => {
+            let body = match data.get(..2) {
+                Some([0xff, 0xfe] | [0xfe, 0xff]) => &data[2..],
+                _ => data,
+            };
+            let unit = |p: &[u8]| match charset {
+                Charset::Utf16Le => u16::from_le_bytes([p[0], p[1]]),
+                _ => u16::from_be_bytes([p[0], p[1]]),
+            };
+            let text: String = char::decode_utf16(body.chunks_exact(2).map(unit))
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect();
+            Cow::Owned(text.into_bytes())
+        }
+    };
+    (charset, out)
+}
+
+/// Offsets of `text`, decoded from `data` as `charset` by
+/// [`decode_to_utf8`], in `data`. Transcoded text is marked at the start
+/// and the delimiter of each line, so offsets between them are estimates.
+pub fn utf8_offsets(data: &[u8], charset: Charset, text: &[u8]) -> OffsetMap {
+    let mut map = OffsetMap::default();
+    match charset {
+        Charset::Utf8 => {}
+        Charset::Utf8Bom => map.mark(0, 3),
+        Charset::Utf16Le | Charset::Utf16Be => {
+            let mut orig = if matches!(data.get(..2), Some([0xff, 0xfe] | [0xfe, 0xff])) { 2 } else { 0 };
+            map.mark(0, orig);
+            for (i, c) in String::from_utf8_lossy(text).char_indices() {
+                if c == '\n' {
+                    map.mark(i, orig);
+                    map.mark(i + 1, orig + 2);
+                }
+                orig += 2 * c.len_utf16();
+            }
+        }
+    }
+    map
+}
+
+// ---------------------------------------------------------------------------
+// Binary content
+// ---------------------------------------------------------------------------
+
+/// Share of control bytes (other than tab, newline, carriage return, form
+/// feed, and escape) in the first 64 KB of `data`.
+pub fn binary_ratio(data: &[u8]) -> f64 {
+    let sample = &data[..data.len().min(BINARY_SNIFF_LEN)];
+    if sample.is_empty() {
+        return 0.0;
+    }
+    let control = sample
+        .iter()
+        .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || b == 0x7f)
+        .count();
+    control as f64 / sample.len() as f64
+}
+
+/// Whether `data` looks like binary content rather than text.
+pub fn looks_binary(data: &[u8]) -> bool {
+    binary_ratio(data) >= BINARY_RATIO
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(s: &str, le: bool, bom: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        for u in bom.then_some(0xfeff).into_iter().chain(s.encode_utf16()) {
+            out.extend(if le { u.to_le_bytes() } else { u.to_be_bytes() });
+        }
+        out
+    }
+
+    #[test]
+    fn transcodes_utf16_with_and_without_bom() {
+        let text = "{\"level\":\"INFO\",\"msg\":\"caf\u{e9} \u{1f600}\"}\r\n";
+        for (le, bom) in [(true, true), (false, true), (true, false), (false, false)] {
+            let input = utf16(text, le, bom);
+            let (cs, out) = decode_to_utf8(&input);
+            assert_eq!(cs, if le { Charset::Utf16Le } else { Charset::Utf16Be });
+            assert_eq!(out.as_ref(), text.as_bytes());
+        }
+    }
+
+    #[test]
+    fn transcoded_lines_map_back_to_the_input() {
+        let text = "caf\u{e9}\r\n\u{1f600} x\n";
+        let input = utf16(text, true, true);
+        let (cs, out) = decode_to_utf8(&input);
+        let map = utf8_offsets(&input, cs, &out);
+        // Line starts and delimiters, after the byte order mark.
+        assert_eq!([0, 6, 7, 13, 14].map(|o| map.orig_offset(o)), [2, 12, 14, 22, 24]);
+        assert_eq!(map.orig_offset(out.len()), input.len());
+        let bom = utf8_offsets(b"\xef\xbb\xbfline\n", Charset::Utf8Bom, b"line\n");
+        assert_eq!((bom.orig_offset(0), bom.orig_offset(4)), (3, 7));
+        assert!(utf8_offsets(b"line\n", Charset::Utf8, b"line\n").is_empty());
+    }
+
+    #[test]
+    fn utf8_passes_through_and_bom_is_stripped() {
+        let (cs, out) = decode_to_utf8(b"plain log line\n");
+        assert_eq!(cs, Charset::Utf8);
+        assert!(matches!(out, Cow::Borrowed(b"plain log line\n")));
+        let (cs, out) = decode_to_utf8(b"\xef\xbb\xbfline\n");
+        assert_eq!((cs, out.as_ref()), (Charset::Utf8Bom, &b"line\n"[..]));
+        assert_eq!(detect_charset(b"a"), Charset::Utf8);
+        assert_eq!(detect_charset(&[0u8; 64]), Charset::Utf8);
+    }
+
+    #[test]
+    fn binary_content_is_flagged() {
+        let log = b"2026-02-16 12:00:01 \x1b[32mINFO\x1b[0m\tserver started\r\n".repeat(50);
+        assert_eq!(binary_ratio(&log), 0.0);
+        assert!(!looks_binary(&log));
+        assert!(!looks_binary(b""));
+        // A tar header: name, then NUL padding and octal fields.
+        let mut tar = b"logs/app.log".to_vec();
+        tar.resize(512, 0);
+        assert!(looks_binary(&tar));
+        let noise: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        assert!(looks_binary(&noise));
+    }
+}
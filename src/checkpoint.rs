@@ -0,0 +1,395 @@
+//! Follow-mode state files.
+//!
+//! `follow --state FILE` saves where it stopped reading and what the
+//! detector had learned, so a restarted run picks up at the next record
+//! with its baseline intact instead of rescoring the file or warming up
+//! again. Each followed source has its own entry. A file is identified by
+//! its inode and by a hash of the bytes just before the saved offset, which
+//! tells a rotated or truncated log from one that only grew.
+//!
+//! Layout (integers little-endian, `varint` = LEB128):
+//!
+//! ```text
+//! magic     8 bytes   "BGTZFOLW"
+//! version   u16
+//! count     varint    sources (version 2+; version 1 holds one, unnamed)
+//! count x {
+//!   source    varint length, UTF-8 bytes (version 2+)
+//!   inode     u64       of the file read (0 if unknown)
+//!   offset    u64       bytes consumed, always at a record boundary
+//!   tail      u64       FNV-1a of the TAIL_BYTES (or fewer) before offset
+//!   detector  scanner history, pattern counts and threshold statistics
+//!             (see `OnlineDetector::write`)
+//! }
+//! ```
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::online::OnlineDetector;
+use crate::opsfile::{fnv1a64, invalid, read_u64, read_varint, write_varint};
+
+pub const STATE_MAGIC: &[u8; 8] = b"BGTZFOLW";
+pub const STATE_VERSION: u16 = 2;
+
+/// Bytes before the saved offset that are hashed to recognize the file.
+pub const TAIL_BYTES: u64 = 256;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// Where a follow run stopped on one source and what it had learned.
+pub struct FollowState {
+    /// The path followed, as given (empty in version 1 files).
+    pub source: String,
+    pub inode: u64,
+    pub offset: u64,
+    /// [`tail_hash`] of the bytes before `offset`.
+    pub tail: u64,
+    pub detector: OnlineDetector,
+}
+
+/// How to pick up a file again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Same file: continue at this offset.
+    At(u64),
+    /// The file was replaced (a new inode): finish the old one, if it can
+    /// be found, then read the new one from the start.
+    Rotated,
+    /// Same inode but shorter than the offset, or with different bytes
+    /// before it: read from the start.
+    Truncated,
+}
+
+impl FollowState {
+    /// Decide how to resume on a file with `inode` and `len` bytes, whose
+    /// bytes before the saved offset hash to `tail` (`None` if it is
+    /// shorter than the offset).
+    pub fn resume(&self, inode: u64, len: u64, tail: Option<u64>) -> Resume {
+        if self.inode != 0 && inode != 0 && self.inode != inode {
+            return Resume::Rotated;
+        }
+        if len < self.offset || tail != Some(self.tail) {
+            return Resume::Truncated;
+        }
+        Resume::At(self.offset)
+    }
+}
+
+/// Hash of the last [`TAIL_BYTES`] of `before`, the data preceding an
+/// offset.
+pub fn tail_hash(before: &[u8]) -> u64 {
+    fnv1a64(&before[before.len().saturating_sub(TAIL_BYTES as usize)..])
+}
+
+// ---------------------------------------------------------------------------
+// Files
+// ---------------------------------------------------------------------------
+
+/// Inode of a file, or 0 where there are none.
+#[cfg(unix)]
+pub fn inode(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.ino()
+}
+
+/// Inode of a file, or 0 where there are none.
+#[cfg(not(unix))]
+pub fn inode(_: &fs::Metadata) -> u64 {
+    0
+}
+
+/// [`tail_hash`] of the bytes of `path` before `offset`; `None` if the
+/// file is shorter.
+pub fn tail_at(path: &Path, offset: u64) -> Option<u64> {
+    let from = offset.saturating_sub(TAIL_BYTES);
+    let mut f = File::open(path).ok()?;
+    f.seek(SeekFrom::Start(from)).ok()?;
+    let mut buf = Vec::new();
+    f.take(offset - from).read_to_end(&mut buf).ok()?;
+    (buf.len() as u64 == offset - from).then(|| tail_hash(&buf))
+}
+
+/// Another file beside `path` with inode `ino`: where a rotated log went.
+pub fn find_rotated(path: &Path, ino: u64) -> Option<PathBuf> {
+    let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::read_dir(dir).ok()?.flatten().map(|e| e.path()).find(|p| {
+        p.file_name() != path.file_name() && p.metadata().is_ok_and(|m| m.is_file() && inode(&m) == ino)
+    })
+}
+
+impl FollowState {
+    /// [`resume`](Self::resume) on the file now at `path`.
+    pub fn resume_file(&self, path: &Path) -> io::Result<Resume> {
+        let meta = fs::metadata(path)?;
+        Ok(self.resume(inode(&meta), meta.len(), tail_at(path, self.offset)))
+    }
+
+    /// State of `source`, read up to `offset` of the file now at `path`.
+    pub fn new(source: &str, path: &Path, offset: u64, detector: OnlineDetector) -> Self {
+        let inode = fs::metadata(path).map_or(0, |m| inode(&m));
+        let tail = tail_at(path, offset).unwrap_or(0);
+        FollowState { source: source.to_string(), inode, offset, tail, detector }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Sources
+// ---------------------------------------------------------------------------
+
+/// The saved states of a run over `inputs`. A version 1 state names no
+/// source; it was written for a single file and is given that file's name.
+pub fn states_for(mut saved: Vec<FollowState>, inputs: &[String]) -> Vec<FollowState> {
+    if let [input] = inputs {
+        saved.iter_mut().filter(|s| s.source.is_empty()).for_each(|s| s.source = input.clone());
+    }
+    saved
+}
+
+/// Remove and return the state saved for `source`.
+pub fn take_state(saved: &mut Vec<FollowState>, source: &str) -> Option<FollowState> {
+    saved.iter().position(|s| s.source == source).map(|i| saved.swap_remove(i))
+}
+
+/// The states to save after a run over `inputs`: those of the sources
+/// read, plus the saved ones of inputs not reached. Sources no longer
+/// followed are dropped.
+pub fn next_states(mut read: Vec<FollowState>, saved: Vec<FollowState>, inputs: &[String]) -> Vec<FollowState> {
+    let kept: Vec<FollowState> = saved
+        .into_iter()
+        .filter(|s| inputs.contains(&s.source) && !read.iter().any(|r| r.source == s.source))
+        .collect();
+    read.extend(kept);
+    read
+}
+
+// ---------------------------------------------------------------------------
+// Write / read
+// ---------------------------------------------------------------------------
+
+pub fn write_state<W: Write>(mut w: W, states: &[FollowState]) -> io::Result<()> {
+    w.write_all(STATE_MAGIC)?;
+    w.write_all(&STATE_VERSION.to_le_bytes())?;
+    write_varint(&mut w, states.len() as u64)?;
+    for s in states {
+        write_varint(&mut w, s.source.len() as u64)?;
+        w.write_all(s.source.as_bytes())?;
+        for v in [s.inode, s.offset, s.tail] {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        s.detector.write(&mut w)?;
+    }
+    w.flush()
+}
+
+pub fn read_state<R: Read>(mut r: R) -> io::Result<Vec<FollowState>> {
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != STATE_MAGIC {
+        return Err(invalid("not a bgtzip follow state file"));
+    }
+    let mut v = [0u8; 2];
+    r.read_exact(&mut v)?;
+    let version = u16::from_le_bytes(v);
+    if version == 0 || version > STATE_VERSION {
+        return Err(invalid(format!(
+            "unsupported follow state version {version} (expected {STATE_VERSION})"
+        )));
+    }
+    let count = if version >= 2 { read_varint(&mut r)? } else { 1 };
+    let mut states = Vec::new();
+    for _ in 0..count {
+        let source = if version >= 2 { read_source(&mut r)? } else { String::new() };
+        let inode = read_u64(&mut r)?;
+        let offset = read_u64(&mut r)?;
+        let tail = read_u64(&mut r)?;
+        let detector = OnlineDetector::read(&mut r)?;
+        states.push(FollowState { source, inode, offset, tail, detector });
+    }
+    Ok(states)
+}
+
+fn read_source<R: Read>(r: &mut R) -> io::Result<String> {
+    let n = read_varint(r)?;
+    let mut buf = Vec::new();
+    r.take(n).read_to_end(&mut buf)?;
+    if buf.len() as u64 != n {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(buf).map_err(|_| invalid("invalid UTF-8 in source name"))
+}
+
+/// Write `states` to `path`, replacing it only once the new state is
+/// complete so an interrupted save leaves the previous one.
+pub fn save_state(path: impl AsRef<Path>, states: &[FollowState]) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    write_state(BufWriter::new(File::create(&tmp)?), states)?;
+    fs::rename(&tmp, path)
+}
+
+pub fn load_state(path: impl AsRef<Path>) -> io::Result<Vec<FollowState>> {
+    read_state(BufReader::new(File::open(path)?))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anomaly::DetectionMethod;
+    use crate::scanner::ScanParams;
+
+    fn line(i: usize) -> String {
+        match i {
+            700 => "KERNEL PANIC: fatal error 0xDEADBEEF segfault in worker\n".to_string(),
+            _ => format!("2026-02-16 app: request {} handled in {}ms\n", i % 10, i % 7),
+        }
+    }
+
+    #[test]
+    fn restored_detector_continues_where_it_stopped() {
+        for method in [DetectionMethod::Score, DetectionMethod::Percentile] {
+            let new = || OnlineDetector::new(&ScanParams::default(), method, None).unwrap();
+            let (mut whole, mut first) = (new(), new());
+            let mut expected = Vec::new();
+            for i in 0..1000 {
+                expected.push(whole.push(line(i).as_bytes()));
+                if i < 400 {
+                    first.push(line(i).as_bytes());
+                }
+            }
+            let state = FollowState {
+                source: "app.log".into(),
+                inode: 7,
+                offset: 1234,
+                tail: tail_hash(b"x"),
+                detector: first,
+            };
+            let mut buf = Vec::new();
+            write_state(&mut buf, &[state]).unwrap();
+            let mut back = read_state(buf.as_slice()).unwrap().pop().unwrap();
+            assert_eq!((back.source.as_str(), back.inode, back.offset), ("app.log", 7, 1234));
+            assert_eq!(back.detector.records(), 400);
+            let rest: Vec<_> = (400..1000).map(|i| back.detector.push(line(i).as_bytes())).collect();
+            assert_eq!(rest, expected[400..]);
+            assert!(rest.iter().flatten().any(|a| a.index == 700));
+
+            // Version 1: one unnamed source, no count or name.
+            let mut v1 = b"BGTZFOLW\x01\x00".to_vec();
+            v1.extend(&buf[10 + 1 + 1 + "app.log".len()..]);
+            let back = read_state(v1.as_slice()).unwrap();
+            assert_eq!((back.len(), back[0].source.as_str(), back[0].offset), (1, "", 1234));
+        }
+        assert!(read_state(&b"BGTZFOLW\x09\x00"[..]).is_err());
+        assert!(read_state(&b"not state"[..]).is_err());
+    }
+
+    #[test]
+    fn rotation_and_truncation_are_told_apart() {
+        let data = b"one\ntwo\nthree\n";
+        let detector = OnlineDetector::new(&ScanParams::default(), DetectionMethod::Score, None).unwrap();
+        let s = FollowState { source: String::new(), inode: 42, offset: 8, tail: tail_hash(&data[..8]), detector };
+        let grown = [&data[..], b"four\n"].concat();
+        assert_eq!(s.resume(42, grown.len() as u64, Some(tail_hash(&grown[..8]))), Resume::At(8));
+        assert_eq!(s.resume(43, 100, None), Resume::Rotated);
+        assert_eq!(s.resume(42, 4, None), Resume::Truncated);
+        // Truncated, then written past the old offset again.
+        let rewritten = b"eight\nnine\nten\n";
+        assert_eq!(s.resume(42, 15, Some(tail_hash(&rewritten[..8]))), Resume::Truncated);
+    }
+
+    fn detector() -> OnlineDetector {
+        OnlineDetector::new(&ScanParams::default(), DetectionMethod::Score, None).unwrap()
+    }
+
+    #[test]
+    fn several_sources_round_trip() {
+        let states: Vec<FollowState> = ["a.log", "b.log", "c.log"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let mut detector = detector();
+                for j in 0..=i * 10 {
+                    detector.push(line(j).as_bytes());
+                }
+                let (inode, offset, tail) = (i as u64 + 1, 100 * i as u64, i as u64);
+                FollowState { source: name.to_string(), inode, offset, tail, detector }
+            })
+            .collect();
+        let mut buf = Vec::new();
+        write_state(&mut buf, &states).unwrap();
+        let back = read_state(buf.as_slice()).unwrap();
+        let got: Vec<_> =
+            back.iter().map(|s| (s.source.as_str(), s.inode, s.offset, s.tail, s.detector.records())).collect();
+        assert_eq!(got, [("a.log", 1, 0, 0, 1), ("b.log", 2, 100, 1, 11), ("c.log", 3, 200, 2, 21)]);
+    }
+
+    #[test]
+    fn version_1_state_is_named_after_the_one_input() {
+        let state = FollowState { source: String::new(), inode: 9, offset: 42, tail: 5, detector: detector() };
+        let mut buf = Vec::new();
+        write_state(&mut buf, &[state]).unwrap();
+        // Version 1: no count, no source name.
+        let mut v1 = b"BGTZFOLW\x01\x00".to_vec();
+        v1.extend(&buf[10 + 1 + 1..]);
+        let saved = read_state(v1.as_slice()).unwrap();
+        assert_eq!((saved.len(), saved[0].source.as_str(), saved[0].inode, saved[0].offset), (1, "", 9, 42));
+
+        let mut named = states_for(saved, &["app.log".to_string()]);
+        assert!(take_state(&mut named, "app.log").is_some_and(|s| s.offset == 42));
+        assert!(named.is_empty());
+        // With several inputs it is not known which one it was.
+        let saved = read_state(v1.as_slice()).unwrap();
+        let inputs = ["a.log".to_string(), "b.log".to_string()];
+        assert!(next_states(Vec::new(), states_for(saved, &inputs), &inputs).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn one_source_rotates_while_the_others_grow() {
+        let dir = std::env::temp_dir().join(format!("bgtzip-checkpoint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.log"), dir.join("b.log"));
+        let text: String = (0..50).map(line).collect();
+        fs::write(&a, &text).unwrap();
+        fs::write(&b, &text).unwrap();
+        let len = text.len() as u64;
+        let inputs: Vec<String> = [&a, &b, &dir.join("c.log")].iter().map(|p| p.display().to_string()).collect();
+        let mut saved = vec![
+            FollowState::new(&inputs[0], &a, len, detector()),
+            FollowState::new(&inputs[1], &b, len, detector()),
+            FollowState { source: "gone.log".into(), inode: 1, offset: 0, tail: 0, detector: detector() },
+        ];
+
+        fs::rename(&a, dir.join("a.log.1")).unwrap();
+        fs::write(&a, line(1)).unwrap();
+        fs::write(&b, [text.as_bytes(), line(2).as_bytes()].concat()).unwrap();
+
+        let sa = take_state(&mut saved, &inputs[0]).unwrap();
+        assert_eq!(sa.resume_file(&a).unwrap(), Resume::Rotated);
+        assert_eq!(find_rotated(&a, sa.inode), Some(dir.join("a.log.1")));
+        let sb = take_state(&mut saved, &inputs[1]).unwrap();
+        assert_eq!(sb.resume_file(&b).unwrap(), Resume::At(len));
+        assert!(take_state(&mut saved, &inputs[2]).is_none());
+
+        // `a` was finished and read anew, `b` read on; `gone.log` is no
+        // longer followed.
+        let read = vec![
+            FollowState::new(&inputs[0], &a, line(1).len() as u64, sa.detector),
+            FollowState::new(&inputs[1], &b, len + line(2).len() as u64, sb.detector),
+        ];
+        let next = next_states(read, saved, &inputs);
+        let offsets: Vec<_> = next.iter().map(|s| (s.source.as_str(), s.offset)).collect();
+        let expected = [(inputs[0].as_str(), line(1).len() as u64), (inputs[1].as_str(), len + line(2).len() as u64)];
+        assert_eq!(offsets, expected);
+        assert_eq!(next[1].resume_file(&b).unwrap(), Resume::At(next[1].offset));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,211 @@
+//! Clustering records by the features they share.
+//!
+//! Thresholding scores looks at each record alone; clustering looks at
+//! groups. Each record becomes a sparse binary feature vector (the
+//! dictionary entries it references in LZ77 mode, its field/template tokens
+//! in JSON mode), normalized to unit length, and spherical k-means groups
+//! records by cosine similarity. A handful of related oddities that would
+//! each pass a threshold end up together in a tiny cluster.
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// Sparse vector as `(dimension, value)` pairs sorted by dimension.
+pub type SparseVec = Vec<(usize, f64)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clustering {
+    /// Cluster of each record; `None` for records with no features.
+    pub assignments: Vec<Option<usize>>,
+    /// Records per cluster. Clusters are numbered largest first.
+    pub sizes: Vec<usize>,
+    /// Per cluster, the member closest to the centroid.
+    pub representatives: Vec<usize>,
+    pub iterations: usize,
+}
+
+impl Clustering {
+    /// Members of cluster `c`, in record order.
+    pub fn members(&self, c: usize) -> Vec<usize> {
+        (0..self.assignments.len()).filter(|&i| self.assignments[i] == Some(c)).collect()
+    }
+
+    /// Records with no features (never assigned).
+    pub fn unassigned(&self) -> Vec<usize> {
+        (0..self.assignments.len()).filter(|&i| self.assignments[i].is_none()).collect()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Vectors
+// ---------------------------------------------------------------------------
+
+/// Unit-length binary vectors from per-record feature IDs (duplicates are
+/// ignored). Records without features get an empty vector.
+pub fn unit_vectors(features: &[Vec<usize>]) -> Vec<SparseVec> {
+    features
+        .iter()
+        .map(|f| {
+            let mut dims = f.clone();
+            dims.sort_unstable();
+            dims.dedup();
+            let w = 1.0 / (dims.len() as f64).sqrt();
+            dims.into_iter().map(|d| (d, w)).collect()
+        })
+        .collect()
+}
+
+#[inline]
+fn dot_dense(v: &SparseVec, dense: &[f64]) -> f64 {
+    v.iter().map(|&(d, x)| x * dense[d]).sum()
+}
+
+fn normalize(dense: &mut [f64]) {
+    let norm = dense.iter().map(|x| x * x).sum::<f64>().sqrt();
+    if norm > 0.0 {
+        dense.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+// ---------------------------------------------------------------------------
+// k-means
+// ---------------------------------------------------------------------------
+
+/// Spherical k-means over unit vectors (see [`unit_vectors`]).
+///
+/// Deterministic: centers are seeded by farthest-first traversal from the
+/// first non-empty vector, which tends to seed outliers as their own
+/// centers. Fewer than `k` clusters are returned if there are fewer
+/// distinct vectors. Empty clusters are dropped.
+pub fn kmeans(vectors: &[SparseVec], k: usize, max_iter: usize) -> Clustering {
+    let dims = vectors.iter().flat_map(|v| v.iter().map(|&(d, _)| d + 1)).max().unwrap_or(0);
+    let live: Vec<usize> = (0..vectors.len()).filter(|&i| !vectors[i].is_empty()).collect();
+    let to_dense = |v: &SparseVec| {
+        let mut d = vec![0.0; dims];
+        v.iter().for_each(|&(i, x)| d[i] = x);
+        d
+    };
+
+    // Farthest-first seeding: best[i] = similarity to the nearest center.
+    let mut centroids: Vec<Vec<f64>> = Vec::new();
+    let mut best = vec![f64::NEG_INFINITY; vectors.len()];
+    let mut next = live.first().copied();
+    while let Some(s) = next {
+        if centroids.len() >= k {
+            break;
+        }
+        let c = to_dense(&vectors[s]);
+        for &i in &live {
+            best[i] = best[i].max(dot_dense(&vectors[i], &c));
+        }
+        centroids.push(c);
+        next = live
+            .iter()
+            .copied()
+            .filter(|&i| best[i] < 1.0 - 1e-9)
+            .min_by(|&a, &b| best[a].partial_cmp(&best[b]).unwrap().then(a.cmp(&b)));
+    }
+
+    let mut assign: Vec<Option<usize>> = vec![None; vectors.len()];
+    let mut iterations = 0;
+    while iterations < max_iter {
+        iterations += 1;
+        let mut changed = false;
+        for &i in &live {
+            let c = (0..centroids.len())
+                .map(|c| (c, dot_dense(&vectors[i], &centroids[c])))
+                .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(b.0.cmp(&a.0)))
+                .map(|(c, _)| c);
+            if assign[i] != c {
+                assign[i] = c;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+        let mut sums = vec![vec![0.0; dims]; centroids.len()];
+        for &i in &live {
+            let c = assign[i].unwrap();
+            vectors[i].iter().for_each(|&(d, x)| sums[c][d] += x);
+        }
+        for (c, mut s) in sums.into_iter().enumerate() {
+            if s.iter().any(|&x| x != 0.0) {
+                normalize(&mut s);
+                centroids[c] = s;
+            }
+        }
+    }
+
+    // Renumber by size (largest first), dropping empty clusters.
+    let mut sizes = vec![0usize; centroids.len()];
+    assign.iter().flatten().for_each(|&c| sizes[c] += 1);
+    let mut order: Vec<usize> = (0..centroids.len()).filter(|&c| sizes[c] > 0).collect();
+    order.sort_by_key(|&c| (std::cmp::Reverse(sizes[c]), c));
+    let mut renum = vec![usize::MAX; centroids.len()];
+    for (new, &old) in order.iter().enumerate() {
+        renum[old] = new;
+    }
+    let mut representatives = vec![usize::MAX; order.len()];
+    let mut rep_sim = vec![f64::NEG_INFINITY; order.len()];
+    for &i in &live {
+        let old = assign[i].unwrap();
+        let new = renum[old];
+        let sim = dot_dense(&vectors[i], &centroids[old]);
+        if sim > rep_sim[new] {
+            rep_sim[new] = sim;
+            representatives[new] = i;
+        }
+    }
+    Clustering {
+        assignments: assign.iter().map(|a| a.map(|c| renum[c])).collect(),
+        sizes: order.iter().map(|&c| sizes[c]).collect(),
+        representatives,
+        iterations,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unit_vectors_are_normalized() {
+        let v = unit_vectors(&[vec![3, 1, 3, 2], vec![]]);
+        assert_eq!(v[0].iter().map(|&(d, _)| d).collect::<Vec<_>>(), [1, 2, 3]);
+        assert!((v[0].iter().map(|&(_, x)| x * x).sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(v[1].is_empty());
+    }
+
+    #[test]
+    fn separates_groups_and_isolates_outlier() {
+        let mut features = Vec::new();
+        for i in 0..20 {
+            features.push(vec![0, 1, 2, 10 + i % 2]);
+        }
+        for i in 0..10 {
+            features.push(vec![5, 6, 7, 12 + i % 2]);
+        }
+        features.push(vec![40, 41]);
+        features.push(vec![]);
+        let cl = kmeans(&unit_vectors(&features), 3, 50);
+
+        assert_eq!(cl.sizes, [20, 10, 1]);
+        assert!(cl.members(0).iter().all(|&i| i < 20));
+        assert_eq!(cl.members(2), [30]);
+        assert_eq!(cl.representatives[2], 30);
+        assert_eq!(cl.unassigned(), [31]);
+        assert!(cl.iterations >= 1);
+    }
+
+    #[test]
+    fn fewer_distinct_vectors_than_k() {
+        let cl = kmeans(&unit_vectors(&[vec![1], vec![1], vec![2]]), 5, 10);
+        assert_eq!(cl.sizes, [2, 1]);
+    }
+}
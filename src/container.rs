@@ -0,0 +1,251 @@
+//! Compressed container format (`.bgtz`).
+//!
+//! Turns the analysis into real output: the input is raw-deflated with a
+//! preset dictionary built from the LZ77 dictionary
+//! ([`crate::dictionary::zlib_preset`]), and the dictionary travels in the
+//! header (itself deflated) so the file is self-contained. [`decompress`]
+//! restores the original bytes and checks them against the stored length
+//! and checksum.
+//!
+//! Layout (integers little-endian, `varint` = LEB128):
+//!
+//! ```text
+//! magic     8 bytes   "BGTZDATA"
+//! version   u16
+//! orig_len  varint    length of the original data
+//! checksum  u64       FNV-1a of the original data
+//! dict_len  varint    preset dictionary length (<= 32 KB)
+//! dict      varint length, bytes: the dictionary, raw-deflated
+//! payload   raw deflate stream, primed with dict, to end of file
+//! ```
+
+use std::io::{self, Write};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use crate::opsfile::{fnv1a64, invalid, read_u64, read_varint, write_varint};
+
+pub const CONTAINER_MAGIC: &[u8; 8] = b"BGTZDATA";
+pub const CONTAINER_VERSION: u16 = 1;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// Everything in a container before the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerHeader {
+    pub version: u16,
+    /// Length of the original data.
+    pub orig_len: usize,
+    /// FNV-1a of the original data.
+    pub checksum: u64,
+    /// Preset dictionary, inflated.
+    pub dict: Vec<u8>,
+    /// Offset of the deflate payload in the file.
+    pub payload_offset: usize,
+}
+
+/// Bytes taken by each part of a written container. The dictionary is
+/// overhead that plain deflate does not pay: it only wins when the payload
+/// saves more than `dict` costs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContainerSizes {
+    /// Magic, version, lengths and checksum.
+    pub header: usize,
+    /// The preset dictionary, deflated.
+    pub dict: usize,
+    /// The deflate stream primed with the dictionary.
+    pub payload: usize,
+}
+
+impl ContainerSizes {
+    pub fn total(&self) -> usize {
+        self.header + self.dict + self.payload
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Compression
+// ---------------------------------------------------------------------------
+
+/// Raw-deflate `data` at `level` (0-9), primed with `dict`.
+pub fn deflate(data: &[u8], dict: &[u8], level: u32) -> io::Result<Vec<u8>> {
+    let mut c = Compress::new(Compression::new(level), false);
+    if !dict.is_empty() {
+        c.set_dictionary(dict).map_err(io::Error::other)?;
+    }
+    let mut out = Vec::with_capacity(data.len() / 4 + 64);
+    loop {
+        let before = c.total_in() as usize;
+        let status = c
+            .compress_vec(&data[before..], &mut out, FlushCompress::Finish)
+            .map_err(io::Error::other)?;
+        if status == Status::StreamEnd {
+            return Ok(out);
+        }
+        out.reserve(out.capacity().max(64));
+    }
+}
+
+/// Write a complete container for `data` with preset dictionary `dict`.
+/// Returns the size of each part written.
+pub fn write_container<W: Write>(
+    mut w: W,
+    data: &[u8],
+    dict: &[u8],
+    level: u32,
+) -> io::Result<ContainerSizes> {
+    let payload = deflate(data, dict, level)?;
+    let packed_dict = deflate(dict, b"", 9)?;
+    let mut header = Vec::with_capacity(40);
+    header.extend_from_slice(CONTAINER_MAGIC);
+    header.extend_from_slice(&CONTAINER_VERSION.to_le_bytes());
+    write_varint(&mut header, data.len() as u64)?;
+    header.extend_from_slice(&fnv1a64(data).to_le_bytes());
+    write_varint(&mut header, dict.len() as u64)?;
+    write_varint(&mut header, packed_dict.len() as u64)?;
+    w.write_all(&header)?;
+    w.write_all(&packed_dict)?;
+    w.write_all(&payload)?;
+    w.flush()?;
+    Ok(ContainerSizes { header: header.len(), dict: packed_dict.len(), payload: payload.len() })
+}
+
+// ---------------------------------------------------------------------------
+// Decompression
+// ---------------------------------------------------------------------------
+
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(CONTAINER_MAGIC)
+}
+
+/// Inflate a raw deflate stream primed with `dict`. `len` is the expected
+/// output size (a capacity hint). The stream must end exactly at the end
+/// of `payload`.
+pub fn inflate(payload: &[u8], dict: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut d = Decompress::new(false);
+    if !dict.is_empty() {
+        d.set_dictionary(dict).map_err(io::Error::other)?;
+    }
+    let mut out = Vec::with_capacity(len.max(64));
+    loop {
+        let before = (d.total_in(), d.total_out());
+        let status = d
+            .decompress_vec(&payload[before.0 as usize..], &mut out, FlushDecompress::Finish)
+            .map_err(|e| invalid(format!("corrupt deflate stream: {e}")))?;
+        if status == Status::StreamEnd {
+            break;
+        }
+        if (d.total_in(), d.total_out()) == before && out.len() < out.capacity() {
+            return Err(invalid("truncated deflate stream"));
+        }
+        out.reserve(out.capacity().max(64));
+    }
+    if d.total_in() as usize != payload.len() {
+        return Err(invalid("trailing bytes after deflate stream"));
+    }
+    Ok(out)
+}
+
+/// Parse the header of container `file`.
+pub fn read_header(file: &[u8]) -> io::Result<ContainerHeader> {
+    if !is_container(file) {
+        return Err(invalid("not a bgtzip container"));
+    }
+    let mut r = &file[CONTAINER_MAGIC.len()..];
+    let ver = r.get(..2).ok_or_else(|| invalid("truncated container header"))?;
+    let version = u16::from_le_bytes([ver[0], ver[1]]);
+    if version == 0 || version > CONTAINER_VERSION {
+        return Err(invalid(format!(
+            "unsupported container version {version} (expected {CONTAINER_VERSION})"
+        )));
+    }
+    r = &r[2..];
+    let orig_len = read_varint(&mut r)? as usize;
+    let checksum = read_u64(&mut r)?;
+    let dict_len = read_varint(&mut r)? as usize;
+    let packed_len = read_varint(&mut r)? as usize;
+    let packed = r.get(..packed_len).ok_or_else(|| invalid("truncated container dictionary"))?;
+    let dict = inflate(packed, b"", dict_len)?;
+    if dict.len() != dict_len {
+        return Err(invalid("container dictionary has the wrong length"));
+    }
+    let payload_offset = file.len() - r.len() + packed_len;
+    Ok(ContainerHeader { version, orig_len, checksum, dict, payload_offset })
+}
+
+/// Restore the original data from container `file`, checking its length
+/// and checksum.
+pub fn decompress(file: &[u8]) -> io::Result<Vec<u8>> {
+    let h = read_header(file)?;
+    let data = inflate(&file[h.payload_offset..], &h.dict, h.orig_len)?;
+    if data.len() != h.orig_len {
+        return Err(invalid(format!(
+            "length mismatch: expected {} bytes, got {}",
+            h.orig_len,
+            data.len()
+        )));
+    }
+    let sum = fnv1a64(&data);
+    if sum != h.checksum {
+        return Err(invalid(format!(
+            "checksum mismatch: expected {:016x}, got {sum:016x}",
+            h.checksum
+        )));
+    }
+    Ok(data)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn container_layout_and_payload() {
+        let data = b"GET /api/v1/users?id=7 HTTP/1.1 200 OK\n".repeat(20);
+        let dict = b"HTTP/1.1 200 OK\nGET /api/v1/users?id=";
+        let mut file = Vec::new();
+        let sizes = write_container(&mut file, &data, dict, 6).unwrap();
+        assert_eq!(sizes.total(), file.len());
+        assert_eq!(sizes.header, 22);
+        let payload_len = sizes.payload;
+
+        assert!(file.starts_with(CONTAINER_MAGIC));
+        assert_eq!(&file[8..10], &CONTAINER_VERSION.to_le_bytes());
+        assert_eq!(file[10] as usize, data.len() & 0x7f | 0x80);
+        assert_eq!(file[20] as usize, dict.len());
+        let packed = file[21] as usize;
+        assert_eq!(packed, sizes.dict);
+        assert_eq!(inflate(&file[22..22 + packed], b"", dict.len()).unwrap(), dict);
+        let payload = &file[22 + packed..];
+        assert_eq!(payload.len(), payload_len);
+        assert_eq!(inflate(payload, dict, data.len()).unwrap(), data);
+        assert!(payload_len < deflate(&data, b"", 6).unwrap().len());
+    }
+
+    #[test]
+    fn round_trip_and_corruption() {
+        let data: Vec<u8> = (0..500).flat_map(|i| format!("req {i} ok\n").into_bytes()).collect();
+        let mut file = Vec::new();
+        write_container(&mut file, &data, b"req  ok\n", 9).unwrap();
+        let h = read_header(&file).unwrap();
+        assert_eq!((h.orig_len, h.dict.as_slice()), (data.len(), &b"req  ok\n"[..]));
+        assert_eq!(decompress(&file).unwrap(), data);
+
+        let mut empty = Vec::new();
+        write_container(&mut empty, b"", b"", 6).unwrap();
+        assert_eq!(decompress(&empty).unwrap(), b"");
+
+        assert!(decompress(&file[..file.len() - 3]).is_err());
+        let mut bad_sum = file.clone();
+        bad_sum[14] ^= 1;
+        let err = decompress(&bad_sum).unwrap_err().to_string();
+        assert!(err.contains("checksum mismatch"), "{err}");
+        assert!(decompress(b"plain text").is_err());
+    }
+}
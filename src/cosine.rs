@@ -0,0 +1,154 @@
+//! Cosine-distance rarity.
+//!
+//! The rank-based `rarity_score` only looks at which dictionary entries a
+//! record references, one at a time. Here each record is a unit vector of
+//! referenced entries ([`crate::cluster::unit_vectors`]) and rarity is its
+//! cosine distance to the corpus: either to the mean direction of all
+//! records, or to its `k` nearest neighbors, which also rates records of a
+//! rare-but-legitimate kind as normal when there are enough of them.
+//!
+//! Vectors are non-negative, so distances lie in `0..=1`. Records with no
+//! features get distance 1.
+
+use std::collections::HashMap;
+
+use crate::cluster::SparseVec;
+
+// ---------------------------------------------------------------------------
+// Centroid
+// ---------------------------------------------------------------------------
+
+/// `1 - cos(v, centroid)` for each vector, where the centroid is the
+/// normalized mean of all non-empty vectors.
+pub fn centroid_distances(vectors: &[SparseVec]) -> Vec<f64> {
+    let dims = vectors.iter().flat_map(|v| v.iter().map(|&(d, _)| d + 1)).max().unwrap_or(0);
+    let mut centroid = vec![0.0f64; dims];
+    for v in vectors {
+        v.iter().for_each(|&(d, x)| centroid[d] += x);
+    }
+    let norm = centroid.iter().map(|x| x * x).sum::<f64>().sqrt();
+    vectors
+        .iter()
+        .map(|v| {
+            if v.is_empty() || norm == 0.0 {
+                return 1.0;
+            }
+            let cos: f64 = v.iter().map(|&(d, x)| x * centroid[d]).sum::<f64>() / norm;
+            (1.0 - cos).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Nearest neighbors
+// ---------------------------------------------------------------------------
+
+/// Mean `1 - cos` to each vector's `k` nearest other vectors.
+///
+/// Identical vectors are collapsed first (log records repeat a lot), and
+/// dot products are accumulated through an inverted index, so cost grows
+/// with the number of distinct vectors sharing entries rather than with
+/// all pairs.
+pub fn knn_distances(vectors: &[SparseVec], k: usize) -> Vec<f64> {
+    // Distinct non-empty vectors and their multiplicities.
+    let mut ids: HashMap<Vec<(usize, u64)>, usize> = HashMap::new();
+    let mut uniq: Vec<&SparseVec> = Vec::new();
+    let mut count: Vec<usize> = Vec::new();
+    let mut of: Vec<Option<usize>> = Vec::with_capacity(vectors.len());
+    for v in vectors {
+        if v.is_empty() {
+            of.push(None);
+            continue;
+        }
+        let key = v.iter().map(|&(d, x)| (d, x.to_bits())).collect();
+        let id = *ids.entry(key).or_insert_with(|| {
+            uniq.push(v);
+            count.push(0);
+            uniq.len() - 1
+        });
+        count[id] += 1;
+        of.push(Some(id));
+    }
+
+    let mut postings: HashMap<usize, Vec<(usize, f64)>> = HashMap::new();
+    for (u, v) in uniq.iter().enumerate() {
+        for &(d, x) in v.iter() {
+            postings.entry(d).or_default().push((u, x));
+        }
+    }
+
+    let neighbors = vectors.len().saturating_sub(1).min(k);
+    let mut dots = vec![0.0f64; uniq.len()];
+    let mut touched: Vec<usize> = Vec::new();
+    let mut dist = vec![1.0f64; uniq.len()];
+    for (u, v) in uniq.iter().enumerate() {
+        if neighbors == 0 {
+            break;
+        }
+        for &(d, x) in v.iter() {
+            for &(w, y) in &postings[&d] {
+                if dots[w] == 0.0 {
+                    touched.push(w);
+                }
+                dots[w] += x * y;
+            }
+        }
+        // (similarity, how many records have it), best first.
+        let mut sims: Vec<(f64, usize)> = touched
+            .iter()
+            .map(|&w| (dots[w], if w == u { count[u] - 1 } else { count[w] }))
+            .collect();
+        sims.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        let (mut taken, mut sum) = (0, 0.0);
+        for (sim, n) in sims {
+            let n = n.min(neighbors - taken);
+            sum += sim.min(1.0) * n as f64;
+            taken += n;
+            if taken == neighbors {
+                break;
+            }
+        }
+        dist[u] = 1.0 - sum / neighbors as f64;
+        for w in touched.drain(..) {
+            dots[w] = 0.0;
+        }
+    }
+    of.iter().map(|o| o.map_or(1.0, |u| dist[u].clamp(0.0, 1.0))).collect()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::unit_vectors;
+
+    fn corpus() -> Vec<SparseVec> {
+        let mut f: Vec<Vec<usize>> = vec![vec![0, 1, 2]; 50];
+        f.extend(vec![vec![5, 6]; 5]);
+        f.push(vec![9]);
+        f.push(vec![]);
+        unit_vectors(&f)
+    }
+
+    #[test]
+    fn centroid_ranks_outliers_far() {
+        let d = centroid_distances(&corpus());
+        assert!(d[0] < 0.05);
+        assert!(d[50] > 0.85 && d[55] > d[50]);
+        assert_eq!(d[56], 1.0);
+    }
+
+    #[test]
+    fn knn_treats_small_groups_as_normal() {
+        let d = knn_distances(&corpus(), 3);
+        assert!(d[0].abs() < 1e-12);
+        assert!(d[50].abs() < 1e-12);
+        assert_eq!(d[55], 1.0);
+        assert_eq!(d[56], 1.0);
+        let d = knn_distances(&corpus(), 10);
+        assert!((d[50] - 0.6).abs() < 1e-9);
+    }
+}
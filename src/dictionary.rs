@@ -45,7 +45,7 @@ impl DictEntry {
         }
         iv.sort_unstable();
         let n = iv.len();
-        if n % 2 == 0 {
+        if n.is_multiple_of(2) {
             (iv[n / 2 - 1] + iv[n / 2]) as f64 / 2.0
         } else {
             iv[n / 2] as f64
@@ -106,19 +106,121 @@ pub fn build_dictionary(data: &[u8], ops: &[ScanOp], min_count: usize) -> Vec<Di
         })
         .collect();
 
+    rank_entries(&mut entries);
+    entries
+}
+
+/// Sort entries by count (then length) descending and renumber `entry_id`.
+/// Remaining ties are broken by content, so the order does not depend on
+/// hash iteration order.
+pub fn rank_entries(entries: &mut [DictEntry]) {
     entries.sort_by(|a, b| {
         b.count
             .cmp(&a.count)
             .then(b.content.len().cmp(&a.content.len()))
+            .then_with(|| a.content.cmp(&b.content))
     });
 
     for (i, e) in entries.iter_mut().enumerate() {
         e.entry_id = i;
     }
+}
 
+/// Combine two dictionaries. Entries with identical content have their
+/// counts summed and positions merged; `b`'s positions are shifted by
+/// `b_shift` (its input's offset in the combined input). The result is
+/// re-ranked.
+pub fn merge_dictionaries(a: &[DictEntry], b: &[DictEntry], b_shift: usize) -> Vec<DictEntry> {
+    let mut by_content: HashMap<&[u8], DictEntry> =
+        a.iter().map(|e| (e.content.as_slice(), e.clone())).collect();
+    for e in b {
+        let shifted = e.positions.iter().map(|&p| p + b_shift);
+        match by_content.get_mut(e.content.as_slice()) {
+            Some(m) => {
+                m.count += e.count;
+                m.positions.extend(shifted);
+                m.positions.sort_unstable();
+            }
+            None => {
+                let mut n = e.clone();
+                n.positions = shifted.collect();
+                by_content.insert(e.content.as_slice(), n);
+            }
+        }
+    }
+    let mut entries: Vec<DictEntry> = by_content.into_values().collect();
+    rank_entries(&mut entries);
     entries
 }
 
+/// Largest preset dictionary deflate can use (its window size).
+pub const ZLIB_PRESET_MAX: usize = 32 * 1024;
+
+/// Build a zlib preset dictionary (for `deflateSetDictionary`) from ranked
+/// entries.
+///
+/// Entries are taken in rank order, skipping any that would push the total
+/// past `limit` or that already occur inside a chosen entry, then laid out
+/// with the most frequent entry last: deflate favours short distances, and
+/// the end of the dictionary is nearest the data.
+pub fn zlib_preset(entries: &[DictEntry], limit: usize) -> Vec<u8> {
+    let mut chosen: Vec<&[u8]> = Vec::new();
+    let mut total = 0;
+    for e in entries {
+        let c = e.content.as_slice();
+        if total + c.len() > limit || chosen.iter().any(|k| k.windows(c.len()).any(|w| w == c)) {
+            continue;
+        }
+        total += c.len();
+        chosen.push(c);
+    }
+    chosen.iter().rev().flat_map(|c| c.iter().copied()).collect()
+}
+
+/// Age a dictionary: counts are scaled by `factor` (rounded down) and
+/// positions before `cut` are dropped, the rest shifted down by `cut` (the
+/// input's first `cut` bytes having been discarded). Entries whose count
+/// reaches zero are removed. The result is re-ranked.
+pub fn decay_dictionary(entries: &[DictEntry], factor: f64, cut: usize) -> Vec<DictEntry> {
+    let mut out: Vec<DictEntry> = entries
+        .iter()
+        .filter_map(|e| {
+            let count = (e.count as f64 * factor).floor() as usize;
+            (count > 0).then(|| DictEntry {
+                entry_id: 0,
+                content: e.content.clone(),
+                count,
+                positions: e.positions.iter().filter(|&&p| p >= cut).map(|&p| p - cut).collect(),
+            })
+        })
+        .collect();
+    rank_entries(&mut out);
+    out
+}
+
+/// Copy of `entries` with ASCII case restored from `original`, the input
+/// before case folding: each entry takes the case of its first occurrence
+/// (other bytes, such as masked ones, are kept). Entries whose first
+/// position is outside `original` are unchanged. For display and preset
+/// dictionaries; records are matched against the folded entries.
+pub fn restore_case(entries: &[DictEntry], original: &[u8]) -> Vec<DictEntry> {
+    entries
+        .iter()
+        .map(|e| {
+            let mut e = e.clone();
+            let src = e.positions.first().and_then(|&p| original.get(p..p + e.content.len()));
+            if let Some(src) = src {
+                for (b, &o) in e.content.iter_mut().zip(src) {
+                    if o.to_ascii_lowercase() == *b {
+                        *b = o;
+                    }
+                }
+            }
+            e
+        })
+        .collect()
+}
+
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -126,7 +228,7 @@ pub fn build_dictionary(data: &[u8], ops: &[ScanOp], min_count: usize) -> Vec<Di
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::scanner::{scan, DEFAULT_WINDOW, MAX_MATCH, MIN_MATCH};
+    use crate::scanner::{scan, ScanParams};
 
     #[test]
     fn empty_ops() {
@@ -136,7 +238,7 @@ mod tests {
     #[test]
     fn no_backrefs_no_entries() {
         let data = b"unique data here";
-        let ops = scan(data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(data, &ScanParams::default());
         assert!(build_dictionary(data, &ops, 1).is_empty());
     }
 
@@ -151,7 +253,7 @@ mod tests {
         for _ in 0..3 {
             data.extend_from_slice(b_pat);
         }
-        let ops = scan(&data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(&data, &ScanParams::default());
         let dict = build_dictionary(&data, &ops, 1);
         if dict.len() >= 2 {
             assert!(dict[0].count >= dict[1].count);
@@ -161,7 +263,7 @@ mod tests {
     #[test]
     fn entry_ids_sequential() {
         let data: Vec<u8> = b"log line template with data here\n".repeat(20);
-        let ops = scan(&data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(&data, &ScanParams::default());
         let dict = build_dictionary(&data, &ops, 1);
         for (i, e) in dict.iter().enumerate() {
             assert_eq!(e.entry_id, i);
@@ -171,10 +273,85 @@ mod tests {
     #[test]
     fn total_bytes_covered() {
         let data: Vec<u8> = b"bytes_covered_check_".repeat(20);
-        let ops = scan(&data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(&data, &ScanParams::default());
         let dict = build_dictionary(&data, &ops, 1);
         for e in &dict {
             assert_eq!(e.total_bytes_covered(), e.count * e.content_length());
         }
     }
+
+    #[test]
+    fn rank_breaks_ties_by_content() {
+        let entry = |content: &[u8], count| DictEntry {
+            entry_id: 0,
+            content: content.to_vec(),
+            count,
+            positions: Vec::new(),
+        };
+        let mut d = vec![entry(b"zz", 3), entry(b"aa", 3), entry(b"abc", 3), entry(b"q", 9)];
+        rank_entries(&mut d);
+        let order: Vec<&[u8]> = d.iter().map(|e| e.content.as_slice()).collect();
+        assert_eq!(order, [&b"q"[..], b"abc", b"aa", b"zz"]);
+
+        let merged = merge_dictionaries(&[entry(b"zz", 3), entry(b"q", 9)], &[entry(b"aa", 3)], 0);
+        let order: Vec<&[u8]> = merged.iter().map(|e| e.content.as_slice()).collect();
+        assert_eq!(order, [&b"q"[..], b"aa", b"zz"]);
+    }
+
+    #[test]
+    fn merge_sums_counts_and_shifts_positions() {
+        let data: Vec<u8> = b"merge me please\n".repeat(10);
+        let ops = scan(&data, &ScanParams::default());
+        let d = build_dictionary(&data, &ops, 1);
+        let merged = merge_dictionaries(&d, &d, data.len());
+        assert_eq!(merged.len(), d.len());
+        for e in &merged {
+            let orig = d.iter().find(|o| o.content == e.content).unwrap();
+            assert_eq!(e.count, orig.count * 2);
+            assert_eq!(e.positions.len(), orig.positions.len() * 2);
+            assert!(e.positions.windows(2).all(|w| w[0] <= w[1]));
+            assert_eq!(*e.positions.last().unwrap(), orig.positions.last().unwrap() + data.len());
+        }
+        for (i, e) in merged.iter().enumerate() {
+            assert_eq!(e.entry_id, i);
+        }
+    }
+
+    #[test]
+    fn restore_case_from_first_occurrence() {
+        let original = b"Error: disk FULL\nERROR: disk full\n".repeat(5);
+        let folded = original.to_ascii_lowercase();
+        let ops = scan(&folded, &ScanParams::default());
+        let d = build_dictionary(&folded, &ops, 1);
+        let shown = restore_case(&d, &original);
+        assert_eq!(shown.len(), d.len());
+        for (e, s) in d.iter().zip(&shown) {
+            let p = e.positions[0];
+            assert_eq!(s.content, &original[p..p + e.content.len()]);
+            assert_eq!(s.content.to_ascii_lowercase(), e.content);
+        }
+        let masked = DictEntry { content: b"\0\0 x".to_vec(), positions: vec![0], ..d[0].clone() };
+        assert_eq!(&restore_case(&[masked], b"12 X")[0].content, b"\0\0 X");
+    }
+
+    #[test]
+    fn zlib_preset_orders_and_limits() {
+        let entry = |content: &[u8], count| DictEntry {
+            entry_id: 0,
+            content: content.to_vec(),
+            count,
+            positions: Vec::new(),
+        };
+        let mut d = vec![
+            entry(b"status=200 ", 50),
+            entry(b"GET /api/", 40),
+            entry(b"=200", 30),
+            entry(b"a long tail entry", 2),
+        ];
+        rank_entries(&mut d);
+        let preset = zlib_preset(&d, 32);
+        assert_eq!(preset, b"GET /api/status=200 ");
+        assert_eq!(zlib_preset(&d, 1000).len(), 11 + 9 + 17);
+        assert_eq!(zlib_preset(&d, 5), b"=200");
+    }
 }
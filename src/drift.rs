@@ -0,0 +1,172 @@
+//! Value-ratio drift.
+//!
+//! Record scoring judges each line against the whole input, so a shift in
+//! how often ordinary values occur goes unseen: `success:true` falling from
+//! 99% to 60% of records is made of records that each look fine. Here the
+//! records are split into windows by position, and for boolean and
+//! small-enum fields the value ratios in each window are compared with the
+//! rest of the input.
+
+use std::collections::BTreeMap;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Fields with more distinct values than this are not checked for drift.
+pub const MAX_DRIFT_VALUES: usize = 10;
+
+/// Windows (or the rest of the input) with fewer records carrying the field
+/// than this are not judged.
+const MIN_WINDOW_RECORDS: usize = 20;
+
+/// Shifts smaller than this many standard errors are treated as noise.
+const MIN_Z: f64 = 3.0;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// A window where a value's share of a field shifted sharply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RatioShift {
+    pub field: String,
+    pub value: String,
+    /// Window number, and the record index range it spans (end exclusive).
+    pub window: usize,
+    pub start: usize,
+    pub end: usize,
+    /// Records in the window that carry the field.
+    pub records: usize,
+    /// Share of records carrying the field that have `value`, in the rest
+    /// of the input and in the window.
+    pub baseline_rate: f64,
+    pub window_rate: f64,
+}
+
+impl RatioShift {
+    /// Signed change from the baseline rate to the window rate.
+    pub fn shift(&self) -> f64 {
+        self.window_rate - self.baseline_rate
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Detection
+// ---------------------------------------------------------------------------
+
+/// Split the records into `windows` equal runs by position and report, per
+/// field and window, the value whose share moved the most, if it moved by
+/// at least `min_shift` and by more than sampling noise (a two-proportion
+/// z-test against the rest of the input).
+///
+/// Each field comes with its value keys, aligned per record (`None` where
+/// the record lacks the field). Shifts are sorted by size, descending.
+pub fn ratio_drift(
+    fields: &[(String, Vec<Option<String>>)],
+    windows: usize,
+    min_shift: f64,
+) -> Vec<RatioShift> {
+    let mut out = Vec::new();
+    for (field, keys) in fields {
+        let n = keys.len();
+        let windows = windows.clamp(1, n.max(1));
+        let edge = |i: usize| i * n / windows;
+
+        let mut totals: BTreeMap<&str, usize> = BTreeMap::new();
+        for k in keys.iter().flatten() {
+            *totals.entry(k).or_default() += 1;
+        }
+        let present: usize = totals.values().sum();
+
+        for w in 0..windows {
+            let (start, end) = (edge(w), edge(w + 1));
+            let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+            for k in keys[start..end].iter().flatten() {
+                *counts.entry(k).or_default() += 1;
+            }
+            let in_window: usize = counts.values().sum();
+            let rest = present - in_window;
+            if in_window < MIN_WINDOW_RECORDS || rest < MIN_WINDOW_RECORDS {
+                continue;
+            }
+
+            let mut best: Option<RatioShift> = None;
+            for (&value, &total) in &totals {
+                let c = counts.get(value).copied().unwrap_or(0);
+                let window_rate = c as f64 / in_window as f64;
+                let baseline_rate = (total - c) as f64 / rest as f64;
+                let pooled = total as f64 / present as f64;
+                let se = (pooled * (1.0 - pooled) * (1.0 / in_window as f64 + 1.0 / rest as f64)).sqrt();
+                let diff = (window_rate - baseline_rate).abs();
+                if diff < min_shift || diff <= MIN_Z * se {
+                    continue;
+                }
+                if best.as_ref().is_none_or(|b| diff > b.shift().abs()) {
+                    best = Some(RatioShift {
+                        field: field.clone(),
+                        value: value.to_string(),
+                        window: w,
+                        start,
+                        end,
+                        records: in_window,
+                        baseline_rate,
+                        window_rate,
+                    });
+                }
+            }
+            out.extend(best);
+        }
+    }
+    out.sort_by(|a, b| {
+        b.shift()
+            .abs()
+            .partial_cmp(&a.shift().abs())
+            .unwrap()
+            .then_with(|| a.field.cmp(&b.field))
+            .then_with(|| a.window.cmp(&b.window))
+    });
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(values: impl IntoIterator<Item = &'static str>) -> Vec<Option<String>> {
+        values.into_iter().map(|v| Some(v.to_string())).collect()
+    }
+
+    #[test]
+    fn success_rate_drop_is_located() {
+        // 1000 records, 99% true, except records 600..700 at 60%.
+        let keys = column((0..1000).map(|i| {
+            let fail = if (600..700).contains(&i) { i % 5 < 2 } else { i % 100 == 0 };
+            if fail { "false" } else { "true" }
+        }));
+        let level = column((0..1000).map(|i| if i % 3 == 0 { "WARN" } else { "INFO" }));
+        let fields = vec![("success".to_string(), keys), ("level".to_string(), level)];
+
+        let shifts = ratio_drift(&fields, 10, 0.2);
+        assert_eq!(shifts.len(), 1);
+        let s = &shifts[0];
+        assert_eq!((s.field.as_str(), s.window, s.start, s.end), ("success", 6, 600, 700));
+        // true and false move by the same amount; ties keep the first value.
+        assert_eq!((s.value.as_str(), s.records), ("false", 100));
+        assert!((s.window_rate - 0.4).abs() < 1e-9);
+        assert!((s.baseline_rate - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn small_windows_and_absent_fields_are_skipped() {
+        let mut keys = column((0..100).map(|i| if i < 50 { "a" } else { "b" }));
+        assert!(ratio_drift(&[("f".into(), keys.clone())], 10, 0.2).is_empty());
+        keys.iter_mut().take(50).for_each(|k| *k = None);
+        assert!(ratio_drift(&[("f".into(), keys)], 2, 0.2).is_empty());
+        assert!(ratio_drift(&[("f".into(), Vec::new())], 4, 0.2).is_empty());
+    }
+}
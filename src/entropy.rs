@@ -0,0 +1,235 @@
+//! Entropy-coding cost model.
+//!
+//! Coverage counts a byte copied by a 4-byte match the same as one copied
+//! by a 200-byte match, although the long match is far cheaper to encode.
+//! [`EntropyModel`] prices each op the way a deflate-style entropy coder
+//! would: literals and match lengths share one order-0 Huffman code built
+//! from the corpus, distances another, plus the length/distance extra
+//! bits. Per-record bits per byte then measures how compressible each
+//! record really is.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::scanner::{OpKind, ScanOp};
+
+/// Literal/length alphabet: 256 literals, then deflate's 29 length codes.
+const LITLEN_SYMBOLS: usize = 256 + 29;
+/// Distance codes: deflate's 30, extended the same way for large windows.
+const DIST_SYMBOLS: usize = 64;
+
+/// Deflate length code base lengths (codes 257..=285).
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+// ---------------------------------------------------------------------------
+// Codes
+// ---------------------------------------------------------------------------
+
+/// Deflate length code index (0..29) and extra bits for a match length.
+/// Lengths outside 3..=258 are clamped.
+fn length_code(length: usize) -> (usize, u32) {
+    let l = length.clamp(3, 258);
+    let code = LENGTH_BASE.partition_point(|&b| b <= l) - 1;
+    (code, LENGTH_EXTRA[code])
+}
+
+/// Distance code and extra bits: deflate's scheme (two codes per power of
+/// two) continued past 32 KB.
+fn distance_code(distance: usize) -> (usize, u32) {
+    let d = distance.max(1) - 1;
+    if d < 4 {
+        return (d, 0);
+    }
+    let log = usize::BITS - 1 - d.leading_zeros();
+    let code = (2 * log as usize + ((d >> (log - 1)) & 1)).min(DIST_SYMBOLS - 1);
+    (code, log - 1)
+}
+
+/// Huffman code lengths for `counts`. Symbols never seen get one bit more
+/// than the longest real code, as if escaped.
+fn huffman_lengths(counts: &[usize]) -> Vec<f64> {
+    let mut lengths = vec![0u32; counts.len()];
+    let mut heap: BinaryHeap<Reverse<(usize, usize)>> = BinaryHeap::new();
+    // Internal nodes are numbered after the symbols; `parent` links both.
+    let mut parent: Vec<usize> = vec![usize::MAX; counts.len()];
+    for (sym, &c) in counts.iter().enumerate() {
+        if c > 0 {
+            heap.push(Reverse((c, sym)));
+        }
+    }
+    let used = heap.len();
+    while heap.len() > 1 {
+        let Reverse((ca, a)) = heap.pop().unwrap();
+        let Reverse((cb, b)) = heap.pop().unwrap();
+        let node = parent.len();
+        parent.push(usize::MAX);
+        parent[a] = node;
+        parent[b] = node;
+        heap.push(Reverse((ca + cb, node)));
+    }
+    for (sym, len) in lengths.iter_mut().enumerate() {
+        if counts[sym] == 0 {
+            continue;
+        }
+        let mut n = sym;
+        while parent[n] != usize::MAX {
+            n = parent[n];
+            *len += 1;
+        }
+        *len = (*len).max(1);
+    }
+    let unseen = if used == 0 { 8 } else { lengths.iter().max().unwrap() + 1 };
+    lengths
+        .iter()
+        .zip(counts)
+        .map(|(&l, &c)| if c > 0 { l as f64 } else { unseen as f64 })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Model
+// ---------------------------------------------------------------------------
+
+/// Bit costs of literals and matches, fitted to one op stream.
+#[derive(Debug, Clone)]
+pub struct EntropyModel {
+    litlen_bits: Vec<f64>,
+    dist_bits: Vec<f64>,
+}
+
+impl EntropyModel {
+    /// Fit Huffman codes to the symbol frequencies of `ops` over `data`.
+    pub fn from_ops(data: &[u8], ops: &[ScanOp]) -> Self {
+        let mut litlen = vec![0usize; LITLEN_SYMBOLS];
+        let mut dist = vec![0usize; DIST_SYMBOLS];
+        for op in ops {
+            match op.kind {
+                OpKind::Literal => {
+                    for &b in op.content(data) {
+                        litlen[b as usize] += 1;
+                    }
+                }
+                OpKind::Backref => {
+                    litlen[256 + length_code(op.length).0] += 1;
+                    dist[distance_code(op.ref_offset).0] += 1;
+                }
+            }
+        }
+        Self { litlen_bits: huffman_lengths(&litlen), dist_bits: huffman_lengths(&dist) }
+    }
+
+    #[inline]
+    pub fn literal_bits(&self, byte: u8) -> f64 {
+        self.litlen_bits[byte as usize]
+    }
+
+    /// Bits for one match: length symbol and extra bits plus distance
+    /// symbol and extra bits.
+    pub fn match_bits(&self, offset: usize, length: usize) -> f64 {
+        let (lc, lx) = length_code(length);
+        let (dc, dx) = distance_code(offset);
+        self.litlen_bits[256 + lc] + lx as f64 + self.dist_bits[dc] + dx as f64
+    }
+
+    /// Total estimated bits for an op stream.
+    pub fn total_bits(&self, data: &[u8], ops: &[ScanOp]) -> f64 {
+        ops.iter()
+            .map(|op| match op.kind {
+                OpKind::Literal => op.content(data).iter().map(|&b| self.literal_bits(b)).sum(),
+                OpKind::Backref => self.match_bits(op.ref_offset, op.length),
+            })
+            .sum()
+    }
+}
+
+/// Estimated compressed bits per byte of each `(offset, length)` record.
+///
+/// Literal bytes are charged their own code length; a match's bits are
+/// spread evenly over the bytes it covers, so a match crossing a record
+/// boundary is split between the records. `ops` must be in position order.
+pub fn record_bits_per_byte(
+    data: &[u8],
+    ops: &[ScanOp],
+    model: &EntropyModel,
+    records: &[(usize, usize)],
+) -> Vec<f64> {
+    records
+        .iter()
+        .map(|&(off, len)| {
+            if len == 0 {
+                return 0.0;
+            }
+            let end = off + len;
+            let first = ops.partition_point(|o| o.position + o.length <= off);
+            let bits: f64 = ops[first..]
+                .iter()
+                .take_while(|o| o.position < end)
+                .map(|op| {
+                    let (from, to) = (op.position.max(off), (op.position + op.length).min(end).min(data.len()));
+                    match op.kind {
+                        OpKind::Literal => data[from..to].iter().map(|&b| model.literal_bits(b)).sum(),
+                        OpKind::Backref => {
+                            model.match_bits(op.ref_offset, op.length) / op.length as f64 * (to - from) as f64
+                        }
+                    }
+                })
+                .sum();
+            bits / len as f64
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{scan, ScanParams};
+    use crate::scorer::split_records;
+
+    #[test]
+    fn deflate_codes() {
+        assert_eq!(length_code(3), (0, 0));
+        assert_eq!(length_code(11), (8, 1));
+        assert_eq!(length_code(12), (8, 1));
+        assert_eq!(length_code(258), (28, 0));
+        assert_eq!(distance_code(1), (0, 0));
+        assert_eq!(distance_code(5), (4, 1));
+        assert_eq!(distance_code(7), (5, 1));
+        assert_eq!(distance_code(24577), (29, 13));
+        assert_eq!(distance_code(32768), (29, 13));
+    }
+
+    #[test]
+    fn huffman_is_prefix_optimal() {
+        let l = huffman_lengths(&[8, 4, 2, 2, 0]);
+        assert_eq!(l, [1.0, 2.0, 3.0, 3.0, 4.0]);
+        let kraft: f64 = l[..4].iter().map(|&b| 0.5f64.powf(b)).sum();
+        assert!((kraft - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn long_matches_cost_less_per_byte() {
+        let mut data: Vec<u8> = b"GET /api/v1/users?id=42 HTTP/1.1 200 OK\n".repeat(40);
+        data.extend_from_slice(b"zq8#unexpected heap corruption xk!\n");
+        let ops = scan(&data, &ScanParams::default());
+        let model = EntropyModel::from_ops(&data, &ops);
+        assert!(model.match_bits(40, 200) / 200.0 < model.match_bits(40, 4) / 4.0);
+
+        let recs = split_records(&data, b'\n');
+        let bpb = record_bits_per_byte(&data, &ops, &model, &recs);
+        assert_eq!(bpb.len(), recs.len());
+        assert!(bpb[1..40].iter().all(|&b| b < 2.0));
+        assert!(*bpb.last().unwrap() > 4.0);
+        let total: f64 = bpb.iter().zip(&recs).map(|(b, r)| b * r.1 as f64).sum();
+        assert!((total - model.total_bits(&data, &ops)).abs() < 1e-6);
+    }
+}
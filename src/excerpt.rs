@@ -0,0 +1,216 @@
+//! Extracted anomalous records.
+//!
+//! `anomalies --extract` hands the flagged records themselves to other
+//! tools: as raw bytes, each optionally prefixed with its line number or
+//! byte offset and surrounded by context records as `grep -C` does, or as
+//! one JSON object per record that says where it came from, so it can be
+//! traced back to its source.
+
+use std::io::{self, Write};
+
+#[cfg(feature = "json")]
+use crate::records::Locator;
+use crate::records::RecordIndex;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// One anomalous record to extract.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Excerpt<'d> {
+    /// Record index in the input (from 0).
+    pub index: usize,
+    /// Byte offset of the record in the input.
+    pub offset: usize,
+    pub score: f64,
+    /// The record, delimiter included if it had one.
+    pub content: &'d [u8],
+}
+
+/// What each raw record starts with. Context records end the prefix
+/// with `-` instead of `:`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Prefix {
+    None,
+    /// `LINE:`, the record's line number in the input (from 1).
+    Line,
+    /// `OFFSET:`, its byte offset.
+    Offset,
+}
+
+// ---------------------------------------------------------------------------
+// Output
+// ---------------------------------------------------------------------------
+
+/// Write the records' bytes, each after its `prefix`, with `context`
+/// lines of `data` (the input) before and after each. With context,
+/// records are written in input order, each line once, and `--` separates
+/// runs of lines that are not adjacent.
+pub fn write_raw<'d>(
+    out: &mut impl Write,
+    data: &[u8],
+    excerpts: impl IntoIterator<Item = Excerpt<'d>>,
+    prefix: Prefix,
+    context: usize,
+) -> io::Result<()> {
+    let lines = RecordIndex::new(data, b'\n');
+    let line_of = |e: &Excerpt| lines.record_at(e.offset).unwrap_or(e.index);
+    if context == 0 {
+        for e in excerpts {
+            write_line(out, prefix, b':', line_of(&e), e.offset, e.content)?;
+        }
+        return Ok(());
+    }
+    let mut flagged: Vec<(usize, Excerpt)> = excerpts.into_iter().map(|e| (line_of(&e), e)).collect();
+    flagged.sort_by_key(|&(line, _)| line);
+    flagged.dedup_by_key(|&mut (line, _)| line);
+    let mut next = None;
+    for &(line, _) in &flagged {
+        let first = line.saturating_sub(context);
+        let last = (line + context).min(lines.len().max(line + 1) - 1);
+        if next.is_some_and(|n| first > n) {
+            out.write_all(b"--\n")?;
+        }
+        for i in first.max(next.unwrap_or(0))..=last {
+            match flagged.binary_search_by_key(&i, |&(line, _)| line) {
+                Ok(k) => write_line(out, prefix, b':', i, flagged[k].1.offset, flagged[k].1.content)?,
+                Err(_) => {
+                    if let Some(r) = lines.range_for(i) {
+                        write_line(out, prefix, b'-', i, r.start, &data[r])?;
+                    }
+                }
+            }
+        }
+        next = Some(last + 1);
+    }
+    Ok(())
+}
+
+/// One line of raw output: `prefix` ended by `sep`, then `content`.
+fn write_line(
+    out: &mut impl Write,
+    prefix: Prefix,
+    sep: u8,
+    line: usize,
+    offset: usize,
+    content: &[u8],
+) -> io::Result<()> {
+    match prefix {
+        Prefix::Line => write!(out, "{}{}", line + 1, sep as char)?,
+        Prefix::Offset => write!(out, "{offset}{}", sep as char)?,
+        Prefix::None => {}
+    }
+    out.write_all(content)
+}
+
+/// One record as an ndjson object: `index`, `score` and `content`
+/// (trailing whitespace trimmed), and its provenance, `source` (the input,
+/// or the archive file the record is in), `line` within it (from 1), and
+/// `offset` and `length` of its bytes in the input file, as read before
+/// any rewriting ([`Locator::file_range`]).
+#[cfg(feature = "json")]
+pub fn excerpt_json(e: &Excerpt, locator: &Locator) -> serde_json::Value {
+    let (source, _) = locator.file_at(e.offset);
+    let (line, _) = locator.line_column(e.offset);
+    let (offset, length) = locator.file_range(e.offset, e.content);
+    serde_json::json!({
+        "index": e.index, "offset": offset, "length": length,
+        "score": (e.score * 1e6).round() / 1e6,
+        "content": String::from_utf8_lossy(e.content).trim_end(),
+        "source": source, "line": line,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_records_are_written_as_they_are() {
+        let excerpts = [
+            Excerpt { index: 0, offset: 0, score: 1.0, content: b"first\n" },
+            Excerpt { index: 2, offset: 10, score: 0.5, content: b"last" },
+        ];
+        let mut out = Vec::new();
+        write_raw(&mut out, b"first\n...\nlast", excerpts, Prefix::None, 0).unwrap();
+        assert_eq!(out, b"first\nlast");
+    }
+
+    fn raw(data: &[u8], lines: &[usize], prefix: Prefix, context: usize) -> String {
+        let index = RecordIndex::new(data, b'\n');
+        let excerpts = lines.iter().map(|&i| {
+            let r = index.range_for(i).unwrap();
+            Excerpt { index: i, offset: r.start, score: 1.0, content: &data[r] }
+        });
+        let mut out = Vec::new();
+        write_raw(&mut out, data, excerpts, prefix, context).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn prefixes_give_line_or_offset() {
+        let data = b"l1\nl2\nline3\nl4\n";
+        assert_eq!(raw(data, &[3, 1], Prefix::Line, 0), "4:l4\n2:l2\n");
+        assert_eq!(raw(data, &[3, 1], Prefix::Offset, 0), "12:l4\n3:l2\n");
+        assert_eq!(raw(data, &[2], Prefix::None, 0), "line3\n");
+    }
+
+    #[test]
+    fn context_surrounds_records_like_grep() {
+        let data: String = (1..=12).map(|i| format!("r{i}\n")).collect();
+        let data = data.as_bytes();
+        // Records 3 and 4 (lines 4 and 5) share their context; line 11's is
+        // apart, and clipped at the end of the input.
+        assert_eq!(
+            raw(data, &[10, 4, 3], Prefix::Line, 1),
+            "3-r3\n4:r4\n5:r5\n6-r6\n--\n10-r10\n11:r11\n12-r12\n"
+        );
+        assert_eq!(raw(data, &[0], Prefix::Offset, 2), "0:r1\n3-r2\n6-r3\n");
+        assert_eq!(raw(data, &[1, 5], Prefix::None, 1), "r1\nr2\nr3\n--\nr5\nr6\nr7\n");
+        // Adjacent runs are not separated.
+        assert_eq!(raw(data, &[1, 4], Prefix::Line, 1), "1-r1\n2:r2\n3-r3\n4-r4\n5:r5\n6-r6\n");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_carries_provenance() {
+        let data = b"a one\na two\nb one\nb two\nb three\n";
+        let locator = Locator::new("logs.tar", data).with_files([("a.log", 0..12), ("b.log", 12..32)]);
+        let e = Excerpt { index: 3, offset: 18, score: 0.12345678, content: b"b two\n" };
+        let v = excerpt_json(&e, &locator);
+        assert_eq!(v["source"], "b.log");
+        assert_eq!(v["line"], 2);
+        assert_eq!(v["offset"], 18);
+        assert_eq!(v["index"], 3);
+        assert_eq!(v["length"], 6);
+        assert_eq!(v["score"], 0.123457);
+        assert_eq!(v["content"], "b two");
+
+        let plain = Locator::new("app.log", data);
+        let v = excerpt_json(&Excerpt { index: 1, offset: 6, score: 1.0, content: b"a two\n" }, &plain);
+        assert_eq!(v["source"], "app.log");
+        assert_eq!((v["line"].as_u64(), v["offset"].as_u64()), (Some(2), Some(6)));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_provenance_points_into_the_file_as_read() {
+        use crate::json_value::join_pretty_records;
+        use crate::records::Source;
+
+        let pretty = b"{\n  \"a\": 1\n}\n{\n  \"a\": 2\n}\n";
+        let joined = join_pretty_records(pretty).unwrap();
+        assert_eq!(joined.data, b"{\"a\":1}\n{\"a\":2}\n");
+        let source = Source { text: Some(pretty.to_vec()), to_text: joined.map, ..Source::default() };
+        let locator = Locator::new("in.json", &joined.data).with_source(&source);
+        let v = excerpt_json(&Excerpt { index: 1, offset: 8, score: 1.0, content: b"{\"a\":2}\n" }, &locator);
+        assert_eq!((v["line"].as_u64(), v["offset"].as_u64(), v["length"].as_u64()), (Some(4), Some(13), Some(13)));
+        assert_eq!(&pretty[13..], b"{\n  \"a\": 2\n}\n");
+        assert_eq!(v["content"], "{\"a\":2}");
+    }
+}
@@ -0,0 +1,276 @@
+//! Pseudo-fields for unstructured logs.
+//!
+//! Plain log lines carry structure the LZ77 path cannot see as fields:
+//! `user=alice latency=35ms` has a user and a latency. Extraction patterns
+//! pull named captures out of each non-JSON line, and the result is scored
+//! by the schema machinery as if the line had been a JSON object.
+//!
+//! A pattern is a regex whose `(?P<name>...)` groups become fields, with
+//! grok references expanded: `%{NAME}` matches the grok pattern `NAME`, and
+//! `%{NAME:field}` also captures it as `field`, optionally typed as
+//! `%{NAME:field:int}` or `%{NAME:field:float}` so it is scored as a
+//! number. A common set of grok patterns is built in; a pattern file (one
+//! `NAME regex` per line, `#` comments) adds to it or overrides it.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::json_analyzer::{parse_records_with, JsonRecord};
+use crate::json_value::{JsonObject, JsonValue};
+use crate::records::RecordLimit;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Built-in grok patterns, a subset of the Logstash set.
+const GROK_PATTERNS: &str = r#"
+USERNAME [a-zA-Z0-9._-]+
+USER %{USERNAME}
+INT (?:[+-]?[0-9]+)
+BASE10NUM (?:[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+))
+NUMBER (?:%{BASE10NUM})
+POSINT \b[1-9][0-9]*\b
+NONNEGINT \b[0-9]+\b
+WORD \b\w+\b
+NOTSPACE \S+
+SPACE \s*
+DATA .*?
+GREEDYDATA .*
+QUOTEDSTRING "(?:[^"\\]|\\.)*"
+UUID [A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}
+IPV4 (?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)
+IPV6 (?:[0-9A-Fa-f]{0,4}:){2,7}[0-9A-Fa-f.]*
+IP (?:%{IPV6}|%{IPV4})
+HOSTNAME \b[0-9A-Za-z][0-9A-Za-z-]{0,62}(?:\.[0-9A-Za-z][0-9A-Za-z-]{0,62})*\b
+IPORHOST (?:%{IP}|%{HOSTNAME})
+PATH (?:/[^\s/]*)+
+URIPATH (?:/[A-Za-z0-9$.+!*'(){},~:;=@#%&_-]*)+
+LOGLEVEL (?:[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo|INFO|[Ww]arn(?:ing)?|WARN(?:ING)?|[Ee]rr(?:or)?|ERR(?:OR)?|[Cc]rit(?:ical)?|CRIT(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|[Ee]merg(?:ency)?|EMERG(?:ENCY)?|[Aa]lert|ALERT)
+TIMESTAMP_ISO8601 \d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}(?::\d{2}(?:[.,]\d+)?)?(?:Z|[+-]\d{2}:?\d{2})?
+HTTPDATE \d{2}/\w{3}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4}
+SYSLOGTIMESTAMP \w{3} +\d{1,2} \d{2}:\d{2}:\d{2}
+"#;
+
+/// Grok references nested deeper than this are taken to be a cycle.
+const MAX_GROK_DEPTH: usize = 32;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// How a captured value is typed in the pseudo-record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Str,
+    Int,
+    Float,
+}
+
+#[derive(Debug, Clone)]
+struct Capture {
+    /// Group name in the compiled regex.
+    group: String,
+    field: String,
+    kind: Kind,
+}
+
+/// Compiled extraction patterns.
+#[derive(Debug, Clone)]
+pub struct Extractor {
+    patterns: Vec<(Regex, Vec<Capture>)>,
+}
+
+impl Extractor {
+    /// Compile `patterns`, expanding grok references with the built-in
+    /// definitions plus `definitions` (a pattern file's text).
+    pub fn new(patterns: &[String], definitions: &str) -> Result<Self, String> {
+        let mut grok = parse_definitions(GROK_PATTERNS)?;
+        grok.extend(parse_definitions(definitions)?);
+        let patterns = patterns
+            .iter()
+            .map(|p| {
+                let mut captures = Vec::new();
+                let expanded = expand(p, &grok, Some(&mut captures), 0).map_err(|e| format!("{p}: {e}"))?;
+                let re = Regex::new(&expanded).map_err(|e| format!("{p}: {e}"))?;
+                for name in re.capture_names().flatten() {
+                    if !captures.iter().any(|c: &Capture| c.group == name) {
+                        captures.push(Capture { group: name.into(), field: name.into(), kind: Kind::Str });
+                    }
+                }
+                if captures.is_empty() {
+                    return Err(format!("{p}: no named captures"));
+                }
+                Ok((re, captures))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+
+    /// Fields captured from `line` by every pattern that matches it; a
+    /// field captured by more than one keeps the first pattern's value.
+    pub fn fields<'a>(&self, line: &'a str) -> JsonObject<'a> {
+        let mut out: JsonObject<'a> = Vec::new();
+        for (re, captures) in &self.patterns {
+            let Some(caps) = re.captures(line) else { continue };
+            for c in captures {
+                let Some(m) = caps.name(&c.group) else { continue };
+                if out.iter().any(|(k, _)| *k == c.field) {
+                    continue;
+                }
+                out.push((Cow::Owned(c.field.clone()), typed(m.as_str(), c.kind)));
+            }
+        }
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+
+    /// Parse records as [`parse_json_records_limited`] does, taking the
+    /// fields of non-JSON lines from the patterns. Lines no pattern matches
+    /// become records without fields.
+    ///
+    /// [`parse_json_records_limited`]: crate::json_analyzer::parse_json_records_limited
+    pub fn records<'a>(&self, data: &'a [u8], delimiter: u8, limit: Option<RecordLimit>) -> Vec<JsonRecord<'a>> {
+        parse_records_with(data, delimiter, limit, |line| Some(self.fields(line)))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Grok
+// ---------------------------------------------------------------------------
+
+fn parse_definitions(text: &str) -> Result<HashMap<String, String>, String> {
+    let mut out = HashMap::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((name, pattern)) = line.split_once(char::is_whitespace) else {
+            return Err(format!("grok definitions, line {}: expected NAME PATTERN", n + 1));
+        };
+        out.insert(name.to_string(), pattern.trim_start().to_string());
+    }
+    Ok(out)
+}
+
+/// Expand the grok references in `pattern`. Named references become
+/// groups and are recorded in `captures` at the top level only; inside a
+/// definition they just match.
+fn expand(
+    pattern: &str,
+    grok: &HashMap<String, String>,
+    mut captures: Option<&mut Vec<Capture>>,
+    depth: usize,
+) -> Result<String, String> {
+    if depth > MAX_GROK_DEPTH {
+        return Err("grok patterns refer to each other in a cycle".into());
+    }
+    let mut out = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find("%{") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or("unclosed %{")? + start;
+        let mut parts = rest[start + 2..end].split(':');
+        let name = parts.next().unwrap_or_default();
+        let def = grok.get(name).ok_or_else(|| format!("unknown grok pattern '{name}'"))?;
+        let inner = expand(def, grok, None, depth + 1)?;
+        match (parts.next(), captures.as_deref_mut()) {
+            (Some(field), Some(captures)) => {
+                let kind = match parts.next() {
+                    None => Kind::Str,
+                    Some("int") => Kind::Int,
+                    Some("float") => Kind::Float,
+                    Some(t) => return Err(format!("unknown grok type '{t}'; use int or float")),
+                };
+                let group = format!("grok{}", captures.len());
+                out.push_str(&format!("(?P<{group}>{inner})"));
+                captures.push(Capture { group, field: field.to_string(), kind });
+            }
+            _ => out.push_str(&format!("(?:{inner})")),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// A captured value as a JSON value: a number for typed captures that
+/// parse as one, a string otherwise.
+fn typed(s: &str, kind: Kind) -> JsonValue<'_> {
+    let number = match kind {
+        Kind::Str => None,
+        Kind::Int => s.parse::<i64>().ok().map(|n| n.to_string()),
+        Kind::Float => s.parse::<f64>().ok().filter(|f| f.is_finite()).map(|f| f.to_string()),
+    };
+    match number {
+        Some(n) => JsonValue::Number(Cow::Owned(n)),
+        None => JsonValue::String(Cow::Borrowed(s)),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(obj: &JsonObject) -> Vec<(String, String)> {
+        obj.iter().map(|(k, v)| (k.to_string(), format!("{:?}:{}", v.json_type(), v.key()))).collect()
+    }
+
+    #[test]
+    fn regex_and_grok_captures() {
+        let x = Extractor::new(
+            &[
+                r"user=(?P<user>\S+)".to_string(),
+                "%{TIMESTAMP_ISO8601:ts} %{LOGLEVEL:level} .*latency=%{NUMBER:latency:float}ms".to_string(),
+                r"status=%{INT:status:int}|user=(?P<user>x)".to_string(),
+            ],
+            "",
+        )
+        .unwrap();
+        let obj = x.fields("2026-02-16 12:00:01 WARN GET / user=alice latency=35.0ms status=abc");
+        assert_eq!(
+            keys(&obj),
+            [
+                ("latency".into(), "Number:35".into()),
+                ("level".into(), "String:WARN".into()),
+                ("ts".into(), "String:2026-02-16 12:00:01".into()),
+                ("user".into(), "String:alice".into()),
+            ]
+        );
+        assert_eq!(keys(&x.fields("status=503")), [("status".into(), "Number:503".into())]);
+        assert!(x.fields("nothing here").is_empty());
+    }
+
+    #[test]
+    fn definitions_and_errors() {
+        let defs = "# custom\nJOB job-%{POSINT}\nPOSINT [0-9]+\n";
+        let x = Extractor::new(&["ran %{JOB:job}".to_string()], defs).unwrap();
+        assert_eq!(keys(&x.fields("ran job-0042")), [("job".into(), "String:job-0042".into())]);
+
+        let err = |p: &str, d: &str| Extractor::new(&[p.to_string()], d).unwrap_err();
+        assert!(err("%{NOPE:x}", "").contains("unknown grok pattern 'NOPE'"));
+        assert!(err("%{A:x}", "A %{B}\nB %{A}\n").contains("cycle"));
+        assert!(err("%{INT:x:bool}", "").contains("unknown grok type 'bool'"));
+        assert!(err(r"\d+", "").contains("no named captures"));
+        assert!(err("(?P<x>", "").starts_with("(?P<x>: "));
+    }
+
+    #[test]
+    fn json_lines_stay_json() {
+        let data = b"{\"user\":\"bob\"}\nuser=alice\nplain\n";
+        let x = Extractor::new(&[r"user=(?P<user>\w+)".to_string()], "").unwrap();
+        let recs = x.records(data, b'\n', None);
+        assert_eq!(recs.len(), 3);
+        assert!(recs.iter().all(|r| !r.parse_error));
+        let users: Vec<_> = recs.iter().map(|r| r.get("user").map(|v| v.key().into_owned())).collect();
+        assert_eq!(users, [Some("bob".into()), Some("alice".into()), None]);
+        assert_eq!(recs[1].content(data), b"user=alice\n");
+    }
+}
@@ -0,0 +1,251 @@
+//! Record fingerprints.
+//!
+//! Known-record filters and alert throttling need one stable identity for
+//! all the variations of a log template. [`fingerprint`] hashes a record
+//! with its digit runs collapsed, so counters, IDs and timestamps do not
+//! matter. A [`Fingerprinter`] built from a dictionary also masks the words
+//! that are not part of any template. The fixed text of a template is
+//! referenced as often as the rest of it; a word beside it referenced far
+//! less (a session token, a host name, a user) is a value. The vocabulary holds
+//! words, not entry IDs or op boundaries, so the same records fingerprint
+//! alike whatever the window size the dictionary came from, and saving it
+//! with the fingerprints keeps them comparable across runs.
+
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Read, Write};
+
+use crate::dictionary::DictEntry;
+use crate::opsfile::{invalid, read_varint, write_varint, FNV_OFFSET, FNV_PRIME};
+
+/// Longest vocabulary word read back from a file.
+const MAX_WORD: u64 = 1 << 16;
+
+/// A dictionary word is a template word if the entries containing it are
+/// used at least this share as often as those of the busiest word it shares
+/// an entry with. The words of one template are used about equally; a value
+/// beside them is split among its many values.
+pub const TEMPLATE_SHARE: f64 = 0.5;
+
+/// Hashed in place of a masked word.
+const MASK: u8 = b'*';
+
+fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// `word` with each run of ASCII digits replaced by a single `0`.
+fn collapse_digits(word: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    for (i, &b) in word.iter().enumerate() {
+        let digit = b.is_ascii_digit();
+        if !(digit && i > 0 && word[i - 1].is_ascii_digit()) {
+            out.push(if digit { b'0' } else { b });
+        }
+    }
+}
+
+/// Identity of a record up to its numbers: FNV-1a of `record` with
+/// trailing whitespace dropped and each run of ASCII digits hashed as a
+/// single `0`. `dedup_records` needs byte-identical copies; known-record
+/// filters and alert throttling treat records that differ only in counters,
+/// IDs and timestamps as the same.
+pub fn fingerprint(record: &[u8]) -> u64 {
+    Fingerprinter::default().fingerprint(record)
+}
+
+/// Fingerprints records by their template: digit runs collapsed and, once
+/// it has a vocabulary, words outside it masked. Without a vocabulary it
+/// gives [`fingerprint`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fingerprinter {
+    /// Template words, digit runs collapsed.
+    words: BTreeSet<Vec<u8>>,
+}
+
+impl Fingerprinter {
+    /// Vocabulary of the whole words inside `entries`' content (a word cut
+    /// off by the start or end of an entry is left out, as are numbers)
+    /// whose entries' counts add up to [`TEMPLATE_SHARE`] of those of every
+    /// word it shares an entry with.
+    pub fn from_dictionary(entries: &[DictEntry]) -> Self {
+        let mut buf = Vec::new();
+        let entry_words: Vec<(Vec<Vec<u8>>, usize)> = entries
+            .iter()
+            .map(|e| {
+                let c = &e.content;
+                let mut pieces: Vec<&[u8]> = c.split(|&b| !is_word(b)).collect();
+                if c.last().is_some_and(|&b| is_word(b)) {
+                    pieces.pop();
+                }
+                let skip = c.first().is_some_and(|&b| is_word(b)) as usize;
+                let words = pieces
+                    .into_iter()
+                    .skip(skip)
+                    .filter(|w| w.iter().any(u8::is_ascii_alphabetic))
+                    .map(|w| {
+                        collapse_digits(w, &mut buf);
+                        buf.clone()
+                    })
+                    .collect();
+                (words, e.count)
+            })
+            .collect();
+        let mut uses: HashMap<&[u8], usize> = HashMap::new();
+        for (words, count) in &entry_words {
+            for w in words {
+                *uses.entry(w).or_default() += count;
+            }
+        }
+        // Uses of the busiest word beside each word.
+        let mut peak: HashMap<&[u8], usize> = HashMap::new();
+        for (words, _) in &entry_words {
+            let most = words.iter().map(|w| uses[w.as_slice()]).max().unwrap_or(0);
+            for w in words {
+                let p = peak.entry(w).or_default();
+                *p = (*p).max(most);
+            }
+        }
+        let words = uses
+            .iter()
+            .filter(|&(w, &n)| n as f64 >= TEMPLATE_SHARE * peak[w] as f64)
+            .map(|(w, _)| w.to_vec())
+            .collect();
+        Self { words }
+    }
+
+    /// Vocabulary of the given words.
+    pub fn from_words<'a>(words: impl IntoIterator<Item = &'a [u8]>) -> Self {
+        let mut buf = Vec::new();
+        let words = words
+            .into_iter()
+            .map(|w| {
+                collapse_digits(w, &mut buf);
+                buf.clone()
+            })
+            .collect();
+        Self { words }
+    }
+
+    /// Template words, digit runs collapsed, in byte order.
+    pub fn words(&self) -> impl Iterator<Item = &[u8]> {
+        self.words.iter().map(Vec::as_slice)
+    }
+
+    /// Whether there is no vocabulary (no word is masked).
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// FNV-1a of `record`'s template: trailing whitespace dropped, each run
+    /// of ASCII digits hashed as a single `0`, and each word with a letter
+    /// that is not in the vocabulary hashed as `*`. A record with no
+    /// vocabulary word follows no known template and keeps all its words,
+    /// so unfamiliar lines are not reduced to the same mask.
+    pub fn fingerprint(&self, record: &[u8]) -> u64 {
+        let end = record.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |i| i + 1);
+        let mut buf = Vec::new();
+        let pieces: Vec<(Vec<u8>, bool)> = record[..end]
+            .chunk_by(|&a, &b| is_word(a) && is_word(b))
+            .map(|p| {
+                collapse_digits(p, &mut buf);
+                let value = is_word(p[0]) && p.iter().any(u8::is_ascii_alphabetic) && !self.words.contains(&buf);
+                (buf.clone(), value)
+            })
+            .collect();
+        let template = pieces.iter().any(|(p, value)| !value && is_word(p[0]) && p.iter().any(u8::is_ascii_alphabetic));
+        let mut h = FNV_OFFSET;
+        for (p, value) in &pieces {
+            let hashed: &[u8] = if *value && template { &[MASK] } else { p };
+            for &b in hashed {
+                h = (h ^ b as u64).wrapping_mul(FNV_PRIME);
+            }
+        }
+        h
+    }
+
+    /// Encoded as `varint n, n x { varint length, bytes }`.
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_varint(w, self.words.len() as u64)?;
+        for word in &self.words {
+            write_varint(w, word.len() as u64)?;
+            w.write_all(word)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let n = read_varint(r)?;
+        let mut words = BTreeSet::new();
+        for _ in 0..n {
+            let len = read_varint(r)?;
+            if len > MAX_WORD {
+                return Err(invalid("template word too long"));
+            }
+            let mut word = vec![0; len as usize];
+            r.read_exact(&mut word)?;
+            words.insert(word);
+        }
+        Ok(Self { words })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::build_dictionary;
+    use crate::scanner::{scan, ScanParams};
+
+    #[test]
+    fn fingerprint_ignores_numbers_and_trailing_space() {
+        let a = fingerprint(b"2026-02-16 12:00:01 user 42 login\n");
+        assert_eq!(a, fingerprint(b"2026-03-01 09:15:59 user 7 login"));
+        assert_ne!(a, fingerprint(b"2026-03-01 09:15:59 user 7 logout"));
+        assert_ne!(fingerprint(b"a1b"), fingerprint(b"ab"));
+        // The hash predates the word-by-word walk; saved filters rely on it.
+        assert_eq!(fingerprint(b"ab 12 c"), 0xca5e_a35d_4070_e067);
+    }
+
+    #[test]
+    fn templates_match_across_variable_words_and_window_sizes() {
+        let users = ["alice", "bob", "carol", "dave", "erin", "frank", "grace"];
+        let data: String = (0..400)
+            .map(|i| match i % 3 {
+                0 => format!("session {:x}{:x} opened for user {}\n", i * 7919, i * 31, users[i % 7]),
+                1 => format!("request {} handled in {}ms\n", i, i % 17),
+                _ => format!("cache miss on key k{}\n", i % 23),
+            })
+            .collect();
+        let data = data.as_bytes();
+        let fingerprinters: Vec<Fingerprinter> = [1024, 64 * 1024]
+            .iter()
+            .map(|&window| {
+                let params = ScanParams { window_size: window, ..ScanParams::default() };
+                let ops = scan(data, &params);
+                Fingerprinter::from_dictionary(&build_dictionary(data, &ops, 2))
+            })
+            .collect();
+        for f in &fingerprinters {
+            assert!(!f.is_empty() && f.words().any(|w| w == b"session"));
+            let a = f.fingerprint(b"session 9f2c1e opened for user zed");
+            assert_eq!(a, f.fingerprint(b"session b7 opened for user yvonne"));
+            assert_ne!(a, f.fingerprint(b"session b7 closed for user yvonne"));
+            assert_eq!(f.fingerprint(b"cache miss on key k12"), f.fingerprint(b"cache miss on key k9\n"));
+            assert!(!f.words().any(|w| w == b"bob"));
+            assert_ne!(f.fingerprint(b"disk full"), f.fingerprint(b"link down"));
+        }
+        let (small, large) = (&fingerprinters[0], &fingerprinters[1]);
+        for line in ["session 1a opened for user bob", "request 5 handled in 3ms", "disk full"] {
+            assert_eq!(small.fingerprint(line.as_bytes()), large.fingerprint(line.as_bytes()), "{line}");
+        }
+
+        let f = Fingerprinter::from_words([&b"user"[..], b"worker12"]);
+        assert_eq!(f.words().collect::<Vec<_>>(), [&b"user"[..], b"worker0"]);
+        let mut buf = Vec::new();
+        f.write(&mut buf).unwrap();
+        assert_eq!(Fingerprinter::read(&mut buf.as_slice()).unwrap(), f);
+    }
+}
@@ -0,0 +1,302 @@
+//! Following growing logs.
+//!
+//! `follow` tails logs and alerts on anomalous records as they are written.
+//! A [`Follower`] scores one source's records with an [`OnlineDetector`],
+//! holds back repeats with an [`AlertThrottle`] (timed by the records' own
+//! timestamps where they have them) and hands each alert to the caller to
+//! print. [`Follower::follow`] reads a file from where a saved
+//! [`FollowState`] stopped, as [`Start`] says, finishing the previous file
+//! first if the log was rotated in between.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checkpoint::{find_rotated, FollowState, Resume};
+use crate::incidents::TimestampParser;
+use crate::online::{AlertThrottle, Anomaly, OnlineDetector, Repeat};
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// An anomalous record let through the throttle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert<'r> {
+    pub anomaly: Anomaly,
+    pub repeat: Repeat,
+    /// The record, without its newline.
+    pub record: &'r [u8],
+}
+
+/// Where to begin reading a followed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Start {
+    /// At this offset: 0 for a file not seen before, else where the last
+    /// run stopped.
+    At(u64),
+    /// The file shrank since the last run: from its start.
+    Truncated,
+    /// The file was replaced: finish `previous`, if it was found, from
+    /// `offset`, then read the new file from its start.
+    Rotated { previous: Option<PathBuf>, offset: u64 },
+}
+
+impl Start {
+    /// Where to pick up `path`, followed before as `state` says.
+    pub fn resume(state: &FollowState, path: &Path) -> io::Result<Self> {
+        Ok(match state.resume_file(path)? {
+            Resume::At(offset) => Start::At(offset),
+            Resume::Truncated => Start::Truncated,
+            Resume::Rotated => Start::Rotated { previous: find_rotated(path, state.inode), offset: state.offset },
+        })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Follower
+// ---------------------------------------------------------------------------
+
+/// Scores one followed source, across the files it spans in a run (a
+/// rotated file and its successor).
+pub struct Follower {
+    detector: OnlineDetector,
+    throttle: AlertThrottle,
+    times: TimestampParser,
+    flagged: usize,
+    alerts: usize,
+}
+
+impl Follower {
+    /// `cooldown` in seconds, as for [`AlertThrottle::new`].
+    pub fn new(detector: OnlineDetector, cooldown: f64) -> Self {
+        Self { detector, throttle: AlertThrottle::new(cooldown), times: TimestampParser::new(), flagged: 0, alerts: 0 }
+    }
+
+    pub fn detector(&self) -> &OnlineDetector {
+        &self.detector
+    }
+
+    pub fn into_detector(self) -> OnlineDetector {
+        self.detector
+    }
+
+    /// Records judged anomalous, alerted or not.
+    pub fn flagged(&self) -> usize {
+        self.flagged
+    }
+
+    /// Alerts let through.
+    pub fn alerts(&self) -> usize {
+        self.alerts
+    }
+
+    /// Anomalies held back since their last alert.
+    pub fn suppressed(&self) -> usize {
+        self.throttle.suppressed()
+    }
+
+    /// Score one record; `Some` if it should be alerted. Records without a
+    /// timestamp are timed by the clock.
+    pub fn push<'r>(&mut self, line: &'r [u8]) -> Option<Alert<'r>> {
+        let anomaly = self.detector.push(line)?;
+        self.flagged += 1;
+        let record = line.strip_suffix(b"\n").unwrap_or(line);
+        let now = self.times.time(record).unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+        });
+        let repeat = self.throttle.check(record, now)?;
+        self.alerts += 1;
+        Some(Alert { anomaly, repeat, record })
+    }
+
+    /// Score records from `r` to the end of input, handing alerts to
+    /// `alert` and adding the bytes of records scored to `consumed`. A
+    /// last record without a delimiter is scored only if `partial` (it may
+    /// still be being written). Returns false once `alert` does; the
+    /// record alerted is counted as consumed, since the detector has seen
+    /// it, so a resumed run does not score it again.
+    pub fn read(
+        &mut self,
+        r: &mut dyn BufRead,
+        partial: bool,
+        consumed: &mut u64,
+        alert: &mut impl FnMut(&Alert) -> bool,
+    ) -> io::Result<bool> {
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if r.read_until(b'\n', &mut line)? == 0 || !(partial || line.ends_with(b"\n")) {
+                return Ok(true);
+            }
+            let open = self.push(&line).is_none_or(|a| alert(&a));
+            *consumed += line.len() as u64;
+            if !open {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Read `path` from `start` to its end, as [`read`](Self::read) does.
+    /// Returns the file and offset reading stopped at, and whether `alert`
+    /// still accepts alerts; errors name the file.
+    pub fn follow(
+        &mut self,
+        path: &Path,
+        start: &Start,
+        partial: bool,
+        alert: &mut impl FnMut(&Alert) -> bool,
+    ) -> (PathBuf, u64, io::Result<bool>) {
+        let offset = match start {
+            Start::At(offset) => *offset,
+            Start::Truncated => 0,
+            Start::Rotated { previous: Some(old), offset } => {
+                let mut n = 0;
+                match self.read_file(old, *offset, true, &mut n, alert) {
+                    Ok(true) => 0,
+                    res => return (old.clone(), offset + n, res),
+                }
+            }
+            Start::Rotated { previous: None, .. } => 0,
+        };
+        let mut n = 0;
+        let res = self.read_file(path, offset, partial, &mut n, alert);
+        (path.to_path_buf(), offset + n, res)
+    }
+
+    fn read_file(
+        &mut self,
+        path: &Path,
+        offset: u64,
+        partial: bool,
+        consumed: &mut u64,
+        alert: &mut impl FnMut(&Alert) -> bool,
+    ) -> io::Result<bool> {
+        open_at(path, offset)
+            .and_then(|mut r| self.read(&mut r, partial, consumed, alert))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+    }
+}
+
+/// Open `path` at `offset`.
+pub fn open_at(path: &Path, offset: u64) -> io::Result<BufReader<File>> {
+    let mut f = File::open(path)?;
+    f.seek(SeekFrom::Start(offset))?;
+    Ok(BufReader::new(f))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anomaly::DetectionMethod;
+    use crate::scanner::ScanParams;
+
+    fn follower() -> Follower {
+        let d = OnlineDetector::new(&ScanParams::default(), DetectionMethod::Score, None).unwrap();
+        Follower::new(d, 300.0)
+    }
+
+    fn line(i: usize) -> String {
+        match i {
+            1500 | 1600 => "KERNEL PANIC: fatal error 0xDEADBEEF segfault in worker\n".to_string(),
+            _ => format!("2026-02-16 app: request {} handled in {}ms\n", i % 10, i % 7),
+        }
+    }
+
+    #[test]
+    fn repeated_alerts_are_held_back() {
+        let data: String = (0..2000).map(line).collect();
+        let mut f = follower();
+        let mut alerted = Vec::new();
+        let mut n = 0;
+        let open = f.read(&mut data.as_bytes(), false, &mut n, &mut |a| {
+            alerted.push((a.anomaly.index, a.repeat, a.record.to_vec()));
+            true
+        });
+        assert!(open.unwrap());
+        assert_eq!(n, data.len() as u64);
+        assert_eq!(f.detector().records(), 2000);
+        // The second panic comes within the cooldown of the first.
+        let panic = alerted.iter().find(|a| a.0 == 1500).expect("panic alerted");
+        assert_eq!(panic.1, Repeat { count: 1, total: 1 });
+        assert!(!panic.2.ends_with(b"\n"));
+        assert!(alerted.iter().all(|a| a.0 != 1600));
+        assert!(f.flagged() > f.alerts() && f.suppressed() >= 1);
+
+    }
+
+    #[test]
+    fn closed_sink_resumes_after_the_alerted_record() {
+        let data: String = (0..2000).map(line).collect();
+        let mut f = follower();
+        let mut n = 0;
+        let mut first = None;
+        let open = f.read(&mut data.as_bytes(), false, &mut n, &mut |a| {
+            first = Some(a.anomaly.index);
+            false
+        });
+        assert!(!open.unwrap());
+        // Stopped just after the alerted record, which the detector has seen.
+        let first = first.expect("an alert");
+        assert_eq!(first, 1500);
+        assert_eq!(f.detector().records(), first + 1);
+        assert_eq!(n, (0..=first).map(|i| line(i).len() as u64).sum::<u64>());
+
+        // Resuming at `n` with the same detector scores each record once.
+        let mut indices = Vec::new();
+        f.read(&mut &data.as_bytes()[n as usize..], false, &mut n, &mut |a| {
+            indices.push(a.anomaly.index);
+            true
+        })
+        .unwrap();
+        assert_eq!(f.detector().records(), 2000);
+        assert_eq!(n, data.len() as u64);
+        // The panic at 1600 repeats 1500's within the cooldown.
+        assert!(indices.is_empty());
+    }
+
+    #[test]
+    fn unterminated_record_waits_unless_partial() {
+        let data = b"one\ntwo\nthr";
+        let mut n = 0;
+        let mut f = follower();
+        f.read(&mut &data[..], false, &mut n, &mut |_| true).unwrap();
+        assert_eq!((n, f.detector().records()), (8, 2));
+        let mut n = 0;
+        let mut f = follower();
+        f.read(&mut &data[..], true, &mut n, &mut |_| true).unwrap();
+        assert_eq!((n, f.detector().records()), (11, 3));
+    }
+
+    #[test]
+    fn rotation_finishes_the_previous_file_first() {
+        let dir = std::env::temp_dir().join(format!("bgtzip-follow-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (old, new) = (dir.join("app.log.1"), dir.join("app.log"));
+        std::fs::write(&old, "a1\na2\na3\n").unwrap();
+        std::fs::write(&new, "b1\nb2\n").unwrap();
+
+        let mut f = follower();
+        let start = Start::Rotated { previous: Some(old.clone()), offset: 3 };
+        let (file, offset, res) = f.follow(&new, &start, false, &mut |_| true);
+        assert!(res.unwrap());
+        assert_eq!((file, offset), (new.clone(), 6));
+        assert_eq!(f.detector().records(), 4);
+
+        let mut f = follower();
+        let (file, offset, _) = f.follow(&new, &Start::At(3), false, &mut |_| true);
+        assert_eq!((file, offset, f.detector().records()), (new.clone(), 6, 1));
+
+        let missing = dir.join("gone.log");
+        let start = Start::Rotated { previous: Some(missing.clone()), offset: 3 };
+        let (file, offset, res) = follower().follow(&new, &start, false, &mut |_| true);
+        assert_eq!((file, offset), (missing.clone(), 3));
+        assert!(res.unwrap_err().to_string().starts_with(&missing.display().to_string()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+//! Gzip input support.
+//!
+//! Rotated logs are often gzipped and concatenated, giving a multi-member
+//! gzip file. Inputs are decompressed member by member so the analysis can
+//! run over the concatenated text while reports can still attribute
+//! records and anomalies to the member (original file) they came from.
+//! [`LineReader`] reads only as many lines as a command needs.
+
+use std::io::{self, BufRead, BufReader, Read};
+
+use flate2::bufread::GzDecoder;
+
+use crate::opsfile::invalid;
+
+/// First two bytes of every gzip member.
+pub const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// One gzip member: where it sits in the compressed file and which bytes it
+/// produced in the decompressed output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzMember {
+    pub compressed_offset: usize,
+    pub compressed_len: usize,
+    /// Start of this member's output in the decompressed data.
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl GzMember {
+    /// Decompressed-to-compressed size ratio.
+    pub fn ratio(&self) -> f64 {
+        if self.compressed_len == 0 {
+            return 0.0;
+        }
+        self.len as f64 / self.compressed_len as f64
+    }
+
+    /// True if decompressed offset `pos` falls in this member.
+    pub fn contains(&self, pos: usize) -> bool {
+        pos >= self.offset && pos < self.offset + self.len
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Decompression
+// ---------------------------------------------------------------------------
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+/// Decompress every member of a (possibly multi-member) gzip file,
+/// returning the concatenated output and the member boundaries.
+///
+/// Zero padding after the last member is ignored; any other trailing
+/// bytes are an error.
+pub fn decompress_members(data: &[u8]) -> io::Result<(Vec<u8>, Vec<GzMember>)> {
+    let mut out = Vec::new();
+    let mut members = Vec::new();
+    let mut rest = data;
+
+    while is_gzip(rest) {
+        let compressed_offset = data.len() - rest.len();
+        let offset = out.len();
+        let mut dec = GzDecoder::new(rest);
+        dec.read_to_end(&mut out)?;
+        rest = dec.into_inner();
+        members.push(GzMember {
+            compressed_offset,
+            compressed_len: data.len() - rest.len() - compressed_offset,
+            offset,
+            len: out.len() - offset,
+        });
+    }
+    if rest.iter().any(|&b| b != 0) {
+        return Err(invalid(format!(
+            "trailing garbage after gzip member at byte {}",
+            data.len() - rest.len()
+        )));
+    }
+    Ok((out, members))
+}
+
+/// Input read a line at a time, for commands that only need its first
+/// records: plain input as it is, gzip input decompressed member by member
+/// with the boundaries [`decompress_members`] would give. A line may run
+/// across members, as in `zcat` output.
+pub struct LineReader<R> {
+    inner: Option<Inner<R>>,
+    data: Vec<u8>,
+    members: Vec<GzMember>,
+}
+
+enum Inner<R> {
+    Plain(Counted<R>),
+    Member(Box<BufReader<GzDecoder<Counted<R>>>>),
+}
+
+impl<R: Read> LineReader<R> {
+    pub fn new(r: R) -> io::Result<Self> {
+        let mut reader = LineReader { inner: None, data: Vec::new(), members: Vec::new() };
+        reader.start(Counted::new(r))?;
+        Ok(reader)
+    }
+
+    /// Read up to `n` more lines. `false` once the input is used up.
+    pub fn read_lines(&mut self, n: usize) -> io::Result<bool> {
+        for _ in 0..n {
+            loop {
+                let read = match &mut self.inner {
+                    None => return Ok(false),
+                    Some(Inner::Plain(r)) => r.read_until(b'\n', &mut self.data)?,
+                    Some(Inner::Member(d)) => d.read_until(b'\n', &mut self.data)?,
+                };
+                if let (Some(m), Some(Inner::Member(d))) = (self.members.last_mut(), &self.inner) {
+                    m.len = self.data.len() - m.offset;
+                    m.compressed_len = d.get_ref().get_ref().count - m.compressed_offset;
+                }
+                if read == 0 {
+                    self.next()?;
+                } else if self.data.ends_with(b"\n") {
+                    break;
+                }
+            }
+        }
+        Ok(self.inner.is_some())
+    }
+
+    /// The lines read so far.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The lines read, and the gzip members they came from (empty for
+    /// plain input); the last one may have been cut short.
+    pub fn into_parts(self) -> (Vec<u8>, Vec<GzMember>) {
+        (self.data, self.members)
+    }
+
+    /// Finish the current member and start the next, if any.
+    fn next(&mut self) -> io::Result<()> {
+        match self.inner.take() {
+            Some(Inner::Member(d)) => self.start(d.into_inner().into_inner()),
+            _ => Ok(()),
+        }
+    }
+
+    fn start(&mut self, mut r: Counted<R>) -> io::Result<()> {
+        if is_gzip(r.peek(GZIP_MAGIC.len())?) {
+            let (compressed_offset, offset) = (r.count, self.data.len());
+            self.members.push(GzMember { compressed_offset, compressed_len: 0, offset, len: 0 });
+            self.inner = Some(Inner::Member(Box::new(BufReader::new(GzDecoder::new(r)))));
+        } else if self.members.is_empty() {
+            self.inner = Some(Inner::Plain(r));
+        } else {
+            let at = r.count;
+            let mut rest = Vec::new();
+            r.read_to_end(&mut rest)?;
+            if rest.iter().any(|&b| b != 0) {
+                return Err(invalid(format!("trailing garbage after gzip member at byte {at}")));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A buffered reader that counts the bytes consumed and can look ahead
+/// across its buffer's end, to tell whether another gzip member follows.
+struct Counted<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    end: usize,
+    count: usize,
+}
+
+impl<R: Read> Counted<R> {
+    fn new(inner: R) -> Self {
+        Counted { inner, buf: vec![0; 64 * 1024].into_boxed_slice(), pos: 0, end: 0, count: 0 }
+    }
+
+    /// The next `n` bytes, or fewer at the end of the input.
+    fn peek(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.end - self.pos < n {
+            self.buf.copy_within(self.pos..self.end, 0);
+            (self.end, self.pos) = (self.end - self.pos, 0);
+            while self.end < n {
+                match self.inner.read(&mut self.buf[self.end..])? {
+                    0 => break,
+                    k => self.end += k,
+                }
+            }
+        }
+        Ok(&self.buf[self.pos..self.end.min(self.pos + n)])
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(out)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for Counted<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.end {
+            (self.pos, self.end) = (0, self.inner.read(&mut self.buf)?);
+        }
+        Ok(&self.buf[self.pos..self.end])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.pos += n;
+        self.count += n;
+    }
+}
+
+/// Count how many of `offsets` (decompressed positions) fall in each
+/// member.
+pub fn member_counts(members: &[GzMember], offsets: &[usize]) -> Vec<usize> {
+    let mut counts = vec![0; members.len()];
+    for &pos in offsets {
+        let i = members.partition_point(|m| m.offset + m.len <= pos);
+        if members.get(i).is_some_and(|m| m.contains(pos)) {
+            counts[i] += 1;
+        }
+    }
+    counts
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn gz(data: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    #[test]
+    fn splits_concatenated_members() {
+        let (a, b) = (b"day one\n".repeat(100), b"day two\n".repeat(50));
+        let (ga, gb) = (gz(&a), gz(&b));
+        let mut file = ga.clone();
+        file.extend_from_slice(&gb);
+        file.extend_from_slice(&[0, 0, 0]);
+
+        let (out, members) = decompress_members(&file).unwrap();
+        assert_eq!(out, [a.as_slice(), b.as_slice()].concat());
+        assert_eq!(members.len(), 2);
+        assert_eq!((members[0].offset, members[0].len), (0, a.len()));
+        assert_eq!((members[1].offset, members[1].len), (a.len(), b.len()));
+        assert_eq!(members[1].compressed_offset, ga.len());
+        assert_eq!(members[1].compressed_len, gb.len());
+        assert!(members[1].contains(a.len()) && !members[0].contains(a.len()));
+        assert_eq!(member_counts(&members, &[0, 8, a.len(), out.len()]), [2, 1]);
+    }
+
+    #[test]
+    fn line_reader_stops_early_and_keeps_members() {
+        let (a, b) = (b"one\ntwo\nthr", b"ee\nfour\nfive\n");
+        let (ga, gb) = (gz(a), gz(b));
+        let file = [ga.as_slice(), &gb, &[0, 0]].concat();
+
+        let mut r = LineReader::new(file.as_slice()).unwrap();
+        assert!(r.read_lines(3).unwrap());
+        assert_eq!(r.data(), b"one\ntwo\nthree\n");
+        assert!(r.read_lines(1).unwrap());
+        let (data, members) = r.into_parts();
+        assert_eq!(data, b"one\ntwo\nthree\nfour\n");
+        assert_eq!(members.len(), 2);
+        assert_eq!((members[0].offset, members[0].len, members[0].compressed_len), (0, a.len(), ga.len()));
+        assert_eq!((members[1].offset, members[1].len, members[1].compressed_offset), (a.len(), 8, ga.len()));
+
+        let mut r = LineReader::new(file.as_slice()).unwrap();
+        assert!(!r.read_lines(10).unwrap());
+        assert_eq!(r.into_parts(), decompress_members(&file).unwrap());
+
+        let mut r = LineReader::new(&b"plain\nlines"[..]).unwrap();
+        assert!(!r.read_lines(5).unwrap());
+        assert_eq!(r.into_parts(), (b"plain\nlines".to_vec(), Vec::new()));
+
+        let bad = [ga.as_slice(), b"junk"].concat();
+        assert!(LineReader::new(bad.as_slice()).unwrap().read_lines(10).is_err());
+    }
+
+    #[test]
+    fn locations_run_across_member_boundaries() {
+        // Lines are numbered as in `zcat` output, even when a member ends
+        // mid-line.
+        let (a, b) = (b"one\ntwo\nthr", b"ee\nfour\n");
+        let (out, members) = decompress_members(&[gz(a), gz(b)].concat()).unwrap();
+        let loc = crate::records::Locator::new("app.log.gz", &out);
+        assert_eq!(loc.locate(members[1].offset), "app.log.gz:3:4");
+        assert_eq!(loc.locate(members[1].offset + 3), "app.log.gz:4:1");
+        assert_eq!(loc.by_offset(true).locate(members[1].offset + 3), "app.log.gz@14");
+    }
+
+    #[test]
+    fn rejects_garbage_and_truncation() {
+        let mut file = gz(b"hello\n");
+        assert!(decompress_members(&file[..file.len() - 4]).is_err());
+        file.push(b'x');
+        assert!(decompress_members(&file).is_err());
+    }
+}
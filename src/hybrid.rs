@@ -0,0 +1,127 @@
+//! Hybrid scoring combining LZ77 and JSON signals.
+//!
+//! Schema scoring sees structure but not what is inside string values;
+//! LZ77 coverage catches unusual free text (say, in a `message` field) but
+//! knows nothing about fields. Hybrid mode runs both analyzers over the
+//! same JSON-lines input and blends their per-record scores.
+
+use crate::json_analyzer::JsonRecordScore;
+use crate::scorer::RecordAnalysis;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// Per-record blend of the two analyzers' scores.
+#[derive(Debug, Clone, Copy)]
+pub struct HybridScore {
+    pub json_score: f64,
+    pub lz77_score: f64,
+    /// `json_weight * json_score + (1 - json_weight) * lz77_score`.
+    pub score: f64,
+}
+
+// ---------------------------------------------------------------------------
+// Blend
+// ---------------------------------------------------------------------------
+
+/// Blend JSON and LZ77 scores, returning one `HybridScore` per JSON record.
+///
+/// Records are aligned by byte offset, since the JSON parser skips blank
+/// lines that the LZ77 scorer keeps. A JSON record with no LZ77 record at
+/// the same offset falls back to its JSON score alone.
+pub fn hybrid_scores(
+    json: &[JsonRecordScore],
+    lz77: &[RecordAnalysis],
+    json_weight: f64,
+) -> Vec<HybridScore> {
+    let w = json_weight.clamp(0.0, 1.0);
+    let mut j = 0;
+
+    json.iter()
+        .map(|s| {
+            while j < lz77.len() && lz77[j].offset < s.offset {
+                j += 1;
+            }
+            match lz77.get(j).filter(|r| r.offset == s.offset) {
+                Some(r) => HybridScore {
+                    json_score: s.anomaly_score,
+                    lz77_score: r.anomaly_score,
+                    score: w * s.anomaly_score + (1.0 - w) * r.anomaly_score,
+                },
+                None => HybridScore {
+                    json_score: s.anomaly_score,
+                    lz77_score: s.anomaly_score,
+                    score: s.anomaly_score,
+                },
+            }
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::build_dictionary;
+    use crate::json_analyzer::{build_schema, parse_json_records, score_json_records};
+    use crate::scanner::{scan, ScanParams};
+    use crate::scorer::score_records;
+
+    fn both(data: &[u8]) -> (Vec<JsonRecordScore>, Vec<RecordAnalysis>) {
+        let recs = parse_json_records(data, b'\n');
+        let schema = build_schema(&recs);
+        let json = score_json_records(data, &recs, &schema);
+        let ops = scan(data, &ScanParams::default());
+        let dict = build_dictionary(data, &ops, 2);
+        (json, score_records(data, &ops, &dict, b'\n'))
+    }
+
+    #[test]
+    fn aligns_across_blank_lines() {
+        let data = b"{\"a\":1}\n\n{\"a\":1}\n\n\n{\"a\":2}\n";
+        let (json, lz) = both(data);
+        assert_eq!(json.len(), 3);
+        assert!(lz.len() > json.len());
+        let h = hybrid_scores(&json, &lz, 0.5);
+        for (hs, js) in h.iter().zip(&json) {
+            let r = lz.iter().find(|r| r.offset == js.offset).unwrap();
+            assert_eq!(hs.lz77_score, r.anomaly_score);
+        }
+    }
+
+    #[test]
+    fn free_text_anomaly_raised_by_lz77() {
+        let mut data = Vec::new();
+        for i in 0..60 {
+            data.extend_from_slice(
+                format!("{{\"level\":\"INFO\",\"message\":\"request handled in {}ms\"}}\n", i % 9)
+                    .as_bytes(),
+            );
+        }
+        data.extend_from_slice(
+            b"{\"level\":\"INFO\",\"message\":\"zq8#unexpected heap corruption detected xk!\"}\n",
+        );
+        let (json, lz) = both(&data);
+        let h = hybrid_scores(&json, &lz, 0.5);
+        let last = h.last().unwrap();
+        let avg: f64 = h[..60].iter().map(|s| s.score).sum::<f64>() / 60.0;
+        assert!(last.lz77_score > last.json_score);
+        assert!(last.score > avg);
+    }
+
+    #[test]
+    fn weight_extremes() {
+        let data = b"{\"a\":1}\n{\"a\":1}\n{\"b\":true}\n";
+        let (json, lz) = both(data);
+        for hs in hybrid_scores(&json, &lz, 1.0) {
+            assert_eq!(hs.score, hs.json_score);
+        }
+        for hs in hybrid_scores(&json, &lz, 0.0) {
+            assert_eq!(hs.score, hs.lz77_score);
+        }
+    }
+}
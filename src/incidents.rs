@@ -0,0 +1,273 @@
+//! Temporal clusters of anomalies.
+//!
+//! An incident shows up as many flagged records close together, and the
+//! first question about a report is how many incidents it holds and when.
+//! Flagged records are grouped into clusters wherever the gap to the next
+//! one, in records or in time read from the records' timestamps, stays
+//! within a limit; each cluster is summarized by its span, size and peak.
+
+use regex::bytes::{Captures, Regex};
+
+// ---------------------------------------------------------------------------
+// Gaps
+// ---------------------------------------------------------------------------
+
+/// Largest gap between consecutive flagged records of one cluster.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gap {
+    Records(usize),
+    Seconds(f64),
+}
+
+impl Gap {
+    /// Parse a record count (`50`) or a duration with a unit: `s`, `m`,
+    /// `h` or `d` (`90s`, `5m`, `1.5h`).
+    pub fn parse(s: &str) -> Result<Gap, String> {
+        let s = s.trim();
+        if let Ok(n) = s.parse::<usize>() {
+            return if n > 0 { Ok(Gap::Records(n)) } else { Err("gap must be at least 1 record".into()) };
+        }
+        parse_duration(s)
+            .map(Gap::Seconds)
+            .map_err(|_| format!("'{s}': expected a record count or a duration like 30s, 5m, 2h"))
+    }
+}
+
+/// Parse a positive duration with a unit, `s`, `m`, `h` or `d` (`90s`,
+/// `5m`, `1.5h`), into seconds.
+pub fn parse_duration(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let unit = match s.chars().last() {
+        Some('s') => 1.0,
+        Some('m') => 60.0,
+        Some('h') => 3600.0,
+        Some('d') => 86400.0,
+        _ => return Err(format!("'{s}': expected a duration like 30s, 5m, 2h")),
+    };
+    match s[..s.len() - 1].parse::<f64>() {
+        Ok(n) if n > 0.0 && n.is_finite() => Ok(n * unit),
+        _ => Err(format!("'{s}': expected a duration like 30s, 5m, 2h")),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Timestamps
+// ---------------------------------------------------------------------------
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Reads a record's time, as seconds since the Unix epoch, from the first
+/// ISO 8601 / RFC 3339 timestamp anywhere in the line (so JSON string
+/// fields work too), a bracketed common log format timestamp, or a leading
+/// syslog timestamp or epoch number. Times without a zone are taken as
+/// UTC; syslog timestamps have no year and are placed in 1970, which keeps
+/// gaps right within a year.
+pub struct TimestampParser {
+    iso: Regex,
+    clf: Regex,
+    syslog: Regex,
+    epoch: Regex,
+}
+
+impl Default for TimestampParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimestampParser {
+    pub fn new() -> Self {
+        let re = |p: &str| Regex::new(p).expect("valid timestamp pattern");
+        TimestampParser {
+            iso: re(r"(\d{4})-(\d{2})-(\d{2})[T ](\d{2}):(\d{2}):(\d{2})([.,]\d+)?\s?(Z|[+-]\d{2}:?\d{2})?"),
+            clf: re(r"\[(\d{2})/([A-Z][a-z]{2})/(\d{4}):(\d{2}):(\d{2}):(\d{2}) ([+-]\d{4})\]"),
+            syslog: re(r"^([A-Z][a-z]{2}) +(\d{1,2}) (\d{2}):(\d{2}):(\d{2})\b"),
+            epoch: re(r"^(\d{10})(\d{3})?(\.\d+)?\b"),
+        }
+    }
+
+    pub fn time(&self, line: &[u8]) -> Option<f64> {
+        if let Some(c) = self.iso.captures(line) {
+            let zone = c.get(8).map_or(Some(0.0), |z| zone_offset(z.as_bytes()))?;
+            let frac = c.get(7).map_or(0.0, |f| num::<f64>(&f.as_bytes()[1..]).map_or(0.0, |n| {
+                n / 10f64.powi(f.len() as i32 - 1)
+            }));
+            return civil_seconds(&c, [1, 2, 3, 4, 5, 6], None).map(|s| s - zone + frac);
+        }
+        if let Some(c) = self.clf.captures(line) {
+            let zone = zone_offset(&c[7])?;
+            return civil_seconds(&c, [3, 2, 1, 4, 5, 6], Some(2)).map(|s| s - zone);
+        }
+        if let Some(c) = self.syslog.captures(line) {
+            let month = month_number(&c[1])?;
+            let day: i64 = num(&c[2])?;
+            let hms = [num::<i64>(&c[3])?, num(&c[4])?, num(&c[5])?];
+            return Some((days_from_civil(1970, month, day) * 86400 + hms[0] * 3600 + hms[1] * 60 + hms[2]) as f64);
+        }
+        let c = self.epoch.captures(line)?;
+        let secs: f64 = num(&c[1])?;
+        let frac: f64 = c.get(3).map_or(Some(0.0), |f| num(f.as_bytes()))?;
+        Some(match c.get(2) {
+            Some(ms) => secs + (num::<f64>(ms.as_bytes())? + frac) / 1000.0,
+            None => secs + frac,
+        })
+    }
+}
+
+fn num<T: std::str::FromStr>(b: &[u8]) -> Option<T> {
+    std::str::from_utf8(b).ok()?.parse().ok()
+}
+
+fn month_number(name: &[u8]) -> Option<i64> {
+    MONTHS.iter().position(|m| m.as_bytes() == name).map(|i| i as i64 + 1)
+}
+
+/// Seconds east of UTC for `Z`, `+hh:mm` or `+hhmm`.
+fn zone_offset(z: &[u8]) -> Option<f64> {
+    if z == b"Z" {
+        return Some(0.0);
+    }
+    let digits: Vec<u8> = z[1..].iter().copied().filter(u8::is_ascii_digit).collect();
+    let (h, m): (f64, f64) = (num(&digits[..2])?, num(&digits[2..])?);
+    let sign = if z[0] == b'-' { -1.0 } else { 1.0 };
+    Some(sign * (h * 3600.0 + m * 60.0))
+}
+
+/// Seconds since the epoch from the year, month, day, hour, minute and
+/// second capture groups, in that order; `month_name` when the month group
+/// is a name rather than a number.
+fn civil_seconds(c: &Captures, groups: [usize; 6], month_name: Option<usize>) -> Option<f64> {
+    let year: i64 = num(&c[groups[0]])?;
+    let month = match month_name {
+        Some(g) => month_number(&c[g])?,
+        None => num(&c[groups[1]])?,
+    };
+    let [day, h, m, s]: [i64; 4] =
+        [num(&c[groups[2]])?, num(&c[groups[3]])?, num(&c[groups[4]])?, num(&c[groups[5]])?];
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some((days_from_civil(year, month, day) * 86400 + h * 3600 + m * 60 + s) as f64)
+}
+
+/// Days from 1970-01-01 to the given proleptic Gregorian date.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// ---------------------------------------------------------------------------
+// Clustering
+// ---------------------------------------------------------------------------
+
+/// Flagged records close together in the input or in time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Incident {
+    /// First and last flagged record, inclusive.
+    pub start: usize,
+    pub end: usize,
+    /// Flagged records in the cluster.
+    pub anomalies: usize,
+    /// The highest-scoring of them.
+    pub peak: usize,
+    /// Earliest and latest time of its records that have one.
+    pub first_time: Option<f64>,
+    pub last_time: Option<f64>,
+}
+
+impl Incident {
+    /// Seconds from the earliest to the latest record, when they have times.
+    pub fn duration(&self) -> Option<f64> {
+        Some(self.last_time? - self.first_time?)
+    }
+}
+
+/// Group flagged record `indices` into clusters in record order. A new one
+/// starts when the next record is more than `gap` records on, or more than
+/// `gap` seconds after the latest time seen in the current cluster; records
+/// without a time stay in the current cluster.
+pub fn incidents(
+    indices: &[usize],
+    scores: &[f64],
+    time: impl Fn(usize) -> Option<f64>,
+    gap: Gap,
+) -> Vec<Incident> {
+    let mut sorted = indices.to_vec();
+    sorted.sort_unstable();
+    let mut out: Vec<Incident> = Vec::new();
+    for i in sorted {
+        let t = match gap {
+            Gap::Records(_) => None,
+            Gap::Seconds(_) => time(i),
+        };
+        let joins = out.last().is_some_and(|c| match gap {
+            Gap::Records(n) => i - c.end <= n,
+            Gap::Seconds(s) => match (t, c.last_time) {
+                (Some(t), Some(last)) => t - last <= s,
+                _ => true,
+            },
+        });
+        if !joins {
+            out.push(Incident { start: i, end: i, anomalies: 0, peak: i, first_time: t, last_time: t });
+        }
+        let c = out.last_mut().unwrap();
+        c.end = i;
+        c.anomalies += 1;
+        if scores[i] > scores[c.peak] {
+            c.peak = i;
+        }
+        if let Some(t) = t {
+            c.first_time = Some(c.first_time.map_or(t, |f| f.min(t)));
+            c.last_time = Some(c.last_time.map_or(t, |l| l.max(t)));
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_and_timestamps_parse() {
+        assert_eq!(Gap::parse("50"), Ok(Gap::Records(50)));
+        assert_eq!(Gap::parse("5m"), Ok(Gap::Seconds(300.0)));
+        assert_eq!(Gap::parse("1.5h"), Ok(Gap::Seconds(5400.0)));
+        assert!(Gap::parse("0").is_err() && Gap::parse("5x").is_err() && Gap::parse("s").is_err());
+
+        let p = TimestampParser::new();
+        let at = |l: &str| p.time(l.as_bytes());
+        assert_eq!(at("2026-02-16T12:00:01Z app started"), Some(1771243201.0));
+        assert_eq!(at("2026-02-16 13:00:01+01:00 app"), Some(1771243201.0));
+        assert_eq!(at(r#"{"msg":"x","ts":"2026-02-16T12:00:01.250Z"}"#), Some(1771243201.25));
+        assert_eq!(at(r#"10.0.0.1 - - [16/Feb/2026:12:00:01 +0000] "GET /""#), Some(1771243201.0));
+        assert_eq!(at("1771243201123 worker up"), Some(1771243201.123));
+        assert_eq!(at("Feb 16 12:00:01 host app: up"), Some((46.0 * 86400.0) + 43201.0));
+        assert_eq!(at("no time here 12:00"), None);
+    }
+
+    #[test]
+    fn clusters_split_on_record_and_time_gaps() {
+        let scores: Vec<f64> = (0..100).map(|i| i as f64 % 7.0).collect();
+        let flagged = [50, 3, 5, 52, 9, 90];
+        let by_index = incidents(&flagged, &scores, |_| None, Gap::Records(4));
+        let spans: Vec<_> = by_index.iter().map(|c| (c.start, c.end, c.anomalies, c.peak)).collect();
+        assert_eq!(spans, [(3, 9, 3, 5), (50, 52, 2, 52), (90, 90, 1, 90)]);
+        assert_eq!(by_index[0].duration(), None);
+
+        // One record a second, except a 10-minute pause before record 52;
+        // record 9 has no time and joins its neighbors.
+        let time = |i: usize| (i != 9).then_some(i as f64 + if i >= 52 { 600.0 } else { 0.0 });
+        let by_time = incidents(&flagged, &scores, time, Gap::Seconds(60.0));
+        let spans: Vec<_> = by_time.iter().map(|c| (c.start, c.end, c.duration())).collect();
+        assert_eq!(spans, [(3, 50, Some(47.0)), (52, 90, Some(38.0))]);
+    }
+}
@@ -5,11 +5,20 @@
 //! by structural deviation: missing fields, rare values, unusual
 //! field combinations, type mismatches.
 
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
+use memchr::memchr;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 use serde_json::Value;
 
 use crate::anomaly::{mean, median_of, sample_stdev};
+#[cfg(feature = "simd-json")]
+use crate::json_value::SimdParser;
+use crate::json_value::{is_object, nesting_depth, parse_record, round_number, JsonObject, JsonValue};
+use crate::records::{sliding_windows, Oversize, RecordIndex, RecordLimit, RecordSubset, WindowBlock};
 
 // ---------------------------------------------------------------------------
 // Constants
@@ -25,6 +34,31 @@ const COMMON_FIELD_THRESHOLD: f64 = 0.5;
 /// Fields present in fewer than this fraction of records are "rare".
 const RARE_FIELD_THRESHOLD: f64 = 0.05;
 
+/// A null in a field that is null less often than this is "unexpected".
+const RARE_NULL_THRESHOLD: f64 = 0.01;
+
+/// Likewise for an empty value in a field that is rarely empty.
+const RARE_EMPTY_THRESHOLD: f64 = 0.01;
+
+/// Value keys of empty strings, arrays and objects.
+const EMPTY_VALUE_KEYS: [&str; 3] = ["", "[]", "{}"];
+
+/// A field with at most this many values in the first half of its
+/// occurrences, all seen in the first quarter, is a closed set (enum).
+const MAX_ENUM_VALUES: usize = 20;
+
+/// Occurrences needed in that first half before a field can be an enum.
+const MIN_ENUM_RECORDS: usize = 100;
+
+/// Weight of enum violations, added on top of the six weighted signals: a
+/// value outside a closed set is a surer sign of trouble than a rare one.
+const ENUM_VIOLATION_WEIGHT: f64 = 0.5;
+
+/// Share of the input, from its start, whose records only learn which
+/// fields and values exist when flagging first occurrences (see
+/// [`add_first_seen`]).
+const NOVELTY_WARMUP_SHARE: f64 = 0.05;
+
 // ---------------------------------------------------------------------------
 // Public types
 // ---------------------------------------------------------------------------
@@ -52,33 +86,192 @@ impl std::fmt::Display for JsonType {
     }
 }
 
-/// A parsed JSON record (one log line).
+/// A parsed JSON record (one log line), borrowing from the input.
 #[derive(Debug)]
-pub struct JsonRecord {
+pub struct JsonRecord<'a> {
     pub offset: usize,
     pub length: usize,
-    pub value: Option<Value>,
+    /// Top-level fields, if the record is a JSON object.
+    pub fields: Option<JsonObject<'a>>,
     pub parse_error: bool,
 }
 
-impl JsonRecord {
+impl JsonRecord<'_> {
     #[inline]
-    pub fn content<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+    pub fn content<'d>(&self, data: &'d [u8]) -> &'d [u8] {
         &data[self.offset..self.offset + self.length]
     }
+
+    /// The value of top-level field `name`.
+    pub fn get(&self, name: &str) -> Option<&JsonValue<'_>> {
+        let fields = self.fields.as_ref()?;
+        let i = fields.binary_search_by(|(k, _)| (**k).cmp(name)).ok()?;
+        Some(&fields[i].1)
+    }
+}
+
+/// Restricts which top-level fields take part in schema building and
+/// scoring. Empty (the default) keeps every field.
+#[derive(Debug, Clone, Default)]
+pub struct FieldFilter {
+    /// Fields to drop.
+    pub ignore: HashSet<String>,
+    /// If set, only these fields are kept (after `ignore`).
+    pub only: Option<HashSet<String>>,
+}
+
+impl FieldFilter {
+    pub fn is_empty(&self) -> bool {
+        self.ignore.is_empty() && self.only.is_none()
+    }
+
+    pub fn allows(&self, field: &str) -> bool {
+        !self.ignore.contains(field) && self.only.as_ref().is_none_or(|o| o.contains(field))
+    }
+
+    /// Remove disallowed fields from every parsed record in place, so that
+    /// `build_schema` and `score_json_records` see the same filtered view.
+    /// Record offsets and raw content are untouched.
+    pub fn apply(&self, records: &mut [JsonRecord]) {
+        if self.is_empty() {
+            return;
+        }
+        for rec in records {
+            if let Some(fields) = &mut rec.fields {
+                fields.retain(|(k, _)| self.allows(k));
+            }
+        }
+    }
+}
+
+/// Guards against degenerate records, so a few adversarial lines cannot
+/// blow up the schema. Unset limits (the default) keep everything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StructureLimit {
+    /// Nesting depth kept, counting the record object as depth 1. A field
+    /// value nested deeper is cut to `{...}` or `[...]`, which becomes its
+    /// value key; 1 cuts every nested value.
+    pub max_depth: Option<usize>,
+    /// Top-level fields kept per record; the rest (by name order) are
+    /// dropped.
+    pub max_fields: Option<usize>,
+}
+
+/// What [`StructureLimit::apply`] cut.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Truncations {
+    /// Records over the field limit.
+    pub wide_records: usize,
+    /// Fields dropped from those records.
+    pub dropped_fields: usize,
+    /// Field values cut for depth.
+    pub deep_values: usize,
+}
+
+impl StructureLimit {
+    pub fn is_empty(&self) -> bool {
+        self.max_depth.is_none() && self.max_fields.is_none()
+    }
+
+    /// Apply the limits to every parsed record in place. Record offsets
+    /// and raw content are untouched.
+    pub fn apply(&self, records: &mut [JsonRecord]) -> Truncations {
+        let mut cut = Truncations::default();
+        if self.is_empty() {
+            return cut;
+        }
+        for fields in records.iter_mut().filter_map(|r| r.fields.as_mut()) {
+            if let Some(max) = self.max_fields.filter(|&m| fields.len() > m) {
+                cut.wide_records += 1;
+                cut.dropped_fields += fields.len() - max;
+                fields.truncate(max);
+            }
+            let Some(max) = self.max_depth else { continue };
+            for (_, v) in fields.iter_mut() {
+                match v {
+                    JsonValue::Array(raw) if 1 + nesting_depth(raw) > max => *raw = "[...]".into(),
+                    JsonValue::Object(raw) if 1 + nesting_depth(raw) > max => *raw = "{...}".into(),
+                    _ => continue,
+                }
+                cut.deep_values += 1;
+            }
+        }
+        cut
+    }
+}
+
+/// Per-field importance weights for scoring. Fields not listed weigh 1.0.
+///
+/// Weights scale a field's share of the missing, rare-value, extra-field,
+/// type-mismatch/enum-violation and unexpected-null/empty components, so a rare `level` can outrank a rare
+/// `user_agent`. Field-set novelty is unaffected.
+#[derive(Debug, Clone, Default)]
+pub struct FieldWeights {
+    pub weights: HashMap<String, f64>,
+}
+
+impl FieldWeights {
+    /// Parse `field=weight` pairs separated by commas or newlines. Blank
+    /// entries and `#` comments are ignored.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut weights = HashMap::new();
+        for entry in spec
+            .lines()
+            .map(|l| l.split('#').next().unwrap_or(""))
+            .flat_map(|l| l.split(','))
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            let (field, w) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("expected field=weight, got '{entry}'"))?;
+            let w: f64 = w
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid weight for '{}': '{}'", field.trim(), w.trim()))?;
+            if !w.is_finite() || w < 0.0 {
+                return Err(format!("weight for '{}' must be >= 0", field.trim()));
+            }
+            weights.insert(field.trim().to_string(), w);
+        }
+        Ok(Self { weights })
+    }
+
+    pub fn get(&self, field: &str) -> f64 {
+        self.weights.get(field).copied().unwrap_or(1.0)
+    }
+
+    fn sum<'a>(&self, fields: impl IntoIterator<Item = &'a str>) -> f64 {
+        fields.into_iter().map(|f| self.get(f)).sum()
+    }
 }
 
+/// An interned field name or value key. The schema holds one allocation
+/// per distinct string; field sets and record scores share it.
+pub type Name = Arc<str>;
+
 /// Statistics for a single field across all records.
 #[derive(Debug, Clone)]
 pub struct FieldProfile {
-    pub name: String,
+    pub name: Name,
+    /// Records with the field, including those where it is `null`.
     pub present_count: usize,
     pub presence_rate: f64,
+    /// Records where the field is present but `null`.
+    pub null_count: usize,
+    /// Records where the field is `""`, `[]` or `{}` (counted from the
+    /// value keys, so a string `"[]"` counts too).
+    pub empty_count: usize,
     pub type_counts: HashMap<JsonType, usize>,
+    /// Most common non-null type (`Null` only if the field is always null).
     pub dominant_type: JsonType,
-    pub value_counts: HashMap<String, usize>,
+    /// Counts of non-null values.
+    pub value_counts: HashMap<Name, usize>,
     pub unique_values: usize,
     pub is_low_cardinality: bool,
+    /// The field's closed value set, if it behaved as an enum (see
+    /// [`build_schema`]).
+    pub enum_values: Option<HashSet<Name>>,
 }
 
 /// Schema profile built from all records.
@@ -87,11 +280,11 @@ pub struct SchemaProfile {
     pub total_records: usize,
     pub valid_records: usize,
     pub parse_errors: usize,
-    pub fields: HashMap<String, FieldProfile>,
+    pub fields: HashMap<Name, FieldProfile>,
     /// Count of each unique field-name set.
-    pub field_set_counts: HashMap<Vec<String>, usize>,
+    pub field_set_counts: HashMap<Vec<Name>, usize>,
     /// The most common field set.
-    pub common_field_set: Vec<String>,
+    pub common_field_set: Vec<Name>,
 }
 
 /// Scored JSON record with explanations of why it's anomalous.
@@ -103,13 +296,22 @@ pub struct JsonRecordScore {
     pub valid_json: bool,
     pub field_count: usize,
     /// Common fields that are missing from this record.
-    pub missing_common: Vec<String>,
+    pub missing_common: Vec<Name>,
     /// Rare fields present in this record.
-    pub extra_rare: Vec<String>,
+    pub extra_rare: Vec<Name>,
     /// (field, value) pairs with low frequency in the corpus.
-    pub rare_values: Vec<(String, String)>,
+    pub rare_values: Vec<(Name, Name)>,
     /// (field, expected_type, actual_type) mismatches.
-    pub type_mismatches: Vec<(String, JsonType, JsonType)>,
+    pub type_mismatches: Vec<(Name, JsonType, JsonType)>,
+    /// Fields that are `null` here but rarely null in the corpus.
+    pub unexpected_nulls: Vec<Name>,
+    /// Fields that are empty here but rarely empty in the corpus.
+    pub unexpected_empties: Vec<Name>,
+    /// (field, value) pairs outside the field's closed value set.
+    pub enum_violations: Vec<(Name, Name)>,
+    /// Fields (`None`) and values of low-cardinality fields first seen in
+    /// this record (see [`add_first_seen`]).
+    pub first_seen: Vec<(Name, Option<Name>)>,
     pub anomaly_score: f64,
 }
 
@@ -137,6 +339,74 @@ pub struct JsonAnomalyReport {
     pub anomaly_indices: Vec<usize>,
 }
 
+/// How much one field contributes to anomalies across the corpus.
+#[derive(Debug, Clone)]
+pub struct FieldContribution {
+    pub name: Name,
+    pub presence_rate: f64,
+    /// Records where this common field was missing.
+    pub missing_count: usize,
+    /// Records where this field's value was rare.
+    pub rare_value_count: usize,
+    /// Records where this field was present but rare.
+    pub extra_rare_count: usize,
+    /// Records where this field's type differed from its dominant type.
+    pub type_mismatch_count: usize,
+    /// Records where this normally non-null field was null.
+    pub unexpected_null_count: usize,
+    /// Records where this normally populated field was empty.
+    pub unexpected_empty_count: usize,
+    /// Records where this enum field had a value outside its set.
+    pub enum_violation_count: usize,
+    /// 4p(1-p) of the presence rate: 0 when always/never present, 1 at 50%.
+    pub presence_instability: f64,
+    /// Weighted sum of the per-field rates (see `field_contributions`).
+    pub contribution: f64,
+}
+
+/// Occurrence statistics for one value of a field.
+#[derive(Debug, Clone)]
+pub struct ValueStats {
+    pub value: String,
+    pub count: usize,
+    /// Record index of the first and last occurrence.
+    pub first_index: usize,
+    pub last_index: usize,
+    /// Up to `max_examples` record indices where the value occurs.
+    pub examples: Vec<usize>,
+}
+
+/// Full value distribution for a single field.
+#[derive(Debug, Clone)]
+pub struct FieldValues {
+    pub name: String,
+    pub total_records: usize,
+    pub present_count: usize,
+    /// Types seen, most common first.
+    pub type_counts: Vec<(JsonType, usize)>,
+    /// Values seen, most common first (ties by first occurrence).
+    pub values: Vec<ValueStats>,
+}
+
+/// Anomaly statistics for one value of a group-by field.
+#[derive(Debug, Clone)]
+pub struct GroupStats {
+    /// Field value, or `(missing)` for records without the field.
+    pub value: String,
+    pub records: usize,
+    pub anomalies: usize,
+    pub mean_score: f64,
+}
+
+impl GroupStats {
+    pub fn anomaly_rate(&self) -> f64 {
+        if self.records == 0 {
+            return 0.0;
+        }
+        self.anomalies as f64 / self.records as f64
+    }
+}
+
 impl JsonAnomalyReport {
     pub fn anomaly_rate(&self) -> f64 {
         if self.total_records == 0 {
@@ -150,75 +420,139 @@ impl JsonAnomalyReport {
 // Helpers
 // ---------------------------------------------------------------------------
 
-fn value_type(v: &Value) -> JsonType {
-    match v {
-        Value::Null => JsonType::Null,
-        Value::Bool(_) => JsonType::Bool,
-        Value::Number(_) => JsonType::Number,
-        Value::String(_) => JsonType::String,
-        Value::Array(_) => JsonType::Array,
-        Value::Object(_) => JsonType::Object,
+impl FieldProfile {
+    fn new(name: Name) -> Self {
+        Self {
+            name,
+            present_count: 0,
+            presence_rate: 0.0,
+            null_count: 0,
+            empty_count: 0,
+            type_counts: HashMap::new(),
+            dominant_type: JsonType::Null,
+            value_counts: HashMap::new(),
+            unique_values: 0,
+            is_low_cardinality: true,
+            enum_values: None,
+        }
+    }
+
+    /// Count `n` occurrences of value key `key`, allocating it only the
+    /// first time it is seen.
+    fn add_value(&mut self, key: &str, n: usize) {
+        match self.value_counts.get_mut(key) {
+            Some(c) => *c += n,
+            None => {
+                self.value_counts.insert(key.into(), n);
+            }
+        }
+    }
+
+    /// Whether the field is empty in under [`RARE_EMPTY_THRESHOLD`] of the
+    /// records where it is non-null.
+    fn rarely_empty(&self) -> bool {
+        let non_null = self.present_count - self.null_count;
+        (self.empty_count as f64 / non_null.max(1) as f64) < RARE_EMPTY_THRESHOLD
+    }
+
+    /// The interned copy of value key `key`, if this field has seen it.
+    fn value_name(&self, key: &str) -> Option<Name> {
+        self.value_counts.get_key_value(key).map(|(k, _)| k.clone())
     }
 }
 
-fn value_to_key(v: &Value) -> String {
-    match v {
-        Value::Null => "null".into(),
-        Value::Bool(b) => b.to_string(),
-        Value::Number(n) => n.to_string(),
-        Value::String(s) => s.clone(),
-        _ => v.to_string(),
+/// Profile for field `name`, created (and the name interned) on first use.
+fn field_entry<'a>(fields: &'a mut HashMap<Name, FieldProfile>, name: &str) -> &'a mut FieldProfile {
+    if !fields.contains_key(name) {
+        let name: Name = name.into();
+        fields.insert(name.clone(), FieldProfile::new(name));
     }
+    fields.get_mut(name).unwrap()
 }
 
 // ---------------------------------------------------------------------------
 // Parse
 // ---------------------------------------------------------------------------
 
-/// Parse each line of `data` as JSON.
-pub fn parse_json_records(data: &[u8], delimiter: u8) -> Vec<JsonRecord> {
-    let mut records = Vec::new();
-    let mut start = 0;
-
-    for (i, &b) in data.iter().enumerate() {
-        if b == delimiter {
-            let length = i + 1 - start;
-            let line = &data[start..i]; // exclude delimiter for parsing
-            let trimmed = trim_ascii(line);
-            if !trimmed.is_empty() {
-                let (value, err) = match serde_json::from_slice::<Value>(trimmed) {
-                    Ok(v) => (Some(v), false),
-                    Err(_) => (None, true),
+/// Parse each line of `data` as JSON. Blank lines are skipped; record
+/// boundaries follow [`RecordIndex`].
+pub fn parse_json_records(data: &[u8], delimiter: u8) -> Vec<JsonRecord<'_>> {
+    parse_json_records_limited(data, delimiter, None)
+}
+
+/// [`parse_json_records`] with oversized records dropped or, when
+/// truncated, shortened to `max_bytes` and left unparsed (a parse error).
+pub fn parse_json_records_limited(
+    data: &[u8],
+    delimiter: u8,
+    limit: Option<RecordLimit>,
+) -> Vec<JsonRecord<'_>> {
+    parse_records_with(data, delimiter, limit, |_| None)
+}
+
+/// [`parse_json_records_limited`], with `fallback` giving fields for
+/// records that are not valid JSON (e.g. captures pulled out of a plain log
+/// line). Records it returns `None` for stay parse errors.
+pub fn parse_records_with<'a>(
+    data: &'a [u8],
+    delimiter: u8,
+    limit: Option<RecordLimit>,
+    mut fallback: impl FnMut(&'a str) -> Option<JsonObject<'a>>,
+) -> Vec<JsonRecord<'a>> {
+    let index = RecordIndex::new(data, delimiter);
+    let mut parser = RecordParser::default();
+    (0..index.len())
+        .filter_map(|i| {
+            let r = index.range_for(i)?;
+            let content = index.content_range(data, i)?;
+            let trimmed = trim_ascii(&data[content.clone()]);
+            if trimmed.is_empty() {
+                return None;
+            }
+            if let Some(l) = limit.filter(|l| content.len() > l.max_bytes) {
+                return match l.policy {
+                    Oversize::Skip => None,
+                    Oversize::Truncate => Some(JsonRecord {
+                        offset: r.start, length: l.max_bytes, fields: None, parse_error: true,
+                    }),
                 };
-                records.push(JsonRecord {
-                    offset: start,
-                    length,
-                    value,
-                    parse_error: err,
-                });
             }
-            start = i + 1;
-        }
-    }
-    // Trailing line without delimiter
-    if start < data.len() {
-        let line = &data[start..];
-        let trimmed = trim_ascii(line);
-        if !trimmed.is_empty() {
-            let (value, err) = match serde_json::from_slice::<Value>(trimmed) {
-                Ok(v) => (Some(v), false),
-                Err(_) => (None, true),
-            };
-            records.push(JsonRecord {
-                offset: start,
-                length: data.len() - start,
-                value,
-                parse_error: err,
+            let parsed = parser.parse(trimmed).or_else(|| {
+                std::str::from_utf8(trimmed).ok().and_then(&mut fallback).map(Some)
             });
+            Some(JsonRecord {
+                offset: r.start,
+                length: r.len(),
+                parse_error: parsed.is_none(),
+                fields: parsed.flatten(),
+            })
+        })
+        .collect()
+}
+
+/// The record parser in use: `"pull"`, or `"simd-json"` when built with
+/// the `simd-json` feature.
+pub const JSON_PARSER: &str = if cfg!(feature = "simd-json") { "simd-json" } else { "pull" };
+
+/// Parses one trimmed record: `None` if it is not valid JSON, otherwise
+/// its top-level fields if it is an object. With the `simd-json` feature,
+/// records simd-json rejects are parsed again by the pull parser, so both
+/// builds accept the same records.
+#[derive(Default)]
+struct RecordParser {
+    #[cfg(feature = "simd-json")]
+    simd: SimdParser,
+}
+
+impl RecordParser {
+    fn parse<'a>(&mut self, line: &'a [u8]) -> Option<Option<JsonObject<'a>>> {
+        let line = std::str::from_utf8(line).ok()?;
+        #[cfg(feature = "simd-json")]
+        if let Ok(fields) = self.simd.parse(line) {
+            return Some(fields);
         }
+        parse_record(line).ok()
     }
-
-    records
 }
 
 fn trim_ascii(s: &[u8]) -> &[u8] {
@@ -229,12 +563,102 @@ fn trim_ascii(s: &[u8]) -> &[u8] {
 
 /// Returns true if the first non-empty line of `data` parses as a JSON object.
 pub fn looks_like_json(data: &[u8]) -> bool {
-    let first_line_end = data.iter().position(|&b| b == b'\n').unwrap_or(data.len());
+    let first_line_end = memchr(b'\n', data).unwrap_or(data.len());
     let line = trim_ascii(&data[..first_line_end]);
     if line.is_empty() {
         return false;
     }
-    matches!(serde_json::from_slice::<Value>(line), Ok(Value::Object(_)))
+    std::str::from_utf8(line).is_ok_and(is_object)
+}
+
+/// Lines sampled by [`is_mixed_format`].
+const FORMAT_SAMPLE_LINES: usize = 1000;
+
+/// Each format must make up at least this share of the sampled lines for
+/// the input to count as mixed.
+const MIN_MIXED_SHARE: f64 = 0.05;
+
+/// Returns true if the first non-empty lines of `data` interleave JSON
+/// objects and plain text, each a sizeable share of them.
+pub fn is_mixed_format(data: &[u8]) -> bool {
+    let (mut json, mut plain) = (0usize, 0usize);
+    for line in data.split(|&b| b == b'\n').map(trim_ascii).filter(|l| !l.is_empty()).take(FORMAT_SAMPLE_LINES) {
+        if std::str::from_utf8(line).is_ok_and(is_object) {
+            json += 1;
+        } else {
+            plain += 1;
+        }
+    }
+    let n = (json + plain).max(1) as f64;
+    json as f64 / n >= MIN_MIXED_SHARE && plain as f64 / n >= MIN_MIXED_SHARE
+}
+
+/// Split records into JSON objects and everything else, for analysis by
+/// the schema and the LZ77 pipeline respectively. Blank records go to
+/// neither.
+pub fn split_by_format(data: &[u8], delimiter: u8) -> (RecordSubset, RecordSubset) {
+    let index = RecordIndex::new(data, delimiter);
+    let mut json = RecordSubset::new(index.len());
+    let mut plain = RecordSubset::new(index.len());
+    for (i, (off, len)) in index.iter().enumerate() {
+        let rec = &data[off..off + len];
+        let line = trim_ascii(rec);
+        if line.is_empty() {
+            continue;
+        }
+        let part = if std::str::from_utf8(line).is_ok_and(is_object) { &mut json } else { &mut plain };
+        part.push(i, off, rec);
+    }
+    (json, plain)
+}
+
+/// Collapse runs of spaces and tabs in every string value (nested ones
+/// included) to one space and trim the ends, so padded and unpadded values
+/// compare equal. Record offsets and raw content are untouched.
+pub fn normalize_space_values(records: &mut [JsonRecord]) {
+    fn padded(s: &str) -> bool {
+        s.contains("  ") || s.contains('\t') || s.starts_with(' ') || s.ends_with(' ')
+    }
+    fn collapse(s: &str) -> String {
+        s.split([' ', '\t']).filter(|w| !w.is_empty()).collect::<Vec<_>>().join(" ")
+    }
+    fn walk(v: &mut Value) {
+        match v {
+            Value::String(s) if padded(s) => *s = collapse(s),
+            Value::Array(a) => a.iter_mut().for_each(walk),
+            Value::Object(m) => m.values_mut().for_each(walk),
+            _ => {}
+        }
+    }
+    for (_, v) in records.iter_mut().filter_map(|r| r.fields.as_mut()).flatten() {
+        match v {
+            JsonValue::String(s) if padded(s) => *s = Cow::Owned(collapse(s)),
+            // Nested values are raw text; only those that could hold
+            // whitespace (or an escaped tab) are parsed and rewritten.
+            JsonValue::Array(raw) | JsonValue::Object(raw)
+                if raw.contains([' ', '\t', '\\']) =>
+            {
+                if let Ok(mut nested) = serde_json::from_str::<Value>(raw) {
+                    walk(&mut nested);
+                    *raw = Cow::Owned(nested.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Round every top-level number to `places` decimal places, so values
+/// that differ only in measurement noise (`12.3456`, `12.3461`) share a
+/// value key. Record offsets and raw content are untouched.
+pub fn round_number_values(records: &mut [JsonRecord], places: usize) {
+    for (_, v) in records.iter_mut().filter_map(|r| r.fields.as_mut()).flatten() {
+        if let JsonValue::Number(n) = v {
+            if let Cow::Owned(rounded) = round_number(n, places) {
+                *n = Cow::Owned(rounded);
+            }
+        }
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -242,57 +666,98 @@ pub fn looks_like_json(data: &[u8]) -> bool {
 // ---------------------------------------------------------------------------
 
 /// Build a schema profile from parsed JSON records.
-pub fn build_schema(records: &[JsonRecord]) -> SchemaProfile {
-    let total = records.len();
+///
+/// A field whose values (nulls aside) all appear within the first quarter
+/// of its occurrences, and no new one until halfway, is learned as a
+/// closed set: `enum_values` holds the values seen in that first half, and
+/// later values outside it are scored as enum violations.
+pub fn build_schema<'r, 'a: 'r>(records: impl IntoIterator<Item = &'r JsonRecord<'a>>) -> SchemaProfile {
+    let mut total = 0usize;
     let mut valid = 0usize;
-    let mut fields: HashMap<String, FieldProfile> = HashMap::new();
-    let mut field_set_counts: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut fields: HashMap<Name, FieldProfile> = HashMap::new();
+    let mut field_set_counts: HashMap<Vec<Name>, usize> = HashMap::new();
+    // Per field, the occurrence number at which each new value appeared,
+    // up to a few more than an enum could hold.
+    let mut arrivals: HashMap<Name, Vec<(usize, Name)>> = HashMap::new();
 
     for rec in records {
-        let map = match &rec.value {
-            Some(Value::Object(m)) => {
-                valid += 1;
-                m
+        total += 1;
+        let Some(map) = &rec.fields else { continue };
+        valid += 1;
+
+        // Track per-field stats
+        let mut keys: Vec<Name> = Vec::with_capacity(map.len());
+        for (key, val) in map {
+            let profile = field_entry(&mut fields, key);
+            profile.present_count += 1;
+            *profile.type_counts.entry(val.json_type()).or_insert(0) += 1;
+            if !matches!(val, JsonValue::Null) {
+                let vkey = val.key();
+                let new = !profile.value_counts.contains_key(&*vkey);
+                profile.add_value(&vkey, 1);
+                if new {
+                    let nulls = profile.type_counts.get(&JsonType::Null).copied().unwrap_or(0);
+                    let at = profile.present_count - 1 - nulls;
+                    let firsts = arrivals.entry(profile.name.clone()).or_default();
+                    if firsts.len() <= 2 * MAX_ENUM_VALUES {
+                        firsts.push((at, profile.value_name(&vkey).unwrap()));
+                    }
+                }
             }
-            _ => continue,
-        };
+            keys.push(profile.name.clone());
+        }
 
         // Track field set
-        let mut keys: Vec<String> = map.keys().cloned().collect();
         keys.sort();
         *field_set_counts.entry(keys).or_insert(0) += 1;
+    }
 
-        // Track per-field stats
-        for (key, val) in map {
-            let profile = fields.entry(key.clone()).or_insert_with(|| FieldProfile {
-                name: key.clone(),
-                present_count: 0,
-                presence_rate: 0.0,
-                type_counts: HashMap::new(),
-                dominant_type: JsonType::Null,
-                value_counts: HashMap::new(),
-                unique_values: 0,
-                is_low_cardinality: true,
-            });
-            profile.present_count += 1;
-            *profile.type_counts.entry(value_type(val)).or_insert(0) += 1;
+    for (name, firsts) in arrivals {
+        let f = fields.get_mut(&name).unwrap();
+        let nulls = f.type_counts.get(&JsonType::Null).copied().unwrap_or(0);
+        f.enum_values = closed_set(&firsts, f.present_count - nulls);
+    }
 
-            let vkey = value_to_key(val);
-            *profile.value_counts.entry(vkey).or_insert(0) += 1;
-        }
+    finalize_schema(total, valid, fields, field_set_counts)
+}
+
+/// The values first seen in the first half of `n` occurrences, if there
+/// are few of them and the last arrived in the first quarter.
+fn closed_set(firsts: &[(usize, Name)], n: usize) -> Option<HashSet<Name>> {
+    let half = n / 2;
+    if half < MIN_ENUM_RECORDS || firsts.len() > 2 * MAX_ENUM_VALUES {
+        return None;
+    }
+    let known = firsts.partition_point(|&(at, _)| at < half);
+    if known > MAX_ENUM_VALUES || firsts[known - 1].0 >= half / 2 {
+        return None;
     }
+    Some(firsts[..known].iter().map(|(_, v)| v.clone()).collect())
+}
 
-    // Compute derived stats
+/// Build a `SchemaProfile` from raw counts, computing presence rates,
+/// cardinality, dominant types and the common field set. Used by
+/// [`build_schema`] and when merging or loading saved schemas.
+pub fn finalize_schema(
+    total: usize,
+    valid: usize,
+    mut fields: HashMap<Name, FieldProfile>,
+    field_set_counts: HashMap<Vec<Name>, usize>,
+) -> SchemaProfile {
     let total_f = total.max(1) as f64;
     for profile in fields.values_mut() {
         profile.presence_rate = profile.present_count as f64 / total_f;
+        profile.null_count = profile.type_counts.get(&JsonType::Null).copied().unwrap_or(0);
+        profile.empty_count =
+            EMPTY_VALUE_KEYS.iter().filter_map(|k| profile.value_counts.get(*k)).sum();
         profile.unique_values = profile.value_counts.len();
         profile.is_low_cardinality = profile.unique_values <= HIGH_CARDINALITY_THRESHOLD;
 
-        // Dominant type = most common type
+        // Dominant type = most common non-null type
         profile.dominant_type = profile
             .type_counts
             .iter()
+            .filter(|(&t, _)| t != JsonType::Null)
             .max_by_key(|(_, &c)| c)
             .map(|(&t, _)| t)
             .unwrap_or(JsonType::Null);
@@ -315,84 +780,187 @@ pub fn build_schema(records: &[JsonRecord]) -> SchemaProfile {
     }
 }
 
+/// Combine two schemas as if built from both inputs: counts are summed and
+/// derived statistics recomputed.
+pub fn merge_schemas(a: &SchemaProfile, b: &SchemaProfile) -> SchemaProfile {
+    let mut fields = a.fields.clone();
+    for (name, fb) in &b.fields {
+        let f = fields.entry(name.clone()).or_insert_with(|| FieldProfile::new(name.clone()));
+        // A field stays an enum only if it is one on both sides it occurs in.
+        f.enum_values = match (&f.enum_values, &fb.enum_values) {
+            _ if !a.fields.contains_key(name) => fb.enum_values.clone(),
+            (Some(x), Some(y)) => Some(x.union(y).cloned().collect()),
+            _ => None,
+        };
+        f.present_count += fb.present_count;
+        for (&t, &n) in &fb.type_counts {
+            *f.type_counts.entry(t).or_insert(0) += n;
+        }
+        for (v, &n) in &fb.value_counts {
+            *f.value_counts.entry(v.clone()).or_insert(0) += n;
+        }
+    }
+    let mut sets = a.field_set_counts.clone();
+    for (k, &n) in &b.field_set_counts {
+        *sets.entry(k.clone()).or_insert(0) += n;
+    }
+    finalize_schema(
+        a.total_records + b.total_records,
+        a.valid_records + b.valid_records,
+        fields,
+        sets,
+    )
+}
+
+/// Age a schema by scaling every count by `factor`, rounded down so that
+/// any factor below 1 strictly shrinks it. Values, types, fields and field
+/// sets whose count reaches zero are dropped, so patterns that stop
+/// appearing eventually disappear.
+pub fn decay_schema(s: &SchemaProfile, factor: f64) -> SchemaProfile {
+    let scale = |n: usize| (n as f64 * factor).floor() as usize;
+    let fields = s
+        .fields
+        .iter()
+        .filter_map(|(name, f)| {
+            let present_count = scale(f.present_count);
+            if present_count == 0 {
+                return None;
+            }
+            let mut f = f.clone();
+            f.present_count = present_count;
+            f.type_counts.values_mut().for_each(|n| *n = scale(*n));
+            f.type_counts.retain(|_, n| *n > 0);
+            f.value_counts.values_mut().for_each(|n| *n = scale(*n));
+            f.value_counts.retain(|_, n| *n > 0);
+            if let Some(set) = &mut f.enum_values {
+                set.retain(|v| f.value_counts.contains_key(v));
+                if set.is_empty() {
+                    f.enum_values = None;
+                }
+            }
+            Some((name.clone(), f))
+        })
+        .collect();
+    let sets = s
+        .field_set_counts
+        .iter()
+        .map(|(k, &n)| (k.clone(), scale(n)))
+        .filter(|&(_, n)| n > 0)
+        .collect();
+    let total = scale(s.total_records);
+    finalize_schema(total, scale(s.valid_records).min(total), fields, sets)
+}
+
 // ---------------------------------------------------------------------------
 // Score
 // ---------------------------------------------------------------------------
 
 /// Score each JSON record against the schema profile.
 pub fn score_json_records(
+    data: &[u8],
+    records: &[JsonRecord],
+    schema: &SchemaProfile,
+) -> Vec<JsonRecordScore> {
+    score_json_records_weighted(data, records, schema, &FieldWeights::default())
+}
+
+/// Like [`score_json_records`], with per-field importance weights.
+pub fn score_json_records_weighted(
     _data: &[u8],
     records: &[JsonRecord],
     schema: &SchemaProfile,
+    weights: &FieldWeights,
 ) -> Vec<JsonRecordScore> {
     let total_f = schema.total_records.max(1) as f64;
 
     // Identify common and rare fields
-    let common_fields: Vec<&str> = schema
+    let common_fields: Vec<&Name> = schema
         .fields
         .iter()
         .filter(|(_, p)| p.presence_rate > COMMON_FIELD_THRESHOLD)
-        .map(|(k, _)| k.as_str())
+        .map(|(k, _)| k)
         .collect();
 
-    let n_common = common_fields.len().max(1) as f64;
-
-    let mut scores = Vec::with_capacity(records.len());
-
-    for (idx, rec) in records.iter().enumerate() {
-        let map = match &rec.value {
-            Some(Value::Object(m)) => m,
-            _ => {
+    let common_weight = weights.sum(common_fields.iter().map(|f| &***f));
+
+    // Records are scored independently; collect keeps input order.
+    #[cfg(feature = "parallel")]
+    let iter = records.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let iter = records.iter();
+    iter.enumerate().map(|(idx, rec)| {
+        let map = match &rec.fields {
+            Some(m) => m,
+            None => {
                 // Not a valid JSON object — maximally anomalous
-                scores.push(JsonRecordScore {
+                return JsonRecordScore {
                     index: idx,
                     offset: rec.offset,
                     length: rec.length,
                     valid_json: false,
                     field_count: 0,
-                    missing_common: common_fields.iter().map(|&s| s.to_string()).collect(),
+                    missing_common: common_fields.iter().map(|&f| f.clone()).collect(),
                     extra_rare: Vec::new(),
                     rare_values: Vec::new(),
                     type_mismatches: Vec::new(),
+                    unexpected_nulls: Vec::new(),
+                    unexpected_empties: Vec::new(),
+                    enum_violations: Vec::new(),
+                    first_seen: Vec::new(),
                     anomaly_score: 1.0,
-                });
-                continue;
+                };
             }
         };
 
-        let keys: HashSet<&str> = map.keys().map(|s| s.as_str()).collect();
+        let keys: HashSet<&str> = map.iter().map(|(k, _)| &**k).collect();
 
         // Missing common fields
-        let missing: Vec<String> = common_fields
+        let missing: Vec<Name> = common_fields
             .iter()
-            .filter(|&&f| !keys.contains(f))
-            .map(|&f| f.to_string())
+            .filter(|&&f| !keys.contains(&**f))
+            .map(|&f| f.clone())
             .collect();
 
-        // Extra rare fields
-        let extra: Vec<String> = keys
+        // Extra rare fields (fields the schema has never seen are interned
+        // here; they are rare by definition)
+        let extra: Vec<Name> = keys
             .iter()
-            .filter(|&&k| {
-                schema
-                    .fields
-                    .get(k)
-                    .map(|p| p.presence_rate < RARE_FIELD_THRESHOLD)
-                    .unwrap_or(true)
+            .filter_map(|&k| match schema.fields.get(k) {
+                Some(p) => (p.presence_rate < RARE_FIELD_THRESHOLD).then(|| p.name.clone()),
+                None => Some(k.into()),
             })
-            .map(|&k| k.to_string())
             .collect();
 
-        // Type mismatches
+        // Type mismatches, unexpected nulls, unexpected empties and enum
+        // violations (a null is scored as the second, not as a change of
+        // type or a rare value; each value gets at most one of the last
+        // three, and the last two are not also rare values)
         let mut type_mismatches = Vec::new();
+        let mut unexpected_nulls = Vec::new();
+        let mut unexpected_empties = Vec::new();
+        let mut enum_violations = Vec::new();
         for (key, val) in map {
-            if let Some(profile) = schema.fields.get(key.as_str()) {
-                let actual = value_type(val);
-                if actual != profile.dominant_type {
+            if let Some(profile) = schema.fields.get(&**key) {
+                let actual = val.json_type();
+                if actual == JsonType::Null {
+                    let null_rate = profile.null_count as f64 / profile.present_count.max(1) as f64;
+                    if null_rate < RARE_NULL_THRESHOLD {
+                        unexpected_nulls.push(profile.name.clone());
+                    }
+                } else if actual != profile.dominant_type {
                     type_mismatches.push((
-                        key.clone(),
+                        profile.name.clone(),
                         profile.dominant_type,
                         actual,
                     ));
+                } else if val.is_empty() && profile.rarely_empty() {
+                    unexpected_empties.push(profile.name.clone());
+                } else if let Some(set) = &profile.enum_values {
+                    let vkey = val.key();
+                    if !set.contains(&*vkey) {
+                        let value = profile.value_name(&vkey).unwrap_or_else(|| vkey.into());
+                        enum_violations.push((profile.name.clone(), value));
+                    }
                 }
             }
         }
@@ -400,60 +968,89 @@ pub fn score_json_records(
         // Rare values (only for low-cardinality fields)
         let mut rare_values = Vec::new();
         let mut value_rarity_sum = 0.0;
-        let mut value_rarity_n = 0usize;
+        let mut value_rarity_w = 0.0;
 
         for (key, val) in map {
-            if let Some(profile) = schema.fields.get(key.as_str()) {
-                if profile.is_low_cardinality {
-                    let vkey = value_to_key(val);
-                    let count = profile.value_counts.get(&vkey).copied().unwrap_or(0);
-                    let freq = count as f64 / profile.present_count.max(1) as f64;
-                    value_rarity_sum += 1.0 - freq;
-                    value_rarity_n += 1;
-                    if freq < 0.01 {
-                        rare_values.push((key.clone(), vkey));
+            if let Some(profile) = schema.fields.get(&**key) {
+                let unexpected_empty = val.is_empty() && profile.rarely_empty();
+                if profile.is_low_cardinality && !matches!(val, JsonValue::Null) && !unexpected_empty {
+                    let vkey = val.key();
+                    let violation = enum_violations.iter().any(|(f, _)| Arc::ptr_eq(f, &profile.name));
+                    let count = profile.value_counts.get(&*vkey).copied().unwrap_or(0);
+                    let non_null = profile.present_count - profile.null_count;
+                    let freq = count as f64 / non_null.max(1) as f64;
+                    let w = weights.get(key);
+                    value_rarity_sum += w * (1.0 - freq);
+                    value_rarity_w += w;
+                    if freq < 0.01 && !violation {
+                        let value = profile.value_name(&vkey).unwrap_or_else(|| vkey.into());
+                        rare_values.push((profile.name.clone(), value));
                     }
                 }
             }
         }
 
-        let avg_value_rarity = if value_rarity_n > 0 {
-            value_rarity_sum / value_rarity_n as f64
+        let avg_value_rarity = if value_rarity_w > 0.0 {
+            value_rarity_sum / value_rarity_w
         } else {
             0.0
         };
 
         // Field set novelty
-        let mut keys_sorted: Vec<String> = map.keys().cloned().collect();
-        keys_sorted.sort();
-        let set_count = schema.field_set_counts.get(&keys_sorted).copied().unwrap_or(0);
+        // (a set with a field the schema lacks was never counted)
+        let names: Option<Vec<Name>> =
+            map.iter().map(|(k, _)| schema.fields.get(&**k).map(|p| p.name.clone())).collect();
+        let set_count = names.map_or(0, |mut n| {
+            n.sort();
+            schema.field_set_counts.get(&n).copied().unwrap_or(0)
+        });
         let set_novelty = 1.0 - (set_count as f64 / total_f);
 
         // Missing score
-        let missing_score = missing.len() as f64 / n_common;
+        let missing_score = if common_weight > 0.0 {
+            weights.sum(missing.iter().map(|f| &**f)) / common_weight
+        } else {
+            0.0
+        };
 
         // Extra score
-        let extra_score = if keys.is_empty() {
-            0.0
+        let key_weight = weights.sum(keys.iter().copied());
+        let extra_score = if key_weight > 0.0 {
+            weights.sum(extra.iter().map(|f| &**f)) / key_weight
         } else {
-            extra.len() as f64 / keys.len() as f64
+            0.0
         };
 
         // Type mismatch score
-        let type_score = if map.is_empty() {
+        let type_score = if key_weight > 0.0 {
+            weights.sum(type_mismatches.iter().map(|(k, _, _)| &**k)) / key_weight
+        } else {
             0.0
+        };
+
+        // Enum violation score (not shared out over the record's fields:
+        // one violation is enough)
+        let enum_score = weights.sum(enum_violations.iter().map(|(k, _)| &**k)).min(1.0);
+
+        // Unexpected null or empty score
+        let null_score = if key_weight > 0.0 {
+            weights.sum(unexpected_nulls.iter().chain(&unexpected_empties).map(|f| &**f)) / key_weight
         } else {
-            type_mismatches.len() as f64 / map.len() as f64
+            0.0
         };
 
         // Weighted combination
         let anomaly_score = 0.30 * missing_score
             + 0.25 * avg_value_rarity
-            + 0.25 * set_novelty
+            + 0.15 * set_novelty
             + 0.10 * extra_score
-            + 0.10 * type_score;
+            + 0.10 * type_score
+            + 0.10 * null_score
+            + ENUM_VIOLATION_WEIGHT * enum_score;
+        // (a parse failure stays the highest score)
+        let anomaly_score = anomaly_score.min(1.0);
 
-        scores.push(JsonRecordScore {
+        JsonRecordScore {
             index: idx,
             offset: rec.offset,
             length: rec.length,
@@ -463,11 +1060,321 @@ pub fn score_json_records(
             extra_rare: extra,
             rare_values,
             type_mismatches,
+            unexpected_nulls,
+            unexpected_empties,
+            enum_violations,
+            first_seen: Vec::new(),
             anomaly_score,
+        }
+    }).collect()
+}
+
+/// Like [`score_json_records_weighted`], scoring each block of records
+/// against a schema built from the `window` records before it
+/// ([`sliding_windows`]), for input whose normal changes over time.
+pub fn score_json_records_windowed(
+    data: &[u8],
+    records: &[JsonRecord],
+    weights: &FieldWeights,
+    window: usize,
+) -> Vec<JsonRecordScore> {
+    let mut out = Vec::with_capacity(records.len());
+    for WindowBlock { block, window } in sliding_windows(records.len(), window) {
+        let schema = build_schema(&records[window]);
+        let mut scored = score_json_records_weighted(data, &records[block.clone()], &schema, weights);
+        for s in &mut scored {
+            s.index += block.start;
+        }
+        out.extend(scored);
+    }
+    out
+}
+
+/// Like [`score_json_records_windowed`], for chunks of `chunk` records,
+/// each scored against a running schema: the previous chunk's with counts
+/// scaled by `decay` ([`decay_schema`]; 0 rebuilds it from scratch), merged
+/// with the chunk's own.
+pub fn score_json_records_chunked(
+    data: &[u8],
+    records: &[JsonRecord],
+    weights: &FieldWeights,
+    chunk: usize,
+    decay: f64,
+) -> Vec<JsonRecordScore> {
+    let mut running = finalize_schema(0, 0, HashMap::new(), HashMap::new());
+    let mut out = Vec::with_capacity(records.len());
+    for (k, part) in records.chunks(chunk.max(1)).enumerate() {
+        running = merge_schemas(&decay_schema(&running, decay), &build_schema(part));
+        let mut scored = score_json_records_weighted(data, part, &running, weights);
+        for s in &mut scored {
+            s.index += k * chunk.max(1);
+        }
+        out.extend(scored);
+    }
+    out
+}
+
+/// Flag, in input order, the first occurrence of each field and of each
+/// value of a low-cardinality field: the most useful signal while a stream
+/// is being watched. Records in the first [`NOVELTY_WARMUP_SHARE`] of the
+/// input only learn what exists. Flags go to `first_seen`, and every valid
+/// record's score becomes `(1 - weight) * score + weight * novelty`, where
+/// novelty is the weighted share of its fields that are new or carry a new
+/// value.
+pub fn add_first_seen(
+    scored: &mut [JsonRecordScore],
+    records: &[JsonRecord],
+    schema: &SchemaProfile,
+    weights: &FieldWeights,
+    weight: f64,
+) {
+    let warmup = (records.len() as f64 * NOVELTY_WARMUP_SHARE).ceil() as usize;
+    let mut seen: HashMap<&str, HashSet<Cow<str>>> = HashMap::new();
+    for (idx, (rec, s)) in records.iter().zip(scored.iter_mut()).enumerate() {
+        let Some(map) = &rec.fields else { continue };
+        for (key, val) in map {
+            let profile = schema.fields.get(&**key);
+            let new_field = !seen.contains_key(&**key);
+            let values = seen.entry(&**key).or_default();
+            let tracked = profile.is_some_and(|p| p.is_low_cardinality) && !matches!(val, JsonValue::Null);
+            let new_value = tracked && values.insert(val.key());
+            if idx < warmup || !(new_field || new_value) {
+                continue;
+            }
+            let name = profile.map_or_else(|| Name::from(&**key), |p| p.name.clone());
+            let value = (!new_field).then(|| {
+                let vkey = val.key();
+                profile.and_then(|p| p.value_name(&vkey)).unwrap_or_else(|| vkey.into())
+            });
+            s.first_seen.push((name, value));
+        }
+        let key_weight = weights.sum(map.iter().map(|(k, _)| &**k));
+        let novelty = if key_weight > 0.0 {
+            weights.sum(s.first_seen.iter().map(|(f, _)| &**f)) / key_weight
+        } else {
+            0.0
+        };
+        s.anomaly_score = (1.0 - weight) * s.anomaly_score + weight * novelty;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Field values
+// ---------------------------------------------------------------------------
+
+/// Collect the value distribution of `field` across `records`.
+///
+/// Returns `None` if the field never occurs in a valid JSON object.
+pub fn field_values(records: &[JsonRecord], field: &str, max_examples: usize) -> Option<FieldValues> {
+    let mut types: HashMap<JsonType, usize> = HashMap::new();
+    let mut values: HashMap<String, ValueStats> = HashMap::new();
+    let mut present = 0usize;
+
+    for (idx, rec) in records.iter().enumerate() {
+        let Some(val) = rec.get(field) else { continue };
+        present += 1;
+        *types.entry(val.json_type()).or_insert(0) += 1;
+        let stats = values.entry(val.key().into_owned()).or_insert_with_key(|k| ValueStats {
+            value: k.clone(),
+            count: 0,
+            first_index: idx,
+            last_index: idx,
+            examples: Vec::new(),
         });
+        stats.count += 1;
+        stats.last_index = idx;
+        if stats.examples.len() < max_examples {
+            stats.examples.push(idx);
+        }
+    }
+
+    if present == 0 {
+        return None;
+    }
+
+    let mut type_counts: Vec<(JsonType, usize)> = types.into_iter().collect();
+    type_counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.to_string().cmp(&b.0.to_string())));
+    let mut values: Vec<ValueStats> = values.into_values().collect();
+    values.sort_by(|a, b| b.count.cmp(&a.count).then(a.first_index.cmp(&b.first_index)));
+
+    Some(FieldValues {
+        name: field.to_string(),
+        total_records: records.len(),
+        present_count: present,
+        type_counts,
+        values,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Field attribution
+// ---------------------------------------------------------------------------
+
+/// Rank fields by how much they contribute to anomalies.
+///
+/// Per-record explanations are tallied per field and turned into rates:
+/// missing (per valid record), rare values, type mismatches, unexpected
+/// nulls or empties and enum violations (per record where the field is
+/// present), and rare presence. These are combined with
+/// the same weights as `score_json_records`, with presence instability
+/// standing in for field-set novelty, since fields that are present only
+/// some of the time are what make field sets vary. Sorted descending.
+pub fn field_contributions(
+    schema: &SchemaProfile,
+    scored: &[JsonRecordScore],
+) -> Vec<FieldContribution> {
+    let mut out: HashMap<&str, FieldContribution> = schema
+        .fields
+        .iter()
+        .map(|(name, p)| {
+            (
+                &**name,
+                FieldContribution {
+                    name: name.clone(),
+                    presence_rate: p.presence_rate,
+                    missing_count: 0,
+                    rare_value_count: 0,
+                    extra_rare_count: 0,
+                    type_mismatch_count: 0,
+                    unexpected_null_count: 0,
+                    unexpected_empty_count: 0,
+                    enum_violation_count: 0,
+                    presence_instability: 4.0 * p.presence_rate * (1.0 - p.presence_rate),
+                    contribution: 0.0,
+                },
+            )
+        })
+        .collect();
+
+    for s in scored.iter().filter(|s| s.valid_json) {
+        for f in &s.missing_common {
+            if let Some(c) = out.get_mut(&**f) {
+                c.missing_count += 1;
+            }
+        }
+        for (f, _) in &s.rare_values {
+            if let Some(c) = out.get_mut(&**f) {
+                c.rare_value_count += 1;
+            }
+        }
+        for f in &s.extra_rare {
+            if let Some(c) = out.get_mut(&**f) {
+                c.extra_rare_count += 1;
+            }
+        }
+        for (f, _, _) in &s.type_mismatches {
+            if let Some(c) = out.get_mut(&**f) {
+                c.type_mismatch_count += 1;
+            }
+        }
+        for f in &s.unexpected_nulls {
+            if let Some(c) = out.get_mut(&**f) {
+                c.unexpected_null_count += 1;
+            }
+        }
+        for f in &s.unexpected_empties {
+            if let Some(c) = out.get_mut(&**f) {
+                c.unexpected_empty_count += 1;
+            }
+        }
+        for (f, _) in &s.enum_violations {
+            if let Some(c) = out.get_mut(&**f) {
+                c.enum_violation_count += 1;
+            }
+        }
     }
 
-    scores
+    let valid = schema.valid_records.max(1) as f64;
+    let mut ranked: Vec<FieldContribution> = out
+        .into_values()
+        .map(|mut c| {
+            let present = schema.fields[&c.name].present_count.max(1) as f64;
+            c.contribution = 0.30 * c.missing_count as f64 / valid
+                + 0.25 * c.rare_value_count as f64 / present
+                + 0.15 * c.presence_instability
+                + 0.10 * c.extra_rare_count as f64 / valid
+                + 0.10 * c.type_mismatch_count as f64 / present
+                + 0.10 * (c.unexpected_null_count + c.unexpected_empty_count) as f64 / present
+                + ENUM_VIOLATION_WEIGHT * c.enum_violation_count as f64 / present;
+            c
+        })
+        .collect();
+    ranked.sort_by(|a, b| {
+        b.contribution
+            .partial_cmp(&a.contribution)
+            .unwrap()
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    ranked
+}
+
+// ---------------------------------------------------------------------------
+// Group breakdown
+// ---------------------------------------------------------------------------
+
+/// Group label used for records that lack the group-by field.
+pub const MISSING_GROUP: &str = "(missing)";
+
+/// The value of top-level `field` in each record, as used for value counts.
+pub fn field_keys(records: &[JsonRecord], field: &str) -> Vec<Option<String>> {
+    records
+        .iter()
+        .map(|r| r.get(field).map(|v| v.key().into_owned()))
+        .collect()
+}
+
+/// A template label for a record: its sorted field names, with the values
+/// of low-cardinality fields (e.g. `event=login`). Invalid records map to
+/// `(invalid)`. Used to compare record kinds across sessions.
+pub fn record_template(rec: &JsonRecord, schema: &SchemaProfile) -> String {
+    let Some(map) = &rec.fields else {
+        return "(invalid)".to_string();
+    };
+    let mut parts: Vec<String> = map
+        .iter()
+        .map(|(k, v)| match schema.fields.get(&**k) {
+            Some(p) if p.is_low_cardinality => format!("{k}={}", v.key()),
+            _ => k.to_string(),
+        })
+        .collect();
+    parts.sort();
+    parts.join(",")
+}
+
+/// Tally records, anomalies and mean score per group key.
+///
+/// `keys` is aligned with `scored` (see [`field_keys`]). Groups are sorted
+/// by anomaly count, then anomaly rate, descending.
+pub fn group_breakdown(
+    keys: &[Option<String>],
+    scored: &[JsonRecordScore],
+    anomaly_indices: &[usize],
+) -> Vec<GroupStats> {
+    let flagged: HashSet<usize> = anomaly_indices.iter().copied().collect();
+    let mut groups: HashMap<&str, (usize, usize, f64)> = HashMap::new();
+    for (i, s) in scored.iter().enumerate() {
+        let key = keys.get(i).and_then(|k| k.as_deref()).unwrap_or(MISSING_GROUP);
+        let g = groups.entry(key).or_default();
+        g.0 += 1;
+        g.1 += flagged.contains(&i) as usize;
+        g.2 += s.anomaly_score;
+    }
+    let mut out: Vec<GroupStats> = groups
+        .into_iter()
+        .map(|(value, (records, anomalies, sum))| GroupStats {
+            value: value.to_string(),
+            records,
+            anomalies,
+            mean_score: sum / records as f64,
+        })
+        .collect();
+    out.sort_by(|a, b| {
+        b.anomalies
+            .cmp(&a.anomalies)
+            .then_with(|| b.anomaly_rate().partial_cmp(&a.anomaly_rate()).unwrap())
+            .then_with(|| a.value.cmp(&b.value))
+    });
+    out
 }
 
 // ---------------------------------------------------------------------------
@@ -544,6 +1451,40 @@ mod tests {
         assert!(!recs[2].parse_error);
     }
 
+    #[test]
+    fn parse_limited_truncates_or_skips() {
+        let blob = format!(r#"{{"level":"INFO","dump":"{}"}}"#, "x".repeat(100));
+        let data = json_lines(&[r#"{"level":"INFO"}"#, &blob, r#"{"level":"WARN"}"#]);
+        let limit = |policy| Some(RecordLimit { max_bytes: 32, policy });
+        let recs = parse_json_records_limited(&data, b'\n', limit(Oversize::Truncate));
+        assert_eq!(recs.len(), 3);
+        assert!(recs[1].parse_error && recs[1].fields.is_none());
+        assert_eq!((recs[1].offset, recs[1].length), (17, 32));
+        let recs = parse_json_records_limited(&data, b'\n', limit(Oversize::Skip));
+        assert_eq!(recs.iter().map(|r| r.offset).collect::<Vec<_>>(), [0, 17 + blob.len() + 1]);
+    }
+
+    #[test]
+    fn record_parser_matches_pull_parser() {
+        let deep = format!(r#"{{"a":{}{}}}"#, "[".repeat(130), "]".repeat(130));
+        let lines = [
+            r#"{"msg":"café","n":-0,"f":1.50,"e":1E+2,"big":123456789012345678901}"#,
+            r#"{"a":1,"a":"two","tags":[1,"x\ty",{"b":2,"a":null}],"ok":false}"#,
+            r#"{"a":"\ud800"}"#, r#"{"a":1e400}"#, r#"{"a":[1,]}"#, r#"[1,2]"#, &deep,
+            "{\"a\":\"\t\"}", r#"{"a":"x\/y\"z","b\u00e9":"\ud83d\ude00"}"#,
+        ];
+        let mut parser = RecordParser::default();
+        for line in lines {
+            let ours = parser.parse(line.as_bytes());
+            let pull = parse_record(line).ok();
+            assert_eq!(ours.is_some(), pull.is_some(), "{line}");
+            let keys = |f: Option<Option<JsonObject>>| -> Option<Vec<(String, String)>> {
+                f.flatten().map(|f| f.iter().map(|(k, v)| (k.to_string(), v.key().into_owned())).collect())
+            };
+            assert_eq!(keys(ours), keys(pull), "{line}");
+        }
+    }
+
     #[test]
     fn looks_like_json_detect() {
         assert!(looks_like_json(br#"{"key":"value"}"#));
@@ -551,6 +1492,22 @@ mod tests {
         assert!(!looks_like_json(b""));
     }
 
+    #[test]
+    fn mixed_input_is_split_by_format() {
+        let data = b"Feb 16 boot\n{\"a\":1}\n\n{\"a\":2}\nplain [1]\n";
+        assert!(is_mixed_format(data));
+        assert!(!is_mixed_format(b"{\"a\":1}\n{\"a\":2}\n"));
+        let mostly_json: Vec<u8> = (0..40).flat_map(|_| b"{\"a\":1}\n".iter().copied())
+            .chain(b"torn line\n".iter().copied()).collect();
+        assert!(!is_mixed_format(&mostly_json));
+
+        let (json, plain) = split_by_format(data, b'\n');
+        assert_eq!((json.index.clone(), plain.index.clone()), (vec![1, 3], vec![0, 4]));
+        assert_eq!(json.data, b"{\"a\":1}\n{\"a\":2}\n");
+        assert_eq!((json.orig_index(8), json.orig_offset(8)), (3, 21));
+        assert_eq!(plain.dropped(), 3);
+    }
+
     #[test]
     fn schema_field_presence() {
         let data = json_lines(&[
@@ -580,11 +1537,8 @@ mod tests {
 
     #[test]
     fn missing_field_scores_high() {
-        let mut lines: Vec<&str> = Vec::new();
         let normal = r#"{"level":"INFO","service":"app","msg":"ok"}"#;
-        for _ in 0..20 {
-            lines.push(normal);
-        }
+        let mut lines: Vec<&str> = vec![normal; 20];
         // Missing "service" and "msg"
         lines.push(r#"{"level":"ERROR"}"#);
 
@@ -606,30 +1560,51 @@ mod tests {
 
     #[test]
     fn rare_value_detected() {
-        let mut lines: Vec<&str> = Vec::new();
-        for _ in 0..200 {
-            lines.push(r#"{"level":"INFO","msg":"ok"}"#);
-        }
-        lines.push(r#"{"level":"FATAL","msg":"segfault"}"#);
+        // First, so that `level` is not a closed set without FATAL.
+        let mut lines: Vec<&str> = vec![r#"{"level":"FATAL","msg":"segfault"}"#];
+        lines.extend([r#"{"level":"INFO","msg":"ok"}"#; 200]);
 
         let data = json_lines(&lines);
         let recs = parse_json_records(&data, b'\n');
         let schema = build_schema(&recs);
         let scored = score_json_records(&data, &recs, &schema);
 
-        let anomaly = &scored[200];
+        let anomaly = &scored[0];
         assert!(
-            anomaly.rare_values.iter().any(|(f, _)| f == "level"),
+            anomaly.rare_values.iter().any(|(f, _)| &**f == "level"),
             "expected 'level' in rare_values"
         );
     }
 
     #[test]
-    fn type_mismatch_detected() {
-        let mut lines: Vec<&str> = Vec::new();
-        for _ in 0..20 {
-            lines.push(r#"{"status":200,"msg":"ok"}"#);
+    fn names_are_interned() {
+        let mut lines: Vec<&str> = vec![r#"{"level":"WARN","extra":1}"#];
+        lines.extend([r#"{"level":"INFO","msg":"ok"}"#; 200]);
+        let data = json_lines(&lines);
+        let recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+
+        let (key, level) = schema.fields.get_key_value("level").unwrap();
+        assert!(Arc::ptr_eq(key, &level.name));
+        for set in schema.field_set_counts.keys() {
+            let name = set.iter().find(|n| &***n == "level").unwrap();
+            assert!(Arc::ptr_eq(name, key));
         }
+        let msg = &schema.fields["msg"];
+        let (ok, _) = msg.value_counts.get_key_value("ok").unwrap();
+
+        let scored = score_json_records(&data, &recs, &schema);
+        let missing = scored[0].missing_common.iter().find(|n| &***n == "msg").unwrap();
+        assert!(Arc::ptr_eq(missing, &msg.name));
+        let (f, v) = scored[0].rare_values.iter().find(|(f, _)| &**f == "level").unwrap();
+        assert!(Arc::ptr_eq(f, key));
+        assert_eq!(&**v, "WARN");
+        assert!(Arc::ptr_eq(msg.value_name("ok").as_ref().unwrap(), ok));
+    }
+
+    #[test]
+    fn type_mismatch_detected() {
+        let mut lines: Vec<&str> = vec![r#"{"status":200,"msg":"ok"}"#; 20];
         // status is string instead of number
         lines.push(r#"{"status":"error","msg":"fail"}"#);
 
@@ -640,17 +1615,405 @@ mod tests {
 
         let anomaly = &scored[20];
         assert!(
-            anomaly.type_mismatches.iter().any(|(f, _, _)| f == "status"),
+            anomaly.type_mismatches.iter().any(|(f, _, _)| &**f == "status"),
             "expected type mismatch on 'status'"
         );
     }
 
     #[test]
-    fn integration_with_detect_indices() {
-        let mut lines: Vec<&str> = Vec::new();
-        for _ in 0..50 {
-            lines.push(r#"{"level":"INFO","service":"app","msg":"request handled"}"#);
+    fn null_is_scored_apart_from_values_and_types() {
+        let mut lines: Vec<String> = (0..200)
+            .map(|i| {
+                let parent = if i % 2 == 0 { "null" } else { r#""p1""# };
+                format!(r#"{{"user":"alice","parent":{parent}}}"#)
+            })
+            .collect();
+        lines.push(r#"{"user":null,"parent":null}"#.to_string());
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+
+        let user = &schema.fields["user"];
+        assert_eq!((user.present_count, user.null_count), (201, 1));
+        assert_eq!(user.dominant_type, JsonType::String);
+        assert!(!user.value_counts.contains_key("null"));
+        assert_eq!(schema.fields["parent"].dominant_type, JsonType::String);
+
+        let scored = score_json_records(&data, &recs, &schema);
+        let s = &scored[200];
+        assert_eq!(s.unexpected_nulls, vec![user.name.clone()]);
+        assert!(s.rare_values.is_empty() && s.type_mismatches.is_empty());
+        assert!(s.missing_common.is_empty());
+        assert!(s.anomaly_score > scored[0].anomaly_score);
+        assert!(scored[..200].iter().all(|s| s.unexpected_nulls.is_empty()));
+
+        let ranked = field_contributions(&schema, &scored);
+        let user = ranked.iter().find(|c| &*c.name == "user").unwrap();
+        assert_eq!(user.unexpected_null_count, 1);
+    }
+
+    #[test]
+    fn empty_values_in_populated_fields_are_flagged() {
+        let mut lines: Vec<String> = (0..200)
+            .map(|i| {
+                let note = if i % 2 == 0 { "" } else { "n" };
+                format!(r#"{{"msg":"m{}","note":"{note}","tags":["a"],"user":"u{i}"}}"#, i % 5)
+            })
+            .collect();
+        lines.push(r#"{"msg":"","note":"","tags":[ ],"user":""}"#.to_string());
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+        assert!(!schema.fields["user"].is_low_cardinality);
+        assert_eq!(schema.fields["tags"].empty_count, 1);
+        assert_eq!(schema.fields["note"].empty_count, 101);
+
+        let scored = score_json_records(&data, &recs, &schema);
+        let s = &scored[200];
+        let empties: Vec<&str> = s.unexpected_empties.iter().map(|f| &**f).collect();
+        assert_eq!(empties, ["msg", "tags", "user"]);
+        assert!(s.rare_values.is_empty() && s.unexpected_nulls.is_empty());
+        assert!(scored[..200].iter().all(|s| s.unexpected_empties.is_empty()));
+
+        let ranked = field_contributions(&schema, &scored);
+        let tags = ranked.iter().find(|c| &*c.name == "tags").unwrap();
+        assert_eq!(tags.unexpected_empty_count, 1);
+    }
+
+    #[test]
+    fn enum_violations_outrank_rare_values() {
+        let states = ["RUNNING", "PENDING", "DONE"];
+        let mut lines: Vec<String> = (0..600)
+            .map(|i| {
+                let ok = i % 150 != 7;
+                format!(r#"{{"state":"{}","ok":{ok},"req":"r{i}"}}"#, states[i % 3])
+            })
+            .collect();
+        lines[500] = r#"{"state":"TERMINATED","ok":true,"req":"r500"}"#.to_string();
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+        assert!(schema.fields["state"].enum_values.is_some());
+
+        let scored = score_json_records(&data, &recs, &schema);
+        assert!(scored[157].rare_values.iter().any(|(f, v)| (&**f, &**v) == ("ok", "false")));
+        let top = scored.iter().max_by(|a, b| a.anomaly_score.partial_cmp(&b.anomaly_score).unwrap()).unwrap();
+        assert_eq!(top.index, 500);
+        assert_eq!(top.enum_violations.len(), 1);
+        assert!(top.anomaly_score > scored[157].anomaly_score + 0.4);
+
+        let ranked = field_contributions(&schema, &scored);
+        assert_eq!(ranked.iter().find(|c| &*c.name == "state").unwrap().enum_violation_count, 1);
+    }
+
+    #[test]
+    fn closed_sets_flag_later_values() {
+        let levels = ["INFO", "WARN", "ERROR"];
+        let mut lines: Vec<String> = (0..400)
+            .map(|i| format!(r#"{{"level":"{}","req":"r{i}","code":{}}}"#, levels[i % 3], i % 7))
+            .collect();
+        // A new level late in the file; a new code after codes kept growing.
+        lines[300] = r#"{"level":"FATAL","req":"r300","code":0}"#.to_string();
+        lines[150] = r#"{"level":"INFO","req":"r150","code":99}"#.to_string();
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+
+        let level = schema.fields["level"].enum_values.as_ref().unwrap();
+        assert_eq!(level.len(), 3);
+        assert!(!level.contains("FATAL"));
+        assert!(schema.fields["code"].enum_values.is_none());
+        assert!(schema.fields["req"].enum_values.is_none());
+
+        let scored = score_json_records(&data, &recs, &schema);
+        let s = &scored[300];
+        assert_eq!(s.enum_violations.len(), 1);
+        assert_eq!((&*s.enum_violations[0].0, &*s.enum_violations[0].1), ("level", "FATAL"));
+        assert!(s.rare_values.iter().all(|(f, _)| &**f != "level"));
+        assert!(scored.iter().filter(|s| !s.enum_violations.is_empty()).count() == 1);
+        assert!(s.anomaly_score > scored[299].anomaly_score);
+
+        // Merging keeps the set only where both sides learned one.
+        let other = build_schema(&[]);
+        let merged = merge_schemas(&schema, &other);
+        assert_eq!(merged.fields["level"].enum_values.as_ref(), Some(level));
+        let mut no_enum = build_schema(&recs[..10]);
+        no_enum.fields.get_mut("level").unwrap().enum_values = None;
+        assert!(merge_schemas(&schema, &no_enum).fields["level"].enum_values.is_none());
+    }
+
+    #[test]
+    fn windowed_schema_follows_the_input() {
+        // The format changes halfway; with a window, the new format is
+        // normal once the window has moved past the change.
+        let lines: Vec<String> = (0..400)
+            .map(|i| if i < 200 { format!(r#"{{"a":{}}}"#, i % 5) } else { format!(r#"{{"b":{}}}"#, i % 5) })
+            .collect();
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let whole = score_json_records(&data, &recs, &build_schema(&recs));
+        let windowed = score_json_records_windowed(&data, &recs, &FieldWeights::default(), 100);
+        assert_eq!(windowed.len(), 400);
+        assert!(windowed.iter().enumerate().all(|(i, s)| s.index == i));
+        assert!(windowed[210].anomaly_score > windowed[399].anomaly_score);
+        assert!(windowed[399].anomaly_score < whole[399].anomaly_score);
+        assert!(windowed[399].extra_rare.is_empty() && windowed[399].missing_common.is_empty());
+    }
+
+    #[test]
+    fn chunked_schema_fades_old_fields() {
+        let lines: Vec<String> = (0..400)
+            .map(|i| if i < 200 { format!(r#"{{"a":{}}}"#, i % 5) } else { format!(r#"{{"b":{}}}"#, i % 5) })
+            .collect();
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let w = FieldWeights::default();
+        let rebuilt = score_json_records_chunked(&data, &recs, &w, 50, 0.0);
+        let faded = score_json_records_chunked(&data, &recs, &w, 50, 0.5);
+        let kept = score_json_records_chunked(&data, &recs, &w, 50, 1.0);
+        assert!(rebuilt.iter().enumerate().all(|(i, s)| s.index == i));
+        // By the last chunk, "a" is gone from the rebuilt schema, faint in
+        // the faded one, and half the records in the kept one.
+        assert!(rebuilt[399].anomaly_score < faded[399].anomaly_score);
+        assert!(faded[399].anomaly_score < kept[399].anomaly_score);
+        assert!(faded[200].anomaly_score > faded[399].anomaly_score);
+    }
+
+    #[test]
+    fn first_occurrences_are_flagged_after_warmup() {
+        let mut lines: Vec<String> = (0..200)
+            .map(|i| format!(r#"{{"level":"{}","req":"r{i}"}}"#, if i % 2 == 0 { "INFO" } else { "WARN" }))
+            .collect();
+        lines[5] = r#"{"level":"DEBUG","req":"r5"}"#.to_string();
+        lines[120] = r#"{"level":"INFO","req":"r120","retry":1}"#.to_string();
+        lines[150] = r#"{"level":"FATAL","req":"r150"}"#.to_string();
+        lines[160] = r#"{"level":"INFO","req":"r160","retry":1}"#.to_string();
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+        let mut scored = score_json_records(&data, &recs, &schema);
+        let before = scored[150].anomaly_score;
+        add_first_seen(&mut scored, &recs, &schema, &FieldWeights::default(), 0.5);
+
+        let flagged: Vec<(usize, String)> = scored
+            .iter()
+            .flat_map(|s| s.first_seen.iter().map(move |(f, v)| (s.index, format!("{f}={v:?}"))))
+            .collect();
+        // DEBUG falls in the warm-up; req is high-cardinality.
+        assert_eq!(flagged, [(120, "retry=None".into()), (150, "level=Some(\"FATAL\")".into())]);
+        assert!((scored[150].anomaly_score - (0.5 * before + 0.25)).abs() < 1e-9);
+        assert!(scored[150].anomaly_score > scored[151].anomaly_score);
+    }
+
+    #[test]
+    fn field_contributions_rank_mismatching_field_first() {
+        let mut lines: Vec<String> = Vec::new();
+        for i in 0..100 {
+            let status = if i % 4 == 0 { r#""err""#.to_string() } else { "200".to_string() };
+            lines.push(format!(r#"{{"level":"INFO","status":{status},"msg":"ok"}}"#));
+        }
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+        let scored = score_json_records(&data, &recs, &schema);
+
+        let ranked = field_contributions(&schema, &scored);
+        assert_eq!(&*ranked[0].name, "status");
+        assert_eq!(ranked[0].type_mismatch_count, 25);
+        assert_eq!(ranked.len(), schema.fields.len());
+        for w in ranked.windows(2) {
+            assert!(w[0].contribution >= w[1].contribution);
         }
+    }
+
+    #[test]
+    fn field_values_distribution() {
+        let data = json_lines(&[
+            r#"{"level":"INFO"}"#,
+            r#"{"level":"WARN"}"#,
+            r#"{"msg":"no level"}"#,
+            r#"{"level":"INFO"}"#,
+            r#"{"level":3}"#,
+            r#"{"level":"INFO"}"#,
+        ]);
+        let recs = parse_json_records(&data, b'\n');
+        let fv = field_values(&recs, "level", 2).unwrap();
+        assert_eq!(fv.present_count, 5);
+        assert_eq!(fv.type_counts[0], (JsonType::String, 4));
+        let info = &fv.values[0];
+        assert_eq!((info.value.as_str(), info.count), ("INFO", 3));
+        assert_eq!((info.first_index, info.last_index), (0, 5));
+        assert_eq!(info.examples, vec![0, 3]);
+        assert!(field_values(&recs, "nope", 2).is_none());
+    }
+
+    #[test]
+    fn normalize_space_values_collapses_padding() {
+        let data = json_lines(&[
+            r#"{"status":"ok  ","tags":["a \t b"],"n":1}"#,
+            r#"{"status":"ok","tags":["a b"],"n":1}"#,
+        ]);
+        let mut recs = parse_json_records(&data, b'\n');
+        normalize_space_values(&mut recs);
+        assert_eq!(recs[0].fields, recs[1].fields);
+        assert_eq!(recs[0].get("tags").unwrap().key(), r#"["a b"]"#);
+        assert_eq!(recs[1].offset, 42);
+    }
+
+    #[test]
+    fn field_filter_applies_to_schema_and_scoring() {
+        let mut lines: Vec<String> = (0..30)
+            .map(|i| format!(r#"{{"level":"INFO","request_id":"r{i}","msg":"ok"}}"#))
+            .collect();
+        lines.push(r#"{"level":"INFO","msg":"ok"}"#.to_string());
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+
+        let mut recs = parse_json_records(&data, b'\n');
+        let filter = FieldFilter {
+            ignore: ["request_id".to_string()].into(),
+            only: None,
+        };
+        filter.apply(&mut recs);
+        let schema = build_schema(&recs);
+        assert!(!schema.fields.contains_key("request_id"));
+        let scored = score_json_records(&data, &recs, &schema);
+        assert!(scored[30].missing_common.is_empty());
+
+        let only = FieldFilter {
+            ignore: HashSet::new(),
+            only: Some(["level".to_string()].into()),
+        };
+        assert!(only.allows("level"));
+        assert!(!only.allows("msg"));
+    }
+
+    #[test]
+    fn numbers_share_value_keys() {
+        let mut lines: Vec<&str> = vec![r#"{"code":1,"ms":12.3456}"#; 100];
+        lines.push(r#"{"code":1.0,"ms":12.3461}"#);
+        lines.push(r#"{"code":1e0,"ms":12.35}"#);
+        let data = json_lines(&lines);
+        let mut recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+        assert_eq!(schema.fields["code"].value_counts.len(), 1);
+        assert_eq!(schema.fields["ms"].value_counts.len(), 3);
+
+        round_number_values(&mut recs, 2);
+        let schema = build_schema(&recs);
+        assert_eq!(schema.fields["ms"].value_counts.len(), 1);
+        assert_eq!(schema.fields["ms"].value_counts.get("12.35"), Some(&102));
+        let scored = score_json_records(&data, &recs, &schema);
+        assert!(scored[100].rare_values.is_empty() && scored[101].rare_values.is_empty());
+    }
+
+    #[test]
+    fn structure_limit_cuts_wide_and_deep_records() {
+        let data = json_lines(&[
+            r#"{"a":1,"b":[1,2],"c":{"d":{"e":[3]}},"z":"x"}"#,
+            r#"{"a":2,"b":[[1]]}"#,
+            "not json",
+        ]);
+        let mut recs = parse_json_records(&data, b'\n');
+        let limit = StructureLimit { max_depth: Some(2), max_fields: Some(3) };
+        let cut = limit.apply(&mut recs);
+        assert_eq!(cut, Truncations { wide_records: 1, dropped_fields: 1, deep_values: 2 });
+        assert!(recs[0].get("z").is_none());
+        assert_eq!(recs[0].get("b").unwrap().key(), "[1,2]");
+        assert_eq!(recs[0].get("c").unwrap().key(), "{...}");
+        assert_eq!(recs[1].get("b").unwrap().key(), "[...]");
+        assert_eq!(recs[1].get("b").unwrap().json_type(), JsonType::Array);
+        assert_eq!(StructureLimit::default().apply(&mut recs), Truncations::default());
+    }
+
+    #[test]
+    fn field_weights_rank_important_fields_higher() {
+        let mut lines: Vec<String> = (0..200)
+            .map(|_| r#"{"level":"INFO","ua":"curl","msg":"ok"}"#.to_string())
+            .collect();
+        lines.push(r#"{"level":"FATAL","ua":"curl","msg":"ok"}"#.to_string());
+        lines.push(r#"{"level":"INFO","ua":"wget","msg":"ok"}"#.to_string());
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+
+        let flat = score_json_records(&data, &recs, &schema);
+        assert!((flat[200].anomaly_score - flat[201].anomaly_score).abs() < 1e-9);
+
+        let weights = FieldWeights::parse("level=3.0, ua=0.5 # comment").unwrap();
+        let scored = score_json_records_weighted(&data, &recs, &schema, &weights);
+        assert!(scored[200].anomaly_score > scored[201].anomaly_score);
+
+        assert!(FieldWeights::parse("level").is_err());
+        assert!(FieldWeights::parse("level=-1").is_err());
+        assert_eq!(FieldWeights::parse("a=2\nb=0.5\n").unwrap().get("c"), 1.0);
+    }
+
+    #[test]
+    fn group_breakdown_attributes_anomalies() {
+        let mut lines: Vec<String> = Vec::new();
+        for i in 0..40 {
+            let svc = if i % 2 == 0 { "api" } else { "db" };
+            lines.push(format!(r#"{{"service":"{svc}","level":"INFO"}}"#));
+        }
+        lines.push(r#"{"service":"db","level":"FATAL"}"#.to_string());
+        lines.push(r#"{"service":"db","level":"FATAL","err":"x"}"#.to_string());
+        lines.push(r#"{"level":"INFO"}"#.to_string());
+        let refs: Vec<&str> = lines.iter().map(|s| s.as_str()).collect();
+        let data = json_lines(&refs);
+        let recs = parse_json_records(&data, b'\n');
+        let schema = build_schema(&recs);
+        let scored = score_json_records(&data, &recs, &schema);
+
+        let keys = field_keys(&recs, "service");
+        let groups = group_breakdown(&keys, &scored, &[40, 41, 42]);
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[0].value, "db");
+        assert_eq!((groups[0].records, groups[0].anomalies), (22, 2));
+        assert_eq!(groups[1].value, MISSING_GROUP);
+        assert_eq!(groups[1].anomaly_rate(), 1.0);
+        assert_eq!(groups[2].anomalies, 0);
+        assert!(groups[0].mean_score > groups[2].mean_score);
+
+        assert_eq!(record_template(&recs[0], &schema), "level=INFO,service=api");
+    }
+
+    #[test]
+    fn merged_schema_matches_combined_build() {
+        let a: Vec<&str> = vec![r#"{"level":"INFO","n":1}"#; 30];
+        let b: Vec<&str> = vec![r#"{"level":"WARN","x":"s"}"#; 10];
+        let (da, db) = (json_lines(&a), json_lines(&b));
+        let mut both = da.clone();
+        both.extend_from_slice(&db);
+
+        let sa = build_schema(&parse_json_records(&da, b'\n'));
+        let sb = build_schema(&parse_json_records(&db, b'\n'));
+        let merged = merge_schemas(&sa, &sb);
+        let direct = build_schema(&parse_json_records(&both, b'\n'));
+
+        assert_eq!(merged.total_records, direct.total_records);
+        assert_eq!(merged.field_set_counts, direct.field_set_counts);
+        assert_eq!(merged.common_field_set, direct.common_field_set);
+        for (k, f) in &direct.fields {
+            let m = &merged.fields[k];
+            assert_eq!(m.present_count, f.present_count);
+            assert_eq!(m.value_counts, f.value_counts);
+            assert!((m.presence_rate - f.presence_rate).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn integration_with_detect_indices() {
+        let mut lines: Vec<&str> = vec![r#"{"level":"INFO","service":"app","msg":"request handled"}"#; 50];
         lines.push(r#"{"level":"FATAL","error_code":42}"#);
 
         let data = json_lines(&lines);
@@ -0,0 +1,985 @@
+//! Borrowed JSON records.
+//!
+//! JSON mode only looks at a record's top-level fields: their names, types
+//! and value keys. Parsing each line into an owned `serde_json::Value` tree
+//! allocated several times the input size. The pull parser here validates
+//! a record as strictly as `serde_json` does, but field names, string
+//! values and number literals borrow from the input; only strings with
+//! escapes are copied, and nested arrays and objects are kept as raw text
+//! until something asks for their value key.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use serde_json::{Number, Value};
+
+use crate::json_analyzer::JsonType;
+use crate::records::OffsetMap;
+
+/// Nesting depth at which parsing fails, as in `serde_json`.
+const MAX_DEPTH: usize = 128;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// A top-level field value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue<'a> {
+    Null,
+    Bool(bool),
+    /// A number literal, as written (or as `serde_json` would write it,
+    /// from the `simd-json` parser).
+    Number(Cow<'a, str>),
+    String(Cow<'a, str>),
+    /// An array, as raw JSON text.
+    Array(Cow<'a, str>),
+    /// A nested object, as raw JSON text.
+    Object(Cow<'a, str>),
+}
+
+/// JSON rewritten one record per line, and where each byte came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reflowed {
+    pub data: Vec<u8>,
+    /// Offsets of `data` in the input. A line's delimiter maps to the byte
+    /// after its record.
+    pub map: OffsetMap,
+}
+
+impl Reflowed {
+    fn with_capacity(n: usize) -> Self {
+        Reflowed { data: Vec::with_capacity(n), map: OffsetMap::default() }
+    }
+
+    /// Copy the JSON value `data[range]` onto a line of its own, without
+    /// the whitespace between tokens.
+    fn push_value(&mut self, data: &[u8], range: Range<usize>) {
+        self.map.mark(self.data.len(), range.start);
+        let Reflowed { data: out, map } = self;
+        minify(&data[range.clone()], out, |at, i| map.mark(at, range.start + i));
+        self.end_line(range.end);
+    }
+
+    /// Copy the text `data[range]` onto a line of its own, trimmed. Blank
+    /// text is left out.
+    fn push_line(&mut self, data: &[u8], range: Range<usize>) {
+        let text = &data[range.clone()];
+        let start = range.start + (text.len() - text.trim_ascii_start().len());
+        let text = text.trim_ascii();
+        if text.is_empty() {
+            return;
+        }
+        self.map.mark(self.data.len(), start);
+        self.data.extend_from_slice(text);
+        self.end_line(start + text.len());
+    }
+
+    /// End the line, its delimiter standing for the input byte at `orig`.
+    fn end_line(&mut self, orig: usize) {
+        self.map.mark(self.data.len(), orig);
+        self.data.push(b'\n');
+    }
+}
+
+/// Top-level fields of a record, sorted by name. A repeated name keeps its
+/// last value, as in `serde_json`.
+pub type JsonObject<'a> = Vec<(Cow<'a, str>, JsonValue<'a>)>;
+
+/// A record that is not valid JSON; `offset` is where parsing stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub offset: usize,
+}
+
+impl JsonValue<'_> {
+    pub fn json_type(&self) -> JsonType {
+        match self {
+            JsonValue::Null => JsonType::Null,
+            JsonValue::Bool(_) => JsonType::Bool,
+            JsonValue::Number(_) => JsonType::Number,
+            JsonValue::String(_) => JsonType::String,
+            JsonValue::Array(_) => JsonType::Array,
+            JsonValue::Object(_) => JsonType::Object,
+        }
+    }
+
+    /// Whether the value is an empty string, array or object.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            JsonValue::String(s) => s.is_empty(),
+            JsonValue::Array(raw) | JsonValue::Object(raw) => {
+                raw.len() >= 2 && raw[1..raw.len() - 1].trim_matches([' ', '\t', '\n', '\r']).is_empty()
+            }
+            _ => false,
+        }
+    }
+
+    /// The value as a count key: strings as themselves, numbers in
+    /// [`canonical_number`] form, and everything else in `serde_json`'s
+    /// compact form (`true`, `{"a":1,"b":2}`). Strings and plain integers
+    /// are borrowed.
+    pub fn key(&self) -> Cow<'_, str> {
+        match self {
+            JsonValue::Null => "null".into(),
+            JsonValue::Bool(b) => if *b { "true" } else { "false" }.into(),
+            JsonValue::Number(n) => canonical_number(n),
+            JsonValue::String(s) => Cow::Borrowed(s),
+            JsonValue::Array(raw) | JsonValue::Object(raw) => serde_json::from_str::<Value>(raw)
+                .map_or_else(|_| Cow::Borrowed(&**raw), |v| v.to_string().into()),
+        }
+    }
+}
+
+/// Integral floats below this print as integers; above it, not every
+/// integer is representable.
+const MAX_EXACT_FLOAT: f64 = 9_007_199_254_740_992.0;
+
+/// Whether `n` is already canonical: an integer literal that fits in 64
+/// bits, other than `-0`.
+fn is_plain_integer(n: &str) -> bool {
+    n != "-0" && (n.parse::<i64>().is_ok() || n.parse::<u64>().is_ok())
+}
+
+/// One text per numeric value, so `1`, `1.0` and `1e0` count as the same
+/// value: integer literals as written, integral floats as integers, and
+/// other floats in shortest round-trip form (`1.50` → `1.5`, `1e-7`).
+pub fn canonical_number(n: &str) -> Cow<'_, str> {
+    if is_plain_integer(n) {
+        return Cow::Borrowed(n);
+    }
+    let Ok(v) = n.parse::<f64>() else {
+        return Cow::Borrowed(n);
+    };
+    if v.fract() == 0.0 && v.abs() < MAX_EXACT_FLOAT {
+        return (v as i64).to_string().into();
+    }
+    Number::from_f64(v).map_or(Cow::Borrowed(n), |x| x.to_string().into())
+}
+
+/// `n` rounded to `places` decimal places, in [`canonical_number`] form.
+/// Integers and floats too large to have a fractional part are unchanged.
+pub fn round_number(n: &str, places: usize) -> Cow<'_, str> {
+    match n.parse::<f64>() {
+        Ok(v) if !is_plain_integer(n) && v.abs() < MAX_EXACT_FLOAT => {
+            let fixed = format!("{v:.places$}");
+            let trimmed = if fixed.contains('.') {
+                fixed.trim_end_matches('0').trim_end_matches('.')
+            } else {
+                &fixed
+            };
+            // Negative values that round to zero print as "-0".
+            let s = if trimmed == "-0" { "0" } else { trimmed };
+            s.to_string().into()
+        }
+        _ => canonical_number(n),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parse
+// ---------------------------------------------------------------------------
+
+/// Parse one record. Returns its fields if it is a JSON object and `None`
+/// if it is some other JSON value; anything `serde_json` would reject is a
+/// [`SyntaxError`].
+pub fn parse_record(s: &str) -> Result<Option<JsonObject<'_>>, SyntaxError> {
+    let mut p = Parser { s, b: s.as_bytes(), pos: 0, depth: 0 };
+    p.ws();
+    let fields = if p.peek() == Some(b'{') {
+        Some(p.top_object()?)
+    } else {
+        p.skip_value()?;
+        None
+    };
+    p.ws();
+    if p.pos != p.b.len() {
+        return Err(p.err());
+    }
+    Ok(fields)
+}
+
+/// How deeply arrays and objects nest in the JSON text `raw`: 0 for a
+/// scalar, 1 for `[1,2]`, 2 for `{"a":[1]}`.
+pub fn nesting_depth(raw: &str) -> usize {
+    let (mut depth, mut max) = (0usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for b in raw.bytes() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            b'[' | b'{' if !in_string => {
+                depth += 1;
+                max = max.max(depth);
+            }
+            b']' | b'}' if !in_string => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max
+}
+
+/// Whether `s` is a single JSON object.
+pub fn is_object(s: &str) -> bool {
+    matches!(parse_record(s), Ok(Some(_)))
+}
+
+/// One line per record for pretty-printed JSON: if the first JSON object
+/// in `data` spans several lines, each object is copied onto one line with
+/// the whitespace between tokens removed. Text between objects becomes
+/// lines of its own, and a final unbalanced object is kept as it is so it
+/// still reads as a parse error. `None` for input that is already one
+/// record per line.
+pub fn join_pretty_records(data: &[u8]) -> Option<Reflowed> {
+    let first = data.iter().position(|b| !b.is_ascii_whitespace()).filter(|&i| data[i] == b'{')?;
+    let end = value_end(data, first)?;
+    if !data[first..end].contains(&b'\n') {
+        return None;
+    }
+
+    let mut out = Reflowed::with_capacity(data.len());
+    let mut pos = first;
+    while pos < data.len() {
+        match data[pos] {
+            b if b.is_ascii_whitespace() => pos += 1,
+            b'{' => {
+                let end = value_end(data, pos).unwrap_or(data.len());
+                out.push_value(data, pos..end);
+                pos = end;
+            }
+            _ => {
+                let end = line_end(data, pos);
+                out.push_line(data, pos..end);
+                pos = end;
+            }
+        }
+    }
+    Some(out)
+}
+
+/// One line per element for input that is a single top-level JSON array
+/// starting with an object, as API exports often are. Elements are read in
+/// order and each is copied onto its own line without the whitespace
+/// between tokens; the array itself is never built. A truncated export
+/// keeps its elements up to the cut, the unfinished one reading as a parse
+/// error; text after the closing bracket becomes lines of its own. `None`
+/// if `data` is not such an array.
+pub fn split_json_array(data: &[u8]) -> Option<Reflowed> {
+    let skip_ws = |from: usize| data[from..].iter().position(|b| !b.is_ascii_whitespace()).map(|i| from + i);
+    let open = skip_ws(0).filter(|&i| data[i] == b'[')?;
+    let first = skip_ws(open + 1).filter(|&i| data[i] == b'{')?;
+    let mut probe = Vec::new();
+    minify(&data[first..value_end(data, first)?], &mut probe, |_, _| {});
+    if !std::str::from_utf8(&probe).is_ok_and(is_object) {
+        return None;
+    }
+
+    let mut out = Reflowed::with_capacity(data.len());
+    let mut pos = first;
+    while pos < data.len() && data[pos] != b']' {
+        if data[pos] == b',' || data[pos].is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+        let end = element_end(data, pos);
+        out.push_value(data, pos..end);
+        pos = end;
+    }
+    let mut pos = pos + 1;
+    while pos < data.len() {
+        let end = line_end(data, pos);
+        out.push_line(data, pos..end);
+        pos = end + 1;
+    }
+    Some(out)
+}
+
+/// One line per record for concatenated JSON, objects written back to
+/// back with no newline between them (`{"a":1}{"a":2}`), as some emitters
+/// and streaming APIs do. Objects are segmented with `serde_json`'s
+/// streaming deserializer and each is copied onto its own line without the
+/// whitespace between tokens. Text that is not an object, and an object
+/// that does not parse, is kept as it is up to the end of its line. `None`
+/// if no line has an object followed by another on the same line.
+pub fn split_concatenated_records(data: &[u8]) -> Option<Reflowed> {
+    let glued = |hit: usize| {
+        let start = data[..hit].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let line = &data[start..];
+        let Some(first) = line.iter().position(|b| !b.is_ascii_whitespace()).filter(|&i| line[i] == b'{') else {
+            return false;
+        };
+        value_end(line, first).is_some_and(|end| end == hit - start + 1)
+    };
+    memchr::memmem::find_iter(data, b"}{").find(|&hit| glued(hit))?;
+
+    let mut out = Reflowed::with_capacity(data.len() + data.len() / 64);
+    let mut pos = 0;
+    while pos < data.len() {
+        if data[pos].is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+        if data[pos] == b'{' {
+            let mut stream = serde_json::Deserializer::from_slice(&data[pos..]).into_iter::<Value>();
+            if let Some(Ok(_)) = stream.next() {
+                let end = pos + stream.byte_offset();
+                out.push_value(data, pos..end);
+                pos = end;
+                continue;
+            }
+        }
+        let end = line_end(data, pos);
+        out.push_line(data, pos..end);
+        pos = end;
+    }
+    Some(out)
+}
+
+/// End of the line `pos` is on: its delimiter, or the end of `data`.
+fn line_end(data: &[u8], pos: usize) -> usize {
+    data[pos..].iter().position(|&b| b == b'\n').map_or(data.len(), |e| pos + e)
+}
+
+/// End of the array element starting at `start`: the end of its object,
+/// array or string, or of the scalar before the next `,`, `]` or
+/// whitespace. Unclosed values run to the end of `data`.
+fn element_end(data: &[u8], start: usize) -> usize {
+    match data[start] {
+        b'{' | b'[' => value_end(data, start).unwrap_or(data.len()),
+        b'"' => {
+            let mut escaped = false;
+            data[start + 1..]
+                .iter()
+                .position(|&b| match b {
+                    _ if escaped => {
+                        escaped = false;
+                        false
+                    }
+                    b'\\' => {
+                        escaped = true;
+                        false
+                    }
+                    b => b == b'"',
+                })
+                .map_or(data.len(), |i| start + 2 + i)
+        }
+        _ => data[start..]
+            .iter()
+            .position(|&b| b == b',' || b == b']' || b.is_ascii_whitespace())
+            .map_or(data.len(), |i| start + i),
+    }
+}
+
+/// End (exclusive) of the array or object starting at `start`, or `None`
+/// if it is not closed.
+fn value_end(data: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let (mut in_string, mut escaped) = (false, false);
+    for (i, &b) in data.iter().enumerate().skip(start) {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            b'[' | b'{' if !in_string => depth += 1,
+            b']' | b'}' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Append `raw` without whitespace outside strings, calling `resume(at, i)`
+/// when copying resumes at `raw[i]`, now at `out[at]`, after whitespace.
+fn minify(raw: &[u8], out: &mut Vec<u8>, mut resume: impl FnMut(usize, usize)) {
+    let (mut in_string, mut escaped, mut skipped) = (false, false, false);
+    for (i, &b) in raw.iter().enumerate() {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            _ if !in_string && b.is_ascii_whitespace() => {
+                skipped = true;
+                continue;
+            }
+            _ => {}
+        }
+        if skipped {
+            resume(out.len(), i);
+            skipped = false;
+        }
+        out.push(b);
+    }
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    b: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn err(&self) -> SyntaxError {
+        SyntaxError { offset: self.pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.b.get(self.pos).copied()
+    }
+
+    fn ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, c: u8) -> Result<(), SyntaxError> {
+        self.ws();
+        if self.peek() != Some(c) {
+            return Err(self.err());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn enter(&mut self) -> Result<(), SyntaxError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.err());
+        }
+        Ok(())
+    }
+
+    /// The record's own object, with values parsed one level deep.
+    fn top_object(&mut self) -> Result<JsonObject<'a>, SyntaxError> {
+        let mut fields = JsonObject::new();
+        self.eat(b'{')?;
+        self.enter()?;
+        self.ws();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            self.depth -= 1;
+            return Ok(fields);
+        }
+        loop {
+            self.ws();
+            let name = self.string()?;
+            self.eat(b':')?;
+            self.ws();
+            let value = self.value()?;
+            fields.push((name, value));
+            self.ws();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => return Err(self.err()),
+            }
+        }
+        self.depth -= 1;
+        // Sort by name; after reversing, a stable sort puts the last of
+        // each repeated name first, and dedup keeps it.
+        fields.reverse();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        fields.dedup_by(|a, b| a.0 == b.0);
+        Ok(fields)
+    }
+
+    fn value(&mut self) -> Result<JsonValue<'a>, SyntaxError> {
+        let start = self.pos;
+        Ok(match self.peek() {
+            Some(b'"') => JsonValue::String(self.string()?),
+            Some(b'[') => {
+                self.skip_value()?;
+                JsonValue::Array(Cow::Borrowed(&self.s[start..self.pos]))
+            }
+            Some(b'{') => {
+                self.skip_value()?;
+                JsonValue::Object(Cow::Borrowed(&self.s[start..self.pos]))
+            }
+            Some(b't') => self.literal("true", JsonValue::Bool(true))?,
+            Some(b'f') => self.literal("false", JsonValue::Bool(false))?,
+            Some(b'n') => self.literal("null", JsonValue::Null)?,
+            _ => JsonValue::Number(Cow::Borrowed(self.number()?)),
+        })
+    }
+
+    /// Validate one value of any kind without keeping it.
+    fn skip_value(&mut self) -> Result<(), SyntaxError> {
+        self.ws();
+        match self.peek() {
+            Some(b'"') => self.string_end().map(|_| ()),
+            Some(open @ (b'[' | b'{')) => {
+                let close = if open == b'[' { b']' } else { b'}' };
+                self.pos += 1;
+                self.enter()?;
+                self.ws();
+                if self.peek() == Some(close) {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        if open == b'{' {
+                            self.ws();
+                            self.string_end()?;
+                            self.eat(b':')?;
+                        }
+                        self.skip_value()?;
+                        self.ws();
+                        match self.peek() {
+                            Some(b',') => self.pos += 1,
+                            Some(c) if c == close => {
+                                self.pos += 1;
+                                break;
+                            }
+                            _ => return Err(self.err()),
+                        }
+                    }
+                }
+                self.depth -= 1;
+                Ok(())
+            }
+            Some(b't') => self.literal("true", ()),
+            Some(b'f') => self.literal("false", ()),
+            Some(b'n') => self.literal("null", ()),
+            _ => self.number().map(|_| ()),
+        }
+    }
+
+    fn literal<T>(&mut self, word: &str, v: T) -> Result<T, SyntaxError> {
+        if !self.b[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.err());
+        }
+        self.pos += word.len();
+        Ok(v)
+    }
+
+    fn number(&mut self) -> Result<&'a str, SyntaxError> {
+        let start = self.pos;
+        let digits = |p: &mut Self| {
+            let from = p.pos;
+            while p.peek().is_some_and(|c| c.is_ascii_digit()) {
+                p.pos += 1;
+            }
+            p.pos > from
+        };
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(b'0') => self.pos += 1,
+            Some(b'1'..=b'9') => {
+                digits(self);
+            }
+            _ => return Err(self.err()),
+        }
+        let mut float = false;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            float = true;
+            if !digits(self) {
+                return Err(self.err());
+            }
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.pos += 1;
+            float = true;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err(self.err());
+            }
+        }
+        let n = &self.s[start..self.pos];
+        // serde_json rejects floats that overflow.
+        if float && !n.parse::<f64>().is_ok_and(f64::is_finite) {
+            return Err(SyntaxError { offset: start });
+        }
+        Ok(n)
+    }
+
+    /// A string, borrowed unless it has escapes.
+    fn string(&mut self) -> Result<Cow<'a, str>, SyntaxError> {
+        let start = self.pos + 1;
+        let escaped = self.string_end()?;
+        let body = &self.s[start..self.pos - 1];
+        Ok(if escaped { Cow::Owned(unescape(body)) } else { Cow::Borrowed(body) })
+    }
+
+    /// Validate a string and move past its closing quote. Returns whether
+    /// it has escapes.
+    fn string_end(&mut self) -> Result<bool, SyntaxError> {
+        if self.peek() != Some(b'"') {
+            return Err(self.err());
+        }
+        self.pos += 1;
+        let mut escaped = false;
+        loop {
+            match self.peek() {
+                None => return Err(self.err()),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(escaped);
+                }
+                Some(b'\\') => {
+                    escaped = true;
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => self.pos += 1,
+                        Some(b'u') => {
+                            let u = self.hex4()?;
+                            if (0xdc00..0xe000).contains(&u) {
+                                return Err(self.err());
+                            }
+                            if (0xd800..0xdc00).contains(&u) {
+                                if self.peek() != Some(b'\\') {
+                                    return Err(self.err());
+                                }
+                                self.pos += 1;
+                                if !(0xdc00..0xe000).contains(&self.hex4()?) {
+                                    return Err(self.err());
+                                }
+                            }
+                        }
+                        _ => return Err(self.err()),
+                    }
+                }
+                Some(0..=0x1f) => return Err(self.err()),
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+
+    /// `u` and four hex digits, after a backslash.
+    fn hex4(&mut self) -> Result<u32, SyntaxError> {
+        if self.peek() != Some(b'u') {
+            return Err(self.err());
+        }
+        let hex = self.b.get(self.pos + 1..self.pos + 5).ok_or_else(|| self.err())?;
+        let u = std::str::from_utf8(hex)
+            .ok()
+            .filter(|h| h.bytes().all(|c| c.is_ascii_hexdigit()))
+            .and_then(|h| u32::from_str_radix(h, 16).ok())
+            .ok_or_else(|| self.err())?;
+        self.pos += 5;
+        Ok(u)
+    }
+}
+
+/// Decode the escapes in a validated string body.
+fn unescape(body: &str) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let esc = rest.as_bytes()[i + 1];
+        rest = &rest[i + 2..];
+        let c = match esc {
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let hi = u32::from_str_radix(&rest[..4], 16).unwrap_or(0xfffd);
+                rest = &rest[4..];
+                let cp = if (0xd800..0xdc00).contains(&hi) {
+                    let lo = u32::from_str_radix(&rest[2..6], 16).unwrap_or(0xdc00);
+                    rest = &rest[6..];
+                    0x10000 + ((hi - 0xd800) << 10) + (lo - 0xdc00)
+                } else {
+                    hi
+                };
+                char::from_u32(cp).unwrap_or(char::REPLACEMENT_CHARACTER)
+            }
+            other => other as char,
+        };
+        out.push(c);
+    }
+    out.push_str(rest);
+    out
+}
+
+// ---------------------------------------------------------------------------
+// simd-json
+// ---------------------------------------------------------------------------
+
+/// Parses records with simd-json's tape. simd-json parses in place, so each
+/// record is copied into a scratch buffer; field names and string values
+/// that come out unchanged are then borrowed from the record itself.
+/// Numbers arrive as values and nested arrays and objects as tape nodes, so
+/// those are written out in `serde_json`'s form.
+#[cfg(feature = "simd-json")]
+#[derive(Default)]
+pub struct SimdParser {
+    scratch: Vec<u8>,
+    buffers: simd_json::Buffers,
+}
+
+#[cfg(feature = "simd-json")]
+impl SimdParser {
+    /// Like [`parse_record`], for records simd-json accepts. Anything it
+    /// rejects (including integers wider than 64 bits) is an error, and
+    /// callers fall back to [`parse_record`].
+    pub fn parse<'a>(&mut self, s: &'a str) -> Result<Option<JsonObject<'a>>, SyntaxError> {
+        use simd_json::{Node, StaticNode};
+
+        // simd-json accepts unpaired surrogate escapes, which serde_json
+        // rejects; leave any record with a surrogate escape to the pull
+        // parser.
+        let surrogate = memchr::memmem::find_iter(s.as_bytes(), b"\\u").any(|at| {
+            matches!(s.as_bytes().get(at + 2..at + 4), Some([b'd' | b'D', b'8'..=b'9' | b'a'..=b'f' | b'A'..=b'F']))
+        });
+        if surrogate {
+            return Err(SyntaxError { offset: 0 });
+        }
+        self.scratch.clear();
+        self.scratch.extend_from_slice(s.as_bytes());
+        let base = self.scratch.as_ptr() as usize;
+        let tape = simd_json::to_tape_with_buffers(&mut self.scratch, &mut self.buffers)
+            .map_err(|e| SyntaxError { offset: e.index() })?;
+        // A string that sits in the scratch buffer exactly where it sits in
+        // `s` had no escapes; borrow it from `s`.
+        let borrow = |t: &str| -> Cow<'a, str> {
+            let at = t.as_ptr() as usize - base;
+            match s.get(at..at + t.len()) {
+                Some(orig) if orig == t => Cow::Borrowed(orig),
+                _ => Cow::Owned(t.to_owned()),
+            }
+        };
+        let nodes = &tape.0;
+        let Some(&Node::Object { len, .. }) = nodes.first() else {
+            return Ok(None);
+        };
+        let mut fields = JsonObject::with_capacity(len);
+        let mut i = 1;
+        for _ in 0..len {
+            let Node::String(name) = nodes[i] else {
+                return Err(SyntaxError { offset: 0 });
+            };
+            let value = match nodes[i + 1] {
+                Node::String(v) => JsonValue::String(borrow(v)),
+                Node::Static(StaticNode::Null) => JsonValue::Null,
+                Node::Static(StaticNode::Bool(b)) => JsonValue::Bool(b),
+                Node::Static(StaticNode::I64(n)) => JsonValue::Number(n.to_string().into()),
+                Node::Static(StaticNode::U64(n)) => JsonValue::Number(n.to_string().into()),
+                Node::Static(StaticNode::F64(f)) => match Number::from_f64(f) {
+                    Some(n) => JsonValue::Number(n.to_string().into()),
+                    None => return Err(SyntaxError { offset: 0 }),
+                },
+                Node::Array { .. } | Node::Object { .. } => {
+                    let (v, _) = tape_value(&nodes[i + 1..], 2)?;
+                    let raw = v.to_string().into();
+                    if v.is_array() { JsonValue::Array(raw) } else { JsonValue::Object(raw) }
+                }
+                #[allow(unreachable_patterns)]
+                _ => return Err(SyntaxError { offset: 0 }),
+            };
+            fields.push((borrow(name), value));
+            i += 1 + node_count(&nodes[i + 1]);
+        }
+        fields.reverse();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        fields.dedup_by(|a, b| a.0 == b.0);
+        Ok(Some(fields))
+    }
+}
+
+/// Nodes taken by the value starting at `n`, itself included.
+#[cfg(feature = "simd-json")]
+fn node_count(n: &simd_json::Node<'_>) -> usize {
+    match n {
+        simd_json::Node::Array { count, .. } | simd_json::Node::Object { count, .. } => count + 1,
+        _ => 1,
+    }
+}
+
+/// The value at the start of `nodes` as a `serde_json::Value`, and the
+/// nodes it took. Fails past `serde_json`'s nesting limit.
+#[cfg(feature = "simd-json")]
+fn tape_value(nodes: &[simd_json::Node<'_>], depth: usize) -> Result<(Value, usize), SyntaxError> {
+    use simd_json::{Node, StaticNode};
+
+    let fail = SyntaxError { offset: 0 };
+    if depth > MAX_DEPTH {
+        return Err(fail);
+    }
+    let v = match nodes[0] {
+        Node::String(s) => Value::String(s.to_owned()),
+        Node::Static(StaticNode::Null) => Value::Null,
+        Node::Static(StaticNode::Bool(b)) => Value::Bool(b),
+        Node::Static(StaticNode::I64(n)) => n.into(),
+        Node::Static(StaticNode::U64(n)) => n.into(),
+        Node::Static(StaticNode::F64(f)) => Value::Number(Number::from_f64(f).ok_or(fail)?),
+        Node::Array { len, .. } => {
+            let mut at = 1;
+            let mut items = Vec::with_capacity(len);
+            for _ in 0..len {
+                let (v, n) = tape_value(&nodes[at..], depth + 1)?;
+                items.push(v);
+                at += n;
+            }
+            Value::Array(items)
+        }
+        Node::Object { len, .. } => {
+            let mut at = 1;
+            let mut map = serde_json::Map::new();
+            for _ in 0..len {
+                let Node::String(k) = nodes[at] else { return Err(fail) };
+                let (v, n) = tape_value(&nodes[at + 1..], depth + 1)?;
+                map.insert(k.to_owned(), v);
+                at += 1 + n;
+            }
+            Value::Object(map)
+        }
+        #[allow(unreachable_patterns)]
+        _ => return Err(fail),
+    };
+    Ok((v, node_count(&nodes[0])))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::records::{Locator, Source};
+
+    #[test]
+    fn pretty_records_are_joined() {
+        let pretty = b"{\n  \"a\": 1,\n  \"msg\": \"x  {y}\\\" z\"\n}\n\n{\n  \"a\": [1,\n 2]\n}\n-- cut --\n{\n  \"a\": 3\n";
+        let joined = join_pretty_records(pretty).unwrap().data;
+        assert_eq!(joined, b"{\"a\":1,\"msg\":\"x  {y}\\\" z\"}\n{\"a\":[1,2]}\n-- cut --\n{\"a\":3\n".to_vec());
+
+        assert_eq!(join_pretty_records(b"{\"a\": 1}\n{\"a\": 2}\n"), None);
+        assert_eq!(join_pretty_records(b"[1, 2]\n"), None);
+        assert_eq!(join_pretty_records(b"plain log line\n"), None);
+        assert_eq!(join_pretty_records(b"{\n\"a\": 1\n"), None);
+    }
+
+    #[test]
+    fn reflowed_records_are_located_in_the_original() {
+        let pretty = b"{\n  \"a\": 1\n}\n\n{\n  \"a\": 2,\n  \"b\": \"x y\"\n}\n";
+        let r = join_pretty_records(pretty).unwrap();
+        assert_eq!(r.data, b"{\"a\":1}\n{\"a\":2,\"b\":\"x y\"}\n");
+        let source = Source { text: Some(pretty.to_vec()), to_text: r.map.clone(), ..Source::default() };
+        let loc = Locator::new("in.json", &r.data).with_source(&source);
+        assert_eq!(loc.locate(8), "in.json:5:1");
+        // The second field, after whitespace left out.
+        assert_eq!(loc.locate(15), "in.json:7:3");
+        assert_eq!(loc.file_range(0, &r.data[..8]), (0, 13));
+        assert_eq!(loc.file_range(8, &r.data[8..]), (14, 27));
+        assert_eq!(loc.by_offset(true).locate(8), "in.json@14");
+
+        let text = b"[{\"a\": 1},\n {\"a\": 2}]\n";
+        let array = split_json_array(text).unwrap();
+        let source = Source { text: Some(text.to_vec()), to_text: array.map, ..Source::default() };
+        let loc = Locator::new("in.json", &array.data).with_source(&source);
+        assert_eq!(loc.locate(8), "in.json:2:2");
+        assert_eq!(loc.file_range(8, b"{\"a\":2}\n"), (12, 8));
+    }
+
+    #[test]
+    fn concatenated_records_are_split() {
+        let data = b"{\"a\":1}{\"a\":{\"b\":\"}{\"}}\n{\"a\":3} {\n \"a\": 4\n}\nplain {\"a\":5}\n{\"a\":}{\"a\":6}\n{\"a\":";
+        assert_eq!(
+            split_concatenated_records(data).unwrap().data,
+            b"{\"a\":1}\n{\"a\":{\"b\":\"}{\"}}\n{\"a\":3}\n{\"a\":4}\nplain {\"a\":5}\n{\"a\":}{\"a\":6}\n{\"a\":\n".to_vec()
+        );
+        // `}{` inside a string, or not after a whole object, is not glue.
+        assert_eq!(split_concatenated_records(b"{\"a\":\"}{\"}\n{\"a\":2}\n"), None);
+        assert_eq!(split_concatenated_records(b"x}{y\n"), None);
+        assert_eq!(split_concatenated_records(b"{\"a\":1}\n{\"a\":2}\n"), None);
+    }
+
+    #[test]
+    fn array_elements_become_records() {
+        let array = b"[\n  {\"a\": 1},\n  {\n    \"a\": [2, 3]\n  }, \"s, ]\\\"\" ,7,null\n]\ntrailer\n";
+        assert_eq!(split_json_array(array).unwrap().data, b"{\"a\":1}\n{\"a\":[2,3]}\n\"s, ]\\\"\"\n7\nnull\ntrailer\n".to_vec());
+        assert_eq!(split_json_array(b"[{\"a\":1},{\"a\":2}]").unwrap().data, b"{\"a\":1}\n{\"a\":2}\n".to_vec());
+        // A truncated export keeps what it has.
+        assert_eq!(split_json_array(b"[{\"a\":1},\n{\"a\":").unwrap().data, b"{\"a\":1}\n{\"a\":\n".to_vec());
+
+        assert_eq!(split_json_array(b"[2026-02-16 12:00:01] INFO start\n"), None);
+        assert_eq!(split_json_array(b"[{x}] plain\n"), None);
+        assert_eq!(split_json_array(b"[1, {\"a\":1}]"), None);
+        assert_eq!(split_json_array(b"{\"a\":1}\n"), None);
+    }
+
+    #[test]
+    fn fields_borrow_from_the_input() {
+        let line = r#"{"msg":"caf\u00e9 \ud83d\ude00","level":"INFO","n":-12,"f":1.50,"ok":true,"x":null,"tags":[1, "a"],"ctx":{ "b":2,"a":1 }}"#;
+        let fields = parse_record(line).unwrap().unwrap();
+        let names: Vec<&str> = fields.iter().map(|(k, _)| &**k).collect();
+        assert_eq!(names, ["ctx", "f", "level", "msg", "n", "ok", "tags", "x"]);
+        let get = |k: &str| &fields.iter().find(|(n, _)| n == k).unwrap().1;
+        assert!(matches!(get("level"), JsonValue::String(Cow::Borrowed("INFO"))));
+        assert!(matches!(get("msg"), JsonValue::String(Cow::Owned(s)) if s == "caf\u{e9} \u{1f600}"));
+        assert!(matches!(get("n").key(), Cow::Borrowed("-12")));
+        assert_eq!(get("f").key(), "1.5");
+        for (n, key) in [("1", "1"), ("1.0", "1"), ("1e0", "1"), ("-0", "0"), ("-0.0", "0"),
+            ("2.50e1", "25"), ("0.1", "0.1"), ("1e-7", "1e-7"), ("18446744073709551615", "18446744073709551615"),
+            ("1e300", "1e+300"), ("123456789012345678901", "1.2345678901234568e+20")]
+        {
+            assert_eq!(canonical_number(n), key, "{n}");
+        }
+        assert_eq!(round_number("12.3456", 2), "12.35");
+        assert_eq!(round_number("12.0001", 2), "12");
+        assert_eq!(round_number("-0.001", 2), "0");
+        assert_eq!(round_number("1e-7", 3), "0");
+        assert_eq!(round_number("42", 0), "42");
+        assert_eq!(get("ok").key(), "true");
+        assert_eq!(get("x").json_type(), JsonType::Null);
+        assert_eq!(get("tags").key(), r#"[1,"a"]"#);
+        assert_eq!(get("ctx").key(), r#"{"a":1,"b":2}"#);
+        assert_eq!(nesting_depth("3"), 0);
+        assert_eq!(nesting_depth(r#"[1, "a"]"#), 1);
+        assert_eq!(nesting_depth(r#"{"a":[{"b":"]]}\"[["}],"c":[]}"#), 3);
+    }
+
+    #[test]
+    fn agrees_with_serde_json() {
+        let cases = [
+            r#"{}"#, r#"{"a":1,"a":2}"#, r#"[1,2]"#, r#""s""#, r#"3"#, r#"{"a":1,}"#, r#"{"a"}"#,
+            r#"{"a":01}"#, r#"{"a":1.}"#, r#"{"a":-}"#, r#"{"a":1e}"#, r#"{"a":1e400}"#,
+            r#"{"a":-0}"#, r#"{"a":1E+2}"#, r#"{"a":123456789012345678901}"#, r#"{"a":"\q"}"#,
+            r#"{"a":"\ud800"}"#, r#"{"a":"\udc00x"}"#, r#"{"a":"\u12"}"#, "{\"a\":\"\t\"}",
+            r#"{"a":tru}"#, r#"{"a":[1,[2,{"b":[]}]]}"#, r#"{"a":[1 2]}"#, r#"{"a":1} x"#,
+            r#"{"a":{"b":1,}}"#, r#"{"a":"x\/y\"z"}"#, r#"{ "a" : [ ] , "b" : { } }"#, "",
+        ];
+        for case in cases {
+            let ours = parse_record(case);
+            let theirs = serde_json::from_str::<Value>(case);
+            assert_eq!(ours.is_ok(), theirs.is_ok(), "{case}");
+            let (Ok(Some(fields)), Ok(Value::Object(map))) = (ours, theirs) else { continue };
+            assert_eq!(fields.len(), map.len(), "{case}");
+            for (k, v) in &fields {
+                let key = match &map[&**k] {
+                    Value::String(s) => s.clone(),
+                    // Same value; serde_json rounds integers past 64 bits
+                    // slightly differently.
+                    Value::Number(n) => {
+                        let (ours, theirs) = (v.key().parse::<f64>().unwrap(), n.as_f64().unwrap());
+                        assert!((ours - theirs).abs() <= theirs.abs() * 1e-15, "{case}");
+                        continue;
+                    }
+                    other => other.to_string(),
+                };
+                assert_eq!(v.key(), key, "{case}");
+            }
+        }
+        let deep = format!("{{\"a\":{}{}}}", "[".repeat(200), "]".repeat(200));
+        assert!(parse_record(&deep).is_err());
+        assert!(serde_json::from_str::<Value>(&deep).is_err());
+    }
+}
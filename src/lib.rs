@@ -2,4 +2,57 @@ pub mod scanner;
 pub mod dictionary;
 pub mod scorer;
 pub mod anomaly;
+#[cfg(feature = "json")]
 pub mod json_analyzer;
+#[cfg(feature = "json")]
+pub mod hybrid;
+pub mod opsfile;
+#[cfg(feature = "regex")]
+pub mod preprocess;
+pub mod session;
+#[cfg(feature = "json")]
+pub mod profile;
+#[cfg(feature = "compress")]
+pub mod gzip;
+pub mod zstd;
+#[cfg(feature = "compress")]
+pub mod savings;
+pub mod entropy;
+#[cfg(feature = "compress")]
+pub mod container;
+#[cfg(feature = "compress")]
+pub mod similarity;
+pub mod cluster;
+pub mod cosine;
+pub mod records;
+pub mod fingerprint;
+pub mod bloom;
+pub mod charset;
+#[cfg(feature = "compress")]
+pub mod archive;
+#[cfg(feature = "json")]
+pub mod json_value;
+pub mod drift;
+pub mod state;
+#[cfg(feature = "json")]
+pub mod severity;
+#[cfg(feature = "rules")]
+pub mod rules;
+#[cfg(feature = "sigma")]
+pub mod sigma;
+#[cfg(all(feature = "json", feature = "regex"))]
+pub mod extract;
+#[cfg(feature = "json")]
+pub mod timeline;
+#[cfg(feature = "regex")]
+pub mod incidents;
+pub mod quantile;
+pub mod online;
+pub mod checkpoint;
+#[cfg(feature = "regex")]
+pub mod follow;
+pub mod seed;
+pub mod tune;
+#[cfg(feature = "json")]
+pub mod score_line;
+pub mod excerpt;
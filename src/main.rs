@@ -1,17 +1,109 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fs;
 use std::io::{self, Write};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::time::Instant;
 
 use clap::{Args, Parser, Subcommand};
+use regex::bytes::Regex;
+use tracing::{info, info_span, warn};
+use tracing_subscriber::fmt::format::FmtSpan;
 
-use bgtzip::anomaly::{detect_anomalies, detect_indices, DetectionMethod};
-use bgtzip::dictionary::build_dictionary;
+use bgtzip::anomaly::{
+    detect_anomalies, detect_anomalies_by, detect_indices, detect_two_sided, merge_blocks, offset_heatmap,
+    review_sample, smooth_scores, sparkline, standard_scores, template_contributions, AnomalyBlock,
+    DetectionMethod, Direction, Histogram, RankMetric, Segment, TemplateStats,
+};
+use bgtzip::bloom::{load_filter, save_filter, unseen_records, BloomFilter, Fingerprinter};
+use bgtzip::archive::{archive_counts, concat_files, is_archive, read_archive, ArchiveMember, ARCHIVE_SNIFF_LEN};
+use bgtzip::charset::{binary_ratio, decode_to_utf8, looks_binary, utf8_offsets, Charset};
+use bgtzip::cluster::{kmeans, unit_vectors};
+use bgtzip::container::{decompress, deflate, read_header, write_container};
+use bgtzip::cosine::{centroid_distances, knn_distances};
+use bgtzip::dictionary::{build_dictionary, restore_case, zlib_preset, DictEntry, ZLIB_PRESET_MAX};
+use bgtzip::excerpt::{excerpt_json, write_raw, Excerpt, Prefix};
+use bgtzip::entropy::{record_bits_per_byte, EntropyModel};
+use bgtzip::gzip::{decompress_members, is_gzip, member_counts, GzMember, LineReader};
+use bgtzip::hybrid::{hybrid_scores, HybridScore};
 use bgtzip::json_analyzer::{
-    build_json_report, build_schema, looks_like_json, parse_json_records,
-    score_json_records,
+    build_json_report, build_schema, field_contributions, field_values, looks_like_json,
+    field_keys, group_breakdown, is_mixed_format, normalize_space_values, split_by_format, round_number_values, parse_json_records, parse_json_records_limited, record_template, score_json_records_weighted, score_json_records_windowed, score_json_records_chunked, add_first_seen, FieldFilter, FieldWeights, JsonRecord,
+    JsonRecordScore, SchemaProfile, StructureLimit, Truncations,
+};
+use bgtzip::opsfile::{load_ops, save_annotations, save_ops, SavedParams};
+use bgtzip::profile::{is_profile, load_profile, read_profile, save_profile, Profile};
+use bgtzip::preprocess::{mask_records, normalize_space, timestamp_regex, TIMESTAMP_PATTERN};
+use bgtzip::records::{
+    count_oversized, dedup_records, head_len, limit_records, Deduped, Locator, OffsetMap, Oversize, RecordIndex,
+    RecordLimit, RecordSubset, Source,
+};
+use bgtzip::scanner::{
+    incompressible_regions, scan, scan_with_baseline, Matcher, OpKind, ParseMode, ScanOp, ScanParams,
+    DEFAULT_EFFORT, DEFAULT_WINDOW, MAX_MATCH, MIN_MATCH, UNBOUNDED_WINDOW,
+};
+use bgtzip::scorer::{
+    byte_entropy, clean_split, count_rarity, coverage_breakdown, entry_bytes, entry_usage, expand_duplicates, idf_rarity,
+    literal_runs, record_coverage, record_spans, rescore_with_bits, score_records, score_records_chunked, score_records_windowed, set_bits_per_byte, set_rarity,
+    split_records, RecordAnalysis, Span,
 };
-use bgtzip::scanner::{scan, OpKind, DEFAULT_WINDOW, MAX_MATCH, MIN_MATCH};
-use bgtzip::scorer::score_records;
+use bgtzip::savings::{estimate_savings, SavingsEstimate};
+use bgtzip::session::score_sessions;
+use bgtzip::drift::{ratio_drift, RatioShift, MAX_DRIFT_VALUES};
+use bgtzip::state::{improbable_transitions, Transition};
+use bgtzip::timeline::{schema_timeline, SchemaChange};
+use bgtzip::severity::{boost_order, line_severity, record_severity};
+use bgtzip::rules::{RuleMatch, RuleSet};
+use bgtzip::extract::Extractor;
+use bgtzip::incidents::{incidents, parse_duration, Gap, Incident, TimestampParser};
+use bgtzip::online::{OnlineDetector, DEFAULT_WARMUP};
+use bgtzip::score_line::{one_record, score_line, LineScore, Verdict};
+use bgtzip::seed::{blend_seed_scores, line_tokens, record_tokens, SeedProfile};
+use bgtzip::tune::{self, assess, best, Trial};
+use bgtzip::checkpoint::{load_state, next_states, save_state, states_for, take_state, FollowState};
+use bgtzip::follow::{Alert, Follower, Start};
+use bgtzip::json_value::{join_pretty_records, split_concatenated_records, split_json_array, Reflowed};
+#[cfg(feature = "sigma")]
+use bgtzip::sigma::SigmaRules;
+use bgtzip::similarity::nearest_records;
+use bgtzip::zstd::{is_zstd, parse_frames, BlockType};
+
+// ---------------------------------------------------------------------------
+// Allocation tracking (for `bench`)
+// ---------------------------------------------------------------------------
+
+/// System allocator that tracks live and peak heap bytes.
+struct CountingAlloc;
+
+static HEAP_LIVE: AtomicUsize = AtomicUsize::new(0);
+static HEAP_PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let p = System.alloc(layout);
+        if !p.is_null() {
+            let live = HEAP_LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            HEAP_PEAK.fetch_max(live, Ordering::Relaxed);
+        }
+        p
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        HEAP_LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Reset the peak to the current live size; returns that size.
+fn reset_heap_peak() -> usize {
+    let live = HEAP_LIVE.load(Ordering::Relaxed);
+    HEAP_PEAK.store(live, Ordering::Relaxed);
+    live
+}
 
 // ---------------------------------------------------------------------------
 // CLI definition
@@ -22,21 +114,199 @@ use bgtzip::scorer::score_records;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Level of bgtzip's own diagnostics on stderr: error, warn, info
+    /// (pipeline stage timings), debug or trace
+    #[arg(long, global = true, default_value = "warn",
+        value_parser = ["error", "warn", "info", "debug", "trace"])]
+    log_level: String,
+    /// Diagnostics format: text, or json for one JSON object per line
+    #[arg(long, global = true, default_value = "text", value_parser = ["text", "json"])]
+    log_format: String,
+    /// Print stage timings and other progress (same as --log-level info)
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Layout of JSON output to produce: 1 for the unversioned layout of
+    /// bgtzip 0.2, or 2 (the default, except that lists stay bare arrays
+    /// unless 2 is given explicitly)
+    #[arg(long, global = true,
+        value_parser = clap::value_parser!(u32).range(1..=OUTPUT_SCHEMA_VERSION as i64))]
+    output_schema_version: Option<u32>,
 }
 
 #[derive(Args, Clone)]
 struct CommonArgs {
     /// Input file to analyze
     input: String,
-    /// LZ77 sliding window size in bytes
-    #[arg(long, default_value_t = DEFAULT_WINDOW)]
+    /// LZ77 sliding window size in bytes (K/M/G suffixes, or "unbounded")
+    #[arg(long, default_value_t = DEFAULT_WINDOW, value_parser = parse_window)]
     window_size: usize,
     /// Minimum match length in bytes
     #[arg(long, default_value_t = MIN_MATCH)]
     min_match: usize,
-    /// Print timing info
-    #[arg(short, long)]
-    verbose: bool,
+    /// Parse strategy: greedy (fast) or optimal (minimal-cost shortest path)
+    #[arg(long, default_value = "greedy", value_parser = ["greedy", "optimal"])]
+    parse: String,
+    /// Match finder: chain (sliding window) or ldm (adds long-distance matching)
+    #[arg(long, default_value = "chain", value_parser = ["chain", "ldm"])]
+    matcher: String,
+    /// Match-finder effort 1-9 (chain depth and hash size)
+    #[arg(long, default_value_t = DEFAULT_EFFORT, value_parser = clap::value_parser!(u8).range(1..=9))]
+    effort: u8,
+    /// Override hash-chain candidates examined per position
+    #[arg(long)]
+    max_chain: Option<usize>,
+    /// Override log2 of the hash table size (8-24)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(8..=24))]
+    hash_bits: Option<u32>,
+    /// Skip faster after N consecutive unmatched bytes (0 disables)
+    #[arg(long)]
+    skip_after: Option<usize>,
+    /// Reuse scan ops saved by `scan --save` instead of rescanning
+    #[arg(long)]
+    ops: Option<String>,
+    /// Mask matches of this regex in each line before scanning
+    #[arg(long, value_parser = parse_regex)]
+    strip_regex: Option<Regex>,
+    /// Mask a leading timestamp in each line before scanning
+    #[arg(long, conflicts_with = "strip_regex")]
+    strip_timestamps: bool,
+    /// Fold ASCII case before scanning, so case variants of a template
+    /// share matches and dictionary entries (output keeps original bytes)
+    #[arg(long)]
+    ignore_case: bool,
+    /// Collapse runs of spaces and tabs before scanning and in JSON values,
+    /// so column padding does not break matches (output keeps original
+    /// bytes)
+    #[arg(long)]
+    normalize_space: bool,
+    /// Record compressibility signal: coverage (backref share) or entropy (estimated bits/byte)
+    #[arg(long, default_value = "coverage", value_parser = ["coverage", "entropy"])]
+    cost_model: String,
+    /// Rarity signal: rank (mean dictionary rank of referenced entries),
+    /// count (mean log-share of backrefs), idf (mean inverse document
+    /// frequency), or cosine distance of the entry vector to the corpus
+    /// centroid or to the --knn nearest records
+    #[arg(long, default_value = "rank",
+        value_parser = ["rank", "count", "idf", "centroid", "knn"])]
+    rarity: String,
+    /// Neighbors for --rarity knn
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..))]
+    knn: u32,
+    /// LZ77: scan and score each distinct record once, copying its score to
+    /// byte-identical duplicates
+    #[arg(long)]
+    dedup: bool,
+    /// Limit records to N bytes (delimiter excluded); see --oversize
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_record_bytes: Option<u64>,
+    /// What to do with records over --max-record-bytes: truncate or skip
+    #[arg(long, default_value = "truncate", value_parser = ["truncate", "skip"],
+        requires = "max_record_bytes")]
+    oversize: String,
+    /// Only read the first N records, for a quick preview of huge files
+    #[arg(long, value_name = "N")]
+    max_records: Option<usize>,
+}
+
+#[derive(Args, Clone)]
+struct ModeArgs {
+    /// Analysis mode: auto (detect from the first lines), lz77, json, or
+    /// mixed (anomalies: JSON lines by schema, other lines by LZ77)
+    #[arg(long, default_value = "auto", value_parser = ["auto", "lz77", "json", "mixed"])]
+    mode: String,
+    /// Force JSON structured log mode (same as --mode json)
+    #[arg(long, conflicts_with_all = ["mode", "no_structured"])]
+    structured: bool,
+    /// Force LZ77 mode even on JSON input (same as --mode lz77)
+    #[arg(long, conflicts_with = "mode")]
+    no_structured: bool,
+}
+
+#[derive(Args, Clone)]
+struct FieldArgs {
+    /// JSON mode: comma-separated fields to exclude from schema and scoring
+    #[arg(long, value_delimiter = ',')]
+    ignore_fields: Vec<String>,
+    /// JSON mode: comma-separated fields to restrict schema and scoring to
+    #[arg(long, value_delimiter = ',')]
+    only_fields: Vec<String>,
+    /// JSON mode: per-field weights, e.g. "level=3,msg=0.5", or @FILE with
+    /// one field=weight per line
+    #[arg(long, value_parser = parse_field_weights)]
+    field_weights: Option<FieldWeights>,
+    /// JSON mode: cut field values nested deeper than N levels (the record
+    /// object is level 1) to {...} or [...]
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_depth: Option<u32>,
+    /// JSON mode: keep at most N top-level fields per record (by name)
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_fields_per_record: Option<u32>,
+    /// JSON mode: round numbers to N decimal places before counting values
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(0..=17))]
+    float_precision: Option<u32>,
+    /// Score non-JSON lines by the named captures of this regex, e.g.
+    /// 'user=(?P<user>\S+)', or grok '%{IP:client} %{INT:status:int}'
+    /// (repeatable; implies JSON mode)
+    #[arg(long, value_name = "PATTERN")]
+    extract_pattern: Vec<String>,
+    /// Grok definitions (NAME regex per line) for --extract-pattern, in
+    /// addition to the built-in ones
+    #[arg(long, value_name = "FILE", requires = "extract_pattern")]
+    grok_patterns: Option<String>,
+    /// JSON mode: flag the first occurrence of a field, or of a value of a
+    /// low-cardinality field, after the first 5% of records, and give that
+    /// novelty WEIGHT of the score
+    #[arg(long, value_name = "WEIGHT", num_args = 0..=1, default_missing_value = "0.25",
+        value_parser = parse_fraction)]
+    novelty: Option<f64>,
+}
+
+#[derive(Args, Clone, Default)]
+struct BaselineArgs {
+    /// Build the dictionary/schema from a reference file (raw input or a
+    /// saved profile) and score every record of the input against it
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["baseline_head", "baseline_range", "ops"])]
+    baseline: Option<String>,
+    /// Build the dictionary/schema from the first N records only and score
+    /// every record against it
+    #[arg(long, value_name = "N", conflicts_with_all = ["baseline_range", "ops", "dedup"])]
+    baseline_head: Option<usize>,
+    /// Like --baseline-head, using records A..B (0-based, end exclusive)
+    #[arg(long, value_name = "A..B", value_parser = parse_range,
+        conflicts_with_all = ["ops", "dedup"])]
+    baseline_range: Option<(usize, usize)>,
+    /// Judge records against the schema/dictionary of the N records before
+    /// them, a window that slides in steps of N/4, rather than the whole
+    /// input
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["baseline", "baseline_head", "baseline_range", "ops", "dedup"])]
+    window_records: Option<u64>,
+    /// Judge records in chunks of N, each against a running
+    /// schema/dictionary whose counts decay by --chunk-decay per chunk
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["baseline", "baseline_head", "baseline_range", "ops", "dedup", "window_records"])]
+    chunk_records: Option<u64>,
+    /// Share of the running counts kept from one chunk to the next under
+    /// --chunk-records (0 rebuilds them from each chunk alone)
+    #[arg(long, default_value_t = 0.5, value_parser = parse_fraction, requires = "chunk_records")]
+    chunk_decay: f64,
+    /// Score twice: leave the records a first pass flags (by the score
+    /// method) out of the dictionary/schema, and score every record
+    /// against what the rest build
+    #[arg(long,
+        conflicts_with_all = ["baseline", "baseline_head", "baseline_range", "ops", "window_records", "chunk_records"])]
+    robust: bool,
+}
+
+impl BaselineArgs {
+    /// Baseline record range within the input, if any.
+    fn range(&self) -> Option<(usize, usize)> {
+        self.baseline_head.map(|n| (0, n)).or(self.baseline_range)
+    }
+
+    fn is_set(&self) -> bool {
+        self.baseline.is_some() || self.range().is_some()
+    }
 }
 
 #[derive(Subcommand)]
@@ -48,6 +318,13 @@ enum Commands {
         /// Show first N operations
         #[arg(long, default_value_t = 0)]
         show_ops: usize,
+        /// Save the operation stream for reuse with --ops
+        #[arg(long)]
+        save: Option<String>,
+        /// Write every op (position, length, kind, source offset) as JSON
+        /// lines to FILE
+        #[arg(long, value_name = "FILE")]
+        annotate: Option<String>,
     },
     /// Build and display frequency-ordered dictionary
     Dict {
@@ -59,514 +336,4906 @@ enum Commands {
         /// Show only top N entries
         #[arg(long)]
         top: Option<usize>,
-        /// Output as JSON format
+        /// Output as JSON format (same as --format json)
         #[arg(long)]
         json: bool,
+        /// Output format; zlib-preset writes raw bytes for
+        /// deflateSetDictionary (top entries within 32 KB, most frequent last)
+        #[arg(long, default_value = "text", value_parser = ["text", "json", "zlib-preset"])]
+        format: String,
+        /// Write zlib-preset output to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+        /// Also show how many distinct records use each entry, with examples
+        #[arg(long)]
+        usage: bool,
     },
-    /// Full analysis: scan + dict + per-record scoring
-    Analyze {
+    /// Estimate bytes a preset dictionary of the top entries would save
+    Savings(SavingsArgs),
+    /// Records most similar to record N (normalized compression distance)
+    Similar(SimilarArgs),
+    /// Group records by shared dictionary entries (or JSON fields) with k-means
+    Cluster(ClusterArgs),
+    /// Compress into a .bgtz container (deflate primed with the dictionary)
+    Compress(CompressArgs),
+    /// Restore the original bytes from a .bgtz container
+    Decompress {
+        /// Container written by `compress`
+        input: String,
+        /// Output file, or - for stdout (default: INPUT without .bgtz)
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Decompress a .bgtz container in memory and check its checksum
+    Verify {
+        /// Container written by `compress`
+        input: String,
+        /// Also compare byte-for-byte with the original file
+        #[arg(long, value_name = "FILE")]
+        original: Option<String>,
+    },
+    /// Quick corpus summary: size, records, coverage, entropy, top entries
+    Stats {
         #[command(flatten)]
         common: CommonArgs,
-        /// Minimum backref count for dictionary
+        /// Minimum backref count to include in dictionary
         #[arg(long, default_value_t = 2)]
         min_count: usize,
-        /// Force JSON structured log mode (auto-detected if omitted)
+        /// Output as JSON format
         #[arg(long)]
-        structured: bool,
+        json: bool,
     },
-    /// Detect and display anomalous records
-    Anomalies {
+    /// Time each pipeline stage across window sizes and effort levels
+    Bench {
         #[command(flatten)]
         common: CommonArgs,
+        /// Window sizes to try (comma-separated; replaces --window-size)
+        #[arg(long, value_delimiter = ',', value_parser = parse_window,
+              default_value = "32K,1M,unbounded")]
+        windows: Vec<usize>,
+        /// Effort levels to try (comma-separated; replaces --effort)
+        #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(1..=9),
+              default_value = "1,5,9")]
+        efforts: Vec<u8>,
         /// Minimum backref count for dictionary
         #[arg(long, default_value_t = 2)]
         min_count: usize,
-        /// Detection method: score, coverage, percentile, top
-        #[arg(long, value_parser = ["score", "coverage", "percentile", "top"])]
-        method: Option<String>,
-        /// Detection threshold (method-dependent)
+        /// Output as JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Try window sizes, minimum match lengths and thresholds, and
+    /// recommend the setting that flags records most consistently (or best
+    /// matches --labels)
+    Tune(TuneArgs),
+    /// Full analysis: scan + dict + per-record scoring
+    Analyze(AnalyzeArgs),
+    /// Detect and display anomalous records
+    Anomalies(Box<AnomaliesArgs>),
+    /// Score one record (given with --line or on stdin) against a saved
+    /// profile and explain the score
+    ScoreLine(ScoreLineArgs),
+    /// Show the full value distribution of one JSON field
+    Values {
+        /// Input file to analyze (JSON lines)
+        input: String,
+        /// Field name to inspect
+        #[arg(long)]
+        field: String,
+        /// Show only top N values
         #[arg(long)]
-        threshold: Option<f64>,
-        /// Show top N anomalies (implies method=top)
+        top: Option<usize>,
+        /// Example records to show per value
+        #[arg(long, default_value_t = 1)]
+        examples: usize,
+        /// Output as JSON format
+        #[arg(long)]
+        json: bool,
+        /// Only read the first N records, for a quick preview of huge files
+        #[arg(long, value_name = "N")]
+        max_records: Option<usize>,
+    },
+    /// Rank JSON fields by their contribution to anomalies
+    Fields {
+        /// Input file to analyze (JSON lines)
+        input: String,
+        #[command(flatten)]
+        fields: FieldArgs,
+        /// Show only top N fields
         #[arg(long)]
-        top_n: Option<usize>,
+        top: Option<usize>,
         /// Output as JSON format
         #[arg(long)]
         json: bool,
-        /// Print raw anomalous record content to stdout
+        /// Only read the first N records, for a quick preview of huge files
+        #[arg(long, value_name = "N")]
+        max_records: Option<usize>,
+    },
+    /// Print records with inline backref markers and dictionary entry IDs
+    Annotate(AnnotateArgs),
+    /// Inspect zstd frames: window, dictionary ID, block types, ratio
+    Zstd {
+        /// Zstandard-compressed file
+        input: String,
+        /// Also list every block
         #[arg(long)]
-        extract: bool,
-        /// Force JSON structured log mode (auto-detected if omitted)
+        blocks: bool,
+        /// Output as JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Histogram of a per-record metric
+    Hist(HistArgs),
+    /// Train and merge saved baseline profiles (use with --baseline)
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCmd,
+    },
+    /// Maintain a Bloom filter of known-normal record fingerprints (use with
+    /// anomalies --known)
+    Known {
+        #[command(subcommand)]
+        action: KnownCmd,
+    },
+    /// Score records as they arrive (e.g. `tail -F app.log | bgtzip follow -`)
+    /// and print anomalies as they are found
+    Follow(FollowArgs),
+}
+
+#[derive(Args)]
+struct TrainArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count for dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    #[command(flatten)]
+    mode: ModeArgs,
+    #[command(flatten)]
+    fields: FieldArgs,
+    /// Profile file to write
+    #[arg(short, long)]
+    output: String,
+}
+
+#[derive(Args)]
+struct UpdateArgs {
+    /// Profile to update
+    profile: String,
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count for dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    #[command(flatten)]
+    mode: ModeArgs,
+    #[command(flatten)]
+    fields: FieldArgs,
+    /// Weight kept by existing counts and reference bytes (0-1); lower
+    /// values forget old patterns faster
+    #[arg(long, default_value_t = 0.5, value_parser = parse_fraction)]
+    decay: f64,
+    /// Profile file to write (default: overwrite PROFILE)
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum ProfileCmd {
+    /// Learn a profile (reference bytes, dictionary, JSON schema) from input
+    Train(Box<TrainArgs>),
+    /// Fold new input into an existing profile, decaying old patterns
+    Update(Box<UpdateArgs>),
+    /// Merge several profiles into one
+    Merge {
+        /// Profiles to merge
+        #[arg(required = true, num_args = 2..)]
+        inputs: Vec<String>,
+        /// Profile file to write
+        #[arg(short, long)]
+        output: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum KnownCmd {
+    /// Add the fingerprint of every input record to a filter, creating it if
+    /// needed
+    Add {
+        /// Input files of normal records (gzip is decompressed)
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Filter file to create or extend
+        #[arg(short, long)]
+        filter: String,
+        /// Distinct fingerprints a new filter is sized for
+        #[arg(long, default_value_t = 1_000_000)]
+        capacity: usize,
+        /// Target false-positive rate of a new filter
+        #[arg(long, default_value_t = 0.001, value_parser = parse_fraction)]
+        fp_rate: f64,
+        /// New filter: learn a template vocabulary from the inputs'
+        /// dictionary and mask words outside it, so records that differ in
+        /// user names, hosts or tokens share a fingerprint
         #[arg(long)]
-        structured: bool,
+        templates: bool,
+    },
+    /// Write only the records whose fingerprint is not in a filter
+    Filter {
+        /// Input file
+        input: String,
+        /// Filter file
+        #[arg(short, long)]
+        filter: String,
+        /// Output file (`-` for stdout)
+        #[arg(short, long, default_value = "-")]
+        output: String,
     },
 }
 
-// ---------------------------------------------------------------------------
-// Helpers
-// ---------------------------------------------------------------------------
+#[derive(Args)]
+struct AnalyzeArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count for dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    #[command(flatten)]
+    mode: ModeArgs,
+    #[command(flatten)]
+    fields: FieldArgs,
+    #[command(flatten)]
+    baseline: BaselineArgs,
+    /// Plot anomaly score over record index, WIDTH columns wide
+    #[arg(long, value_name = "WIDTH", num_args = 0..=1, default_missing_value = "80")]
+    sparkline: Option<usize>,
+}
 
-fn read_input(path: &str) -> Vec<u8> {
-    fs::read(path).unwrap_or_else(|e| {
-        eprintln!("error: {path}: {e}");
-        std::process::exit(1);
-    })
+#[derive(Args)]
+struct SavingsArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count to include in dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    /// Use only the top K entries (default: as many as fit in 32 KB)
+    #[arg(long, value_name = "K")]
+    top: Option<usize>,
+    /// Also measure savings on this held-out file
+    #[arg(long, value_name = "FILE")]
+    holdout: Option<String>,
+    /// Compress at most N records per file (evenly sampled)
+    #[arg(long, value_name = "N", default_value_t = 10_000)]
+    sample: usize,
+    /// Output as JSON format
+    #[arg(long)]
+    json: bool,
 }
 
-fn pct(num: usize, den: usize) -> f64 {
-    if den == 0 { 0.0 } else { num as f64 / den as f64 * 100.0 }
+#[derive(Args)]
+struct SimilarArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count to include in dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    /// Record index to find neighbors of
+    #[arg(long, value_name = "N")]
+    record: usize,
+    /// Number of neighbors to show
+    #[arg(long, default_value_t = 10)]
+    top_n: usize,
+    /// Output as JSON format
+    #[arg(long)]
+    json: bool,
 }
 
-fn parse_method(method_str: &Option<String>, top_n: &Option<usize>) -> DetectionMethod {
-    if top_n.is_some() {
-        DetectionMethod::Top
-    } else {
-        match method_str.as_deref() {
-            Some("coverage") => DetectionMethod::Coverage,
-            Some("percentile") => DetectionMethod::Percentile,
-            Some("top") => DetectionMethod::Top,
-            _ => DetectionMethod::Score,
-        }
-    }
+#[derive(Args)]
+struct ClusterArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count for dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    #[command(flatten)]
+    mode: ModeArgs,
+    #[command(flatten)]
+    fields: FieldArgs,
+    #[command(flatten)]
+    baseline: BaselineArgs,
+    /// Number of clusters
+    #[arg(short, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    k: u32,
+    /// Clusters holding at most this share of records are reported as
+    /// candidate anomalies
+    #[arg(long, default_value_t = 0.01, value_parser = parse_fraction)]
+    tiny: f64,
+    /// Member records to print per tiny cluster
+    #[arg(long, default_value_t = 5)]
+    show: usize,
+    /// Output as JSON format
+    #[arg(long)]
+    json: bool,
 }
 
-fn is_json_mode(data: &[u8], force: bool) -> bool {
-    if force {
-        return true;
-    }
-    looks_like_json(data)
+#[derive(Args)]
+struct CompressArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count to include in dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    /// Use only the top K entries (default: as many as fit in 32 KB)
+    #[arg(long, value_name = "K")]
+    top: Option<usize>,
+    /// Deflate level 0-9
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=9))]
+    level: u32,
+    /// Output file (default: INPUT.bgtz)
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<String>,
+    /// Output as JSON format
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct AnnotateArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count for dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    #[command(flatten)]
+    baseline: BaselineArgs,
+    /// Records to print, by index (end exclusive)
+    #[arg(long, value_name = "A..B", default_value = "0..20", value_parser = parse_range)]
+    line_range: (usize, usize),
+}
+
+#[derive(Args)]
+struct HistArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count for dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    #[command(flatten)]
+    mode: ModeArgs,
+    #[command(flatten)]
+    fields: FieldArgs,
+    #[command(flatten)]
+    baseline: BaselineArgs,
+    /// Metric to plot: coverage or bpb (LZ77 only), score, or length
+    #[arg(long, default_value = "score", value_parser = ["coverage", "bpb", "score", "length"])]
+    metric: String,
+    /// Number of bins
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    bins: u32,
+    /// Scale bars by log(count) so sparse tail bins stay visible
+    #[arg(long)]
+    log_scale: bool,
+    /// Output as JSON format
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct TuneArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count for dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    #[command(flatten)]
+    baseline: BaselineArgs,
+    /// Window sizes to try (comma-separated; replaces --window-size)
+    #[arg(long, value_delimiter = ',', value_parser = parse_window,
+          default_value = "4K,32K,256K,1M")]
+    windows: Vec<usize>,
+    /// Minimum match lengths to try (comma-separated; replaces --min-match)
+    #[arg(long, value_delimiter = ',', default_value = "4,5,6,8")]
+    min_matches: Vec<usize>,
+    /// Score thresholds to try, in standard deviations above the mean
+    /// score (comma-separated)
+    #[arg(long, value_delimiter = ',', default_value = "1.5,2,2.5,3")]
+    sigmas: Vec<f64>,
+    /// Records known to be anomalous, one 0-based index per line (blank
+    /// lines and # comments ignored); settings are ranked by F1 against
+    /// them
+    #[arg(long, value_name = "FILE")]
+    labels: Option<String>,
+    /// Show the N best settings
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+    /// Output as JSON format
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct ScoreLineArgs {
+    /// Saved profile (`profile train`) to score against
+    #[arg(long, value_name = "FILE")]
+    profile: String,
+    /// The record to score, plain text or a JSON object (read from stdin
+    /// if omitted)
+    #[arg(long, value_name = "TEXT")]
+    line: Option<String>,
+    #[command(flatten)]
+    mode: ModeArgs,
+    #[command(flatten)]
+    fields: FieldArgs,
+    /// Minimum backref count for the reference dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    /// LZ77: show the N dictionary entries covering the most bytes
+    #[arg(long, default_value_t = 5)]
+    top_entries: usize,
+    /// Output as JSON format
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct FollowArgs {
+    /// Input files, each with its own baseline and threshold, or - for
+    /// stdin
+    #[arg(required = true)]
+    inputs: Vec<String>,
+    /// LZ77 sliding window size in bytes (K/M/G suffixes)
+    #[arg(long, default_value_t = DEFAULT_WINDOW, value_parser = parse_window)]
+    window_size: usize,
+    /// Detection method: score, coverage, percentile
+    #[arg(long, value_parser = ["score", "coverage", "percentile"])]
+    method: Option<String>,
+    /// Detection threshold (method-dependent)
+    #[arg(long)]
+    threshold: Option<f64>,
+    /// Records scored before any is flagged
+    #[arg(long, default_value_t = DEFAULT_WARMUP)]
+    warmup: usize,
+    /// Alert once per record fingerprint within this duration (30s, 5m,
+    /// 2h); repeats are counted into the next alert. Times come from the
+    /// records' timestamps, or the clock for records without one
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    alert_cooldown: Option<f64>,
+    /// Print alerts as JSON lines
+    #[arg(long)]
+    json: bool,
+    /// Save the read offset and learned baseline to FILE at exit, and
+    /// resume from it: later runs score only new records and notice a
+    /// rotated or truncated log. Method, threshold, window and warm-up
+    /// come from the state once it exists
+    #[arg(long, value_name = "FILE")]
+    state: Option<String>,
+}
+
+#[derive(Args)]
+struct AnomaliesArgs {
+    #[command(flatten)]
+    common: CommonArgs,
+    /// Minimum backref count for dictionary
+    #[arg(long, default_value_t = 2)]
+    min_count: usize,
+    /// Detection method: score, coverage, percentile, top
+    #[arg(long, value_parser = ["score", "coverage", "percentile", "top"])]
+    method: Option<String>,
+    /// Detection threshold (method-dependent)
+    #[arg(long)]
+    threshold: Option<f64>,
+    /// Show top N anomalies (implies method=top)
+    #[arg(long)]
+    top_n: Option<usize>,
+    /// LZ77: what the percentile and top methods rank records by: score,
+    /// coverage (lowest first), or literal-bytes
+    #[arg(long, default_value = "score", value_parser = ["score", "coverage", "literal-bytes"])]
+    metric: String,
+    /// Which records to flag: high (odd: high scores, low coverage), low
+    /// (suspiciously normal: low scores, high coverage, e.g. replayed
+    /// lines), or both
+    #[arg(long, default_value = "high", value_parser = ["high", "low", "both"])]
+    direction: String,
+    /// Output as JSON format
+    #[arg(long)]
+    json: bool,
+    /// Print raw anomalous record content to stdout
+    #[arg(long)]
+    extract: bool,
+    /// Extracted records as raw bytes after the report, or as ndjson: only
+    /// one {index, offset, length, score, content, source, line} object
+    /// per anomaly (implies --extract)
+    #[arg(long, default_value = "raw", value_parser = ["raw", "ndjson"])]
+    extract_format: String,
+    /// Write extracted records to this file, keeping the report on stdout
+    /// (implies --extract)
+    #[arg(long, value_name = "FILE")]
+    extract_to: Option<String>,
+    /// Start each raw extracted record with its line number (from 1) or
+    /// byte offset and a colon
+    #[arg(long, default_value = "none", value_parser = ["none", "line", "offset"])]
+    extract_prefix: String,
+    /// Raw extraction: also write the N lines before and after each
+    /// anomaly, as grep -C does (context lines are prefixed `N-` rather
+    /// than `N:`, and `--` separates runs that are not adjacent)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    context: usize,
+    /// Where each anomaly is in the input, for editors: FILE:LINE:COLUMN
+    /// (from 1), or FILE@OFFSET in bytes (from 0). Gzip and archive input
+    /// is located in the decompressed file
+    #[arg(long, default_value = "line", value_parser = ["line", "offset"])]
+    locator: String,
+    /// LZ77 mode: list up to N dictionary entries per anomaly with the bytes
+    /// they cover (0 disables)
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    top_entries: usize,
+    /// LZ77 mode: order anomalies by score, or by bits per byte (least
+    /// compressible first)
+    #[arg(long, default_value = "score", value_parser = ["score", "bpb"])]
+    sort: String,
+    /// Order anomalies by score times FACTOR for ERROR/FATAL records (its
+    /// square root for WARN), from a level field or an upper-case level token
+    #[arg(long, value_name = "FACTOR", num_args = 0..=1, default_missing_value = "2",
+        value_parser = parse_boost)]
+    level_boost: Option<f64>,
+    /// Tag, grade or suppress anomalies with the rules in this YAML file
+    /// (regex on the line and/or JSON field conditions)
+    #[arg(long, value_name = "FILE", value_parser = parse_rules)]
+    rules: Option<RuleSet>,
+    /// Also report records matching the Sigma rules in this file or
+    /// directory, as findings separate from the anomalies
+    #[cfg(feature = "sigma")]
+    #[arg(long, value_name = "PATH", value_parser = parse_sigma)]
+    sigma: Option<SigmaRules>,
+    #[command(flatten)]
+    mode: ModeArgs,
+    #[command(flatten)]
+    fields: FieldArgs,
+    #[command(flatten)]
+    baseline: BaselineArgs,
+    /// In JSON mode, also run LZ77 scoring and blend both scores
+    #[arg(long)]
+    hybrid: bool,
+    /// Share of the JSON score in the hybrid blend (0-1)
+    #[arg(long, default_value_t = 0.5)]
+    hybrid_weight: f64,
+    /// Plot anomaly score over record index, WIDTH columns wide
+    #[arg(long, value_name = "WIDTH", num_args = 0..=1, default_missing_value = "80")]
+    sparkline: Option<usize>,
+    /// Split the file into N offset segments and report backref coverage
+    /// and anomaly density per segment
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "20",
+        conflicts_with_all = ["dedup", "normalize_space", "max_record_bytes"])]
+    heatmap: Option<usize>,
+    /// Score each record by the mean over N records centered on it, so
+    /// sustained runs outrank isolated spikes
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "5",
+        value_parser = clap::value_parser!(u32).range(1..=100000))]
+    smooth: Option<u32>,
+    /// Known-bad lines (plain or JSON, one per line): records sharing their
+    /// characteristic words or fields score higher and are reported first
+    #[arg(long, value_name = "FILE")]
+    seed: Option<String>,
+    /// Share of the anomaly score given to similarity with the --seed lines
+    #[arg(long, default_value_t = 0.5, value_parser = parse_fraction, requires = "seed")]
+    seed_weight: f64,
+    /// Report flagged records at most GAP records apart as one anomalous
+    /// block [start..end]
+    #[arg(long, value_name = "GAP", num_args = 0..=1, default_missing_value = "1",
+        value_parser = clap::value_parser!(u32).range(1..=100000))]
+    blocks: Option<u32>,
+    /// Summarize anomalies as clusters at the top of the report, splitting
+    /// where flagged records are more than GAP records apart, or more than
+    /// a duration apart (30s, 5m, 2h) by their timestamps
+    #[arg(long, value_name = "GAP", num_args = 0..=1, default_missing_value = "10",
+        value_parser = Gap::parse)]
+    incidents: Option<Gap>,
+    /// Rank record templates (dominant dictionary entry in LZ77 mode, field
+    /// set in JSON mode) by their share of the anomaly score; report the
+    /// top N
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "10")]
+    templates: Option<usize>,
+    /// Also list N records spread across the score deciles, not only the
+    /// top, so reviewers calibrating the threshold see borderline cases
+    #[arg(long, value_name = "N")]
+    review_sample: Option<usize>,
+    /// JSON mode: break down anomaly count and rate by this field's values
+    #[arg(long, value_name = "FIELD")]
+    group_by: Option<String>,
+    /// JSON mode: group records into sessions by this field and score them
+    #[arg(long, value_name = "FIELD")]
+    session_field: Option<String>,
+    /// Number of sessions to report
+    #[arg(long, default_value_t = 10)]
+    top_sessions: usize,
+    /// JSON mode: learn transitions between consecutive values of this
+    /// field (per session with --session-field) and flag improbable ones
+    #[arg(long, value_name = "FIELD")]
+    state_field: Option<String>,
+    /// JSON mode: split records into N windows by position and report
+    /// boolean and small-enum fields whose value ratios shift sharply
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "20",
+        value_parser = clap::value_parser!(u32).range(2..=10000))]
+    drift: Option<u32>,
+    /// Smallest change in a value's share of its field reported by --drift
+    #[arg(long, default_value_t = 0.2, value_parser = parse_fraction)]
+    drift_threshold: f64,
+    /// JSON mode: report fields and values that first appear or stop
+    /// appearing partway through the input, and at which record
+    #[arg(long)]
+    timeline: bool,
+    /// LZ77 mode: skip records whose fingerprint is in this filter (see
+    /// `known add`) and score only never-seen-before records
+    #[arg(long, value_name = "FILE",
+        conflicts_with_all = ["heatmap", "baseline_head", "baseline_range", "ops"])]
+    known: Option<String>,
 }
 
 // ---------------------------------------------------------------------------
-// LZ77 commands (unchanged)
+// Helpers
 // ---------------------------------------------------------------------------
 
-fn cmd_scan(c: CommonArgs, show_ops: usize) -> i32 {
-    let data = read_input(&c.input);
+/// Literal runs at least this long are reported as high-entropy regions.
+const HIGH_ENTROPY_MIN_LEN: usize = 1024;
 
-    let t0 = Instant::now();
-    let ops = scan(&data, c.window_size, c.min_match, MAX_MATCH);
-    let elapsed = t0.elapsed().as_secs_f64();
+/// Version of the JSON output layout, carried as `schema_version` in every
+/// JSON document and alert. Within a version fields are only ever added;
+/// renaming or removing one bumps it, and the previous layout stays
+/// available through `--output-schema-version`.
+///
+/// - 1: bgtzip 0.2, no `schema_version`.
+/// - 2: `schema_version` added. The lists printed by `dict`, `bench` and
+///   `zstd` can't carry it, so they stay bare arrays by default and move
+///   under `entries`, `runs` and `frames` only when version 2 is asked
+///   for explicitly.
+const OUTPUT_SCHEMA_VERSION: u32 = 2;
 
-    let n_lit = ops.iter().filter(|o| o.kind == OpKind::Literal).count();
-    let n_ref = ops.iter().filter(|o| o.kind == OpKind::Backref).count();
-    let lit_bytes: usize = ops.iter().filter(|o| o.kind == OpKind::Literal).map(|o| o.length).sum();
-    let ref_bytes: usize = ops.iter().filter(|o| o.kind == OpKind::Backref).map(|o| o.length).sum();
-    let total = data.len();
+/// The layout asked for with `--output-schema-version`, 0 if none was.
+static OUTPUT_SCHEMA: AtomicU32 = AtomicU32::new(0);
 
-    println!("=== LZ77 Scan: {} ===", c.input);
-    println!("  input size:     {total:>10} bytes");
-    println!("  scan time:      {elapsed:>10.4}s");
-    println!("  operations:     {:>10}", ops.len());
-    println!("    literals:     {n_lit:>10}  ({lit_bytes} bytes, {:.1}%)", pct(lit_bytes, total));
-    println!("    backrefs:     {n_ref:>10}  ({ref_bytes} bytes, {:.1}%)", pct(ref_bytes, total));
+fn output_schema() -> u32 {
+    match OUTPUT_SCHEMA.load(Ordering::Relaxed) {
+        0 => OUTPUT_SCHEMA_VERSION,
+        v => v,
+    }
+}
 
-    if show_ops > 0 {
-        println!("\n--- Operations (first {show_ops}) ---");
-        for op in ops.iter().take(show_ops) {
-            let preview = op.content(&data);
-            let trunc = preview.len().min(40);
-            let suffix = if preview.len() > 40 { "..." } else { "" };
-            let shown = String::from_utf8_lossy(&preview[..trunc]);
-            match op.kind {
-                OpKind::Backref => println!(
-                    "  [{:8}] BACKREF  len={:4}  off={:6}  {shown:?}{suffix}",
-                    op.position, op.length, op.ref_offset
-                ),
-                OpKind::Literal => println!(
-                    "  [{:8}] LITERAL  len={:4}  {shown:?}{suffix}",
-                    op.position, op.length
-                ),
-            }
+/// `v` in the requested layout: tagged with its `schema_version` from
+/// version 2 on.
+fn versioned(mut v: serde_json::Value) -> serde_json::Value {
+    let version = output_schema();
+    if version >= 2 {
+        if let Some(obj) = v.as_object_mut() {
+            obj.insert("schema_version".into(), version.into());
         }
     }
-    0
+    v
 }
 
-fn cmd_dict(c: CommonArgs, min_count: usize, top: Option<usize>, json: bool) -> i32 {
-    let data = read_input(&c.input);
-    let t0 = Instant::now();
-    let ops = scan(&data, c.window_size, c.min_match, MAX_MATCH);
-    if c.verbose { eprintln!("  scan: {:.4}s", t0.elapsed().as_secs_f64()); }
-    let dict = build_dictionary(&data, &ops, min_count);
-
-    let total_covered: usize = dict.iter().map(|e| e.total_bytes_covered()).sum();
-    let limit = top.unwrap_or(dict.len());
+/// Print a command's JSON document.
+fn print_json(v: serde_json::Value) {
+    println!("{}", serde_json::to_string_pretty(&versioned(v)).unwrap());
+}
 
-    if json {
-        let entries: Vec<serde_json::Value> = dict.iter().take(limit).map(|e| {
-            serde_json::json!({
-                "id": e.entry_id, "count": e.count, "length": e.content_length(),
-                "total_bytes": e.total_bytes_covered(),
-                "median_interval": e.median_interval(), "mean_interval": e.mean_interval(),
-                "content_preview": String::from_utf8_lossy(&e.content[..e.content.len().min(80)]),
-            })
-        }).collect();
-        println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+/// Print a command's JSON list: a bare array, or under `key` in an object
+/// if `--output-schema-version 2` or later was given.
+fn print_json_list(key: &str, items: Vec<serde_json::Value>) {
+    if OUTPUT_SCHEMA.load(Ordering::Relaxed) < 2 {
+        println!("{}", serde_json::to_string_pretty(&items).unwrap());
     } else {
-        println!("=== Dictionary: {} ===", c.input);
-        println!("  entries:  {}", dict.len());
-        if !data.is_empty() {
-            println!("  total backref bytes covered: {total_covered} / {} ({:.1}%)",
-                data.len(), pct(total_covered, data.len()));
-        }
-        println!("\n--- Top {limit} entries ---");
-        for e in dict.iter().take(limit) {
-            let trunc = e.content.len().min(60);
-            let suffix = if e.content.len() > 60 { "..." } else { "" };
-            let shown = String::from_utf8_lossy(&e.content[..trunc]);
-            println!("  [{:4}]  count={:6}  len={:4}  med_iv={:8.0}  {shown:?}{suffix}",
-                e.entry_id, e.count, e.content_length(), e.median_interval());
-        }
+        print_json(serde_json::json!({ key: items }));
     }
-    0
 }
 
-// ---------------------------------------------------------------------------
-// Analyze command (LZ77 or JSON)
-// ---------------------------------------------------------------------------
+fn read_input(path: &str) -> Vec<u8> {
+    read_input_members(path).data
+}
 
-fn cmd_analyze(c: CommonArgs, min_count: usize, structured: bool) -> i32 {
-    let data = read_input(&c.input);
+/// Analysis input and where its bytes came from.
+struct Input {
+    data: Vec<u8>,
+    /// Gzip member boundaries (empty for plain input and archives).
+    members: Vec<GzMember>,
+    /// Files of a tar or zip archive (empty otherwise).
+    files: Vec<ArchiveMember>,
+    /// Where `data` is in the file, if it was rewritten.
+    source: Source,
+}
 
-    if is_json_mode(&data, structured) {
-        return cmd_analyze_json(&c, &data);
+impl Input {
+    /// Keep the first `len` bytes, clipping member and file boundaries.
+    fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+        self.members.retain(|m| m.offset < len || m.offset == 0);
+        for m in &mut self.members {
+            m.len = m.len.min(len - m.offset);
+        }
+        self.files.retain(|f| f.offset < len || f.offset == 0);
+        for f in &mut self.files {
+            f.len = f.len.min(len - f.offset);
+        }
     }
+}
 
-    let t0 = Instant::now();
-    let ops = scan(&data, c.window_size, c.min_match, MAX_MATCH);
-    let t1 = Instant::now();
-    let dict = build_dictionary(&data, &ops, min_count);
-    let t2 = Instant::now();
-    let records = score_records(&data, &ops, &dict, b'\n');
-    let t3 = Instant::now();
+/// Read a command's input ([`read_input_members`]), honouring
+/// `--max-records`.
+fn read_common(c: &CommonArgs) -> Input {
+    read_records(&c.input, c.max_records)
+}
 
-    if c.verbose {
-        eprintln!("  scan:  {:.4}s", (t1 - t0).as_secs_f64());
-        eprintln!("  dict:  {:.4}s", (t2 - t1).as_secs_f64());
-        eprintln!("  score: {:.4}s", (t3 - t2).as_secs_f64());
+/// Read `path` for analysis ([`read_input_members`]), or with `max`, only
+/// its first `max` records ([`read_head`]).
+fn read_records(path: &str, max: Option<usize>) -> Input {
+    let Some(n) = max else { return read_input_members(path) };
+    let mut input = read_head(path, n);
+    let len = head_len(&input.data, b'\n', n);
+    if len < input.data.len() {
+        info!(input = path, records = n, "stopped early (--max-records)");
+        input.truncate(len);
     }
+    input
+}
 
-    let n_lit = ops.iter().filter(|o| o.kind == OpKind::Literal).count();
-    let n_ref = ops.iter().filter(|o| o.kind == OpKind::Backref).count();
-    let ref_bytes: usize = ops.iter().filter(|o| o.kind == OpKind::Backref).map(|o| o.length).sum();
+/// Read `path` for analysis: [`read_bytes`], then [`decode_input`].
+fn read_input_members(path: &str) -> Input {
+    let (raw, members) = read_bytes(path);
+    decode_input(path, raw, members)
+}
 
-    println!("=== Analysis (LZ77): {} ===", c.input);
-    println!("  input size:     {:>10} bytes", data.len());
-    println!("  records:        {:>10}", records.len());
-    println!("  scan ops:       {:>10}  ({n_lit} literal, {n_ref} backref)", ops.len());
-    println!("  backref cover:  {:>9.1}%", pct(ref_bytes, data.len()));
-    println!("  dict entries:   {:>10}", dict.len());
+/// Read the start of `path` for analysis ([`decode_input`]): lines are
+/// read ([`LineReader`]) until, transcoded and reflowed, they hold more
+/// than `n` records, so the rest of a huge file is never read. Archives
+/// are read whole.
+fn read_head(path: &str, n: usize) -> Input {
+    fn or_exit<T>(path: &str, r: io::Result<T>) -> T {
+        r.unwrap_or_else(|e| {
+            eprintln!("error: {path}: {e}");
+            std::process::exit(1);
+        })
+    }
+    let mut r = or_exit(path, fs::File::open(path).and_then(LineReader::new));
+    let mut lines = n.saturating_add(1);
+    let mut more = or_exit(path, r.read_lines(lines));
+    while more && r.data().len() < ARCHIVE_SNIFF_LEN {
+        more = or_exit(path, r.read_lines(1));
+    }
+    if is_archive(r.data()) {
+        return read_input_members(path);
+    }
+    while more && !holds_records(r.data(), n) {
+        more = or_exit(path, r.read_lines(lines));
+        lines = lines.saturating_mul(2);
+    }
+    let (raw, members) = r.into_parts();
+    decode_input(path, raw, members)
+}
 
-    if !records.is_empty() {
-        let coverages: Vec<f64> = records.iter().map(|r| r.coverage).collect();
-        let sum: f64 = coverages.iter().sum();
-        let mean = sum / coverages.len() as f64;
-        let mut sorted = coverages.clone();
-        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let median = if sorted.len() % 2 == 0 {
-            (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.0
-        } else { sorted[sorted.len() / 2] };
+/// Whether `head`, transcoded and reflowed as [`decode_input`] would, holds
+/// more than `n` records, so the first `n` are whole.
+fn holds_records(head: &[u8], n: usize) -> bool {
+    let text = decode_to_utf8(head).1;
+    let reflowed = one_record_per_line(&text);
+    let data = reflowed.as_ref().map_or(&text[..], |(r, _)| &r.data);
+    head_len(data, b'\n', n) < data.len()
+}
 
-        println!("\n--- Coverage Distribution ---");
-        println!("  mean:    {mean:.4}");
-        println!("  median:  {median:.4}");
-        println!("  min:     {:.4}", sorted.first().unwrap_or(&0.0));
-        println!("  max:     {:.4}", sorted.last().unwrap_or(&0.0));
+/// Input as read from `path` ([`read_bytes`]), ready for analysis: UTF-16
+/// is transcoded to UTF-8 (per gzip member) and byte order marks dropped.
+/// Tar and zip archives are replaced by their files, concatenated.
+fn decode_input(path: &str, raw: Vec<u8>, members: Vec<GzMember>) -> Input {
+    if is_archive(&raw) {
+        let (data, files, source) = read_archive_files(path, &raw);
+        return Input { data, members: Vec::new(), files, source };
+    }
+    if members.is_empty() {
+        let (data, source, joined) = read_text(path, raw);
+        note_joined(path, joined);
+        return Input { data, members, files: Vec::new(), source };
+    }
+    let mut data = Vec::with_capacity(raw.len());
+    let mut out = Vec::with_capacity(members.len());
+    let mut parts = Vec::with_capacity(members.len());
+    let mut any_joined = None;
+    for m in members {
+        let (text, source, joined) = read_text(path, raw[m.offset..m.offset + m.len].to_vec());
+        any_joined = any_joined.or(joined);
+        parts.push((data.len(), m.offset, source));
+        out.push(GzMember { offset: data.len(), len: text.len(), ..m });
+        data.extend_from_slice(&text);
+    }
+    note_joined(path, any_joined);
+    let source = join_sources(&data, parts);
+    Input { data, members: out, files: Vec::new(), source }
+}
 
-        let mut buckets = [0usize; 10];
-        for &c in &coverages { buckets[(c * 10.0).min(9.0) as usize] += 1; }
-        let max_count = *buckets.iter().max().unwrap_or(&1).max(&1);
-        println!("\n--- Coverage Histogram ---");
-        for (i, &count) in buckets.iter().enumerate() {
-            let bar = "#".repeat((count as f64 / max_count as f64 * 40.0) as usize);
-            println!("  {:3}-{:3}%: {:6} {bar}", i * 10, (i + 1) * 10, count);
+/// One part of the input (the file, a gzip member or an archive file) as
+/// UTF-8 text one record per line ([`to_utf8`], [`one_record_per_line`]),
+/// where its bytes came from, and what was done, for a note.
+fn read_text(path: &str, raw: Vec<u8>) -> (Vec<u8>, Source, Option<&'static str>) {
+    let (text, to_file) = to_utf8_mapped(path, raw);
+    match one_record_per_line(&text) {
+        Some((reflowed, how)) => {
+            let source = Source { text: Some(text), to_text: reflowed.map, to_file };
+            (reflowed.data, source, Some(how))
         }
+        None => (text, Source { to_file, ..Source::default() }, None),
     }
+}
 
-    let top = dict.len().min(10);
-    if top > 0 {
-        println!("\n--- Top {top} Dictionary Entries ---");
-        for e in dict.iter().take(top) {
-            let trunc = e.content.len().min(50);
-            let suffix = if e.content.len() > 50 { "..." } else { "" };
-            let shown = String::from_utf8_lossy(&e.content[..trunc]);
-            println!("  [{:4}]  count={:6}  len={:4}  {shown:?}{suffix}",
-                e.entry_id, e.count, e.content_length());
+/// The [`Source`] of input made of `parts`, each given as its offset in
+/// `data`, its offset in the file and its own source.
+fn join_sources(data: &[u8], parts: Vec<(usize, usize, Source)>) -> Source {
+    let reflowed = parts.iter().any(|(_, _, s)| s.text.is_some());
+    let mut out = Source { text: reflowed.then(|| Vec::with_capacity(data.len())), ..Source::default() };
+    for (k, (at, file, part)) in parts.iter().enumerate() {
+        let end = parts.get(k + 1).map_or(data.len(), |p| p.0);
+        let text_at = out.text.as_ref().map_or(*at, Vec::len);
+        out.to_text.append(&part.to_text, *at, text_at);
+        out.to_file.append(&part.to_file, text_at, *file);
+        if let Some(text) = &mut out.text {
+            text.extend_from_slice(part.text.as_deref().unwrap_or(&data[*at..end]));
         }
     }
-    0
+    out
 }
 
-fn cmd_analyze_json(c: &CommonArgs, data: &[u8]) -> i32 {
-    let t0 = Instant::now();
-    let records = parse_json_records(data, b'\n');
-    let t1 = Instant::now();
-    let schema = build_schema(&records);
-    let t2 = Instant::now();
-    let scored = score_json_records(data, &records, &schema);
-    let t3 = Instant::now();
+/// `data` with one JSON record per line: the elements of a top-level
+/// array ([`split_json_array`]), concatenated records split
+/// ([`split_concatenated_records`]) or pretty-printed records joined
+/// ([`join_pretty_records`]), with what was done, for a note. `None` if
+/// it already is.
+fn one_record_per_line(data: &[u8]) -> Option<(Reflowed, &'static str)> {
+    if let Some(split) = split_json_array(data) {
+        return Some((split, "top-level JSON array; each element read as a record"));
+    }
+    if let Some(split) = split_concatenated_records(data) {
+        return Some((split, "concatenated JSON; records split onto lines of their own"));
+    }
+    join_pretty_records(data).map(|joined| (joined, "pretty-printed JSON; each record joined onto one line"))
+}
 
-    if c.verbose {
-        eprintln!("  parse:  {:.4}s", (t1 - t0).as_secs_f64());
-        eprintln!("  schema: {:.4}s", (t2 - t1).as_secs_f64());
-        eprintln!("  score:  {:.4}s", (t3 - t2).as_secs_f64());
+fn note_joined(path: &str, how: Option<&str>) {
+    if let Some(how) = how {
+        info!(input = path, "{how}");
     }
+}
 
-    println!("=== Analysis (JSON): {} ===", c.input);
-    println!("  input size:     {:>10} bytes", data.len());
-    println!("  records:        {:>10}", records.len());
-    println!("  valid JSON:     {:>10}", schema.valid_records);
-    println!("  parse errors:   {:>10}", schema.parse_errors);
-    println!("  unique fields:  {:>10}", schema.fields.len());
-    println!("  field sets:     {:>10}", schema.field_set_counts.len());
+/// Text files of an archive, concatenated, and where their bytes came
+/// from. Gzipped files are decompressed and binary ones skipped with a
+/// note.
+fn read_archive_files(path: &str, raw: &[u8]) -> (Vec<u8>, Vec<ArchiveMember>, Source) {
+    let files = read_archive(raw).unwrap_or_else(|e| {
+        eprintln!("error: {path}: {e}");
+        std::process::exit(1);
+    });
+    let mut text = Vec::with_capacity(files.len());
+    let mut sources = Vec::with_capacity(files.len());
+    for mut f in files {
+        let name = format!("{path}:{}", f.name);
+        if is_gzip(&f.data) {
+            f.data = decompress_members(&f.data).map(|d| d.0).unwrap_or_else(|e| {
+                eprintln!("error: {name}: {e}");
+                std::process::exit(1);
+            });
+        }
+        let (data, source, joined) = read_text(&name, f.data);
+        f.data = data;
+        note_joined(&name, joined);
+        if looks_binary(&f.data) {
+            warn!(input = name, "skipping binary file");
+            continue;
+        }
+        text.push(f);
+        sources.push(source);
+    }
+    let (data, members) = concat_files(text);
+    // Each file's offsets count from where it starts in `data` (`Source::to_file`).
+    let parts = members.iter().zip(sources).map(|(m, s)| (m.offset, m.offset, s)).collect();
+    let source = join_sources(&data, parts);
+    (data, members, source)
+}
+
+/// `data` as UTF-8, with a note on stderr if it had to be transcoded.
+fn to_utf8(path: &str, data: Vec<u8>) -> Vec<u8> {
+    to_utf8_mapped(path, data).0
+}
+
+/// [`to_utf8`], and the offsets of the text in `data` ([`utf8_offsets`]).
+fn to_utf8_mapped(path: &str, data: Vec<u8>) -> (Vec<u8>, OffsetMap) {
+    match decode_to_utf8(&data) {
+        (_, Cow::Borrowed(b)) if b.len() == data.len() => (data, OffsetMap::default()),
+        (Charset::Utf8Bom, Cow::Borrowed(b)) => (b.to_vec(), utf8_offsets(&data, Charset::Utf8Bom, b)),
+        (cs, out) => {
+            info!(input = path, charset = cs.name(), "transcoded to UTF-8");
+            let map = utf8_offsets(&data, cs, &out);
+            (out.into_owned(), map)
+        }
+    }
+}
+
+/// Read `path`, transparently decompressing gzip input. Also returns the
+/// gzip member boundaries (empty for plain input).
+fn read_bytes(path: &str) -> (Vec<u8>, Vec<GzMember>) {
+    let raw = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("error: {path}: {e}");
+        std::process::exit(1);
+    });
+    if !is_gzip(&raw) {
+        return (raw, Vec::new());
+    }
+    decompress_members(&raw).unwrap_or_else(|e| {
+        eprintln!("error: {path}: {e}");
+        std::process::exit(1);
+    })
+}
+
+/// Per-member sizes plus record and anomaly counts for gzip input.
+fn print_members(members: &[GzMember], records: &[usize], anomalies: &[usize]) {
+    if members.is_empty() {
+        return;
+    }
+    let (recs, anoms) = (member_counts(members, records), member_counts(members, anomalies));
+    println!("\n--- Gzip Members ---");
+    println!("  {:>6} {:>12} {:>12} {:>7} {:>8} {:>9}",
+        "member", "compressed", "bytes", "ratio", "records", "anomalies");
+    for (i, m) in members.iter().enumerate() {
+        println!("  {i:>6} {:>12} {:>12} {:>6.1}x {:>8} {:>9}",
+            m.compressed_len, m.len, m.ratio(), recs[i], anoms[i]);
+    }
+}
+
+fn members_json(members: &[GzMember], records: &[usize], anomalies: &[usize]) -> serde_json::Value {
+    let (recs, anoms) = (member_counts(members, records), member_counts(members, anomalies));
+    members.iter().enumerate().map(|(i, m)| serde_json::json!({
+        "member": i, "compressed_offset": m.compressed_offset,
+        "compressed_bytes": m.compressed_len, "offset": m.offset, "bytes": m.len,
+        "records": recs[i], "anomalies": anoms[i],
+    })).collect()
+}
+
+fn parse_window(s: &str) -> Result<usize, String> {
+    if s.eq_ignore_ascii_case("unbounded") {
+        return Ok(UNBOUNDED_WINDOW);
+    }
+    let (digits, mult) = match s.as_bytes().last().map(|b| b.to_ascii_uppercase()) {
+        Some(b'K') => (&s[..s.len() - 1], 1usize << 10),
+        Some(b'M') => (&s[..s.len() - 1], 1 << 20),
+        Some(b'G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    let n: usize = digits.parse().map_err(|_| format!("invalid window size: {s}"))?;
+    if n == 0 {
+        return Err("window size must be positive".into());
+    }
+    n.checked_mul(mult).ok_or_else(|| format!("window size too large: {s}"))
+}
+
+/// Truncate `s` to at most `max` bytes on a char boundary, adding "...".
+fn shorten(s: &str, max: usize) -> String {
+    if s.len() <= max {
+        return s.to_string();
+    }
+    let mut end = max.saturating_sub(3);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}...", &s[..end])
+}
+
+fn pct(num: usize, den: usize) -> f64 {
+    if den == 0 { 0.0 } else { num as f64 / den as f64 * 100.0 }
+}
+
+fn parse_method(method_str: &Option<String>, top_n: &Option<usize>) -> DetectionMethod {
+    if top_n.is_some() {
+        DetectionMethod::Top
+    } else {
+        match method_str.as_deref() {
+            Some("coverage") => DetectionMethod::Coverage,
+            Some("percentile") => DetectionMethod::Percentile,
+            Some("top") => DetectionMethod::Top,
+            _ => DetectionMethod::Score,
+        }
+    }
+}
+
+fn parse_mode(parse_str: &str) -> ParseMode {
+    match parse_str {
+        "optimal" => ParseMode::Optimal,
+        _ => ParseMode::Greedy,
+    }
+}
+
+fn scan_params(c: &CommonArgs) -> ScanParams {
+    let mut p = ScanParams::with_effort(c.effort);
+    p.window_size = c.window_size;
+    p.min_match = c.min_match;
+    p.parse = parse_mode(&c.parse);
+    p.matcher = match c.matcher.as_str() {
+        "ldm" => Matcher::Ldm,
+        _ => Matcher::HashChain,
+    };
+    if let Some(n) = c.max_chain {
+        p.max_chain = n;
+    }
+    if let Some(b) = c.hash_bits {
+        p.hash_bits = b;
+    }
+    if let Some(n) = c.skip_after {
+        p.skip_trigger = n;
+    }
+    p
+}
+
+fn parse_range(s: &str) -> Result<(usize, usize), String> {
+    let (a, b) = s.split_once("..").ok_or("expected A..B")?;
+    let a: usize = a.trim().parse().map_err(|_| format!("invalid start '{a}'"))?;
+    let b: usize = b.trim().parse().map_err(|_| format!("invalid end '{b}'"))?;
+    if a >= b {
+        return Err(format!("empty range {a}..{b}"));
+    }
+    Ok((a, b))
+}
+
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    let v: f64 = s.parse().map_err(|_| format!("invalid number '{s}'"))?;
+    if !(0.0..=1.0).contains(&v) {
+        return Err(format!("{v} is not between 0 and 1"));
+    }
+    Ok(v)
+}
+
+fn parse_boost(s: &str) -> Result<f64, String> {
+    let v: f64 = s.parse().map_err(|_| format!("invalid number '{s}'"))?;
+    if !v.is_finite() || v < 1.0 {
+        return Err(format!("{v} is not a factor of 1 or more"));
+    }
+    Ok(v)
+}
+
+fn parse_rules(path: &str) -> Result<RuleSet, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    RuleSet::parse(&text).map_err(|e| format!("{path}: {e}"))
+}
+
+#[cfg(feature = "sigma")]
+fn parse_sigma(path: &str) -> Result<SigmaRules, String> {
+    SigmaRules::load(std::path::Path::new(path))
+}
+
+fn parse_regex(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| e.to_string())
+}
+
+/// The bytes the LZ77 pipeline runs on: `data` with space runs collapsed
+/// (`--normalize-space`), `--strip-regex` or `--strip-timestamps` matches
+/// masked, and case folded (`--ignore-case`). Masking and folding keep
+/// offsets, so reports still show the original bytes; normalizing does
+/// not, and [`run_lz77`] maps records back.
+fn scan_input<'a>(data: &'a [u8], c: &CommonArgs) -> Cow<'a, [u8]> {
+    let data = match c.normalize_space {
+        true => Cow::Owned(normalize_space(data).data),
+        false => Cow::Borrowed(data),
+    };
+    let masked = match (&c.strip_regex, c.strip_timestamps) {
+        (Some(re), _) => Cow::Owned(mask_records(&data, re, b'\n')),
+        (None, true) => Cow::Owned(mask_records(&data, &timestamp_regex(), b'\n')),
+        (None, false) => data,
+    };
+    if !c.ignore_case {
+        return masked;
+    }
+    let mut folded = masked.into_owned();
+    folded.make_ascii_lowercase();
+    Cow::Owned(folded)
+}
+
+/// Dictionary as shown to the user: under `--ignore-case`, entries get
+/// back the case of their first occurrence in `original` (the input before
+/// [`scan_input`], or after `--normalize-space` only).
+fn shown_dict<'a>(dict: &'a [DictEntry], original: &[u8], c: &CommonArgs) -> Cow<'a, [DictEntry]> {
+    if !c.ignore_case {
+        return Cow::Borrowed(dict);
+    }
+    match c.normalize_space {
+        true => Cow::Owned(restore_case(dict, &normalize_space(original).data)),
+        false => Cow::Owned(restore_case(dict, original)),
+    }
+}
+
+/// Scan settings to record in, and check against, saved files.
+fn saved_params(c: &CommonArgs) -> SavedParams {
+    let mask = match (&c.strip_regex, c.strip_timestamps) {
+        (Some(re), _) => re.as_str().to_string(),
+        (None, true) => TIMESTAMP_PATTERN.to_string(),
+        (None, false) => String::new(),
+    };
+    SavedParams {
+        window_size: c.window_size as u64,
+        min_match: c.min_match as u64,
+        max_match: MAX_MATCH as u64,
+        delimiter: b'\n',
+        mask,
+        ignore_case: c.ignore_case,
+        normalize_space: c.normalize_space,
+    }
+}
+
+/// Refuse saved data recorded with conflicting scan parameters; warn on
+/// differences that only change results, or when none were recorded.
+fn check_saved_params(path: &str, saved: Option<&SavedParams>, c: &CommonArgs) {
+    let current = saved_params(c);
+    let Some(saved) = saved else {
+        warn!(input = path, "no recorded scan parameters (older format)");
+        return;
+    };
+    let conflicts = saved.conflicts(&current);
+    if !conflicts.is_empty() {
+        eprintln!("error: {path}: saved with different parameters: {}", conflicts.join(", "));
+        std::process::exit(1);
+    }
+    for w in saved.warnings(&current) {
+        warn!(input = path, difference = %w, "saved with different parameters");
+    }
+}
+
+fn run_scan(data: &[u8], c: &CommonArgs) -> Vec<ScanOp> {
+    match &c.ops {
+        Some(path) => {
+            let (ops, saved) = load_ops(path, data).unwrap_or_else(|e| {
+                eprintln!("error: {path}: {e}");
+                std::process::exit(1);
+            });
+            check_saved_params(path, saved.as_ref(), c);
+            ops
+        }
+        None => scan(data, &scan_params(c)),
+    }
+}
+
+fn parse_field_weights(s: &str) -> Result<FieldWeights, String> {
+    match s.strip_prefix('@') {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+            FieldWeights::parse(&text)
+        }
+        None => FieldWeights::parse(s),
+    }
+}
+
+fn field_filter(f: &FieldArgs) -> FieldFilter {
+    FieldFilter {
+        ignore: f.ignore_fields.iter().cloned().collect(),
+        only: (!f.only_fields.is_empty()).then(|| f.only_fields.iter().cloned().collect()),
+    }
+}
+
+/// `--max-depth` and `--max-fields-per-record`.
+fn structure_limit(f: &FieldArgs) -> StructureLimit {
+    StructureLimit {
+        max_depth: f.max_depth.map(|n| n as usize),
+        max_fields: f.max_fields_per_record.map(|n| n as usize),
+    }
+}
+
+/// Report lines for [`Truncations`], aligned like [`print_oversized`].
+fn print_truncations(t: Truncations, f: &FieldArgs, label_width: usize, value_width: usize) {
+    if let Some(max) = f.max_fields_per_record {
+        println!("  {:<label_width$}{:>value_width$}  ({} fields dropped, over {max} fields)",
+            "wide records:", t.wide_records, t.dropped_fields);
+    }
+    if let Some(max) = f.max_depth {
+        println!("  {:<label_width$}{:>value_width$}  (cut, over depth {max})", "deep values:", t.deep_values);
+    }
+}
+
+/// [`Truncations`] for JSON reports, if a structure limit is set.
+fn truncations_json(t: Truncations, f: &FieldArgs) -> Option<serde_json::Value> {
+    (!structure_limit(f).is_empty()).then(|| serde_json::json!({
+        "wide_records": t.wide_records, "dropped_fields": t.dropped_fields,
+        "deep_values": t.deep_values,
+        "max_fields_per_record": f.max_fields_per_record, "max_depth": f.max_depth,
+    }))
+}
+
+/// `--max-record-bytes` and `--oversize`, if a limit is set.
+fn record_limit(c: &CommonArgs) -> Option<RecordLimit> {
+    let policy = if c.oversize == "skip" { Oversize::Skip } else { Oversize::Truncate };
+    c.max_record_bytes.map(|n| RecordLimit { max_bytes: n as usize, policy })
+}
+
+/// Records over `--max-record-bytes` in `data`, for reports.
+fn oversized_count(data: &[u8], c: &CommonArgs) -> Option<usize> {
+    c.max_record_bytes.map(|n| count_oversized(data, b'\n', n as usize))
+}
+
+/// Report line for [`oversized_count`], aligned with the report's other
+/// `label: value` lines.
+fn print_oversized(n: Option<usize>, c: &CommonArgs, label_width: usize, value_width: usize) {
+    if let (Some(n), Some(max)) = (n, c.max_record_bytes) {
+        let action = if c.oversize == "skip" { "skipped" } else { "truncated" };
+        println!("  {:<label_width$}{n:>value_width$}  ({action}, over {max} bytes)", "oversized:");
+    }
+}
+
+/// Parse JSON records, with non-JSON lines read through the
+/// `--extract-pattern` patterns if any.
+fn parse_records<'a>(data: &'a [u8], f: &FieldArgs, limit: Option<RecordLimit>) -> Vec<JsonRecord<'a>> {
+    if f.extract_pattern.is_empty() {
+        return parse_json_records_limited(data, b'\n', limit);
+    }
+    let definitions = f.grok_patterns.as_ref().map_or(Ok(String::new()), |path| {
+        fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))
+    });
+    let extractor = definitions.and_then(|d| Extractor::new(&f.extract_pattern, &d)).unwrap_or_else(|e| {
+        eprintln!("error: {e}");
+        std::process::exit(1);
+    });
+    extractor.records(data, b'\n', limit)
+}
+
+/// Parse JSON records and apply the `--ignore-fields`/`--only-fields`
+/// filter, the `--max-fields-per-record`/`--max-depth` limits,
+/// `--float-precision`, and, if `normalize`, `--normalize-space` to string
+/// values.
+fn parse_filtered<'a>(
+    data: &'a [u8],
+    f: &FieldArgs,
+    normalize: bool,
+    limit: Option<RecordLimit>,
+) -> (Vec<JsonRecord<'a>>, Truncations) {
+    let mut records = parse_records(data, f, limit);
+    field_filter(f).apply(&mut records);
+    let cut = structure_limit(f).apply(&mut records);
+    if let Some(places) = f.float_precision {
+        round_number_values(&mut records, places as usize);
+    }
+    if normalize {
+        normalize_space_values(&mut records);
+    }
+    (records, cut)
+}
+
+/// Score JSON records with the `--field-weights` configuration, against
+/// `schema` or, with `--window-records` or `--chunk-records`, against
+/// schemas that follow the input.
+fn score_weighted(
+    data: &[u8],
+    records: &[JsonRecord],
+    schema: &SchemaProfile,
+    f: &FieldArgs,
+    b: Option<&BaselineArgs>,
+) -> Vec<JsonRecordScore> {
+    let weights = f.field_weights.clone().unwrap_or_default();
+    let mut scored = match b {
+        Some(BaselineArgs { window_records: Some(n), .. }) => {
+            score_json_records_windowed(data, records, &weights, *n as usize)
+        }
+        Some(BaselineArgs { chunk_records: Some(n), chunk_decay, .. }) => {
+            score_json_records_chunked(data, records, &weights, *n as usize, *chunk_decay)
+        }
+        _ => score_json_records_weighted(data, records, schema, &weights),
+    };
+    if let Some(weight) = f.novelty {
+        add_first_seen(&mut scored, records, schema, &weights, weight);
+    }
+    scored
+}
+
+/// Byte range of records `a..b` in `data`, clamped to the input.
+fn record_byte_range(data: &[u8], (a, b): (usize, usize)) -> (usize, usize) {
+    let r = RecordIndex::new(data, b'\n').byte_range(a..b);
+    (r.start, r.end)
+}
+
+/// Output of the LZ77 scan/dictionary/score pipeline.
+struct Lz77Run {
+    ops: Vec<ScanOp>,
+    dict: Vec<DictEntry>,
+    records: Vec<RecordAnalysis>,
+    /// What `ops` were scanned from, if not the input itself.
+    scanned: Option<Scanned>,
+}
+
+/// Input as scanned under `--dedup`, `--normalize-space`, or
+/// `--max-record-bytes`.
+struct Scanned {
+    data: Vec<u8>,
+    /// `(offset, length)` in `data` standing in for each input record
+    /// (`(0, 0)` for skipped ones).
+    bounds: Vec<(usize, usize)>,
+    /// Distinct records, under `--dedup`.
+    distinct: Option<usize>,
+}
+
+/// Bytes the op stream of a run refers to, and `(offset, length)` of each
+/// of `records` within them.
+fn scanned_records<'a>(
+    data: &'a [u8],
+    scanned: Option<&'a Scanned>,
+    records: &[&RecordAnalysis],
+) -> (&'a [u8], Vec<(usize, usize)>) {
+    match scanned {
+        None => (data, records.iter().map(|r| (r.offset, r.length)).collect()),
+        Some(s) => (&s.data, records.iter().map(|r| s.bounds[r.index]).collect()),
+    }
+}
+
+/// Run the LZ77 pipeline over `data`, honouring `--strip-*` masking and a
+/// baseline. With a baseline, the baseline bytes are prepended and used as
+/// the only match source ([`scan_with_baseline`]), the dictionary is built
+/// from the baseline alone, and only `data`'s records are returned, indexed
+/// and offset as if the baseline were not there. `ops` then covers only
+/// `data` and is for summary counts.
+///
+/// With `--normalize-space`, records are scanned with space runs
+/// collapsed and their offsets mapped back to `data`. With `--dedup`, only
+/// the distinct records are scanned and scored, and each input record gets
+/// the analysis of its distinct copy. With `--max-record-bytes`, oversized
+/// records are truncated or dropped first; kept records are indexed and
+/// offset as in `data`.
+fn run_lz77(data: &[u8], c: &CommonArgs, min_count: usize, b: &BaselineArgs, bpb: bool) -> Lz77Run {
+    let limit = record_limit(c);
+    if !c.dedup && !c.normalize_space && limit.is_none() {
+        return scan_and_score(data, c, min_count, b, bpb);
+    }
+    let limited = limit.map(|l| limit_records(data, b'\n', l));
+    let kept = limited.as_ref().map_or(data, |l| &l.data[..]);
+    let norm = c.normalize_space.then(|| normalize_space(kept));
+    let input = norm.as_ref().map_or(kept, |n| &n.data[..]);
+    let bounds = split_records(input, b'\n');
+    let dedup = c.dedup.then(|| dedup_records(input, b'\n'));
+    let scanned = dedup.as_ref().map_or(input, |d| &d.data[..]);
+
+    let mut run = scan_and_score(scanned, c, min_count, b, bpb);
+    let mut scanned_bounds = bounds.clone();
+    if let Some(d) = &dedup {
+        info!(distinct = d.distinct(), records = d.of.len(), "deduplicated records");
+        run.records = expand_duplicates(&run.records, &d.of, &bounds);
+        let copies = split_records(&d.data, b'\n');
+        scanned_bounds = d.of.iter().map(|&i| copies[i]).collect();
+    }
+    if let Some(n) = &norm {
+        for r in &mut run.records {
+            (r.offset, r.length) = n.orig_range(r.offset, r.length);
+        }
+    }
+    if let Some(l) = &limited {
+        let mut bounds = vec![(0, 0); l.total];
+        for (r, &b) in run.records.iter_mut().zip(&scanned_bounds) {
+            (r.index, r.offset) = (l.index[r.index], l.orig_offset(r.offset));
+            bounds[r.index] = b;
+        }
+        scanned_bounds = bounds;
+    }
+    run.scanned = Some(Scanned {
+        data: scanned.to_vec(),
+        bounds: scanned_bounds,
+        distinct: dedup.as_ref().map(Deduped::distinct),
+    });
+    run
+}
+
+fn scan_and_score(data: &[u8], c: &CommonArgs, min_count: usize, b: &BaselineArgs, bpb: bool) -> Lz77Run {
+    let masked = scan_input(data, c);
+
+    let mut base_dict = None;
+    let base: Cow<[u8]> = match (&b.baseline, b.range()) {
+        (Some(path), _) => {
+            let raw = read_bytes(path).0;
+            if is_profile(&raw) {
+                let p = parse_profile(path, &raw);
+                check_saved_params(path, p.params.as_ref(), c);
+                base_dict = Some(p.dictionary);
+                Cow::Owned(p.reference)
+            } else {
+                Cow::Owned(scan_input(&to_utf8(path, raw), c).into_owned())
+            }
+        }
+        (None, Some(range)) => {
+            let (start, end) = record_byte_range(&masked, range);
+            Cow::Borrowed(&masked[start..end])
+        }
+        (None, None) => {
+            let ops = info_span!("scan").in_scope(|| run_scan(&masked, c));
+            let dict = info_span!("dictionary").in_scope(|| build_dictionary(&masked, &ops, min_count));
+            let _score = info_span!("score").entered();
+            let mut records = match b {
+                BaselineArgs { window_records: Some(n), .. } => {
+                    score_records_windowed(&masked, &ops, &dict, b'\n', min_count, *n as usize)
+                }
+                BaselineArgs { chunk_records: Some(n), chunk_decay, .. } => {
+                    score_records_chunked(&masked, &ops, &dict, b'\n', min_count, *n as usize, *chunk_decay)
+                }
+                _ => score_records(&masked, &ops, &dict, b'\n'),
+            };
+            price_records(&masked, &ops, &ops, &mut records, c, bpb);
+            apply_rarity(&mut records, &dict, c);
+            if b.robust {
+                drop(_score);
+                return rescore_robust(&masked, c, min_count, &records, dict.len(), bpb);
+            }
+            return Lz77Run { ops, dict, records, scanned: None };
+        }
+    };
+
+    let mut buf = base.into_owned();
+    if buf.last().is_some_and(|&c| c != b'\n') {
+        buf.push(b'\n');
+    }
+    let split = buf.len();
+    buf.extend_from_slice(&masked);
+
+    info!(bytes = split, "baseline");
+    let ops = info_span!("scan").in_scope(|| scan_with_baseline(&buf, split, &scan_params(c)));
+    let k = ops.partition_point(|o| o.position < split);
+    let dict = info_span!("dictionary")
+        .in_scope(|| base_dict.unwrap_or_else(|| build_dictionary(&buf, &ops[..k], min_count)));
+    let _score = info_span!("score").entered();
+    let mut records = score_records(&buf, &ops, &dict, b'\n');
+    // Only the target's records count toward its rarity statistics.
+    records.retain(|r| r.offset >= split);
+    price_records(&buf, &ops[..k], &ops, &mut records, c, bpb);
+    apply_rarity(&mut records, &dict, c);
+    for (i, r) in records.iter_mut().enumerate() {
+        r.index = i;
+        r.offset -= split;
+    }
+    let ops = ops[k..]
+        .iter()
+        .map(|o| ScanOp { position: o.position - split, ..o.clone() })
+        .collect();
+    Lz77Run { ops, dict, records, scanned: None }
+}
+
+/// `--robust`: score `masked` again with the records `first` flags left out
+/// of the dictionary and matched only against the others
+/// (`scorer::CleanSplit`).
+/// Records and ops come back in `masked`'s order and offsets.
+fn rescore_robust(
+    masked: &[u8],
+    c: &CommonArgs,
+    min_count: usize,
+    first: &[RecordAnalysis],
+    dict_len: usize,
+    bpb: bool,
+) -> Lz77Run {
+    let flagged = detect_anomalies(first, dict_len, DetectionMethod::Score, None, None).anomaly_indices;
+    info!(records = flagged.len(), "robust: left out of the baseline");
+    let split = clean_split(masked, b'\n', &flagged);
+    let buf = &split.data[..];
+    let ops = info_span!("scan").in_scope(|| scan_with_baseline(buf, split.split, &scan_params(c)));
+    let k = ops.partition_point(|o| o.position < split.split);
+    let dict = info_span!("dictionary").in_scope(|| build_dictionary(buf, &ops[..k], min_count));
+    let _score = info_span!("score").entered();
+    let mut records = score_records(buf, &ops, &dict, b'\n');
+    price_records(buf, &ops[..k], &ops, &mut records, c, bpb);
+    apply_rarity(&mut records, &dict, c);
+    split.restore_records(&mut records);
+    Lz77Run { ops: split.restore_ops(&ops), dict, records, scanned: None }
+}
+
+/// `--seed`: each record's similarity to the seed lines in `path`, both
+/// given as tokens. Warns when no seed token occurs in the input.
+fn seed_similarity(path: &str, seeds: &[Vec<String>], records: &[Vec<String>]) -> Vec<f64> {
+    let profile = SeedProfile::new(seeds, records);
+    if profile.is_empty() {
+        warn!(seed = path, "no word or field of the seed lines occurs in the input");
+    }
+    records.iter().map(|r| profile.similarity(r)).collect()
+}
+
+/// Price every record with an entropy model fitted to `fit_ops` (the
+/// baseline, if any), and rescore by it under `--cost-model entropy`.
+/// Skipped unless the caller reports bits per byte (`bpb`) or the cost
+/// model needs it; `bits_per_byte` then stays 0.
+fn price_records(
+    data: &[u8],
+    fit_ops: &[ScanOp],
+    ops: &[ScanOp],
+    records: &mut [RecordAnalysis],
+    c: &CommonArgs,
+    bpb: bool,
+) {
+    if !bpb && c.cost_model != "entropy" {
+        return;
+    }
+    let model = EntropyModel::from_ops(data, fit_ops);
+    let bounds: Vec<(usize, usize)> = records.iter().map(|r| (r.offset, r.length)).collect();
+    set_bits_per_byte(records, &record_bits_per_byte(data, ops, &model, &bounds));
+    if c.cost_model == "entropy" {
+        rescore_with_bits(records);
+    }
+}
+
+/// Replace rank-based rarity with the signal chosen by `--rarity`.
+fn apply_rarity(records: &mut [RecordAnalysis], dict: &[DictEntry], c: &CommonArgs) {
+    let rarity = match c.rarity.as_str() {
+        "rank" => return,
+        "count" => count_rarity(records, dict),
+        "idf" => idf_rarity(records),
+        mode => {
+            let features: Vec<Vec<usize>> = records.iter().map(|r| r.ref_entries.clone()).collect();
+            let vectors = unit_vectors(&features);
+            if mode == "knn" {
+                knn_distances(&vectors, c.knn as usize)
+            } else {
+                centroid_distances(&vectors)
+            }
+        }
+    };
+    set_rarity(records, &rarity);
+}
+
+fn parse_profile(path: &str, raw: &[u8]) -> Profile {
+    read_profile(raw).unwrap_or_else(|e| {
+        eprintln!("error: {path}: {e}");
+        std::process::exit(1);
+    })
+}
+
+/// Schema from the baseline records (all records if no baseline).
+fn baseline_schema(
+    data: &[u8],
+    records: &[JsonRecord],
+    b: &BaselineArgs,
+    f: &FieldArgs,
+    c: &CommonArgs,
+) -> SchemaProfile {
+    match (&b.baseline, b.range()) {
+        (Some(path), _) => {
+            let raw = read_bytes(path).0;
+            if !is_profile(&raw) {
+                let raw = to_utf8(path, raw);
+                return build_schema(&parse_filtered(&raw, f, c.normalize_space, record_limit(c)).0);
+            }
+            parse_profile(path, &raw).schema.unwrap_or_else(|| {
+                eprintln!("error: {path}: profile has no JSON schema");
+                std::process::exit(1);
+            })
+        }
+        (None, Some((a, e))) => {
+            build_schema(&records[a.min(records.len())..e.min(records.len())])
+        }
+        (None, None) if b.robust => {
+            let first = build_schema(records);
+            let scores: Vec<f64> =
+                score_weighted(data, records, &first, f, None).iter().map(|s| s.anomaly_score).collect();
+            let (_, flagged) = detect_indices(&scores, None, DetectionMethod::Score, None, None);
+            info!(records = flagged.len(), "robust: left out of the baseline");
+            let mut clean = vec![true; records.len()];
+            for i in flagged {
+                clean[i] = false;
+            }
+            build_schema(records.iter().zip(clean).filter(|(_, keep)| *keep).map(|(r, _)| r))
+        }
+        (None, None) => build_schema(records),
+    }
+}
+
+/// The detection threshold line of a report, and the low tail's under
+/// `--direction both`.
+fn print_thresholds(threshold: f64, low: Option<f64>, direction: Direction) {
+    let tail = if direction == Direction::Low { "  (low tail)" } else { "" };
+    println!("  threshold:       {threshold:>8.4}{tail}");
+    if let Some(t) = low {
+        println!("  low threshold:   {t:>8.4}");
+    }
+}
+
+/// Print a "score over records" sparkline section, if requested.
+fn print_sparkline(scores: &[f64], width: Option<usize>) {
+    let Some(width) = width else { return };
+    if scores.is_empty() {
+        return;
+    }
+    let line = sparkline(scores, width);
+    let cols = line.chars().count();
+    println!("\n--- Anomaly Score by Record ---");
+    println!("  {line}");
+    let last = format!("{}", scores.len() - 1);
+    println!("  0{last:>w$}", w = cols.saturating_sub(1).max(last.len()));
+}
+
+/// Text heatmap: one row per segment with a coverage bar (if `coverage`)
+/// and an anomaly-density bar scaled to the densest segment.
+fn print_heatmap(segs: &[Segment], coverage: bool) {
+    const BAR: usize = 20;
+    let max_density = segs.iter().map(|s| s.anomaly_density()).fold(0.0, f64::max);
+    println!("\n--- Offset Heatmap ({} segments) ---", segs.len());
+    for s in segs {
+        let cover = if coverage {
+            let n = (s.coverage() * BAR as f64).round() as usize;
+            format!("{:>6.1}% {:BAR$}  ", s.coverage() * 100.0, "█".repeat(n))
+        } else {
+            String::new()
+        };
+        let d = s.anomaly_density();
+        let n = if max_density > 0.0 { (d / max_density * BAR as f64).round() as usize } else { 0 };
+        println!("  [{:>10}..{:>10}]  {cover}{:>5}/{:<6} {:>5.1}% {}",
+            s.start, s.end, s.anomalies, s.records, d * 100.0, "█".repeat(n));
+    }
+}
+
+fn heatmap_json(segs: &[Segment], coverage: bool) -> serde_json::Value {
+    segs.iter().map(|s| {
+        let mut v = serde_json::json!({
+            "start": s.start, "end": s.end, "records": s.records, "anomalies": s.anomalies,
+            "anomaly_density": (s.anomaly_density() * 1e6).round() / 1e6,
+        });
+        if coverage {
+            v["coverage"] = serde_json::json!((s.coverage() * 1e6).round() / 1e6);
+        }
+        v
+    }).collect()
+}
+
+fn is_json_mode(data: &[u8], m: &ModeArgs, f: &FieldArgs) -> bool {
+    if m.structured {
+        return true;
+    }
+    if m.no_structured {
+        return false;
+    }
+    if !f.extract_pattern.is_empty() {
+        return true;
+    }
+    match m.mode.as_str() {
+        "json" => true,
+        "lz77" => false,
+        "mixed" => {
+            eprintln!("error: --mode mixed is only supported by anomalies");
+            std::process::exit(1);
+        }
+        _ => {
+            let json = looks_like_json(data);
+            if is_mixed_format(data) {
+                warn!(analyzed_as = if json { "JSON" } else { "plain text" },
+                    "input mixes JSON and plain lines; all analyzed as one format (anomalies \
+                    analyzes each line by its format)");
+            }
+            json
+        }
+    }
+}
+
+/// Whether `anomalies` should analyze JSON and plain lines separately:
+/// `--mode mixed`, or auto mode on input that interleaves both.
+fn is_mixed_mode(data: &[u8], m: &ModeArgs, f: &FieldArgs) -> bool {
+    match m.mode.as_str() {
+        "mixed" => true,
+        "auto" => !m.structured && !m.no_structured && f.extract_pattern.is_empty() && is_mixed_format(data),
+        _ => false,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// LZ77 commands
+// ---------------------------------------------------------------------------
+
+fn cmd_scan(c: CommonArgs, show_ops: usize, save: Option<String>, annotate: Option<String>) -> i32 {
+    let input = read_common(&c).data;
+    // Borrowed unless preprocessing changed the input.
+    let data = scan_input(&input, &c);
+
+    let t0 = Instant::now();
+    let ops = run_scan(&data, &c);
+    let elapsed = t0.elapsed().as_secs_f64();
+
+    if let Some(path) = &save {
+        if let Err(e) = save_ops(path, &data, &ops, &saved_params(&c)) {
+            eprintln!("error: {path}: {e}");
+            return 1;
+        }
+    }
+    if let Some(path) = &annotate {
+        if let Err(e) = save_annotations(path, &ops) {
+            eprintln!("error: {path}: {e}");
+            return 1;
+        }
+    }
+
+    let n_lit = ops.iter().filter(|o| o.kind == OpKind::Literal).count();
+    let n_ref = ops.iter().filter(|o| o.kind == OpKind::Backref).count();
+    let lit_bytes: usize = ops.iter().filter(|o| o.kind == OpKind::Literal).map(|o| o.length).sum();
+    let ref_bytes: usize = ops.iter().filter(|o| o.kind == OpKind::Backref).map(|o| o.length).sum();
+    let n_overlap = ops.iter().filter(|o| o.is_overlapping()).count();
+    let total = data.len();
+
+    println!("=== LZ77 Scan: {} ===", c.input);
+    println!("  input size:     {total:>10} bytes");
+    println!("  parse:          {:>10}", c.parse);
+    println!("  matcher:        {:>10}", c.matcher);
+    println!("  effort:         {:>10}", c.effort);
+    println!("  scan time:      {elapsed:>10.4}s");
+    println!("  operations:     {:>10}", ops.len());
+    println!("    literals:     {n_lit:>10}  ({lit_bytes} bytes, {:.1}%)", pct(lit_bytes, total));
+    println!("    backrefs:     {n_ref:>10}  ({ref_bytes} bytes, {:.1}%)", pct(ref_bytes, total));
+    println!("      overlapping:{n_overlap:>10}");
+
+    let regions = incompressible_regions(&ops, HIGH_ENTROPY_MIN_LEN);
+    if !regions.is_empty() {
+        let region_bytes: usize = regions.iter().map(|(s, e)| e - s).sum();
+        println!("  high-entropy:   {:>10}  ({region_bytes} bytes, {:.1}%)",
+            regions.len(), pct(region_bytes, total));
+        let mut largest = regions.clone();
+        largest.sort_by_key(|&(s, e)| std::cmp::Reverse(e - s));
+        for (s, e) in largest.iter().take(5) {
+            println!("    [{s:10}..{e:10}]  {} bytes", e - s);
+        }
+    }
+
+    if show_ops > 0 {
+        println!("\n--- Operations (first {show_ops}) ---");
+        for op in ops.iter().take(show_ops) {
+            let preview = op.content(&data);
+            let trunc = preview.len().min(40);
+            let suffix = if preview.len() > 40 { "..." } else { "" };
+            let shown = String::from_utf8_lossy(&preview[..trunc]);
+            match op.kind {
+                OpKind::Backref => println!(
+                    "  [{:8}] BACKREF  len={:4}  off={:6}{}  {shown:?}{suffix}",
+                    op.position, op.length, op.ref_offset,
+                    if op.is_overlapping() { " (run)" } else { "" }
+                ),
+                OpKind::Literal => println!(
+                    "  [{:8}] LITERAL  len={:4}  {shown:?}{suffix}",
+                    op.position, op.length
+                ),
+            }
+        }
+    }
+    0
+}
+
+/// Example record indices listed per entry by `dict --usage`.
+const DICT_USAGE_EXAMPLES: usize = 5;
+
+fn cmd_dict(
+    c: CommonArgs,
+    min_count: usize,
+    top: Option<usize>,
+    format: &str,
+    output: Option<String>,
+    usage: bool,
+) -> i32 {
+    let raw = read_common(&c).data;
+    let data = scan_input(&raw, &c).into_owned();
+    let ops = info_span!("scan").in_scope(|| run_scan(&data, &c));
+    let dict = build_dictionary(&data, &ops, min_count);
+    let shown = shown_dict(&dict, &raw, &c);
+
+    let total_covered: usize = dict.iter().map(|e| e.total_bytes_covered()).sum();
+    let limit = top.unwrap_or(dict.len());
+
+    if format == "zlib-preset" {
+        let preset = zlib_preset(&shown[..limit.min(dict.len())], ZLIB_PRESET_MAX);
+        let res = match &output {
+            Some(path) => fs::write(path, &preset),
+            None => io::stdout().lock().write_all(&preset),
+        };
+        if let Err(e) = res {
+            eprintln!("error: {}: {e}", output.as_deref().unwrap_or("stdout"));
+            return 1;
+        }
+        info!(bytes = preset.len(), "zlib preset");
+        return 0;
+    }
+
+    let usage = usage.then(|| {
+        let records = score_records(&data, &ops, &dict, b'\n');
+        (entry_usage(&records, dict.len(), DICT_USAGE_EXAMPLES), records.len())
+    });
+
+    if format == "json" {
+        let entries: Vec<serde_json::Value> = shown.iter().take(limit).map(|e| {
+            let mut v = serde_json::json!({
+                "id": e.entry_id, "count": e.count, "length": e.content_length(),
+                "total_bytes": e.total_bytes_covered(),
+                "median_interval": e.median_interval(), "mean_interval": e.mean_interval(),
+                "content_preview": String::from_utf8_lossy(&e.content[..e.content.len().min(80)]),
+            });
+            if let Some((u, _)) = &usage {
+                v["records"] = serde_json::json!(u[e.entry_id].records);
+                v["example_records"] = serde_json::json!(u[e.entry_id].examples);
+            }
+            v
+        }).collect();
+        print_json_list("entries", entries);
+    } else {
+        println!("=== Dictionary: {} ===", c.input);
+        println!("  entries:  {}", dict.len());
+        if !data.is_empty() {
+            println!("  total backref bytes covered: {total_covered} / {} ({:.1}%)",
+                data.len(), pct(total_covered, data.len()));
+        }
+        println!("\n--- Top {limit} entries ---");
+        for e in shown.iter().take(limit) {
+            let trunc = e.content.len().min(60);
+            let suffix = if e.content.len() > 60 { "..." } else { "" };
+            let shown = String::from_utf8_lossy(&e.content[..trunc]);
+            println!("  [{:4}]  count={:6}  len={:4}  med_iv={:8.0}  {shown:?}{suffix}",
+                e.entry_id, e.count, e.content_length(), e.median_interval());
+            if let Some((u, n)) = &usage {
+                let u = &u[e.entry_id];
+                let ex: Vec<String> = u.examples.iter().map(|i| i.to_string()).collect();
+                println!("          records={:6} ({:.1}%)  per record={:.2}  e.g. {}",
+                    u.records, pct(u.records, *n),
+                    if u.records > 0 { e.count as f64 / u.records as f64 } else { 0.0 },
+                    ex.join(", "));
+            }
+        }
+    }
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Savings command
+// ---------------------------------------------------------------------------
+
+/// Deflate each record alone with and without a preset dictionary built
+/// from this corpus, on the corpus itself and optionally a held-out file.
+fn cmd_savings(a: SavingsArgs) -> i32 {
+    let c = &a.common;
+    let data = read_common(c).data;
+    let masked = scan_input(&data, c);
+    let ops = run_scan(&masked, c);
+    let dict = build_dictionary(&masked, &ops, a.min_count);
+    let k = a.top.unwrap_or(dict.len()).min(dict.len());
+    let preset = zlib_preset(&shown_dict(&dict, &data, c)[..k], ZLIB_PRESET_MAX);
+
+    let measure = |path: &str, bytes: &[u8]| {
+        let recs = split_records(bytes, b'\n');
+        match estimate_savings(bytes, &recs, &preset, a.sample) {
+            Ok(est) => (est, recs.len()),
+            Err(e) => {
+                eprintln!("error: {path}: {e}");
+                std::process::exit(1);
+            }
+        }
+    };
+    let mut rows = vec![(c.input.clone(), measure(&c.input, &data))];
+    if let Some(path) = &a.holdout {
+        rows.push((path.clone(), measure(path, &read_input(path))));
+    }
+
+    if a.json {
+        let r6 = |x: f64| (x * 1e6).round() / 1e6;
+        let files: Vec<serde_json::Value> = rows.iter().map(|(path, (e, total))| serde_json::json!({
+            "input": path, "records": total, "sampled_records": e.records,
+            "raw_bytes": e.raw_bytes, "deflate_bytes": e.plain_bytes,
+            "preset_deflate_bytes": e.preset_bytes, "saved_bytes": e.saved(),
+            "saved_pct": r6(e.saved_pct()), "projected_saved_bytes": e.projected_saved(*total).round(),
+        })).collect();
+        let out = serde_json::json!({
+            "dict_entries": k, "preset_bytes": preset.len(), "files": files,
+        });
+        print_json(out);
+        return 0;
+    }
+
+    println!("=== Preset Dictionary Savings: {} ===", c.input);
+    println!("  dict entries:   {k:>10}  (of {})", dict.len());
+    println!("  preset size:    {:>10} bytes", preset.len());
+    println!("  (each record deflated alone, with vs without the preset)");
+    for (path, (e, total)) in &rows {
+        print_savings(path, e, *total);
+    }
+    0
+}
+
+fn print_savings(path: &str, e: &SavingsEstimate, total: usize) {
+    println!("\n--- {path} ---");
+    println!("  records:        {total:>10}  ({} sampled)", e.records);
+    println!("  raw:            {:>10} bytes", e.raw_bytes);
+    println!("  deflate:        {:>10} bytes", e.plain_bytes);
+    println!("  with preset:    {:>10} bytes", e.preset_bytes);
+    println!("  saved:          {:>10} bytes  ({:.1}%)", e.saved(), e.saved_pct());
+    if e.records < total {
+        println!("  projected:      {:>10.0} bytes over all records", e.projected_saved(total));
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Similar command
+// ---------------------------------------------------------------------------
+
+/// Nearest records to `--record N` by NCD, with deflate primed by the
+/// corpus preset dictionary.
+fn cmd_similar(a: SimilarArgs) -> i32 {
+    let c = &a.common;
+    let data = read_common(c).data;
+    let masked = scan_input(&data, c);
+    let ops = run_scan(&masked, c);
+    let dict = build_dictionary(&masked, &ops, a.min_count);
+    let preset = zlib_preset(&dict, ZLIB_PRESET_MAX);
+    let recs = split_records(&masked, b'\n');
+    if a.record >= recs.len() {
+        eprintln!("error: {}: record {} out of range ({} records)", c.input, a.record, recs.len());
+        return 1;
+    }
+    let near = match info_span!("ncd").in_scope(|| nearest_records(&masked, &recs, a.record, &preset, a.top_n)) {
+        Ok(n) => n,
+        Err(e) => {
+            eprintln!("error: {}: {e}", c.input);
+            return 1;
+        }
+    };
+    let orig = split_records(&data, b'\n');
+    let line = |i: usize| {
+        let (off, len) = orig[i];
+        String::from_utf8_lossy(&data[off..off + len]).trim_end().to_string()
+    };
+
+    if a.json {
+        let neighbors: Vec<serde_json::Value> = near.iter().map(|&(i, d)| serde_json::json!({
+            "index": i, "ncd": (d * 1e6).round() / 1e6, "content": line(i),
+        })).collect();
+        let out = serde_json::json!({
+            "input": c.input, "record": a.record, "content": line(a.record),
+            "preset_bytes": preset.len(), "neighbors": neighbors,
+        });
+        print_json(out);
+        return 0;
+    }
+    println!("=== Similar to record {}: {} ===", a.record, c.input);
+    println!("  {}", line(a.record));
+    println!("  (NCD with a {} byte preset dictionary; 0 = identical)", preset.len());
+    println!();
+    for (rank, &(i, d)) in near.iter().enumerate() {
+        let l = line(i);
+        let shown = if l.len() > 110 { format!("{}...", &l[..107]) } else { l };
+        println!("  {:3}. [{i:6}]  ncd={d:.4}  {shown}", rank + 1);
+    }
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Cluster command
+// ---------------------------------------------------------------------------
+
+/// k-means iteration cap for `cluster`.
+const CLUSTER_MAX_ITER: usize = 50;
+
+/// Cluster records by referenced dictionary entries (LZ77) or template
+/// tokens, i.e. field names and low-cardinality `field=value` pairs (JSON).
+fn cmd_cluster(a: ClusterArgs) -> i32 {
+    let c = &a.common;
+    let data = read_common(c).data;
+    let json_mode = is_json_mode(&data, &a.mode, &a.fields);
+
+    let (bounds, features): (Vec<(usize, usize)>, Vec<Vec<usize>>) = if json_mode {
+        let (records, _) = parse_filtered(&data, &a.fields, c.normalize_space, record_limit(c));
+        let schema = baseline_schema(&data, &records, &a.baseline, &a.fields, c);
+        let mut vocab: HashMap<String, usize> = HashMap::new();
+        let features = records.iter().map(|r| {
+            record_template(r, &schema).split(',').map(|t| {
+                let n = vocab.len();
+                *vocab.entry(t.to_string()).or_insert(n)
+            }).collect()
+        }).collect();
+        (records.iter().map(|r| (r.offset, r.length)).collect(), features)
+    } else {
+        let records = run_lz77(&data, c, a.min_count, &a.baseline, false).records;
+        let bounds = records.iter().map(|r| (r.offset, r.length)).collect();
+        (bounds, records.into_iter().map(|r| r.ref_entries).collect())
+    };
+
+    let cl = info_span!("kmeans").in_scope(|| kmeans(&unit_vectors(&features), a.k as usize, CLUSTER_MAX_ITER));
+    info!(iterations = cl.iterations, "kmeans done");
+    let n = bounds.len();
+    let tiny_max = ((n as f64 * a.tiny).floor() as usize).max(1);
+    let tiny: Vec<usize> = (0..cl.sizes.len()).filter(|&k| cl.sizes[k] <= tiny_max).collect();
+    let unassigned = cl.unassigned();
+    let line = |i: usize| {
+        let (off, len) = bounds[i];
+        String::from_utf8_lossy(&data[off..off + len]).trim_end().to_string()
+    };
+    let mode = if json_mode { "json" } else { "lz77" };
+
+    if a.json {
+        let clusters: Vec<serde_json::Value> = cl.sizes.iter().enumerate().map(|(k, &size)| {
+            let mut v = serde_json::json!({
+                "cluster": k, "size": size, "tiny": size <= tiny_max,
+                "representative": cl.representatives[k],
+                "representative_content": line(cl.representatives[k]),
+            });
+            if size <= tiny_max {
+                v["members"] = serde_json::json!(cl.members(k));
+            }
+            v
+        }).collect();
+        let out = serde_json::json!({
+            "mode": mode, "input": c.input, "records": n, "k": a.k,
+            "iterations": cl.iterations, "tiny_max_size": tiny_max,
+            "clusters": clusters, "unassigned": unassigned,
+        });
+        print_json(out);
+        return 0;
+    }
+
+    let shorten = |l: String| if l.len() > 100 { format!("{}...", &l[..97]) } else { l };
+    println!("=== Clusters ({mode}, k={}): {} ===", a.k, c.input);
+    println!("  records:        {n:>10}");
+    println!("  clusters:       {:>10}  ({} iterations)", cl.sizes.len(), cl.iterations);
+    println!("  no features:    {:>10}", unassigned.len());
+    println!("\n--- Clusters (largest first) ---");
+    for (k, &size) in cl.sizes.iter().enumerate() {
+        println!("  #{k:<3} {size:>7}  {:5.1}%  [{:6}]  {}", pct(size, n),
+            cl.representatives[k], shorten(line(cl.representatives[k])));
+    }
+    if !tiny.is_empty() {
+        println!("\n--- Candidate anomalies (clusters of <= {tiny_max} records) ---");
+    }
+    let list = |label: String, members: &[usize]| {
+        println!("  {label}: {} record(s)", members.len());
+        for &i in members.iter().take(a.show) {
+            println!("    [{i:6}]  {}", shorten(line(i)));
+        }
+        if members.len() > a.show {
+            println!("    ... {} more", members.len() - a.show);
+        }
+    };
+    for &k in &tiny {
+        list(format!("#{k}"), &cl.members(k));
+    }
+    if !unassigned.is_empty() {
+        println!("\n--- Records with no features (no dictionary entries / fields) ---");
+        list("unclustered".to_string(), &unassigned);
+    }
+    0
+}
+
+/// Per-file sizes plus record and (if given) anomaly counts for archive
+/// input.
+fn print_files(files: &[ArchiveMember], records: &[usize], anomalies: Option<&[usize]>) {
+    if files.is_empty() {
+        return;
+    }
+    let recs = archive_counts(files, records);
+    let anoms = anomalies.map(|a| archive_counts(files, a));
+    println!("\n--- Archive Files ---");
+    print!("  {:>12} {:>8}", "bytes", "records");
+    if anoms.is_some() {
+        print!(" {:>9}", "anomalies");
+    }
+    println!("  name");
+    for (i, f) in files.iter().enumerate() {
+        print!("  {:>12} {:>8}", f.len, recs[i]);
+        if let Some(a) = &anoms {
+            print!(" {:>9}", a[i]);
+        }
+        println!("  {}", f.name);
+    }
+}
+
+fn files_json(files: &[ArchiveMember], records: &[usize], anomalies: Option<&[usize]>) -> serde_json::Value {
+    let recs = archive_counts(files, records);
+    let anoms = anomalies.map(|a| archive_counts(files, a));
+    files.iter().enumerate().map(|(i, f)| {
+        let mut v = serde_json::json!({
+            "name": f.name, "offset": f.offset, "bytes": f.len, "records": recs[i],
+        });
+        if let Some(a) = &anoms {
+            v["anomalies"] = serde_json::json!(a[i]);
+        }
+        v
+    }).collect()
+}
+
+// ---------------------------------------------------------------------------
+// Compress command
+// ---------------------------------------------------------------------------
+
+/// Write a .bgtz container and compare its size with plain deflate and with
+/// what the LZ77 analysis predicts (entropy model over the scan ops).
+fn cmd_compress(a: CompressArgs) -> i32 {
+    let c = &a.common;
+    // Stored as is: the container must round-trip the input byte for byte
+    // (or its first --max-records records, read without the rest).
+    let data = match c.max_records {
+        Some(n) => {
+            let lines = fs::File::open(&c.input).and_then(LineReader::new).and_then(|mut r| {
+                r.read_lines(n)?;
+                Ok(r.into_parts().0)
+            });
+            match lines {
+                Ok(d) => d,
+                Err(e) => {
+                    eprintln!("error: {}: {e}", c.input);
+                    return 1;
+                }
+            }
+        }
+        None => read_bytes(&c.input).0,
+    };
+    let masked = scan_input(&data, c);
+    let analyze = info_span!("analyze").entered();
+    let ops = run_scan(&masked, c);
+    let dict = build_dictionary(&masked, &ops, a.min_count);
+    let k = a.top.unwrap_or(dict.len()).min(dict.len());
+    let preset = zlib_preset(&shown_dict(&dict, &data, c)[..k], ZLIB_PRESET_MAX);
+    let estimate = EntropyModel::from_ops(&masked, &ops).total_bits(&masked, &ops) / 8.0;
+    drop(analyze);
+    let compress = info_span!("compress").entered();
+
+    let output = a.output.clone().unwrap_or_else(|| format!("{}.bgtz", c.input));
+    let mut file = Vec::new();
+    let written = write_container(&mut file, &data, &preset, a.level)
+        .and_then(|sizes| fs::write(&output, &file).map(|_| sizes));
+    let sizes = match written {
+        Ok(sizes) => sizes,
+        Err(e) => {
+            eprintln!("error: {output}: {e}");
+            return 1;
+        }
+    };
+    let plain = deflate(&data, b"", a.level).map(|p| p.len()).unwrap_or(0);
+    drop(compress);
+
+    let size = sizes.total();
+    let bpb = |n: f64| if data.is_empty() { 0.0 } else { n * 8.0 / data.len() as f64 };
+    if a.json {
+        let r6 = |x: f64| (x * 1e6).round() / 1e6;
+        let out = serde_json::json!({
+            "input": c.input, "output": output, "input_bytes": data.len(),
+            "dict_entries": k, "preset_bytes": preset.len(), "container_bytes": size,
+            "header_bytes": sizes.header, "packed_dict_bytes": sizes.dict, "payload_bytes": sizes.payload,
+            "bits_per_byte": r6(bpb(size as f64)), "deflate_bytes": plain,
+            "payload_vs_deflate_bytes": sizes.payload as i64 - plain as i64,
+            "container_vs_deflate_bytes": size as i64 - plain as i64,
+            "lz77_estimate_bytes": estimate.round(),
+        });
+        print_json(out);
+        return 0;
+    }
+    println!("=== Compress: {} -> {output} ===", c.input);
+    println!("  input:          {:>10} bytes", data.len());
+    println!("  preset dict:    {:>10} bytes  ({k} entries)", preset.len());
+    println!("  container:      {:>10} bytes  ({:.3} bits/byte, {:.1}% of input)",
+        size, bpb(size as f64), pct(size, data.len()));
+    println!("    header:       {:>10} bytes", sizes.header);
+    println!("    dictionary:   {:>10} bytes  (preset dict, deflated)", sizes.dict);
+    println!("    payload:      {:>10} bytes  (deflate primed with the preset)", sizes.payload);
+    println!("  plain deflate:  {plain:>10} bytes  (same level, no dictionary)");
+    println!("  vs deflate:     {:>+10} bytes payload, {:+} bytes container",
+        sizes.payload as i64 - plain as i64, size as i64 - plain as i64);
+    if size > plain {
+        println!("  note: the embedded dictionary ({} bytes) outweighs what it saves the payload ({} bytes)",
+            sizes.dict, plain.saturating_sub(sizes.payload));
+    }
+    println!("  LZ77 estimate:  {:>10.0} bytes  ({:.3} bits/byte, entropy model)",
+        estimate, bpb(estimate));
+    0
+}
+
+fn cmd_decompress(input: String, output: Option<String>) -> i32 {
+    let output = match output.or_else(|| input.strip_suffix(".bgtz").map(str::to_string)) {
+        Some(o) => o,
+        None => {
+            eprintln!("error: {input}: no .bgtz suffix to strip; use -o FILE");
+            return 1;
+        }
+    };
+    let data = match decompress(&read_bytes(&input).0) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("error: {input}: {e}");
+            return 1;
+        }
+    };
+    let res = if output == "-" {
+        io::stdout().lock().write_all(&data)
+    } else {
+        fs::write(&output, &data)
+    };
+    if let Err(e) = res {
+        eprintln!("error: {output}: {e}");
+        return 1;
+    }
+    0
+}
+
+fn cmd_verify(input: String, original: Option<String>) -> i32 {
+    let file = read_bytes(&input).0;
+    let t0 = Instant::now();
+    let header = read_header(&file).and_then(|h| decompress(&file).map(|d| (h, d)));
+    let (h, data) = match header {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("error: {input}: {e}");
+            return 1;
+        }
+    };
+    let elapsed = t0.elapsed().as_secs_f64();
+    if let Some(path) = &original {
+        let orig = read_bytes(path).0;
+        if let Some(at) = orig.iter().zip(&data).position(|(a, b)| a != b) {
+            eprintln!("error: {input}: differs from {path} at byte {at}");
+            return 1;
+        }
+        if orig.len() != data.len() {
+            eprintln!("error: {input}: {} bytes, but {path} has {}", data.len(), orig.len());
+            return 1;
+        }
+    }
+    println!("=== Verify: {input} ===");
+    println!("  version:        {:>10}", h.version);
+    println!("  original:       {:>10} bytes", h.orig_len);
+    println!("  container:      {:>10} bytes  ({:.1}%)", file.len(), pct(file.len(), h.orig_len));
+    println!("  preset dict:    {:>10} bytes", h.dict.len());
+    println!("  checksum:       {:016x}  ok", h.checksum);
+    if let Some(path) = &original {
+        println!("  matches:        {path}");
+    }
+    println!("  decompressed in {elapsed:.4}s");
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Stats command
+// ---------------------------------------------------------------------------
+
+/// Corpus-level summary. Skips per-record scoring and detection: only the
+/// scan, the dictionary, and per-record coverage are computed.
+fn cmd_stats(c: CommonArgs, min_count: usize, json: bool) -> i32 {
+    let raw = read_common(&c).data;
+    let data = scan_input(&raw, &c).into_owned();
+    let t0 = Instant::now();
+    let ops = run_scan(&data, &c);
+    let elapsed = t0.elapsed().as_secs_f64();
+    let dict = build_dictionary(&data, &ops, min_count);
+
+    let ref_bytes: usize = ops.iter().filter(|o| o.kind == OpKind::Backref).map(|o| o.length).sum();
+    let entropy = byte_entropy(&data);
+    let mut cov = record_coverage(&data, &ops, b'\n');
+    cov.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let q = |p: f64| match cov.len() {
+        0 => 0.0,
+        n => cov[((n - 1) as f64 * p).round() as usize],
+    };
+    let mean = if cov.is_empty() { 0.0 } else { cov.iter().sum::<f64>() / cov.len() as f64 };
+    let r6 = |x: f64| (x * 1e6).round() / 1e6;
+    let shown = shown_dict(&dict, &raw, &c);
+    let top: Vec<&DictEntry> = shown.iter().take(10).collect();
+
+    if json {
+        let entries: Vec<serde_json::Value> = top.iter().map(|e| serde_json::json!({
+            "id": e.entry_id, "count": e.count, "length": e.content_length(),
+            "total_bytes": e.total_bytes_covered(),
+            "content_preview": String::from_utf8_lossy(&e.content[..e.content.len().min(80)]),
+        })).collect();
+        let out = serde_json::json!({
+            "input": c.input,
+            "size": data.len(),
+            "records": cov.len(),
+            "scan_seconds": r6(elapsed),
+            "backref_coverage": r6(ref_bytes as f64 / data.len().max(1) as f64),
+            "entropy_bits_per_byte": r6(entropy),
+            "record_coverage": {
+                "mean": r6(mean), "min": r6(q(0.0)), "p10": r6(q(0.1)),
+                "median": r6(q(0.5)), "p90": r6(q(0.9)), "max": r6(q(1.0)),
+            },
+            "dict_entries": dict.len(),
+            "top_entries": entries,
+        });
+        print_json(out);
+        return 0;
+    }
+
+    println!("=== Stats: {} ===", c.input);
+    println!("  input size:     {:>10} bytes", data.len());
+    println!("  records:        {:>10}", cov.len());
+    println!("  scan time:      {elapsed:>10.4}s");
+    println!("  backref cover:  {:>9.1}%", pct(ref_bytes, data.len()));
+    println!("  entropy:        {entropy:>10.3} bits/byte");
+    println!("  dict entries:   {:>10}", dict.len());
+    if !cov.is_empty() {
+        println!("\n--- Record Coverage ---");
+        println!("  mean {mean:.4}  min {:.4}  p10 {:.4}  median {:.4}  p90 {:.4}  max {:.4}",
+            q(0.0), q(0.1), q(0.5), q(0.9), q(1.0));
+    }
+    if !top.is_empty() {
+        println!("\n--- Top {} Dictionary Entries ---", top.len());
+        for e in &top {
+            let trunc = e.content.len().min(50);
+            let suffix = if e.content.len() > 50 { "..." } else { "" };
+            let shown = String::from_utf8_lossy(&e.content[..trunc]);
+            println!("  [{:4}]  count={:6}  len={:4}  {shown:?}{suffix}",
+                e.entry_id, e.count, e.content_length());
+        }
+    }
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Bench command
+// ---------------------------------------------------------------------------
+
+const BENCH_STAGES: [&str; 4] = ["scan", "dict", "score", "detect"];
+
+fn format_window(w: usize) -> String {
+    match w {
+        UNBOUNDED_WINDOW => "unbounded".to_string(),
+        w if w >= 1 << 20 && w % (1 << 20) == 0 => format!("{}M", w >> 20),
+        w if w >= 1 << 10 && w % (1 << 10) == 0 => format!("{}K", w >> 10),
+        w => w.to_string(),
+    }
+}
+
+/// Run the LZ77 pipeline (scan, dictionary, scoring, detection) once per
+/// window/effort combination, timing each stage and tracking peak heap use.
+/// Other scan flags (`--parse`, `--matcher`, `--min-match`, masking) apply
+/// to every run.
+fn cmd_bench(c: CommonArgs, windows: Vec<usize>, efforts: Vec<u8>, min_count: usize, json: bool) -> i32 {
+    let data = read_common(&c).data;
+    let data = scan_input(&data, &c).into_owned();
+    let mb = data.len() as f64 / (1024.0 * 1024.0);
+    let mut rows = Vec::new();
+
+    for &window in &windows {
+        for &effort in &efforts {
+            let mut params = scan_params(&CommonArgs { effort, ..c.clone() });
+            params.window_size = window;
+            let base = reset_heap_peak();
+            let mut secs = [0.0f64; 4];
+
+            let t = Instant::now();
+            let ops = scan(&data, &params);
+            secs[0] = t.elapsed().as_secs_f64();
+            let t = Instant::now();
+            let dict = build_dictionary(&data, &ops, min_count);
+            secs[1] = t.elapsed().as_secs_f64();
+            let t = Instant::now();
+            let records = score_records(&data, &ops, &dict, b'\n');
+            secs[2] = t.elapsed().as_secs_f64();
+            let t = Instant::now();
+            let report = detect_anomalies(&records, dict.len(), DetectionMethod::Score, None, None);
+            secs[3] = t.elapsed().as_secs_f64();
+
+            let peak = HEAP_PEAK.load(Ordering::Relaxed).saturating_sub(base);
+            let ref_bytes: usize =
+                ops.iter().filter(|o| o.kind == OpKind::Backref).map(|o| o.length).sum();
+            info!(window = %format_window(window), effort, anomalies = report.anomaly_count, "bench run");
+            rows.push((window, effort, secs, peak, pct(ref_bytes, data.len())));
+        }
+    }
+
+    let mbps = |s: f64| if s > 0.0 { mb / s } else { f64::INFINITY };
+    if json {
+        let r6 = |x: f64| (x * 1e6).round() / 1e6;
+        let out: Vec<serde_json::Value> = rows.iter().map(|&(w, e, secs, peak, cover)| {
+            let total: f64 = secs.iter().sum();
+            let stages: serde_json::Map<String, serde_json::Value> = BENCH_STAGES.iter().zip(secs)
+                .map(|(name, s)| (name.to_string(), serde_json::json!(r6(s))))
+                .collect();
+            serde_json::json!({
+                "window": format_window(w), "effort": e,
+                "seconds": stages, "total_seconds": r6(total),
+                "mb_per_sec": r6(mbps(total)), "peak_heap_bytes": peak,
+                "backref_coverage_pct": r6(cover),
+            })
+        }).collect();
+        print_json_list("runs", out);
+        return 0;
+    }
+
+    println!("=== Bench: {} ({} bytes) ===", c.input, data.len());
+    println!("  {:>9} {:>6} {:>8} {:>8} {:>8} {:>8} {:>9} {:>10} {:>7}",
+        "window", "effort", "scan", "dict", "score", "detect", "MB/s", "peak heap", "cover");
+    for (w, e, secs, peak, cover) in &rows {
+        let total: f64 = secs.iter().sum();
+        println!("  {:>9} {e:>6} {:>7.3}s {:>7.3}s {:>7.3}s {:>7.3}s {:>9.1} {:>9.1}M {cover:>6.1}%",
+            format_window(*w), secs[0], secs[1], secs[2], secs[3], mbps(total),
+            *peak as f64 / (1024.0 * 1024.0));
+    }
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Tune command
+// ---------------------------------------------------------------------------
+
+/// Record indices from a `--labels` file, sorted and deduplicated.
+fn read_labels(path: &str) -> Result<Vec<usize>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut labels = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        labels.push(line.parse().map_err(|_| format!("{path}:{}: '{line}' is not a record index", n + 1))?);
+    }
+    labels.sort_unstable();
+    labels.dedup();
+    Ok(labels)
+}
+
+/// Run the LZ77 pipeline for every `--windows` x `--min-matches` setting,
+/// flag records at each `--sigmas` threshold, and rank the trials
+/// ([`bgtzip::tune::assess`]).
+fn cmd_tune(a: TuneArgs) -> i32 {
+    let c = &a.common;
+    if c.ops.is_some() {
+        eprintln!("error: tune rescans the input for every setting; --ops cannot be used");
+        return 1;
+    }
+    if a.sigmas.iter().any(|k| !k.is_finite()) {
+        eprintln!("error: --sigmas must be numbers");
+        return 1;
+    }
+    let labels = match a.labels.as_deref().map(read_labels).transpose() {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("error: {e}");
+            return 1;
+        }
+    };
+    let data = read_common(c).data;
+
+    // (window, min-match, sigma, threshold) of each trial.
+    let mut settings = Vec::new();
+    let mut trials = Vec::new();
+    let mut records = 0;
+    for (wi, &window) in a.windows.iter().enumerate() {
+        for (mi, &min_match) in a.min_matches.iter().enumerate() {
+            let c = CommonArgs { window_size: window, min_match, ..c.clone() };
+            let run = run_lz77(&data, &c, a.min_count, &a.baseline, false);
+            records = run.records.len();
+            let scores: Vec<f64> = run.records.iter().map(|r| r.anomaly_score).collect();
+            let z = standard_scores(&scores);
+            info!(window = %format_window(window), min_match, records, "tune run");
+            for (si, &k) in a.sigmas.iter().enumerate() {
+                let mut flagged: Vec<usize> =
+                    z.iter().enumerate().filter(|(_, &z)| z >= k).map(|(i, _)| run.records[i].index).collect();
+                flagged.sort_unstable();
+                // The score at k standard deviations, for --threshold.
+                let threshold = sigma_score(&scores, k).unwrap_or(if k > 0.0 { 1.0 } else { 0.0 });
+                settings.push((window, min_match, k, threshold));
+                trials.push(Trial { at: [wi, mi, si], flagged });
+            }
+        }
+    }
+    if let Some(l) = &labels {
+        if let Some(&i) = l.iter().find(|&&i| i >= records) {
+            warn!(labels = a.labels.as_deref().unwrap_or(""), record = i, records, "label past the last record");
+        }
+    }
+    let assessed = assess(&trials, records, labels.as_deref());
+    let Some(best) = best(&assessed) else {
+        eprintln!("error: nothing to try");
+        return 1;
+    };
+    let mut order: Vec<usize> = (0..trials.len()).collect();
+    order.sort_by(|&x, &y| {
+        let (x, y) = (&assessed[x], &assessed[y]);
+        y.quality.total_cmp(&x.quality).then(y.stability.total_cmp(&x.stability))
+    });
+
+    if a.json {
+        let r6 = |x: f64| (x * 1e6).round() / 1e6;
+        let rows: Vec<serde_json::Value> = settings.iter().zip(&trials).zip(&assessed)
+            .map(|(((w, m, k, t), trial), s)| {
+                let mut v = serde_json::json!({
+                    "window": format_window(*w), "min_match": m, "sigma": k, "threshold": r6(*t),
+                    "flagged": trial.flagged.len(), "rate": r6(s.rate),
+                    "stability": r6(s.stability), "quality": r6(s.quality),
+                });
+                if let Some((p, r, f)) = s.labeled {
+                    v["precision"] = serde_json::json!(r6(p));
+                    v["recall"] = serde_json::json!(r6(r));
+                    v["f1"] = serde_json::json!(r6(f));
+                }
+                v
+            })
+            .collect();
+        let (w, m, _, t) = settings[best];
+        print_json(serde_json::json!({
+            "input": c.input, "records": records, "labels": labels.as_ref().map(Vec::len),
+            "trials": rows,
+            "recommended": {
+                "window_size": format_window(w), "min_match": m, "method": "score", "threshold": r6(t),
+            },
+        }));
+        return 0;
+    }
+
+    println!("=== Tune: {} ({records} records, {} settings) ===", c.input, trials.len());
+    match &labels {
+        Some(l) => println!("  ranked by F1 against {} labeled records", l.len()),
+        None => println!("  ranked by stability, for flag rates of {:.1}%-{:.0}%",
+            tune::MIN_RATE * 100.0, tune::MAX_RATE * 100.0),
+    }
+    println!("  {:>9} {:>9} {:>5} {:>9} {:>7} {:>7} {:>9} {:>7}",
+        "window", "min-match", "sigma", "threshold", "flagged", "rate", "stability",
+        if labels.is_some() { "F1" } else { "quality" });
+    for &i in order.iter().take(a.top) {
+        let ((w, m, k, t), s) = (settings[i], &assessed[i]);
+        println!("  {:>9} {m:>9} {k:>5.1} {t:>9.4} {:>7} {:>6.2}% {:>9.3} {:>7.3}{}",
+            format_window(w), trials[i].flagged.len(), s.rate * 100.0, s.stability, s.quality,
+            s.labeled.map_or(String::new(), |(p, r, _)| format!("  (precision {p:.2}, recall {r:.2})")));
+    }
+    if trials.len() > a.top {
+        println!("  ... {} more", trials.len() - a.top);
+    }
+    let (w, m, k, t) = settings[best];
+    println!("\nRecommended configuration (score >= mean + {k}σ):");
+    println!("  --window-size {}", format_window(w));
+    println!("  --min-match {m}");
+    println!("  --method score");
+    println!("  --threshold {t:.4}");
+    0
+}
+
+/// The score `k` sample standard deviations above the mean; `None` when the
+/// scores do not vary.
+fn sigma_score(scores: &[f64], k: f64) -> Option<f64> {
+    let n = scores.len() as f64;
+    let mean = scores.iter().sum::<f64>() / n;
+    let sd = (scores.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    (sd > 0.0 && sd.is_finite()).then_some(mean + k * sd)
+}
+
+// ---------------------------------------------------------------------------
+// Annotate command
+// ---------------------------------------------------------------------------
+
+/// Print the records in `--line-range` with each back-reference wrapped in
+/// `«...»` and tagged with the dictionary entry it resolves to (`#-` if it
+/// is not in the dictionary). A leading `…` marks a span continuing a match
+/// that began in an earlier record.
+fn cmd_annotate(a: AnnotateArgs) -> i32 {
+    let c = &a.common;
+    let data = read_common(c).data;
+    let Lz77Run { ops, dict, records, scanned } = run_lz77(&data, c, a.min_count, &a.baseline, true);
+    let (lo, hi) = a.line_range;
+    let shown = &records[lo.min(records.len())..hi.min(records.len())];
+    let (scanned, bounds) =
+        scanned_records(&data, scanned.as_ref(), &shown.iter().collect::<Vec<_>>());
+    let masked = scan_input(scanned, c);
+    let spans = record_spans(&masked, &ops, &dict, &bounds);
+
+    println!("=== Annotate: {} (records {lo}..{hi} of {}) ===", c.input, records.len());
+    println!("  «text»#N = backref resolving to dictionary entry N (#- = not in dictionary)");
+    for (r, spans) in shown.iter().zip(&spans) {
+        let mut line = String::new();
+        for sp in spans {
+            let text = String::from_utf8_lossy(&scanned[sp.start..sp.end]);
+            let text = text.trim_end_matches('\n');
+            if text.is_empty() {
+                continue;
+            }
+            match sp.kind {
+                OpKind::Literal => line.push_str(text),
+                OpKind::Backref => {
+                    let id = sp.entry_id.map_or("-".to_string(), |id| id.to_string());
+                    let cont = if sp.continued { "…" } else { "" };
+                    line.push_str(&format!("«{cont}{text}»#{id}"));
+                }
+            }
+        }
+        println!("\n  [{:6}]  score={:.4}  cov={:.2}  bpb={:.2}",
+            r.index, r.anomaly_score, r.coverage, r.bits_per_byte);
+        println!("    {line}");
+    }
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Zstd command
+// ---------------------------------------------------------------------------
+
+fn cmd_zstd(input: String, show_blocks: bool, json: bool) -> i32 {
+    let data = match fs::read(&input) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("error: {input}: {e}");
+            return 1;
+        }
+    };
+    if !is_zstd(&data) {
+        eprintln!("error: {input}: not a zstd file");
+        return 1;
+    }
+    let frames = match parse_frames(&data) {
+        Ok(f) => f,
+        Err(e) => {
+            eprintln!("error: {input}: {e}");
+            return 1;
+        }
+    };
+    const TYPES: [BlockType; 3] = [BlockType::Raw, BlockType::Rle, BlockType::Compressed];
+
+    if json {
+        let out: Vec<serde_json::Value> = frames.iter().map(|f| {
+            let mut v = serde_json::json!({
+                "offset": f.offset, "bytes": f.len, "skippable": f.skippable,
+                "window_size": f.window_size, "dictionary_id": f.dictionary_id,
+                "content_size": f.content_size, "checksum": f.checksum,
+                "ratio": f.ratio().map(|r| (r * 1e6).round() / 1e6),
+                "block_counts": TYPES.iter()
+                    .map(|&t| (t.name().to_string(), serde_json::json!(f.block_count(t))))
+                    .collect::<serde_json::Map<_, _>>(),
+            });
+            if show_blocks {
+                v["blocks"] = f.blocks.iter().map(|b| serde_json::json!({
+                    "offset": b.offset, "type": b.block_type.name(),
+                    "compressed_bytes": b.compressed_len, "decompressed_bytes": b.decompressed_len,
+                })).collect();
+            }
+            v
+        }).collect();
+        print_json_list("frames", out);
+        return 0;
+    }
+
+    let content: Option<u64> = frames.iter().filter(|f| !f.skippable).map(|f| f.content_size).sum();
+    println!("=== Zstd Frames: {input} ===");
+    println!("  file size:      {:>10} bytes", data.len());
+    println!("  frames:         {:>10}", frames.len());
+    if let Some(n) = content {
+        println!("  content size:   {n:>10} bytes  ({:.1}x)", n as f64 / data.len().max(1) as f64);
+    }
+    println!("\n  {:>5} {:>10} {:>10} {:>9} {:>10} {:>11} {:>7} {:>5} {:>5} {:>6}",
+        "frame", "offset", "bytes", "window", "dict id", "content", "ratio", "raw", "rle", "cmp");
+    let opt = |v: Option<u64>| v.map_or("-".to_string(), |n| n.to_string());
+    for (i, f) in frames.iter().enumerate() {
+        if f.skippable {
+            println!("  {i:>5} {:>10} {:>10}  (skippable)", f.offset, f.len);
+            continue;
+        }
+        println!("  {i:>5} {:>10} {:>10} {:>9} {:>10} {:>11} {:>7} {:>5} {:>5} {:>6}",
+            f.offset, f.len, opt(f.window_size), opt(f.dictionary_id.map(u64::from)),
+            opt(f.content_size), f.ratio().map_or("-".to_string(), |r| format!("{r:.1}x")),
+            f.block_count(BlockType::Raw), f.block_count(BlockType::Rle),
+            f.block_count(BlockType::Compressed));
+        if show_blocks {
+            for b in &f.blocks {
+                println!("        block @{:<10} {:>10}  {:>8} bytes{}", b.offset, b.block_type.name(),
+                    b.compressed_len,
+                    b.decompressed_len.map_or(String::new(), |n| format!(" -> {n}")));
+            }
+        }
+    }
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Analyze command (LZ77 or JSON)
+// ---------------------------------------------------------------------------
+
+fn cmd_analyze(a: AnalyzeArgs) -> i32 {
+    let c = &a.common;
+    let Input { data, files, .. } = read_common(c);
+
+    if is_json_mode(&data, &a.mode, &a.fields) {
+        return cmd_analyze_json(&a, &data, &files);
+    }
+    if looks_binary(&data) {
+        warn!(input = c.input, control_pct = (binary_ratio(&data) * 100.0).round(),
+            "input looks binary; newline-split records are arbitrary, see `anomalies --heatmap` \
+            for offset-based coverage");
+    }
+
+    let Lz77Run { ops, dict, records, scanned } = run_lz77(&data, c, a.min_count, &a.baseline, false);
+    let spark = a.sparkline;
+    let scanned_bytes = scanned.as_ref().map_or(&data[..], |s| &s.data);
+
+    let n_lit = ops.iter().filter(|o| o.kind == OpKind::Literal).count();
+    let n_ref = ops.iter().filter(|o| o.kind == OpKind::Backref).count();
+    let ref_bytes: usize = ops.iter().filter(|o| o.kind == OpKind::Backref).map(|o| o.length).sum();
+
+    println!("=== Analysis (LZ77): {} ===", c.input);
+    println!("  input size:     {:>10} bytes", data.len());
+    println!("  records:        {:>10}", records.len());
+    print_oversized(oversized_count(&data, c), c, 16, 10);
+    if let Some(n) = scanned.as_ref().and_then(|s| s.distinct) {
+        println!("  distinct:       {:>10}  ({} bytes scanned)", n, scanned_bytes.len());
+    }
+    println!("  scan ops:       {:>10}  ({n_lit} literal, {n_ref} backref)", ops.len());
+    println!("  backref cover:  {:>9.1}%", pct(ref_bytes, scanned_bytes.len()));
+    println!("  dict entries:   {:>10}", dict.len());
+
+    if !records.is_empty() {
+        let coverages: Vec<f64> = records.iter().map(|r| r.coverage).collect();
+        let sum: f64 = coverages.iter().sum();
+        let mean = sum / coverages.len() as f64;
+        let mut sorted = coverages.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = if sorted.len().is_multiple_of(2) {
+            (sorted[sorted.len() / 2 - 1] + sorted[sorted.len() / 2]) / 2.0
+        } else { sorted[sorted.len() / 2] };
+
+        println!("\n--- Coverage Distribution ---");
+        println!("  mean:    {mean:.4}");
+        println!("  median:  {median:.4}");
+        println!("  min:     {:.4}", sorted.first().unwrap_or(&0.0));
+        println!("  max:     {:.4}", sorted.last().unwrap_or(&0.0));
+
+        let buckets = Histogram::with_range(&coverages, 10, 0.0, 1.0).counts;
+        let max_count = *buckets.iter().max().unwrap_or(&1).max(&1);
+        println!("\n--- Coverage Histogram ---");
+        for (i, &count) in buckets.iter().enumerate() {
+            let bar = "#".repeat((count as f64 / max_count as f64 * 40.0) as usize);
+            println!("  {:3}-{:3}%: {:6} {bar}", i * 10, (i + 1) * 10, count);
+        }
+        let scores: Vec<f64> = records.iter().map(|r| r.anomaly_score).collect();
+        print_sparkline(&scores, spark);
+    }
+    let offsets: Vec<usize> = records.iter().map(|r| r.offset).collect();
+    print_files(&files, &offsets, None);
+
+    // Entry positions index the scanned input; a baseline's original bytes
+    // are not kept, so its entries are shown as scanned.
+    let shown = if a.baseline.is_set() {
+        Cow::Borrowed(&dict[..])
+    } else {
+        shown_dict(&dict, scanned_bytes, c)
+    };
+    let top = dict.len().min(10);
+    if top > 0 {
+        println!("\n--- Top {top} Dictionary Entries ---");
+        for e in shown.iter().take(top) {
+            let trunc = e.content.len().min(50);
+            let suffix = if e.content.len() > 50 { "..." } else { "" };
+            let shown = String::from_utf8_lossy(&e.content[..trunc]);
+            println!("  [{:4}]  count={:6}  len={:4}  {shown:?}{suffix}",
+                e.entry_id, e.count, e.content_length());
+        }
+    }
+    0
+}
+
+fn cmd_analyze_json(a: &AnalyzeArgs, data: &[u8], files: &[ArchiveMember]) -> i32 {
+    let (c, fields, spark) = (&a.common, &a.fields, a.sparkline);
+    let (records, cut) = info_span!("parse")
+        .in_scope(|| parse_filtered(data, fields, c.normalize_space, record_limit(c)));
+    let schema = info_span!("schema").in_scope(|| baseline_schema(data, &records, &a.baseline, &a.fields, c));
+    let scored = info_span!("score")
+        .in_scope(|| score_weighted(data, &records, &schema, fields, Some(&a.baseline)));
+
+    println!("=== Analysis (JSON): {} ===", c.input);
+    println!("  input size:     {:>10} bytes", data.len());
+    println!("  records:        {:>10}", records.len());
+    print_oversized(oversized_count(data, c), c, 16, 10);
+    print_truncations(cut, fields, 16, 10);
+    if a.baseline.is_set() {
+        println!("  baseline:       {:>10}  (schema below is from these)", schema.total_records);
+    }
+    println!("  valid JSON:     {:>10}", schema.valid_records);
+    println!("  parse errors:   {:>10}", schema.parse_errors);
+    println!("  unique fields:  {:>10}", schema.fields.len());
+    println!("  field sets:     {:>10}", schema.field_set_counts.len());
 
     // Field presence table
     let mut fields: Vec<_> = schema.fields.values().collect();
-    fields.sort_by(|a, b| b.present_count.cmp(&a.present_count));
+    fields.sort_by_key(|f| std::cmp::Reverse(f.present_count));
+
+    println!("\n--- Field Profiles ---");
+    println!("  {:20} {:>6} {:>7} {:>6} {:>6} {:>5} {:>6}",
+        "field", "count", "rate", "nulls", "empty", "type", "uniq");
+    for f in fields.iter().take(20) {
+        println!("  {:20} {:>6} {:>6.1}% {:>6} {:>6} {:>5} {:>6}{}",
+            f.name, f.present_count,
+            f.presence_rate * 100.0,
+            f.null_count,
+            f.empty_count,
+            f.dominant_type,
+            f.unique_values,
+            if f.enum_values.is_some() { " (enum)" } else if f.is_low_cardinality { "" } else { " (high)" });
+    }
+
+    // Top values for low-cardinality fields
+    let low_card: Vec<_> = fields.iter()
+        .filter(|f| f.is_low_cardinality && f.unique_values > 1 && f.unique_values <= 20)
+        .take(5)
+        .collect();
+    if !low_card.is_empty() {
+        println!("\n--- Value Distributions (low-cardinality fields) ---");
+        for f in low_card {
+            let mut vals: Vec<_> = f.value_counts.iter().collect();
+            vals.sort_by(|a, b| b.1.cmp(a.1));
+            let shown: Vec<String> = vals.iter().take(5)
+                .map(|(v, c)| {
+                    let trunc = if v.len() > 20 { format!("{}...", &v[..17]) } else { v.to_string() };
+                    format!("{trunc}={c}")
+                })
+                .collect();
+            let more = if vals.len() > 5 { format!(" (+{} more)", vals.len() - 5) } else { String::new() };
+            println!("  {}: {}{more}", f.name, shown.join(", "));
+        }
+    }
+
+    // Score distribution
+    if !scored.is_empty() {
+        let scores: Vec<f64> = scored.iter().map(|s| s.anomaly_score).collect();
+        let sum: f64 = scores.iter().sum();
+        let mean = sum / scores.len() as f64;
+        println!("\n--- Anomaly Score Distribution ---");
+        println!("  mean:   {mean:.4}");
+
+        let buckets = Histogram::with_range(&scores, 10, 0.0, 1.0).counts;
+        let max_count = *buckets.iter().max().unwrap_or(&1).max(&1);
+        for (i, &count) in buckets.iter().enumerate() {
+            let bar = "#".repeat((count as f64 / max_count as f64 * 40.0) as usize);
+            println!("  0.{i}-0.{}: {:6} {bar}", i + 1, count);
+        }
+        print_sparkline(&scores, spark);
+    }
+    let offsets: Vec<usize> = scored.iter().map(|s| s.offset).collect();
+    print_files(files, &offsets, None);
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Anomalies command (LZ77 or JSON)
+// ---------------------------------------------------------------------------
+
+fn cmd_anomalies(a: AnomaliesArgs) -> i32 {
+    let c = &a.common;
+    let Input { data, members, files, source } = read_common(c);
+    let method = parse_method(&a.method, &a.top_n);
+    let metric = match a.metric.as_str() {
+        "coverage" => RankMetric::Coverage,
+        "literal-bytes" => RankMetric::LiteralBytes,
+        _ => RankMetric::Score,
+    };
+    let direction = match a.direction.as_str() {
+        "low" => Direction::Low,
+        "both" => Direction::Both,
+        _ => Direction::High,
+    };
+    let ranked = matches!(method, DetectionMethod::Percentile | DetectionMethod::Top);
+    if metric != RankMetric::Score && !ranked
+        && !(method == DetectionMethod::Coverage && metric == RankMetric::Coverage)
+    {
+        eprintln!("error: --metric {} ranks records for --method percentile or top (--top-n); --method {} \
+            thresholds its own signal", a.metric, a.method.as_deref().unwrap_or("score"));
+        return 1;
+    }
+    if a.context > 0 && a.extract_format == "ndjson" {
+        eprintln!("error: --context applies to raw extraction; ndjson records carry their line instead");
+        return 1;
+    }
+    if a.extract_format == "ndjson" && a.json && a.extract_to.is_none() {
+        eprintln!("error: --extract-format ndjson replaces the --json report on stdout; add --extract-to FILE");
+        return 1;
+    }
+
+    if is_mixed_mode(&data, &a.mode, &a.fields) {
+        if metric != RankMetric::Score {
+            eprintln!("error: --metric {} is not supported on mixed input; use --mode lz77", a.metric);
+            return 1;
+        }
+        if direction != Direction::High {
+            eprintln!("error: --direction {} is not supported on mixed input; pick one analyzer with --mode json or --mode lz77", a.direction);
+            return 1;
+        }
+        return cmd_anomalies_mixed(&a, &data, &files, &source, method);
+    }
+    if is_json_mode(&data, &a.mode, &a.fields) {
+        if a.known.is_some() {
+            eprintln!("error: --known requires LZ77 mode");
+            return 1;
+        }
+        if metric != RankMetric::Score {
+            eprintln!("error: --metric {} requires LZ77 mode", a.metric);
+            return 1;
+        }
+        return cmd_anomalies_json(&a, &data, &members, &files, &source, method, direction);
+    }
+    if a.group_by.is_some() || a.session_field.is_some() || a.drift.is_some() || a.state_field.is_some()
+        || a.timeline
+    {
+        eprintln!("error: --group-by, --session-field, --state-field, --drift and --timeline require JSON mode");
+        return 1;
+    }
+    // Newline records mean nothing in binary input; fall back to the offset
+    // heatmap when no option rules it out.
+    let mut heatmap_segments = a.heatmap;
+    if looks_binary(&data) {
+        let switch = heatmap_segments.is_none() && a.known.is_none() && !c.dedup && !c.normalize_space
+            && c.max_record_bytes.is_none();
+        warn!(input = c.input, control_pct = (binary_ratio(&data) * 100.0).round(), heatmap = switch,
+            "input looks binary; newline-split records are arbitrary");
+        if switch {
+            heatmap_segments = Some(20);
+        }
+    }
+
+    // Known-normal records are dropped before scanning; `data` is then the
+    // unseen records alone, and indices and offsets are mapped back to the
+    // input for display.
+    let oversized = oversized_count(&data, c);
+    let locator = locator(&a, &data, &files, &source);
+    let known: Option<RecordSubset> =
+        a.known.as_ref().map(|path| unseen_records(&data, b'\n', &load_filter_or_exit(path)));
+    let data: &[u8] = known.as_ref().map_or(&data, |u| &u.data);
+    let orig_index = |i: usize| known.as_ref().map_or(i, |u| u.index[i]);
+    let orig_offset = |o: usize| known.as_ref().map_or(o, |u| u.orig_offset(o));
+
+    let Lz77Run { ops, dict, mut records, scanned } = run_lz77(data, c, a.min_count, &a.baseline, true);
+    let seeded: Option<Vec<f64>> = a.seed.as_ref().map(|path| {
+        let seeds = read_bytes(path).0.split(|&b| b == b'\n').map(line_tokens).collect::<Vec<_>>();
+        let tokens: Vec<Vec<String>> = records.iter().map(|r| line_tokens(r.content(data))).collect();
+        let sim = seed_similarity(path, &seeds, &tokens);
+        let mut scores: Vec<f64> = records.iter().map(|r| r.anomaly_score).collect();
+        blend_seed_scores(&mut scores, &sim, a.seed_weight);
+        for (r, s) in records.iter_mut().zip(scores) {
+            r.anomaly_score = s;
+        }
+        sim
+    });
+    let raw_scores: Option<Vec<f64>> = a.smooth.map(|n| {
+        let raw: Vec<f64> = records.iter().map(|r| r.anomaly_score).collect();
+        for (r, s) in records.iter_mut().zip(smooth_scores(&raw, n as usize)) {
+            r.anomaly_score = s;
+        }
+        raw
+    });
+    let scores: Vec<f64> = records.iter().map(|r| r.anomaly_score).collect();
+
+    let mut report = detect_anomalies_by(&records, dict.len(), method, metric, direction, a.threshold, a.top_n);
+    if a.sort == "bpb" {
+        report.anomaly_indices.sort_by(|&i, &j| {
+            records[j].bits_per_byte.partial_cmp(&records[i].bits_per_byte).unwrap()
+        });
+    } else if let Some(factor) = a.level_boost {
+        let levels: Vec<_> = records.iter().map(|r| line_severity(r.content(data))).collect();
+        boost_order(&mut report.anomaly_indices, &scores, &levels, factor);
+    }
+    let (tags, suppressed) = apply_rules(a.rules.as_ref(), &mut report.anomaly_indices,
+        &mut report.anomaly_count, |i| records[i].content(data));
+    let blocks = a.blocks.map(|gap| merge_blocks(&report.anomaly_indices, &scores, gap as usize));
+    let clusters = a.incidents.map(|gap| {
+        anomaly_clusters(&report.anomaly_indices, &scores, gap, |i| records[i].content(data))
+    });
+    let offsets: Vec<usize> = records.iter().map(|r| orig_offset(r.offset)).collect();
+    let flagged: Vec<usize> = report.anomaly_indices.iter().map(|&i| offsets[i]).collect();
+    let sigma = sigma_findings(&a, records.iter()
+        .map(|r| (orig_index(r.index), orig_offset(r.offset), r.content(data))));
+    let heatmap = heatmap_segments.map(|n| offset_heatmap(data.len(), n, &ops, &offsets, &flagged));
+    // Op spans of each anomaly, for --top-entries and the JSON
+    // explanation; literal text comes from the unmasked bytes.
+    let spans = (a.top_entries > 0 || a.json).then(|| {
+        let shown: Vec<&RecordAnalysis> = report.anomaly_indices.iter().map(|&i| &records[i]).collect();
+        let (scanned, bounds) = scanned_records(data, scanned.as_ref(), &shown);
+        let masked = scan_input(scanned, c);
+        (record_spans(&masked, &ops, &dict, &bounds), scanned, bounds)
+    });
+    let top_entries: Vec<Vec<(usize, usize)>> = match &spans {
+        Some((spans, _, _)) if a.top_entries > 0 => spans.iter().map(|sp| {
+            let mut e = entry_bytes(sp);
+            e.truncate(a.top_entries);
+            e
+        }).collect(),
+        _ => vec![Vec::new(); report.anomaly_indices.len()],
+    };
+    let templates = a.templates.map(|n| {
+        let all: Vec<&RecordAnalysis> = records.iter().collect();
+        let (scanned, bounds) = scanned_records(data, scanned.as_ref(), &all);
+        let masked = scan_input(scanned, c);
+        let labels: Vec<String> = record_spans(&masked, &ops, &dict, &bounds).iter()
+            .map(|sp| lz77_template(&masked, sp))
+            .collect();
+        let mut t = template_contributions(&labels, &scores, &report.anomaly_indices);
+        t.truncate(n);
+        t
+    });
+
+    let review = a.review_sample.map(|n| review_sample(&scores, n));
+
+    let extracted = || report.anomaly_indices.iter().map(|&i| {
+        let r = &records[i];
+        let (index, offset) = (orig_index(r.index), orig_offset(r.offset));
+        Excerpt { index, offset, score: r.anomaly_score, content: r.content(data) }
+    });
+    if let Some(code) = extract(&a, data, &locator, extracted()) {
+        return code;
+    }
+    if a.json {
+        let anomalies: Vec<serde_json::Value> = report.anomaly_indices.iter().zip(&top_entries)
+            .enumerate().map(|(k, (&i, top))| {
+            let r = &records[i];
+            let mut v = serde_json::json!({
+                "index": orig_index(r.index), "offset": orig_offset(r.offset), "length": r.length,
+                "location": locator.locate(orig_offset(r.offset)),
+                "coverage": (r.coverage * 1e6).round() / 1e6,
+                "anomaly_score": (r.anomaly_score * 1e6).round() / 1e6,
+                "bits_per_byte": (r.bits_per_byte * 1e6).round() / 1e6,
+                "literal_bytes": r.literal_bytes, "backref_bytes": r.backref_bytes,
+                "ref_entries": r.ref_entries,
+                "content": String::from_utf8_lossy(r.content(data)).trim_end(),
+            });
+            if let Some(raw) = &raw_scores {
+                v["raw_score"] = serde_json::json!((raw[i] * 1e6).round() / 1e6);
+            }
+            if let Some(sim) = &seeded {
+                v["seed_similarity"] = serde_json::json!((sim[i] * 1e6).round() / 1e6);
+            }
+            if a.top_entries > 0 {
+                v["top_entries"] = top.iter()
+                    .map(|&(id, bytes)| serde_json::json!({"id": id, "bytes": bytes}))
+                    .collect();
+            }
+            if let Some((spans, scanned, bounds)) = &spans {
+                v["literal_spans"] = literal_spans_json(&spans[k], scanned, bounds[k].0);
+            }
+            v["coverage_breakdown"] = coverage_breakdown_json(r, top);
+            if let Some(m) = tags.get(k) {
+                rule_json(&mut v, m);
+            }
+            v
+        }).collect();
+        let mut out = serde_json::json!({
+            "mode": "lz77", "total_records": report.total_records,
+            "anomaly_count": report.anomaly_count,
+            "anomaly_rate": (report.anomaly_rate() * 1e6).round() / 1e6,
+            "threshold": (report.threshold * 1e6).round() / 1e6,
+            "anomalies": anomalies,
+        });
+        if let Some(u) = &known {
+            out["known_records"] = serde_json::json!(u.dropped());
+        }
+        if let Some(n) = a.smooth {
+            out["smooth"] = serde_json::json!(n);
+        }
+        if a.seed.is_some() {
+            out["seed_weight"] = serde_json::json!(a.seed_weight);
+        }
+        if let Some(blocks) = &blocks {
+            out["anomaly_blocks"] = blocks_json(blocks, &scores, |i| orig_index(records[i].index));
+        }
+        if let (Some(gap), Some(clusters)) = (a.incidents, &clusters) {
+            out["cluster_gap"] = gap_json(gap);
+            out["anomaly_clusters"] = clusters_json(clusters, &scores, |i| orig_index(records[i].index));
+        }
+        if let Some(t) = &templates {
+            out["templates"] = templates_json(t, |i| orig_index(records[i].index));
+        }
+        if let Some(sample) = &review {
+            out["review_sample"] = review_json(sample, &scores, &report.anomaly_indices,
+                |i| orig_index(records[i].index), |i| locator.locate(orig_offset(records[i].offset)),
+                |i| records[i].content(data));
+        }
+        if metric != RankMetric::Score {
+            out["metric"] = serde_json::json!(a.metric);
+        }
+        if let Some(t) = report.low_threshold {
+            out["low_threshold"] = serde_json::json!((t * 1e6).round() / 1e6);
+        }
+        if direction != Direction::High {
+            out["direction"] = serde_json::json!(a.direction);
+        }
+        if let Some(f) = a.level_boost.filter(|_| a.sort == "score") {
+            out["level_boost"] = serde_json::json!(f);
+        }
+        if a.rules.is_some() {
+            out["suppressed_count"] = serde_json::json!(suppressed);
+        }
+        if let Some(findings) = &sigma {
+            out["sigma_matches"] = sigma_json(findings);
+        }
+        if let Some(n) = oversized {
+            out["oversized_records"] = serde_json::json!(n);
+            out["oversize_policy"] = serde_json::json!(c.oversize);
+        }
+        if let Some(segs) = &heatmap {
+            out["heatmap"] = heatmap_json(segs, true);
+        }
+        if !members.is_empty() {
+            out["gzip_members"] = members_json(&members, &offsets, &flagged);
+        }
+        if !files.is_empty() {
+            out["archive_files"] = files_json(&files, &offsets, Some(&flagged));
+        }
+        print_json(out);
+    } else {
+        println!("=== Anomaly Report (LZ77): {} ===", c.input);
+        if let Some(u) = &known {
+            println!("  known (skipped): {:>8}", u.dropped());
+        }
+        println!("  records:         {:>8}", report.total_records);
+        print_oversized(oversized, c, 17, 8);
+        println!("  mean coverage:   {:>8.4}", report.mean_coverage);
+        println!("  median coverage: {:>8.4}", report.median_coverage);
+        println!("  stdev coverage:  {:>8.4}", report.stdev_coverage);
+        print_thresholds(report.threshold, report.low_threshold, direction);
+        if metric != RankMetric::Score {
+            println!("  ranked by:       {}", a.metric);
+        }
+        println!("  anomalies:       {:>8}  ({:.1}%)",
+            report.anomaly_count, report.anomaly_rate() * 100.0);
+        if let Some(f) = a.level_boost.filter(|_| a.sort == "score") {
+            println!("  level boost:     {:>8}  (ERROR/FATAL; WARN x{:.2})", format!("x{f}"), f.sqrt());
+        }
+        if a.rules.is_some() {
+            println!("  suppressed:      {suppressed:>8}  (by --rules)");
+        }
+        if let Some(n) = a.smooth {
+            println!("  smoothing:       {n:>8}  (records per score)");
+        }
+        if let Some(path) = &a.seed {
+            println!("  seed weight:     {:>8}  ({path})", a.seed_weight);
+        }
+        if let (Some(gap), Some(clusters)) = (a.incidents, &clusters) {
+            print_clusters(clusters, gap, &scores, |i| orig_index(records[i].index), |i| records[i].content(data));
+        }
+        print_sparkline(&scores, a.sparkline);
+        if let Some(segs) = &heatmap {
+            print_heatmap(segs, true);
+        }
+        print_members(&members, &offsets, &flagged);
+        print_files(&files, &offsets, Some(&flagged));
+        if let Some(findings) = &sigma {
+            print_sigma(findings);
+        }
+        if let Some(t) = &templates {
+            print_templates(t, |i| orig_index(records[i].index), |i| records[i].content(data));
+        }
+        let blocks = blocks.unwrap_or_default();
+        print_blocks(&blocks, &scores, |i| orig_index(records[i].index), |i| records[i].content(data));
+
+        if report.anomaly_indices.iter().any(|&i| !in_block(&blocks, i)) {
+            println!("\n--- Anomalous Records ---");
+            for (k, (&i, top)) in report.anomaly_indices.iter().zip(&top_entries).enumerate() {
+                if in_block(&blocks, i) {
+                    continue;
+                }
+                let r = &records[i];
+                let line = String::from_utf8_lossy(r.content(data)).trim_end().to_string();
+                let shown = if line.len() > 120 { format!("{}...", &line[..117]) } else { line };
+                let mut raw = raw_scores.as_ref().map_or(String::new(), |raw| format!("  raw={:.4}", raw[i]));
+                if let Some(sim) = &seeded {
+                    raw += &format!("  seed={:.2}", sim[i]);
+                }
+                println!("  [{:6}]  {}  score={:.4}{raw}  cov={:.2}  bpb={:.2}  lit={:4}  refs={:2}  {shown}",
+                    orig_index(r.index), locator.locate(orig_offset(r.offset)), r.anomaly_score, r.coverage,
+                    r.bits_per_byte, r.literal_bytes, r.ref_entries.len());
+                if a.top_entries > 0 {
+                    let mut parts: Vec<String> = top.iter()
+                        .map(|&(id, n)| format!("#{id} {n}B ({:.0}%)", pct(n, r.length)))
+                        .collect();
+                    let other = r.backref_bytes.saturating_sub(top.iter().map(|e| e.1).sum());
+                    if other > 0 {
+                        parts.push(format!("other backrefs {other}B"));
+                    }
+                    parts.push(format!("literal {}B", r.literal_bytes));
+                    println!("            {}", parts.join(", "));
+                }
+                if let Some(m) = tags.get(k) {
+                    print_rule_match(m);
+                }
+            }
+        }
+        if let Some(sample) = &review {
+            print_review(sample, &scores, &report.anomaly_indices, |i| orig_index(records[i].index),
+                |i| locator.locate(orig_offset(records[i].offset)), |i| records[i].content(data));
+        }
+        if a.extract && a.extract_to.is_none() {
+            println!("\n--- Extracted Anomalous Lines ---");
+            let _ = write_extracted(&mut io::stdout().lock(), &a, data, &locator, extracted());
+        }
+    }
+    0
+}
+
+/// Handle `--extract-to` and `--extract-format ndjson` for the anomalies.
+/// `Some` exit code when the command is done: ndjson went to stdout in
+/// place of the report, or the file could not be written.
+fn extract<'d>(
+    a: &AnomaliesArgs,
+    data: &[u8],
+    locator: &Locator,
+    anomalies: impl Iterator<Item = Excerpt<'d>>,
+) -> Option<i32> {
+    match &a.extract_to {
+        Some(path) => {
+            let res = fs::File::create(path).and_then(|f| {
+                let mut out = io::BufWriter::new(f);
+                write_extracted(&mut out, a, data, locator, anomalies)?;
+                out.flush()
+            });
+            if let Err(e) = res {
+                eprintln!("error: {path}: {e}");
+                return Some(1);
+            }
+            None
+        }
+        None if a.extract_format == "ndjson" => {
+            // A closed pipe (e.g. `| head`) ends the output quietly.
+            let _ = write_extracted(&mut io::stdout().lock(), a, data, locator, anomalies);
+            Some(0)
+        }
+        None => None,
+    }
+}
+
+/// Write extracted anomalies as `--extract-format` asks: the records' bytes,
+/// after any `--extract-prefix` and with any `--context` lines of `data`,
+/// or one JSON object per line saying where each came from.
+fn write_extracted<'d>(
+    out: &mut impl Write,
+    a: &AnomaliesArgs,
+    data: &[u8],
+    locator: &Locator,
+    anomalies: impl Iterator<Item = Excerpt<'d>>,
+) -> io::Result<()> {
+    if a.extract_format == "ndjson" {
+        for e in anomalies {
+            writeln!(out, "{}", versioned(excerpt_json(&e, locator)))?;
+        }
+        return Ok(());
+    }
+    let prefix = match a.extract_prefix.as_str() {
+        "line" => Prefix::Line,
+        "offset" => Prefix::Offset,
+        _ => Prefix::None,
+    };
+    write_raw(out, data, anomalies, prefix, a.context)
+}
+
+/// `--locator`: the place of a record in the input file, in the form
+/// editors and pagers jump to. Archive files are located by their own names
+/// and lines.
+fn locator<'a>(
+    a: &'a AnomaliesArgs,
+    data: &'a [u8],
+    files: &'a [ArchiveMember],
+    source: &'a Source,
+) -> Locator<'a> {
+    Locator::new(&a.common.input, data)
+        .with_files(files.iter().map(|f| (f.name.as_str(), f.offset..f.offset + f.len)))
+        .by_offset(a.locator == "offset")
+        .with_source(source)
+}
+
+/// Apply `--rules` to the anomalies: suppressed ones are dropped from
+/// `indices` and `count`, and the rest get their matches, aligned with
+/// `indices`. Returns the matches and the number suppressed; without rules,
+/// no matches.
+fn apply_rules<'d>(
+    rules: Option<&RuleSet>,
+    indices: &mut Vec<usize>,
+    count: &mut usize,
+    content: impl Fn(usize) -> &'d [u8],
+) -> (Vec<RuleMatch>, usize) {
+    let Some(rules) = rules else { return (Vec::new(), 0) };
+    let mut matches = Vec::with_capacity(indices.len());
+    let before = indices.len();
+    indices.retain(|&i| {
+        let m = rules.apply(content(i));
+        let keep = !m.suppress;
+        if keep {
+            matches.push(m);
+        }
+        keep
+    });
+    let suppressed = before - indices.len();
+    *count = count.saturating_sub(suppressed);
+    (matches, suppressed)
+}
+
+/// Literal spans listed per LZ77 anomaly in JSON output.
+const MAX_LITERAL_SPANS: usize = 32;
+
+/// The bytes of an LZ77 record that no back-reference explains, up to
+/// [`MAX_LITERAL_SPANS`]: offset within the record (as scanned, starting at
+/// `start` in `scanned`), length and text.
+fn literal_spans_json(spans: &[Span], scanned: &[u8], start: usize) -> serde_json::Value {
+    literal_runs(spans, start).into_iter().take(MAX_LITERAL_SPANS).map(|run| serde_json::json!({
+        "offset": run.start, "length": run.len(),
+        "text": String::from_utf8_lossy(&scanned[start + run.start..start + run.end]),
+    })).collect()
+}
+
+/// Percentages of an LZ77 record covered by its top dictionary entries and
+/// other back-references, and left literal; they add up to 100.
+fn coverage_breakdown_json(r: &RecordAnalysis, top: &[(usize, usize)]) -> serde_json::Value {
+    let b = coverage_breakdown(r, top);
+    let p = |x: f64| (x * 1e4).round() / 1e4;
+    serde_json::json!({
+        "entries": b.entries.iter().map(|&(id, x)| serde_json::json!({"id": id, "pct": p(x)})).collect::<Vec<_>>(),
+        "other_backref_pct": p(b.other_backref_pct),
+        "literal_pct": p(b.literal_pct),
+    })
+}
+
+/// Rule tags and severity on a JSON anomaly.
+fn rule_json(v: &mut serde_json::Value, m: &RuleMatch) {
+    v["tags"] = serde_json::json!(m.tags);
+    v["rule_severity"] = serde_json::json!(m.severity);
+}
+
+/// Whether record `i` falls in a `--blocks` entry of more than one anomaly;
+/// those are listed as blocks rather than record by record.
+fn in_block(blocks: &[AnomalyBlock], i: usize) -> bool {
+    blocks.iter().any(|b| b.anomalies > 1 && (b.start..=b.end).contains(&i))
+}
+
+fn blocks_json(blocks: &[AnomalyBlock], scores: &[f64], index: impl Fn(usize) -> usize) -> serde_json::Value {
+    blocks.iter().map(|b| serde_json::json!({
+        "start_index": index(b.start), "end_index": index(b.end),
+        "records": index(b.end) - index(b.start) + 1, "anomalies": b.anomalies,
+        "peak_index": index(b.peak), "peak_score": (scores[b.peak] * 1e6).round() / 1e6,
+    })).collect()
+}
+
+/// Print the `--blocks` entries of more than one anomaly, with the line of
+/// each block's highest-scoring record.
+fn print_blocks<'d>(
+    blocks: &[AnomalyBlock],
+    scores: &[f64],
+    index: impl Fn(usize) -> usize,
+    content: impl Fn(usize) -> &'d [u8],
+) {
+    let runs: Vec<&AnomalyBlock> = blocks.iter().filter(|b| b.anomalies > 1).collect();
+    if runs.is_empty() {
+        return;
+    }
+    println!("\n--- Anomalous Blocks ---");
+    for b in runs {
+        let (start, end) = (index(b.start), index(b.end));
+        println!("  [{start:6}..{end:6}]  {} records, {} flagged  peak [{}] score={:.4}",
+            end - start + 1, b.anomalies, index(b.peak), scores[b.peak]);
+        println!("            {}", shorten(String::from_utf8_lossy(content(b.peak)).trim_end(), 110));
+    }
+}
+
+/// `--incidents` clusters of the anomalies; with a time gap, times are read
+/// from the flagged records' lines.
+fn anomaly_clusters<'d>(
+    indices: &[usize],
+    scores: &[f64],
+    gap: Gap,
+    content: impl Fn(usize) -> &'d [u8],
+) -> Vec<Incident> {
+    let clock = matches!(gap, Gap::Seconds(_)).then(TimestampParser::new);
+    let clusters = incidents(indices, scores, |i| clock.as_ref()?.time(content(i)), gap);
+    if clock.is_some() && !clusters.is_empty() && clusters.iter().all(|c| c.first_time.is_none()) {
+        warn!("no timestamps found in the anomalous records; --incidents put them in one cluster");
+    }
+    clusters
+}
+
+fn gap_json(gap: Gap) -> serde_json::Value {
+    match gap {
+        Gap::Records(n) => serde_json::json!({"records": n}),
+        Gap::Seconds(s) => serde_json::json!({"seconds": s}),
+    }
+}
+
+fn clusters_json(clusters: &[Incident], scores: &[f64], index: impl Fn(usize) -> usize) -> serde_json::Value {
+    clusters.iter().map(|c| serde_json::json!({
+        "start_index": index(c.start), "end_index": index(c.end), "anomalies": c.anomalies,
+        "peak_index": index(c.peak), "peak_score": (scores[c.peak] * 1e6).round() / 1e6,
+        "first_time": c.first_time, "last_time": c.last_time, "duration_secs": c.duration(),
+    })).collect()
+}
+
+/// Clusters listed in text output; the JSON report has all.
+const MAX_CLUSTERS_SHOWN: usize = 20;
+
+fn format_duration(secs: f64) -> String {
+    let s = secs.round() as u64;
+    match s {
+        s if s >= 86400 => format!("{}d{:02}h", s / 86400, s % 86400 / 3600),
+        s if s >= 3600 => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{s}s"),
+    }
+}
+
+fn print_clusters<'d>(
+    clusters: &[Incident],
+    gap: Gap,
+    scores: &[f64],
+    index: impl Fn(usize) -> usize,
+    content: impl Fn(usize) -> &'d [u8],
+) {
+    let gap = match gap {
+        Gap::Records(n) => format!("{n} records"),
+        Gap::Seconds(s) => format_duration(s),
+    };
+    println!("\n--- Anomaly Clusters ({} within a gap of {gap}) ---", clusters.len());
+    for c in clusters.iter().take(MAX_CLUSTERS_SHOWN) {
+        let span = c.duration().map_or(String::new(), |d| format!("  over {}", format_duration(d)));
+        println!("  [{:6}..{:6}]  {} flagged{span}  peak [{}] score={:.4}",
+            index(c.start), index(c.end), c.anomalies, index(c.peak), scores[c.peak]);
+        println!("            {}", shorten(String::from_utf8_lossy(content(c.peak)).trim_end(), 110));
+    }
+    if clusters.len() > MAX_CLUSTERS_SHOWN {
+        println!("  (+{} more)", clusters.len() - MAX_CLUSTERS_SHOWN);
+    }
+}
+
+/// `--templates` label for an LZ77 record: its longest back-referenced
+/// run, as scanned (so masked timestamps read alike). Entries can straddle
+/// the line break, so the run is taken from the record, not the entry.
+fn lz77_template(scanned: &[u8], spans: &[Span]) -> String {
+    let longest = spans.iter()
+        .filter(|sp| sp.kind == OpKind::Backref)
+        .max_by_key(|sp| (sp.end - sp.start, std::cmp::Reverse(sp.start)));
+    let Some(sp) = longest else { return "(all literal)".to_string() };
+    let run = &scanned[sp.start..sp.end.min(sp.start + 60)];
+    let suffix = if sp.end - sp.start > 60 { "..." } else { "" };
+    format!("{:?}{suffix}", String::from_utf8_lossy(run))
+}
+
+fn templates_json(templates: &[TemplateStats], index: impl Fn(usize) -> usize) -> serde_json::Value {
+    templates.iter().map(|t| serde_json::json!({
+        "template": t.template, "records": t.records, "anomalies": t.anomalies,
+        "anomaly_rate": (t.anomaly_rate() * 1e6).round() / 1e6,
+        "contribution": (t.contribution * 1e6).round() / 1e6,
+        "share": (t.share * 1e6).round() / 1e6, "peak_index": index(t.peak),
+    })).collect()
+}
+
+fn print_templates<'d>(
+    templates: &[TemplateStats],
+    index: impl Fn(usize) -> usize,
+    content: impl Fn(usize) -> &'d [u8],
+) {
+    println!("\n--- Top Anomalous Templates ---");
+    println!("  {:>6} {:>9} {:>8} {:>7}  template", "share", "anomalies", "records", "rate");
+    for t in templates {
+        println!("  {:>5.1}% {:>9} {:>8} {:>6.1}%  {}", t.share * 100.0, t.anomalies, t.records,
+            t.anomaly_rate() * 100.0, shorten(&t.template, 90));
+        println!("            e.g. [{}] {}", index(t.peak),
+            shorten(String::from_utf8_lossy(content(t.peak)).trim_end(), 100));
+    }
+}
+
+/// `--review-sample` entries, `(decile, record)`, in JSON; `flagged` are
+/// the reported anomalies.
+fn review_json<'d>(
+    sample: &[(usize, usize)],
+    scores: &[f64],
+    flagged: &[usize],
+    index: impl Fn(usize) -> usize,
+    location: impl Fn(usize) -> String,
+    content: impl Fn(usize) -> &'d [u8],
+) -> serde_json::Value {
+    sample.iter().map(|&(decile, i)| serde_json::json!({
+        "decile": decile, "index": index(i), "location": location(i),
+        "score": (scores[i] * 1e6).round() / 1e6, "flagged": flagged.contains(&i),
+        "content": String::from_utf8_lossy(content(i)).trim_end(),
+    })).collect()
+}
+
+fn print_review<'d>(
+    sample: &[(usize, usize)],
+    scores: &[f64],
+    flagged: &[usize],
+    index: impl Fn(usize) -> usize,
+    location: impl Fn(usize) -> String,
+    content: impl Fn(usize) -> &'d [u8],
+) {
+    println!("\n--- Review Sample ({} records across score deciles; * = flagged) ---", sample.len());
+    for &(decile, i) in sample {
+        let mark = if flagged.contains(&i) { '*' } else { ' ' };
+        println!("  d{decile:<2} {mark} [{:6}]  {}  score={:.4}  {}", index(i), location(i), scores[i],
+            shorten(String::from_utf8_lossy(content(i)).trim_end(), 100));
+    }
+}
+
+/// Rule tags and severity under a text anomaly line, if any rule matched.
+fn print_rule_match(m: &RuleMatch) {
+    if m.tags.is_empty() && m.severity.is_none() {
+        return;
+    }
+    let severity = m.severity.as_ref().map(|s| format!(" (severity {s})")).unwrap_or_default();
+    println!("           tags: {}{severity}", m.tags.join(", "));
+}
+
+/// Why a JSON record scored as it did, added to its JSON report entry.
+fn add_json_reasons(v: &mut serde_json::Value, s: &JsonRecordScore) {
+    v["field_count"] = serde_json::json!(s.field_count);
+    v["missing_common"] = serde_json::json!(s.missing_common.iter().map(|f| &**f).collect::<Vec<_>>());
+    v["extra_rare"] = serde_json::json!(s.extra_rare.iter().map(|f| &**f).collect::<Vec<_>>());
+    v["rare_values"] = s.rare_values.iter().map(|(f, v)| format!("{f}={v}")).collect();
+    v["type_mismatches"] = s.type_mismatches.iter()
+        .map(|(f, exp, act)| format!("{f}: expected {exp}, got {act}")).collect();
+    v["unexpected_nulls"] = serde_json::json!(s.unexpected_nulls.iter().map(|f| &**f).collect::<Vec<_>>());
+    v["unexpected_empties"] = serde_json::json!(s.unexpected_empties.iter().map(|f| &**f).collect::<Vec<_>>());
+    v["enum_violations"] = s.enum_violations.iter().map(|(f, v)| format!("{f}={v}")).collect();
+    v["first_seen"] = s.first_seen.iter().map(|(f, v)| first_seen_label(f, v.as_deref())).collect();
+}
+
+/// `field` for a new field, `field=value` for a new value.
+fn first_seen_label(field: &str, value: Option<&str>) -> String {
+    match value {
+        Some(v) => format!("{field}={v}"),
+        None => field.to_string(),
+    }
+}
+
+/// Why a JSON record scored as it did, under its text report line.
+fn print_json_reasons(s: &JsonRecordScore) {
+    if !s.missing_common.is_empty() {
+        println!("           missing: {}", s.missing_common.join(", "));
+    }
+    if !s.extra_rare.is_empty() {
+        println!("           rare fields: {}", s.extra_rare.join(", "));
+    }
+    if !s.rare_values.is_empty() {
+        let vals: Vec<String> = s.rare_values.iter()
+            .map(|(f, v)| {
+                let vt = if v.len() > 20 { format!("{}...", &v[..17]) } else { v.to_string() };
+                format!("{f}={vt}")
+            })
+            .collect();
+        println!("           rare values: {}", vals.join(", "));
+    }
+    if !s.type_mismatches.is_empty() {
+        let mm: Vec<String> = s.type_mismatches.iter()
+            .map(|(f, exp, act)| format!("{f}: expected {exp}, got {act}"))
+            .collect();
+        println!("           type mismatch: {}", mm.join(", "));
+    }
+    if !s.unexpected_nulls.is_empty() {
+        let nulls: Vec<&str> = s.unexpected_nulls.iter().map(|f| &**f).collect();
+        println!("           unexpected null: {}", nulls.join(", "));
+    }
+    if !s.unexpected_empties.is_empty() {
+        let empties: Vec<&str> = s.unexpected_empties.iter().map(|f| &**f).collect();
+        println!("           unexpected empty: {}", empties.join(", "));
+    }
+    if !s.enum_violations.is_empty() {
+        let vals: Vec<String> = s.enum_violations.iter()
+            .map(|(f, v)| format!("{f}={}", shorten(v, 20)))
+            .collect();
+        println!("           enum violation: {}", vals.join(", "));
+    }
+    if !s.first_seen.is_empty() {
+        let firsts: Vec<String> = s.first_seen.iter()
+            .map(|(f, v)| first_seen_label(f, v.as_deref().map(|v| shorten(v, 20)).as_deref()))
+            .collect();
+        println!("           first seen: {}", firsts.join(", "));
+    }
+}
+
+/// A record matched by a `--sigma` rule.
+#[cfg_attr(not(feature = "sigma"), allow(dead_code))]
+struct SigmaFinding {
+    index: usize,
+    offset: usize,
+    title: String,
+    id: Option<String>,
+    level: Option<String>,
+    line: String,
+}
+
+/// Sigma rule hits over `(index, offset, line)` records; `None` without
+/// `--sigma`.
+#[cfg(feature = "sigma")]
+fn sigma_findings<'d>(
+    a: &AnomaliesArgs,
+    records: impl Iterator<Item = (usize, usize, &'d [u8])>,
+) -> Option<Vec<SigmaFinding>> {
+    let rules = a.sigma.as_ref()?;
+    Some(records.flat_map(|(index, offset, line)| {
+        rules.matching(line).into_iter().map(move |r| {
+            let rule = &rules.rules[r];
+            SigmaFinding {
+                index, offset, title: rule.title.clone(), id: rule.id.clone(), level: rule.level.clone(),
+                line: String::from_utf8_lossy(line).trim_end().to_string(),
+            }
+        })
+    }).collect())
+}
+
+#[cfg(not(feature = "sigma"))]
+fn sigma_findings<'d>(
+    _: &AnomaliesArgs,
+    _: impl Iterator<Item = (usize, usize, &'d [u8])>,
+) -> Option<Vec<SigmaFinding>> {
+    None
+}
+
+fn sigma_json(findings: &[SigmaFinding]) -> serde_json::Value {
+    findings.iter().map(|f| serde_json::json!({
+        "index": f.index, "offset": f.offset, "rule": f.title, "id": f.id, "level": f.level,
+        "content": f.line,
+    })).collect()
+}
+
+/// Sigma hits shown in text output; the JSON report has all.
+const MAX_SIGMA_SHOWN: usize = 50;
+
+fn print_sigma(findings: &[SigmaFinding]) {
+    println!("\n--- Sigma Matches ({}) ---", findings.len());
+    if findings.is_empty() {
+        println!("  none");
+        return;
+    }
+    println!("  {:>8} {:13} {:32} line", "record", "level", "rule");
+    for f in findings.iter().take(MAX_SIGMA_SHOWN) {
+        println!("  {:>8} {:13} {:32} {}", f.index, f.level.as_deref().unwrap_or("-"),
+            shorten(&f.title, 32), shorten(&f.line, 60));
+    }
+    if findings.len() > MAX_SIGMA_SHOWN {
+        println!("  (+{} more)", findings.len() - MAX_SIGMA_SHOWN);
+    }
+}
+
+/// A flagged record of mixed input, from either analyzer.
+struct MixedAnomaly {
+    index: usize,
+    offset: usize,
+    /// Index into the JSON scores or the LZ77 records.
+    json: bool,
+    at: usize,
+    score: f64,
+    /// Standard score of `score` among its analyzer's records.
+    z: f64,
+}
+
+/// `anomalies` on input that interleaves JSON objects and plain lines: the
+/// JSON lines are scored against their schema, the rest by LZ77, each part
+/// is detected with the same method, and the flagged records are merged,
+/// the most unusual for their part (by standard score) first.
+fn cmd_anomalies_mixed(
+    a: &AnomaliesArgs,
+    data: &[u8],
+    files: &[ArchiveMember],
+    source: &Source,
+    method: DetectionMethod,
+) -> i32 {
+    let c = &a.common;
+    let unsupported = [
+        (a.known.is_some(), "--known"), (a.hybrid, "--hybrid"), (a.heatmap.is_some(), "--heatmap"),
+        (a.sparkline.is_some(), "--sparkline"), (a.baseline.is_set(), "--baseline"),
+        (a.baseline.robust, "--robust"),
+        (a.group_by.is_some(), "--group-by"), (a.session_field.is_some(), "--session-field"),
+        (a.state_field.is_some(), "--state-field"), (a.drift.is_some(), "--drift"),
+        (a.level_boost.is_some(), "--level-boost"), (a.rules.is_some(), "--rules"),
+        (a.timeline, "--timeline"), (a.sort != "score", "--sort bpb"),
+        (a.smooth.is_some(), "--smooth"), (a.blocks.is_some(), "--blocks"),
+        (a.incidents.is_some(), "--incidents"), (a.templates.is_some(), "--templates"),
+        (a.seed.is_some(), "--seed"), (a.review_sample.is_some(), "--review-sample"),
+    ];
+    if let Some((_, flag)) = unsupported.iter().find(|(set, _)| *set) {
+        eprintln!("error: {flag} is not supported on mixed input; pick one analyzer with --mode json or --mode lz77");
+        return 1;
+    }
+    let (json_part, plain_part) = split_by_format(data, b'\n');
+
+    let (records, _) = parse_filtered(&json_part.data, &a.fields, c.normalize_space, record_limit(c));
+    let schema = build_schema(&records);
+    let scored = score_weighted(&json_part.data, &records, &schema, &a.fields, Some(&a.baseline));
+    let json_scores: Vec<f64> = scored.iter().map(|s| s.anomaly_score).collect();
+    let (json_threshold, json_flagged) = detect_indices(&json_scores, None, method, a.threshold, a.top_n);
+
+    let lz = (!plain_part.data.is_empty()).then(|| run_lz77(&plain_part.data, c, a.min_count, &a.baseline, true));
+    let lz_records = lz.as_ref().map_or(&[][..], |r| &r.records[..]);
+    let lz_report = detect_anomalies(lz_records, lz.as_ref().map_or(0, |r| r.dict.len()), method,
+        a.threshold, a.top_n);
+    let lz_scores: Vec<f64> = lz_records.iter().map(|r| r.anomaly_score).collect();
+
+    let (json_z, lz_z) = (standard_scores(&json_scores), standard_scores(&lz_scores));
+    let mut merged: Vec<MixedAnomaly> = json_flagged.iter().map(|&i| {
+        let s = &scored[i];
+        MixedAnomaly {
+            index: json_part.orig_index(s.offset), offset: json_part.orig_offset(s.offset),
+            json: true, at: i, score: s.anomaly_score, z: json_z[i],
+        }
+    }).chain(lz_report.anomaly_indices.iter().map(|&i| {
+        let r = &lz_records[i];
+        MixedAnomaly {
+            index: plain_part.orig_index(r.offset), offset: plain_part.orig_offset(r.offset),
+            json: false, at: i, score: r.anomaly_score, z: lz_z[i],
+        }
+    })).collect();
+    merged.sort_by(|x, y| y.z.partial_cmp(&x.z).unwrap().then_with(|| x.index.cmp(&y.index)));
+    if let Some(n) = a.top_n {
+        merged.truncate(n);
+    }
+    let locator = locator(a, data, files, source);
+    let content = |m: &MixedAnomaly| -> &[u8] {
+        if m.json { scored[m.at].content(&json_part.data) } else { lz_records[m.at].content(&plain_part.data) }
+    };
+    let total = scored.len() + lz_records.len();
+    let rate = if total > 0 { merged.len() as f64 / total as f64 } else { 0.0 };
+    let sigma = sigma_findings(a, RecordIndex::new(data, b'\n').iter().enumerate()
+        .map(|(i, (off, len))| (i, off, &data[off..off + len])));
+
+    let extracted =
+        || merged.iter().map(|m| Excerpt { index: m.index, offset: m.offset, score: m.score, content: content(m) });
+    if let Some(code) = extract(a, data, &locator, extracted()) {
+        return code;
+    }
+    if a.json {
+        let anomalies: Vec<serde_json::Value> = merged.iter().map(|m| {
+            let mut v = serde_json::json!({
+                "index": m.index, "offset": m.offset, "length": content(m).len(),
+                "location": locator.locate(m.offset),
+                "analyzer": if m.json { "json" } else { "lz77" },
+                "anomaly_score": (m.score * 1e6).round() / 1e6,
+                "z_score": (m.z * 1e6).round() / 1e6,
+                "content": String::from_utf8_lossy(content(m)).trim_end(),
+            });
+            if m.json {
+                add_json_reasons(&mut v, &scored[m.at]);
+            } else {
+                let r = &lz_records[m.at];
+                v["coverage"] = serde_json::json!((r.coverage * 1e6).round() / 1e6);
+                v["bits_per_byte"] = serde_json::json!((r.bits_per_byte * 1e6).round() / 1e6);
+                v["literal_bytes"] = serde_json::json!(r.literal_bytes);
+            }
+            v
+        }).collect();
+        let mut out = serde_json::json!({
+            "mode": "mixed", "total_records": total,
+            "json_records": scored.len(), "plain_records": lz_records.len(),
+            "json_threshold": (json_threshold * 1e6).round() / 1e6,
+            "lz77_threshold": (lz_report.threshold * 1e6).round() / 1e6,
+            "anomaly_count": merged.len(),
+            "anomaly_rate": (rate * 1e6).round() / 1e6,
+            "anomalies": anomalies,
+        });
+        if let Some(findings) = &sigma {
+            out["sigma_matches"] = sigma_json(findings);
+        }
+        print_json(out);
+    } else {
+        println!("=== Anomaly Report (mixed): {} ===", c.input);
+        println!("  records:         {total:>8}");
+        println!("  JSON records:    {:>8}  (threshold {json_threshold:.4})", scored.len());
+        println!("  plain records:   {:>8}  (threshold {:.4})", lz_records.len(), lz_report.threshold);
+        println!("  anomalies:       {:>8}  ({:.1}%)", merged.len(), rate * 100.0);
+        if let Some(findings) = &sigma {
+            print_sigma(findings);
+        }
+        if !merged.is_empty() {
+            println!("\n--- Anomalous Records ---");
+            for m in &merged {
+                let line = String::from_utf8_lossy(content(m)).trim_end().to_string();
+                let shown = if line.len() > 120 { format!("{}...", &line[..117]) } else { line };
+                if m.json {
+                    println!("  [{:6}]  {}  json  score={:.4}  z={:5.2}  {shown}",
+                        m.index, locator.locate(m.offset), m.score, m.z);
+                    print_json_reasons(&scored[m.at]);
+                } else {
+                    let r = &lz_records[m.at];
+                    println!("  [{:6}]  {}  lz77  score={:.4}  z={:5.2}  cov={:.2}  bpb={:.2}  {shown}",
+                        m.index, locator.locate(m.offset), m.score, m.z, r.coverage, r.bits_per_byte);
+                }
+            }
+        }
+        if a.extract && a.extract_to.is_none() {
+            println!("\n--- Extracted Anomalous Lines ---");
+            let _ = write_extracted(&mut io::stdout().lock(), a, data, &locator, extracted());
+        }
+    }
+    0
+}
+
+/// Improbable transitions shown in text output; the JSON report has all.
+const MAX_TRANSITIONS_SHOWN: usize = 20;
+
+/// Schema changes shown in text output; the JSON report has all.
+const MAX_CHANGES_SHOWN: usize = 30;
+
+/// Text table of improbable state transitions found by `--state-field`.
+fn print_transitions(field: &str, transitions: &[Transition]) {
+    println!("\n--- Improbable Transitions ({field}) ---");
+    if transitions.is_empty() {
+        println!("  none");
+        return;
+    }
+    println!("  {:>8} {:>8} {:40} {:>8}", "record", "previous", "transition", "P");
+    for t in transitions.iter().take(MAX_TRANSITIONS_SHOWN) {
+        println!("  {:>8} {:>8} {:40} {:>8.4}",
+            t.index, t.prev_index, shorten(&format!("{} -> {}", t.from, t.to), 40), t.probability);
+    }
+    if transitions.len() > MAX_TRANSITIONS_SHOWN {
+        println!("  (+{} more)", transitions.len() - MAX_TRANSITIONS_SHOWN);
+    }
+}
+
+/// Text table of value-ratio shifts found by `--drift`.
+fn print_drift(shifts: &[RatioShift], windows: u32) {
+    println!("\n--- Ratio Drift ({windows} windows) ---");
+    if shifts.is_empty() {
+        println!("  no sharp shifts");
+        return;
+    }
+    println!("  {:24} {:>15} {:>7} {:>9} {:>8}",
+        "field=value", "records", "n", "elsewhere", "window");
+    for d in shifts {
+        println!("  {:24} {:>15} {:>7} {:>8.1}% {:>7.1}%",
+            shorten(&format!("{}={}", d.field, d.value), 24),
+            format!("{}-{}", d.start, d.end.saturating_sub(1)), d.records,
+            d.baseline_rate * 100.0, d.window_rate * 100.0);
+    }
+}
+
+/// Text list of schema changes found by `--timeline`.
+fn print_timeline(changes: &[SchemaChange]) {
+    println!("\n--- Schema Timeline ---");
+    if changes.is_empty() {
+        println!("  no fields or values start or stop partway through");
+        return;
+    }
+    println!("  {:11} {:>8}  {:32} {:>8} {:>8}", "change", "record", "field[=value]", "records", "density");
+    for ch in changes.iter().take(MAX_CHANGES_SHOWN) {
+        let name = match &ch.value {
+            Some(v) => format!("{}={v}", ch.field),
+            None => ch.field.clone(),
+        };
+        println!("  {:11} {:>8}  {:32} {:>8} {:>7.1}%",
+            ch.kind.name(), ch.index, shorten(&name, 32), ch.span.count, ch.span.density() * 100.0);
+    }
+    if changes.len() > MAX_CHANGES_SHOWN {
+        println!("  (+{} more)", changes.len() - MAX_CHANGES_SHOWN);
+    }
+}
+
+fn cmd_anomalies_json(
+    a: &AnomaliesArgs,
+    data: &[u8],
+    members: &[GzMember],
+    files: &[ArchiveMember],
+    source: &Source,
+    method: DetectionMethod,
+    direction: Direction,
+) -> i32 {
+    let c = &a.common;
+    let pipeline = info_span!("pipeline").entered();
+    let mut records = parse_records(data, &a.fields, record_limit(c));
+    // Group keys are read before filtering so a group-by field can also be
+    // excluded from scoring.
+    let group_keys = a.group_by.as_ref().map(|f| field_keys(&records, f));
+    let session_keys = a.session_field.as_ref().map(|f| field_keys(&records, f));
+    let state_keys = a.state_field.as_ref().map(|f| field_keys(&records, f));
+    let levels = a.level_boost.map(|_| records.iter().map(record_severity).collect::<Vec<_>>());
+    field_filter(&a.fields).apply(&mut records);
+    let cut = structure_limit(&a.fields).apply(&mut records);
+    if let Some(places) = a.fields.float_precision {
+        round_number_values(&mut records, places as usize);
+    }
+    if c.normalize_space {
+        normalize_space_values(&mut records);
+    }
+    let schema = baseline_schema(data, &records, &a.baseline, &a.fields, c);
+    let mut scored = score_weighted(data, &records, &schema, &a.fields, Some(&a.baseline));
+
+    // Hybrid: blend in LZ77 scores; `scored` then carries the blended score
+    // and `hybrid` keeps both components for the report.
+    let hybrid: Option<Vec<HybridScore>> = a.hybrid.then(|| {
+        let lz = run_lz77(data, c, a.min_count, &a.baseline, false).records;
+        let h = hybrid_scores(&scored, &lz, a.hybrid_weight);
+        for (s, hs) in scored.iter_mut().zip(&h) {
+            s.anomaly_score = hs.score;
+        }
+        h
+    });
+    drop(pipeline);
+    let mode = if hybrid.is_some() { "hybrid" } else { "json" };
+    let seeded: Option<Vec<f64>> = a.seed.as_ref().map(|path| {
+        let raw = read_bytes(path).0;
+        let seeds: Vec<Vec<String>> = parse_json_records(&raw, b'\n').iter().map(record_tokens).collect();
+        let tokens: Vec<Vec<String>> = records.iter().map(record_tokens).collect();
+        let sim = seed_similarity(path, &seeds, &tokens);
+        let mut scores: Vec<f64> = scored.iter().map(|s| s.anomaly_score).collect();
+        blend_seed_scores(&mut scores, &sim, a.seed_weight);
+        for (s, v) in scored.iter_mut().zip(scores) {
+            s.anomaly_score = v;
+        }
+        sim
+    });
+    let raw_scores: Option<Vec<f64>> = a.smooth.map(|n| {
+        let raw: Vec<f64> = scored.iter().map(|s| s.anomaly_score).collect();
+        for (s, v) in scored.iter_mut().zip(smooth_scores(&raw, n as usize)) {
+            s.anomaly_score = v;
+        }
+        raw
+    });
+
+    let scores: Vec<f64> = scored.iter().map(|s| s.anomaly_score).collect();
+    let (high, low, anomaly_indices) = detect_two_sided(&scores, None, method, direction, a.threshold, a.top_n);
+    let low_threshold = high.and(low);
+    let threshold_used = high.or(low).unwrap_or(0.0);
+    let mut report = build_json_report(&records, &scored, &schema, threshold_used, anomaly_indices);
+    if let (Some(factor), Some(levels)) = (a.level_boost, &levels) {
+        boost_order(&mut report.anomaly_indices, &scores, levels, factor);
+    }
+    let (tags, suppressed) = apply_rules(a.rules.as_ref(), &mut report.anomaly_indices,
+        &mut report.anomaly_count, |i| scored[i].content(data));
+    let blocks = a.blocks.map(|gap| merge_blocks(&report.anomaly_indices, &scores, gap as usize));
+    let clusters = a.incidents.map(|gap| {
+        anomaly_clusters(&report.anomaly_indices, &scores, gap, |i| scored[i].content(data))
+    });
+    let templates = a.templates.map(|n| {
+        let labels: Vec<String> = records.iter().map(|r| record_template(r, &schema)).collect();
+        let mut t = template_contributions(&labels, &scores, &report.anomaly_indices);
+        t.truncate(n);
+        t
+    });
+    let groups = group_keys.map(|k| group_breakdown(&k, &scored, &report.anomaly_indices));
+    let review = a.review_sample.map(|n| review_sample(&scores, n));
+    // Schema scoring has no op stream, so the heatmap shows density only.
+    let offsets: Vec<usize> = scored.iter().map(|s| s.offset).collect();
+    let flagged: Vec<usize> = report.anomaly_indices.iter().map(|&i| scored[i].offset).collect();
+    let heatmap = a.heatmap.map(|n| offset_heatmap(data.len(), n, &[], &offsets, &flagged));
+    let sigma = sigma_findings(a, scored.iter().map(|s| (s.index, s.offset, s.content(data))));
+    let transitions = state_keys.map(|k| improbable_transitions(&k, session_keys.as_deref()));
+    let sessions = session_keys.map(|k| {
+        let templates: Vec<String> = records.iter().map(|r| record_template(r, &schema)).collect();
+        let mut s = score_sessions(&k, &templates, &scores);
+        s.truncate(a.top_sessions);
+        s
+    });
+    let drift = a.drift.map(|n| {
+        let mut fields: Vec<String> = schema.fields.values()
+            .filter(|f| f.is_low_cardinality && (2..=MAX_DRIFT_VALUES).contains(&f.unique_values))
+            .map(|f| f.name.to_string())
+            .collect();
+        fields.sort();
+        let columns: Vec<(String, Vec<Option<String>>)> = fields.into_iter()
+            .map(|f| { let keys = field_keys(&records, &f); (f, keys) })
+            .collect();
+        ratio_drift(&columns, n as usize, a.drift_threshold)
+    });
+    let timeline = a.timeline.then(|| schema_timeline(&records));
+    let changes = timeline.as_ref().map(|t| t.changes());
+
+    let locator = locator(a, data, files, source);
+    let extracted = || report.anomaly_indices.iter().map(|&i| {
+        let s = &scored[i];
+        Excerpt { index: s.index, offset: s.offset, score: s.anomaly_score, content: s.content(data) }
+    });
+    if let Some(code) = extract(a, data, &locator, extracted()) {
+        return code;
+    }
+    if a.json {
+        let anomalies: Vec<serde_json::Value> = report.anomaly_indices.iter().enumerate().map(|(k, &i)| {
+            let s = &scored[i];
+            let mut v = serde_json::json!({
+                "index": s.index, "offset": s.offset, "length": s.length,
+                "location": locator.locate(s.offset),
+                "anomaly_score": (s.anomaly_score * 1e6).round() / 1e6,
+                "content": String::from_utf8_lossy(s.content(data)).trim_end(),
+            });
+            add_json_reasons(&mut v, s);
+            if let Some(levels) = &levels {
+                v["severity"] = serde_json::json!(levels[i].map(|l| l.name()));
+            }
+            if let Some(raw) = &raw_scores {
+                v["raw_score"] = serde_json::json!((raw[i] * 1e6).round() / 1e6);
+            }
+            if let Some(sim) = &seeded {
+                v["seed_similarity"] = serde_json::json!((sim[i] * 1e6).round() / 1e6);
+            }
+            if let Some(h) = &hybrid {
+                v["json_score"] = serde_json::json!((h[i].json_score * 1e6).round() / 1e6);
+                v["lz77_score"] = serde_json::json!((h[i].lz77_score * 1e6).round() / 1e6);
+            }
+            if let Some(m) = tags.get(k) {
+                rule_json(&mut v, m);
+            }
+            v
+        }).collect();
+        let mut out = serde_json::json!({
+            "mode": mode, "total_records": report.total_records,
+            "valid_records": report.valid_records, "parse_errors": report.parse_errors,
+            "field_count": report.field_count,
+            "anomaly_count": report.anomaly_count,
+            "anomaly_rate": (report.anomaly_rate() * 1e6).round() / 1e6,
+            "threshold": (report.threshold * 1e6).round() / 1e6,
+            "anomalies": anomalies,
+        });
+        if let Some(t) = low_threshold {
+            out["low_threshold"] = serde_json::json!((t * 1e6).round() / 1e6);
+        }
+        if direction != Direction::High {
+            out["direction"] = serde_json::json!(a.direction);
+        }
+        if let Some(f) = a.level_boost {
+            out["level_boost"] = serde_json::json!(f);
+        }
+        if a.rules.is_some() {
+            out["suppressed_count"] = serde_json::json!(suppressed);
+        }
+        if let Some(n) = a.smooth {
+            out["smooth"] = serde_json::json!(n);
+        }
+        if a.seed.is_some() {
+            out["seed_weight"] = serde_json::json!(a.seed_weight);
+        }
+        if let Some(blocks) = &blocks {
+            out["anomaly_blocks"] = blocks_json(blocks, &scores, |i| scored[i].index);
+        }
+        if let (Some(gap), Some(clusters)) = (a.incidents, &clusters) {
+            out["cluster_gap"] = gap_json(gap);
+            out["anomaly_clusters"] = clusters_json(clusters, &scores, |i| scored[i].index);
+        }
+        if let Some(t) = &templates {
+            out["templates"] = templates_json(t, |i| scored[i].index);
+        }
+        if let Some(sample) = &review {
+            out["review_sample"] = review_json(sample, &scores, &report.anomaly_indices, |i| scored[i].index,
+                |i| locator.locate(scored[i].offset), |i| scored[i].content(data));
+        }
+        if let Some(findings) = &sigma {
+            out["sigma_matches"] = sigma_json(findings);
+        }
+        if let Some(n) = oversized_count(data, c) {
+            out["oversized_records"] = serde_json::json!(n);
+            out["oversize_policy"] = serde_json::json!(c.oversize);
+        }
+        if let Some(t) = truncations_json(cut, &a.fields) {
+            out["truncated"] = t;
+        }
+        if let Some(segs) = &heatmap {
+            out["heatmap"] = heatmap_json(segs, false);
+        }
+        if !members.is_empty() {
+            out["gzip_members"] = members_json(members, &offsets, &flagged);
+        }
+        if !files.is_empty() {
+            out["archive_files"] = files_json(files, &offsets, Some(&flagged));
+        }
+        if let (Some(field), Some(groups)) = (&a.group_by, &groups) {
+            out["group_by"] = serde_json::json!(field);
+            out["groups"] = groups.iter().map(|g| serde_json::json!({
+                "value": g.value, "records": g.records, "anomalies": g.anomalies,
+                "anomaly_rate": (g.anomaly_rate() * 1e6).round() / 1e6,
+                "mean_score": (g.mean_score * 1e6).round() / 1e6,
+            })).collect();
+        }
+        if let (Some(field), Some(sessions)) = (&a.session_field, &sessions) {
+            out["session_field"] = serde_json::json!(field);
+            out["sessions"] = sessions.iter().map(|s| serde_json::json!({
+                "key": s.key, "records": s.members.len(),
+                "score": (s.score * 1e6).round() / 1e6,
+                "length_score": (s.length_score * 1e6).round() / 1e6,
+                "sequence_score": (s.sequence_score * 1e6).round() / 1e6,
+                "member_score": (s.member_score * 1e6).round() / 1e6,
+                "members": s.members.iter().map(|&i| scored[i].index).collect::<Vec<_>>(),
+            })).collect();
+        }
+        if let (Some(field), Some(transitions)) = (&a.state_field, &transitions) {
+            out["state_field"] = serde_json::json!(field);
+            out["transitions"] = transitions.iter().map(|t| serde_json::json!({
+                "index": t.index, "prev_index": t.prev_index, "from": t.from, "to": t.to,
+                "probability": (t.probability * 1e6).round() / 1e6, "from_count": t.from_count,
+            })).collect();
+        }
+        if let Some(shifts) = &drift {
+            out["drift"] = shifts.iter().map(|d| serde_json::json!({
+                "field": d.field, "value": d.value, "window": d.window,
+                "start_index": d.start, "end_index": d.end, "records": d.records,
+                "baseline_rate": (d.baseline_rate * 1e6).round() / 1e6,
+                "window_rate": (d.window_rate * 1e6).round() / 1e6,
+            })).collect();
+        }
+        if let (Some(t), Some(changes)) = (&timeline, &changes) {
+            let fields: serde_json::Map<String, serde_json::Value> = t.fields.iter()
+                .map(|(f, s)| (f.clone(), serde_json::json!({
+                    "first_index": s.first, "last_index": s.last, "records": s.count,
+                })))
+                .collect();
+            out["schema_timeline"] = serde_json::json!({
+                "fields": fields,
+                "changes": changes.iter().map(|ch| serde_json::json!({
+                    "field": ch.field, "value": ch.value, "change": ch.kind.name(),
+                    "index": ch.index, "first_index": ch.span.first, "last_index": ch.span.last,
+                    "records": ch.span.count,
+                    "density": (ch.span.density() * 1e6).round() / 1e6,
+                })).collect::<Vec<_>>(),
+            });
+        }
+        print_json(out);
+    } else {
+        let label = if hybrid.is_some() { "hybrid" } else { "JSON" };
+        println!("=== Anomaly Report ({label}): {} ===", c.input);
+        println!("  records:         {:>8}", report.total_records);
+        print_oversized(oversized_count(data, c), c, 17, 8);
+        print_truncations(cut, &a.fields, 17, 8);
+        println!("  valid JSON:      {:>8}", report.valid_records);
+        println!("  parse errors:    {:>8}", report.parse_errors);
+        println!("  schema fields:   {:>8}", report.field_count);
+        println!("  mean score:      {:>8.4}", report.mean_score);
+        println!("  stdev score:     {:>8.4}", report.stdev_score);
+        print_thresholds(report.threshold, low_threshold, direction);
+        println!("  anomalies:       {:>8}  ({:.1}%)",
+            report.anomaly_count, report.anomaly_rate() * 100.0);
+        if let Some(f) = a.level_boost {
+            println!("  level boost:     {:>8}  (ERROR/FATAL; WARN x{:.2})", format!("x{f}"), f.sqrt());
+        }
+        if a.rules.is_some() {
+            println!("  suppressed:      {suppressed:>8}  (by --rules)");
+        }
+        if let Some(n) = a.smooth {
+            println!("  smoothing:       {n:>8}  (records per score)");
+        }
+        if let Some(path) = &a.seed {
+            println!("  seed weight:     {:>8}  ({path})", a.seed_weight);
+        }
+        if let (Some(gap), Some(clusters)) = (a.incidents, &clusters) {
+            print_clusters(clusters, gap, &scores, |i| scored[i].index, |i| scored[i].content(data));
+        }
+        print_sparkline(&scores, a.sparkline);
+        if let Some(segs) = &heatmap {
+            print_heatmap(segs, false);
+        }
+        print_members(members, &offsets, &flagged);
+        print_files(files, &offsets, Some(&flagged));
+
+        if let (Some(field), Some(groups)) = (&a.group_by, &groups) {
+            println!("\n--- Anomalies by {field} ---");
+            println!("  {:24} {:>8} {:>9} {:>7} {:>10}",
+                "value", "records", "anomalies", "rate", "mean score");
+            for g in groups {
+                println!("  {:24} {:>8} {:>9} {:>6.1}% {:>10.4}",
+                    shorten(&g.value, 24), g.records, g.anomalies,
+                    g.anomaly_rate() * 100.0, g.mean_score);
+            }
+        }
+
+        if let (Some(field), Some(sessions)) = (&a.session_field, &sessions) {
+            println!("\n--- Anomalous Sessions (by {field}) ---");
+            println!("  {:24} {:>7} {:>7} {:>7} {:>7} {:>7}",
+                "session", "records", "score", "length", "seq", "member");
+            for s in sessions {
+                println!("  {:24} {:>7} {:>7.4} {:>7.4} {:>7.4} {:>7.4}",
+                    shorten(&s.key, 24), s.members.len(), s.score,
+                    s.length_score, s.sequence_score, s.member_score);
+            }
+        }
+
+        if let (Some(field), Some(transitions)) = (&a.state_field, &transitions) {
+            print_transitions(field, transitions);
+        }
 
-    println!("\n--- Field Profiles ---");
-    println!("  {:20} {:>6} {:>7} {:>5} {:>6}",
-        "field", "count", "rate", "type", "uniq");
-    for f in fields.iter().take(20) {
-        println!("  {:20} {:>6} {:>6.1}% {:>5} {:>6}{}",
-            f.name, f.present_count,
-            f.presence_rate * 100.0,
-            f.dominant_type,
-            f.unique_values,
-            if f.is_low_cardinality { "" } else { " (high)" });
-    }
+        if let Some(shifts) = &drift {
+            print_drift(shifts, a.drift.unwrap_or(0));
+        }
 
-    // Top values for low-cardinality fields
-    let low_card: Vec<_> = fields.iter()
-        .filter(|f| f.is_low_cardinality && f.unique_values > 1 && f.unique_values <= 20)
-        .take(5)
-        .collect();
-    if !low_card.is_empty() {
-        println!("\n--- Value Distributions (low-cardinality fields) ---");
-        for f in low_card {
-            let mut vals: Vec<_> = f.value_counts.iter().collect();
-            vals.sort_by(|a, b| b.1.cmp(a.1));
-            let shown: Vec<String> = vals.iter().take(5)
-                .map(|(v, c)| {
-                    let trunc = if v.len() > 20 { format!("{}...", &v[..17]) } else { v.to_string() };
-                    format!("{trunc}={c}")
-                })
-                .collect();
-            let more = if vals.len() > 5 { format!(" (+{} more)", vals.len() - 5) } else { String::new() };
-            println!("  {}: {}{more}", f.name, shown.join(", "));
+        if let Some(changes) = &changes {
+            print_timeline(changes);
         }
-    }
 
-    // Score distribution
-    if !scored.is_empty() {
-        let scores: Vec<f64> = scored.iter().map(|s| s.anomaly_score).collect();
-        let sum: f64 = scores.iter().sum();
-        let mean = sum / scores.len() as f64;
-        println!("\n--- Anomaly Score Distribution ---");
-        println!("  mean:   {mean:.4}");
+        if let Some(findings) = &sigma {
+            print_sigma(findings);
+        }
+        if let Some(t) = &templates {
+            print_templates(t, |i| scored[i].index, |i| scored[i].content(data));
+        }
+        let blocks = blocks.unwrap_or_default();
+        print_blocks(&blocks, &scores, |i| scored[i].index, |i| scored[i].content(data));
 
-        let mut buckets = [0usize; 10];
-        for &s in &scores { buckets[(s * 10.0).min(9.0) as usize] += 1; }
-        let max_count = *buckets.iter().max().unwrap_or(&1).max(&1);
-        for (i, &count) in buckets.iter().enumerate() {
-            let bar = "#".repeat((count as f64 / max_count as f64 * 40.0) as usize);
-            println!("  0.{i}-0.{}: {:6} {bar}", i + 1, count);
+        if report.anomaly_indices.iter().any(|&i| !in_block(&blocks, i)) {
+            println!("\n--- Anomalous Records ---");
+            for (k, &i) in report.anomaly_indices.iter().enumerate() {
+                if in_block(&blocks, i) {
+                    continue;
+                }
+                let s = &scored[i];
+                let line = String::from_utf8_lossy(s.content(data)).trim_end().to_string();
+                let shown = if line.len() > 120 { format!("{}...", &line[..117]) } else { line };
+                let mut parts = match &hybrid {
+                    Some(h) => format!("  json={:.4}  lz77={:.4}", h[i].json_score, h[i].lz77_score),
+                    None => String::new(),
+                };
+                if let Some(raw) = &raw_scores {
+                    parts += &format!("  raw={:.4}", raw[i]);
+                }
+                if let Some(sim) = &seeded {
+                    parts += &format!("  seed={:.2}", sim[i]);
+                }
+                println!("  [{:6}]  {}  score={:.4}{parts}  fields={:2}  {shown}",
+                    s.index, locator.locate(s.offset), s.anomaly_score, s.field_count);
+
+                print_json_reasons(s);
+                if let Some(m) = tags.get(k) {
+                    print_rule_match(m);
+                }
+            }
+        }
+        if let Some(sample) = &review {
+            print_review(sample, &scores, &report.anomaly_indices, |i| scored[i].index,
+                |i| locator.locate(scored[i].offset), |i| scored[i].content(data));
+        }
+        if a.extract && a.extract_to.is_none() {
+            println!("\n--- Extracted Anomalous Lines ---");
+            let _ = write_extracted(&mut io::stdout().lock(), a, data, &locator, extracted());
         }
     }
     0
 }
 
 // ---------------------------------------------------------------------------
-// Anomalies command (LZ77 or JSON)
+// Values and fields commands (JSON)
 // ---------------------------------------------------------------------------
 
-fn cmd_anomalies(
-    c: CommonArgs, min_count: usize, method_str: Option<String>,
-    threshold: Option<f64>, top_n: Option<usize>,
-    json: bool, extract: bool, structured: bool,
+fn cmd_values(
+    input: String,
+    field: String,
+    top: Option<usize>,
+    examples: usize,
+    json: bool,
+    max_records: Option<usize>,
 ) -> i32 {
-    let data = read_input(&c.input);
-    let method = parse_method(&method_str, &top_n);
-
-    if is_json_mode(&data, structured) {
-        return cmd_anomalies_json(&c, &data, method, threshold, top_n, json, extract);
-    }
-
-    let t0 = Instant::now();
-    let ops = scan(&data, c.window_size, c.min_match, MAX_MATCH);
-    let dict = build_dictionary(&data, &ops, min_count);
-    let records = score_records(&data, &ops, &dict, b'\n');
-    if c.verbose { eprintln!("  pipeline: {:.4}s", t0.elapsed().as_secs_f64()); }
-
-    let report = detect_anomalies(&records, dict.len(), method, threshold, top_n);
+    let data = read_records(&input, max_records).data;
+    let records = parse_json_records(&data, b'\n');
+    let Some(fv) = field_values(&records, &field, examples) else {
+        eprintln!("error: field {field:?} not found in {input}");
+        return 1;
+    };
+    let limit = top.unwrap_or(fv.values.len());
+    let line_of = |i: usize| {
+        String::from_utf8_lossy(records[i].content(&data)).trim_end().to_string()
+    };
 
     if json {
-        let anomalies: Vec<serde_json::Value> = report.anomaly_indices.iter().map(|&i| {
-            let r = &records[i];
+        let values: Vec<serde_json::Value> = fv.values.iter().take(limit).map(|v| {
             serde_json::json!({
-                "index": r.index, "offset": r.offset, "length": r.length,
-                "coverage": (r.coverage * 1e6).round() / 1e6,
-                "anomaly_score": (r.anomaly_score * 1e6).round() / 1e6,
-                "literal_bytes": r.literal_bytes, "backref_bytes": r.backref_bytes,
-                "ref_entries": r.ref_entries,
-                "content": String::from_utf8_lossy(r.content(&data)).trim_end(),
+                "value": v.value, "count": v.count,
+                "first_index": v.first_index, "last_index": v.last_index,
+                "examples": v.examples.iter().map(|&i| serde_json::json!({
+                    "index": i, "offset": records[i].offset, "content": line_of(i),
+                })).collect::<Vec<_>>(),
             })
         }).collect();
+        let types: serde_json::Map<String, serde_json::Value> = fv.type_counts.iter()
+            .map(|(t, c)| (t.to_string(), serde_json::json!(c)))
+            .collect();
         let out = serde_json::json!({
-            "mode": "lz77", "total_records": report.total_records,
-            "anomaly_count": report.anomaly_count,
-            "anomaly_rate": (report.anomaly_rate() * 1e6).round() / 1e6,
-            "threshold": (report.threshold * 1e6).round() / 1e6,
-            "anomalies": anomalies,
+            "field": fv.name, "total_records": fv.total_records,
+            "present_count": fv.present_count, "unique_values": fv.values.len(),
+            "types": types, "values": values,
         });
-        println!("{}", serde_json::to_string_pretty(&out).unwrap());
+        print_json(out);
     } else {
-        println!("=== Anomaly Report (LZ77): {} ===", c.input);
-        println!("  records:         {:>8}", report.total_records);
-        println!("  mean coverage:   {:>8.4}", report.mean_coverage);
-        println!("  median coverage: {:>8.4}", report.median_coverage);
-        println!("  stdev coverage:  {:>8.4}", report.stdev_coverage);
-        println!("  threshold:       {:>8.4}", report.threshold);
-        println!("  anomalies:       {:>8}  ({:.1}%)",
-            report.anomaly_count, report.anomaly_rate() * 100.0);
+        println!("=== Values: {} in {input} ===", fv.name);
+        println!("  present:        {:>10}  ({:.1}% of {} records)",
+            fv.present_count, pct(fv.present_count, fv.total_records), fv.total_records);
+        println!("  unique values:  {:>10}", fv.values.len());
+        let types: Vec<String> = fv.type_counts.iter().map(|(t, c)| format!("{t}={c}")).collect();
+        println!("  types:          {}", types.join(", "));
 
-        if !report.anomaly_indices.is_empty() {
-            println!("\n--- Anomalous Records ---");
-            for &i in &report.anomaly_indices {
-                let r = &records[i];
-                let line = String::from_utf8_lossy(r.content(&data)).trim_end().to_string();
-                let shown = if line.len() > 120 { format!("{}...", &line[..117]) } else { line };
-                println!("  [{:6}]  score={:.4}  cov={:.2}  lit={:4}  refs={:2}  {shown}",
-                    r.index, r.anomaly_score, r.coverage, r.literal_bytes, r.ref_entries.len());
+        println!("\n  {:>8} {:>7} {:>8} {:>8}  value", "count", "share", "first", "last");
+        for v in fv.values.iter().take(limit) {
+            let shown = shorten(&v.value, 60);
+            println!("  {:>8} {:>6.1}% {:>8} {:>8}  {shown}",
+                v.count, pct(v.count, fv.present_count), v.first_index, v.last_index);
+            for &i in &v.examples {
+                println!("  {:>35}  e.g. [{i}] {}", "", shorten(&line_of(i), 100));
             }
         }
-        if extract {
-            println!("\n--- Extracted Anomalous Lines ---");
-            let stdout = io::stdout();
-            let mut out = stdout.lock();
-            for &i in &report.anomaly_indices {
-                let _ = out.write_all(records[i].content(&data));
-            }
+        if limit < fv.values.len() {
+            println!("  ... {} more values", fv.values.len() - limit);
         }
     }
     0
 }
 
-fn cmd_anomalies_json(
-    c: &CommonArgs, data: &[u8], method: DetectionMethod,
-    threshold: Option<f64>, top_n: Option<usize>,
-    json_out: bool, extract: bool,
-) -> i32 {
-    let t0 = Instant::now();
-    let records = parse_json_records(data, b'\n');
+fn cmd_fields(input: String, fields: FieldArgs, top: Option<usize>, json: bool, max_records: Option<usize>) -> i32 {
+    let data = read_records(&input, max_records).data;
+    let (records, _) = parse_filtered(&data, &fields, false, None);
     let schema = build_schema(&records);
-    let scored = score_json_records(data, &records, &schema);
-    if c.verbose { eprintln!("  pipeline: {:.4}s", t0.elapsed().as_secs_f64()); }
-
-    let scores: Vec<f64> = scored.iter().map(|s| s.anomaly_score).collect();
-    let (threshold_used, anomaly_indices) =
-        detect_indices(&scores, None, method, threshold, top_n);
-    let report = build_json_report(&records, &scored, &schema, threshold_used, anomaly_indices);
+    let scored = score_weighted(&data, &records, &schema, &fields, None);
+    let ranked = field_contributions(&schema, &scored);
+    let limit = top.unwrap_or(ranked.len());
 
-    if json_out {
-        let anomalies: Vec<serde_json::Value> = report.anomaly_indices.iter().map(|&i| {
-            let s = &scored[i];
+    if json {
+        let fields: Vec<serde_json::Value> = ranked.iter().take(limit).map(|f| {
             serde_json::json!({
-                "index": s.index, "offset": s.offset, "length": s.length,
-                "anomaly_score": (s.anomaly_score * 1e6).round() / 1e6,
-                "field_count": s.field_count,
-                "missing_common": s.missing_common,
-                "extra_rare": s.extra_rare,
-                "rare_values": s.rare_values.iter().map(|(f,v)| format!("{f}={v}")).collect::<Vec<_>>(),
-                "type_mismatches": s.type_mismatches.iter()
-                    .map(|(f,exp,act)| format!("{f}: expected {exp}, got {act}")).collect::<Vec<_>>(),
-                "content": String::from_utf8_lossy(s.content(data)).trim_end(),
+                "field": &*f.name,
+                "contribution": (f.contribution * 1e6).round() / 1e6,
+                "presence_rate": (f.presence_rate * 1e6).round() / 1e6,
+                "presence_instability": (f.presence_instability * 1e6).round() / 1e6,
+                "missing_count": f.missing_count,
+                "rare_value_count": f.rare_value_count,
+                "extra_rare_count": f.extra_rare_count,
+                "type_mismatch_count": f.type_mismatch_count,
+                "unexpected_null_count": f.unexpected_null_count,
+                "unexpected_empty_count": f.unexpected_empty_count,
+                "enum_violation_count": f.enum_violation_count,
             })
         }).collect();
         let out = serde_json::json!({
-            "mode": "json", "total_records": report.total_records,
-            "valid_records": report.valid_records, "parse_errors": report.parse_errors,
-            "field_count": report.field_count,
-            "anomaly_count": report.anomaly_count,
-            "anomaly_rate": (report.anomaly_rate() * 1e6).round() / 1e6,
-            "threshold": (report.threshold * 1e6).round() / 1e6,
-            "anomalies": anomalies,
+            "total_records": schema.total_records,
+            "valid_records": schema.valid_records,
+            "fields": fields,
         });
-        println!("{}", serde_json::to_string_pretty(&out).unwrap());
+        print_json(out);
     } else {
-        println!("=== Anomaly Report (JSON): {} ===", c.input);
-        println!("  records:         {:>8}", report.total_records);
-        println!("  valid JSON:      {:>8}", report.valid_records);
-        println!("  parse errors:    {:>8}", report.parse_errors);
-        println!("  schema fields:   {:>8}", report.field_count);
-        println!("  mean score:      {:>8.4}", report.mean_score);
-        println!("  stdev score:     {:>8.4}", report.stdev_score);
-        println!("  threshold:       {:>8.4}", report.threshold);
-        println!("  anomalies:       {:>8}  ({:.1}%)",
-            report.anomaly_count, report.anomaly_rate() * 100.0);
+        println!("=== Field Contributions: {input} ===");
+        println!("  records:        {:>10}", schema.total_records);
+        println!("  valid JSON:     {:>10}", schema.valid_records);
+        println!("  unique fields:  {:>10}", schema.fields.len());
+        println!("\n  {:20} {:>8} {:>7} {:>7} {:>8} {:>8} {:>6} {:>6} {:>6} {:>6} {:>6}",
+            "field", "contrib", "rate", "missing", "rare-val", "mismatch", "enum", "null", "empty", "rare", "instab");
+        for f in ranked.iter().take(limit) {
+            println!("  {:20} {:>8.4} {:>6.1}% {:>7} {:>8} {:>8} {:>6} {:>6} {:>6} {:>6} {:>6.3}",
+                f.name, f.contribution, f.presence_rate * 100.0, f.missing_count,
+                f.rare_value_count, f.type_mismatch_count, f.enum_violation_count,
+                f.unexpected_null_count, f.unexpected_empty_count, f.extra_rare_count,
+                f.presence_instability);
+        }
+    }
+    0
+}
 
-        if !report.anomaly_indices.is_empty() {
-            println!("\n--- Anomalous Records ---");
-            for &i in &report.anomaly_indices {
-                let s = &scored[i];
-                let line = String::from_utf8_lossy(s.content(data)).trim_end().to_string();
-                let shown = if line.len() > 120 { format!("{}...", &line[..117]) } else { line };
-                println!("  [{:6}]  score={:.4}  fields={:2}  {shown}",
-                    s.index, s.anomaly_score, s.field_count);
+// ---------------------------------------------------------------------------
+// Histogram command
+// ---------------------------------------------------------------------------
 
-                // Show WHY it's anomalous
-                if !s.missing_common.is_empty() {
-                    println!("           missing: {}", s.missing_common.join(", "));
-                }
-                if !s.extra_rare.is_empty() {
-                    println!("           rare fields: {}", s.extra_rare.join(", "));
-                }
-                if !s.rare_values.is_empty() {
-                    let vals: Vec<String> = s.rare_values.iter()
-                        .map(|(f, v)| {
-                            let vt = if v.len() > 20 { format!("{}...", &v[..17]) } else { v.clone() };
-                            format!("{f}={vt}")
-                        })
-                        .collect();
-                    println!("           rare values: {}", vals.join(", "));
+/// Width of the longest histogram bar.
+const HIST_BAR_WIDTH: usize = 50;
+
+fn cmd_hist(h: HistArgs) -> i32 {
+    let c = &h.common;
+    let data = read_common(c).data;
+    let json_mode = is_json_mode(&data, &h.mode, &h.fields);
+
+    let values: Vec<f64> = if json_mode {
+        if h.metric == "coverage" || h.metric == "bpb" {
+            eprintln!("error: --metric {} requires LZ77 mode", h.metric);
+            return 1;
+        }
+        let (records, _) = parse_filtered(&data, &h.fields, c.normalize_space, record_limit(c));
+        let schema = baseline_schema(&data, &records, &h.baseline, &h.fields, c);
+        let scored = score_weighted(&data, &records, &schema, &h.fields, Some(&h.baseline));
+        scored.iter().map(|r| match h.metric.as_str() {
+            "length" => r.length as f64,
+            _ => r.anomaly_score,
+        }).collect()
+    } else {
+        let records = run_lz77(&data, c, h.min_count, &h.baseline, h.metric == "bpb").records;
+        records.iter().map(|r| match h.metric.as_str() {
+            "coverage" => r.coverage,
+            "bpb" => r.bits_per_byte,
+            "length" => r.length as f64,
+            _ => r.anomaly_score,
+        }).collect()
+    };
+
+    let bins = h.bins as usize;
+    let hist = if h.metric == "length" || h.metric == "bpb" {
+        Histogram::new(&values, bins)
+    } else {
+        Histogram::with_range(&values, bins, 0.0, 1.0)
+    };
+
+    if h.json {
+        let buckets: Vec<serde_json::Value> = hist.counts.iter().enumerate().map(|(i, &n)| {
+            let (lo, hi) = hist.bin_range(i);
+            serde_json::json!({
+                "lo": (lo * 1e6).round() / 1e6, "hi": (hi * 1e6).round() / 1e6, "count": n,
+            })
+        }).collect();
+        let out = serde_json::json!({
+            "mode": if json_mode { "json" } else { "lz77" },
+            "metric": h.metric, "records": values.len(), "bins": buckets,
+        });
+        print_json(out);
+        return 0;
+    }
+
+    let scale = |n: usize| if h.log_scale { (n as f64).ln_1p() } else { n as f64 };
+    let max = hist.counts.iter().map(|&n| scale(n)).fold(0.0, f64::max).max(1e-12);
+    println!("=== Histogram ({}): {} ===", h.metric, c.input);
+    println!("  records: {}", values.len());
+    println!("  bins:    {bins}{}", if h.log_scale { "  (log scale)" } else { "" });
+    println!();
+    for (i, &n) in hist.counts.iter().enumerate() {
+        let (lo, hi) = hist.bin_range(i);
+        let mut len = (scale(n) / max * HIST_BAR_WIDTH as f64).round() as usize;
+        if n > 0 {
+            len = len.max(1);
+        }
+        println!("  {lo:>10.4} - {hi:<10.4} {n:>8} {}", "#".repeat(len));
+    }
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Profile command
+// ---------------------------------------------------------------------------
+
+fn cmd_profile(action: ProfileCmd) -> i32 {
+    match action {
+        ProfileCmd::Train(t) => {
+            let TrainArgs { common: c, min_count, mode, fields, output } = *t;
+            let p = train_profile(&c, min_count, &mode, &fields);
+            save_profile_or_exit(&output, &p);
+            print_profile_summary(&output, &p);
+        }
+        ProfileCmd::Update(u) => {
+            let UpdateArgs { profile, common: c, min_count, mode, fields, decay, output } = *u;
+            let old = load_profile(&profile).unwrap_or_else(|e| {
+                eprintln!("error: {profile}: {e}");
+                std::process::exit(1);
+            });
+            check_saved_params(&profile, old.params.as_ref(), &c);
+            let new = train_profile(&c, min_count, &mode, &fields);
+            let p = old.decay(decay).merge(&new).unwrap_or_else(|e| {
+                eprintln!("error: {profile}: {e}");
+                std::process::exit(1);
+            });
+            let output = output.unwrap_or(profile);
+            save_profile_or_exit(&output, &p);
+            print_profile_summary(&output, &p);
+        }
+        ProfileCmd::Merge { inputs, output } => {
+            let mut merged: Option<Profile> = None;
+            for path in &inputs {
+                let p = load_profile(path).unwrap_or_else(|e| {
+                    eprintln!("error: {path}: {e}");
+                    std::process::exit(1);
+                });
+                merged = Some(match merged {
+                    Some(m) => m.merge(&p).unwrap_or_else(|e| {
+                        eprintln!("error: {path}: {e}");
+                        std::process::exit(1);
+                    }),
+                    None => p,
+                });
+            }
+            let p = merged.expect("clap requires inputs");
+            save_profile_or_exit(&output, &p);
+            print_profile_summary(&output, &p);
+        }
+    }
+    0
+}
+
+fn train_profile(c: &CommonArgs, min_count: usize, mode: &ModeArgs, fields: &FieldArgs) -> Profile {
+    let data = read_common(c).data;
+    let reference = scan_input(&data, c).into_owned();
+    let ops = run_scan(&reference, c);
+    let dictionary = build_dictionary(&reference, &ops, min_count);
+    let schema = is_json_mode(&data, mode, fields).then(|| build_schema(&parse_filtered(&data, fields, c.normalize_space, record_limit(c)).0));
+    Profile { params: Some(saved_params(c)), reference, dictionary, schema }
+}
+
+fn save_profile_or_exit(path: &str, p: &Profile) {
+    if let Err(e) = save_profile(path, p) {
+        eprintln!("error: {path}: {e}");
+        std::process::exit(1);
+    }
+}
+
+fn print_profile_summary(path: &str, p: &Profile) {
+    println!("=== Profile: {path} ===");
+    println!("  reference:      {:>10} bytes", p.reference.len());
+    println!("  dict entries:   {:>10}", p.dictionary.len());
+    match &p.schema {
+        Some(s) => {
+            println!("  JSON records:   {:>10}", s.total_records);
+            println!("  unique fields:  {:>10}", s.fields.len());
+        }
+        None => println!("  JSON schema:    {:>10}", "none"),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Score-line command
+// ---------------------------------------------------------------------------
+
+/// Scan options to score against a profile with: the defaults, with the
+/// window, match length and preprocessing the profile was trained with.
+fn profile_common(path: &str, p: &Profile) -> CommonArgs {
+    #[derive(Parser)]
+    struct Defaults {
+        #[command(flatten)]
+        common: CommonArgs,
+    }
+    let mut c = Defaults::parse_from(["bgtzip", "-"]).common;
+    if let Some(s) = &p.params {
+        c.window_size = s.window_size as usize;
+        c.min_match = s.min_match as usize;
+        c.ignore_case = s.ignore_case;
+        c.normalize_space = s.normalize_space;
+        if !s.mask.is_empty() {
+            c.strip_regex = Some(Regex::new(&s.mask).unwrap_or_else(|e| {
+                eprintln!("error: {path}: saved mask: {e}");
+                std::process::exit(1);
+            }));
+        }
+    }
+    c
+}
+
+fn verdict_json(v: &mut serde_json::Value, verdict: &Verdict) {
+    v["anomaly_score"] = serde_json::json!((verdict.score * 1e6).round() / 1e6);
+    v["threshold"] = serde_json::json!((verdict.threshold * 1e6).round() / 1e6);
+    v["percentile"] = serde_json::json!((verdict.percentile * 1e4).round() / 1e4);
+    v["anomalous"] = serde_json::json!(verdict.is_anomalous());
+    v["reference_records"] = serde_json::json!(verdict.reference_records);
+}
+
+fn print_verdict(verdict: &Verdict) {
+    println!("  score:           {:>8.4}", verdict.score);
+    println!("  threshold:       {:>8.4}", verdict.threshold);
+    println!("  percentile:      {:>8.1}  (of {} profile records)", verdict.percentile, verdict.reference_records);
+    println!("  verdict:         {}", if verdict.is_anomalous() { "anomalous" } else { "normal" });
+}
+
+fn cmd_score_line(a: ScoreLineArgs) -> i32 {
+    if a.mode.mode == "mixed" {
+        eprintln!("error: --mode mixed is not supported by score-line, which scores one record by one format");
+        return 1;
+    }
+    let raw = read_bytes(&a.profile).0;
+    let profile = parse_profile(&a.profile, &raw);
+    let text = match &a.line {
+        Some(line) => line.clone().into_bytes(),
+        None => {
+            use std::io::Read;
+            let mut buf = Vec::new();
+            if let Err(e) = io::stdin().read_to_end(&mut buf) {
+                eprintln!("error: stdin: {e}");
+                return 1;
+            }
+            buf
+        }
+    };
+    let line = match one_record(&text) {
+        Ok(line) => line,
+        Err(lines) => {
+            eprintln!("error: score-line scores one record; got {lines} lines");
+            return 1;
+        }
+    };
+    if line.is_empty() {
+        eprintln!("error: no record to score");
+        return 1;
+    }
+    let data = line.to_vec();
+    let c = profile_common(&a.profile, &profile);
+
+    let forced = a.mode.structured || a.mode.mode == "json" || !a.fields.extract_pattern.is_empty();
+    if is_json_mode(&data, &a.mode, &a.fields) {
+        match &profile.schema {
+            Some(schema) => return score_line_json(&a, &c, &profile, schema, &data),
+            None if forced => {
+                eprintln!("error: {}: profile has no JSON schema", a.profile);
+                return 1;
+            }
+            None => {}
+        }
+    }
+    score_line_lz77(&a, &c, &profile, &data)
+}
+
+fn score_line_lz77(a: &ScoreLineArgs, c: &CommonArgs, profile: &Profile, data: &[u8]) -> i32 {
+    let masked = scan_input(data, c);
+    let Some(LineScore { record: r, spans, verdict }) = score_line(profile, &masked, &scan_params(c), a.min_count)
+    else {
+        eprintln!("error: no record to score");
+        return 1;
+    };
+    let dict = &profile.dictionary;
+    let mut top = entry_bytes(&spans);
+    top.truncate(a.top_entries);
+    let content = String::from_utf8_lossy(r.content(data)).trim_end().to_string();
+
+    if a.json {
+        let mut v = serde_json::json!({
+            "mode": "lz77", "profile": a.profile, "content": content, "length": r.length,
+        });
+        verdict_json(&mut v, &verdict);
+        v["coverage"] = serde_json::json!((r.coverage * 1e6).round() / 1e6);
+        v["bits_per_byte"] = serde_json::json!((r.bits_per_byte * 1e6).round() / 1e6);
+        v["literal_bytes"] = serde_json::json!(r.literal_bytes);
+        v["backref_bytes"] = serde_json::json!(r.backref_bytes);
+        v["ref_entries"] = serde_json::json!(r.ref_entries);
+        v["top_entries"] = top.iter().map(|&(id, bytes)| serde_json::json!({
+            "id": id, "bytes": bytes,
+            "content": dict.get(id).map(|e| String::from_utf8_lossy(&e.content)),
+        })).collect();
+        v["literal_spans"] = literal_spans_json(&spans, data, 0);
+        v["coverage_breakdown"] = coverage_breakdown_json(&r, &top);
+        print_json(v);
+        return 0;
+    }
+    println!("=== Score Line (LZ77): {} ===", a.profile);
+    print_verdict(&verdict);
+    println!("  coverage:        {:>8.4}", r.coverage);
+    println!("  bits/byte:       {:>8.2}", r.bits_per_byte);
+    println!("  literal bytes:   {:>8}  (of {})", r.literal_bytes, r.length);
+    println!("  dict entries:    {:>8}", r.ref_entries.len());
+    println!("\n  {content}");
+    if !top.is_empty() {
+        println!("\n--- Top Entries ---");
+        for &(id, n) in &top {
+            let text = dict.get(id).map_or(String::new(), |e| String::from_utf8_lossy(&e.content).into_owned());
+            println!("  #{id:<6} {n:>4}B ({:>3.0}%)  {text:?}", pct(n, r.length));
+        }
+    }
+    let literals = literal_runs(&spans, 0);
+    if !literals.is_empty() {
+        println!("\n--- Literal Spans ---");
+        for run in literals.into_iter().take(MAX_LITERAL_SPANS) {
+            println!("  @{:<6} {:>4}B  {:?}", run.start, run.len(), String::from_utf8_lossy(&data[run.clone()]));
+        }
+    }
+    0
+}
+
+fn score_line_json(a: &ScoreLineArgs, c: &CommonArgs, profile: &Profile, schema: &SchemaProfile, data: &[u8]) -> i32 {
+    let records = parse_filtered(data, &a.fields, c.normalize_space, None).0;
+    let Some(s) = score_weighted(data, &records, schema, &a.fields, None).into_iter().next() else {
+        eprintln!("error: no record to score");
+        return 1;
+    };
+    let base = parse_filtered(&profile.reference, &a.fields, c.normalize_space, None).0;
+    let reference: Vec<f64> = score_weighted(&profile.reference, &base, schema, &a.fields, None)
+        .iter().map(|s| s.anomaly_score).collect();
+    let verdict = Verdict::new(s.anomaly_score, &reference);
+    let content = String::from_utf8_lossy(s.content(data)).trim_end().to_string();
+
+    if a.json {
+        let mut v = serde_json::json!({
+            "mode": "json", "profile": a.profile, "content": content, "length": s.length,
+            "valid_json": s.valid_json,
+        });
+        verdict_json(&mut v, &verdict);
+        add_json_reasons(&mut v, &s);
+        print_json(v);
+        return 0;
+    }
+    println!("=== Score Line (JSON): {} ===", a.profile);
+    print_verdict(&verdict);
+    println!("  fields:          {:>8}{}", s.field_count, if s.valid_json { "" } else { "  (not valid JSON)" });
+    println!("\n  {content}");
+    print_json_reasons(&s);
+    0
+}
+
+// ---------------------------------------------------------------------------
+// Known command
+// ---------------------------------------------------------------------------
+
+fn load_filter_or_exit(path: &str) -> BloomFilter {
+    load_filter(path).unwrap_or_else(|e| {
+        eprintln!("error: {path}: {e}");
+        std::process::exit(1);
+    })
+}
+
+fn cmd_known(action: KnownCmd) -> i32 {
+    match action {
+        KnownCmd::Add { inputs, filter, capacity, fp_rate, templates } => {
+            let datas: Vec<Vec<u8>> = inputs.iter().map(|p| read_input(p)).collect();
+            let mut f = if std::path::Path::new(&filter).exists() {
+                if templates {
+                    info!(filter, "exists; keeping its template vocabulary");
                 }
-                if !s.type_mismatches.is_empty() {
-                    let mm: Vec<String> = s.type_mismatches.iter()
-                        .map(|(f, exp, act)| format!("{f}: expected {exp}, got {act}"))
-                        .collect();
-                    println!("           type mismatch: {}", mm.join(", "));
+                load_filter_or_exit(&filter)
+            } else if templates {
+                let all = datas.concat();
+                let ops = scan(&all, &ScanParams::default());
+                let vocab = Fingerprinter::from_dictionary(&build_dictionary(&all, &ops, 2));
+                BloomFilter::with_rate(capacity, fp_rate).with_templates(vocab)
+            } else {
+                BloomFilter::with_rate(capacity, fp_rate)
+            };
+            let (mut records, mut added) = (0usize, 0usize);
+            for data in &datas {
+                for (off, len) in RecordIndex::new(data, b'\n').iter() {
+                    records += 1;
+                    added += f.insert(f.fingerprint(&data[off..off + len])) as usize;
                 }
             }
+            if let Err(e) = save_filter(&filter, &f) {
+                eprintln!("error: {filter}: {e}");
+                return 1;
+            }
+            println!("=== Known Fingerprints: {filter} ===");
+            println!("  records read:   {records:>10}");
+            println!("  new templates:  {added:>10}");
+            println!("  total:          {:>10}", f.items());
+            if !f.templates().is_empty() {
+                println!("  template words: {:>10}", f.templates().words().count());
+            }
+            println!("  filter size:    {:>10} bytes", f.size_bytes());
+            println!("  est. FP rate:   {:>10.6}", f.fp_rate());
         }
-        if extract {
-            println!("\n--- Extracted Anomalous Lines ---");
-            let stdout = io::stdout();
-            let mut out = stdout.lock();
-            for &i in &report.anomaly_indices {
-                let _ = out.write_all(scored[i].content(data));
+        KnownCmd::Filter { input, filter, output } => {
+            let f = load_filter_or_exit(&filter);
+            let u = unseen_records(&read_input(&input), b'\n', &f);
+            let res = if output == "-" {
+                io::stdout().lock().write_all(&u.data)
+            } else {
+                fs::write(&output, &u.data)
+            };
+            if let Err(e) = res {
+                eprintln!("error: {output}: {e}");
+                return 1;
             }
+            info!(input, unseen = u.index.len(), records = u.total, "records unseen");
         }
     }
     0
 }
 
+/// Print one follow alert from `source`; false once `out` is closed.
+fn print_alert(out: &mut impl Write, source: &str, json: bool, alert: &Alert) -> bool {
+    let (anomaly, repeat) = (&alert.anomaly, alert.repeat);
+    let text = String::from_utf8_lossy(alert.record);
+    let res = if json {
+        let alert = serde_json::json!({
+            "index": anomaly.index,
+            "score": (anomaly.score * 1e6).round() / 1e6,
+            "coverage": (anomaly.coverage * 1e6).round() / 1e6,
+            "threshold": (anomaly.threshold * 1e6).round() / 1e6,
+            "count": repeat.count,
+            "total": repeat.total,
+            "record": text,
+            "source": source,
+        });
+        writeln!(out, "{}", versioned(alert))
+    } else {
+        let repeats = match repeat.count {
+            1 => String::new(),
+            n => format!("  (x{n}, {} total)", repeat.total),
+        };
+        writeln!(out, "  {}  [{:>6}]  score={:.4}  cov={:.2}{repeats}  {}",
+            source, anomaly.index, anomaly.score, anomaly.coverage, shorten(&text, 200))
+    };
+    // A closed pipe (e.g. `| head`) ends the run quietly.
+    res.and_then(|_| out.flush()).is_ok()
+}
+
+/// Follow one source, resuming from `prev`, and print its alerts. Returns
+/// the state to save for it, if any, and whether `out` is still open.
+fn follow_source(
+    a: &FollowArgs,
+    input: &str,
+    prev: Option<FollowState>,
+    out: &mut impl Write,
+) -> (Option<FollowState>, Result<bool, String>) {
+    let path = std::path::Path::new(input);
+    if input != "-" {
+        if let Err(e) = fs::metadata(path) {
+            return (prev, Err(format!("{input}: {e}")));
+        }
+    }
+    let (detector, start) = match prev {
+        Some(s) if input != "-" => match Start::resume(&s, path) {
+            Ok(start) => (s.detector, start),
+            Err(e) => return (Some(s), Err(format!("{input}: {e}"))),
+        },
+        _ => {
+            let params = ScanParams { window_size: a.window_size, ..ScanParams::default() };
+            let method = parse_method(&a.method, &None);
+            let Some(detector) = OnlineDetector::new(&params, method, a.threshold) else {
+                return (None, Err("follow cannot use --method top".into()));
+            };
+            (detector.with_warmup(a.warmup), Start::At(0))
+        }
+    };
+    match &start {
+        Start::At(_) => {}
+        Start::Truncated => warn!(source = input, "truncated since the last run; reading from the start"),
+        Start::Rotated { previous: Some(old), offset } => {
+            warn!(source = input, previous = %old.display(), offset, "rotated; finishing the previous file");
+        }
+        Start::Rotated { previous: None, .. } => {
+            warn!(source = input, "rotated, and the previous file was not found; reading from the start");
+        }
+    }
+
+    let mut f = Follower::new(detector, a.alert_cooldown.unwrap_or(0.0));
+    let mut alert = |al: &Alert| print_alert(out, input, a.json, al);
+    let (file, offset, result) = if input == "-" {
+        let mut n = 0;
+        let res = f.read(&mut io::stdin().lock(), true, &mut n, &mut alert);
+        (path.to_path_buf(), n, res.map_err(|e| format!("{input}: {e}")))
+    } else {
+        let (file, offset, res) = f.follow(path, &start, a.state.is_none(), &mut alert);
+        (file, offset, res.map_err(|e| e.to_string()))
+    };
+    info!(source = input, records = f.detector().records(), anomalies = f.flagged(), alerts = f.alerts(),
+        pending = f.suppressed(), "source done");
+    let state = a.state.as_ref().map(|_| FollowState::new(input, &file, offset, f.into_detector()));
+    (state, result)
+}
+
+fn cmd_follow(a: FollowArgs) -> i32 {
+    if a.inputs.iter().any(|i| i == "-") && (a.inputs.len() > 1 || a.state.is_some()) {
+        eprintln!("error: stdin (-) can only be followed on its own and without --state");
+        return 1;
+    }
+    let saved = match &a.state {
+        Some(p) if std::path::Path::new(p).exists() => match load_state(p) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("error: {p}: {e}");
+                return 1;
+            }
+        },
+        _ => Vec::new(),
+    };
+    let mut saved = states_for(saved, &a.inputs);
+
+    let mut out = io::stdout().lock();
+    let mut states = Vec::new();
+    let mut code = 0;
+    for input in &a.inputs {
+        let prev = take_state(&mut saved, input);
+        let (state, result) = follow_source(&a, input, prev, &mut out);
+        states.extend(state);
+        match result {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                eprintln!("error: {e}");
+                code = 1;
+            }
+        }
+    }
+    if let Some(path) = &a.state {
+        if let Err(e) = save_state(path, &next_states(states, saved, &a.inputs)) {
+            eprintln!("error: {path}: {e}");
+            return 1;
+        }
+    }
+    code
+}
+
 // ---------------------------------------------------------------------------
 // Main
 // ---------------------------------------------------------------------------
 
+/// Send bgtzip's own diagnostics to stderr at `level`, as text or JSON
+/// lines. Pipeline stages are spans; each reports its time when it closes.
+fn init_logging(level: &str, format: &str) {
+    let level: tracing::Level = level.parse().unwrap_or(tracing::Level::WARN);
+    let builder = tracing_subscriber::fmt()
+        .with_writer(io::stderr)
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_target(false);
+    if format == "json" {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
+    let level = if cli.verbose && cli.log_level == "warn" { "info" } else { &cli.log_level };
+    init_logging(level, &cli.log_format);
+    OUTPUT_SCHEMA.store(cli.output_schema_version.unwrap_or(0), Ordering::Relaxed);
     let code = match cli.command {
-        Commands::Scan { common, show_ops } => cmd_scan(common, show_ops),
-        Commands::Dict { common, min_count, top, json } =>
-            cmd_dict(common, min_count, top, json),
-        Commands::Analyze { common, min_count, structured } =>
-            cmd_analyze(common, min_count, structured),
-        Commands::Anomalies {
-            common, min_count, method, threshold,
-            top_n, json, extract, structured,
-        } => cmd_anomalies(common, min_count, method, threshold, top_n, json, extract, structured),
+        Commands::Scan { common, show_ops, save, annotate } =>
+            cmd_scan(common, show_ops, save, annotate),
+        Commands::Dict { common, min_count, top, json, format, output, usage } => {
+            let format = if json { "json" } else { format.as_str() };
+            cmd_dict(common, min_count, top, format, output, usage)
+        }
+        Commands::Stats { common, min_count, json } => cmd_stats(common, min_count, json),
+        Commands::Savings(a) => cmd_savings(a),
+        Commands::Similar(a) => cmd_similar(a),
+        Commands::Cluster(a) => cmd_cluster(a),
+        Commands::Compress(a) => cmd_compress(a),
+        Commands::Decompress { input, output } => cmd_decompress(input, output),
+        Commands::Verify { input, original } => cmd_verify(input, original),
+        Commands::Bench { common, windows, efforts, min_count, json } =>
+            cmd_bench(common, windows, efforts, min_count, json),
+        Commands::Tune(a) => cmd_tune(a),
+        Commands::Analyze(a) => cmd_analyze(a),
+        Commands::Anomalies(a) => cmd_anomalies(*a),
+        Commands::ScoreLine(a) => cmd_score_line(a),
+        Commands::Values { input, field, top, examples, json, max_records } =>
+            cmd_values(input, field, top, examples, json, max_records),
+        Commands::Fields { input, fields, top, json, max_records } => cmd_fields(input, fields, top, json, max_records),
+        Commands::Annotate(a) => cmd_annotate(a),
+        Commands::Zstd { input, blocks, json } => cmd_zstd(input, blocks, json),
+        Commands::Hist(h) => cmd_hist(h),
+        Commands::Profile { action } => cmd_profile(action),
+        Commands::Known { action } => cmd_known(action),
+        Commands::Follow(a) => cmd_follow(a),
     };
     std::process::exit(code);
 }
@@ -0,0 +1,323 @@
+//! Record-at-a-time anomaly detection.
+//!
+//! The batch pipeline scans the whole input, builds a dictionary, scores
+//! every record and only then picks a threshold. An ingestion path sees one
+//! record at a time and cannot hold the input, so [`OnlineDetector`] keeps
+//! everything incremental and bounded instead:
+//!
+//! - coverage comes from a [`StreamScanner`] over the last window or two
+//!   of input;
+//! - rarity comes from running counts of the back-referenced patterns,
+//!   halved whenever the history slides so stale patterns fade and the
+//!   table stays small;
+//! - the threshold is an [`OnlineThreshold`] over the scores so far.
+//!
+//! Scores are weighted as in batch mode ([`COVERAGE_WEIGHT`]), but rarity
+//! is `1 / (1 + uses)` per referenced pattern rather than a dictionary
+//! rank, so the two are comparable rather than identical.
+//!
+//! A live feed repeats its anomalies: a failing dependency logs the same
+//! error thousands of times. [`AlertThrottle`] lets one alert through per
+//! record [`fingerprint`] and cooldown, counting the repeats in between.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::anomaly::{DetectionMethod, OnlineThreshold};
+use crate::opsfile::{fnv1a64, read_u64, read_varint, write_varint};
+use crate::records::fingerprint;
+use crate::scanner::{OpKind, ScanParams, StreamScanner};
+use crate::scorer::COVERAGE_WEIGHT;
+
+/// Records scored before any is judged, so the threshold has settled.
+pub const DEFAULT_WARMUP: usize = 100;
+
+/// Fingerprints an [`AlertThrottle`] tracks before forgetting those whose
+/// cooldown has passed.
+const MAX_THROTTLED: usize = 100_000;
+
+/// A record judged anomalous by [`OnlineDetector::push`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    /// Position of the record among those pushed, from 0.
+    pub index: usize,
+    pub score: f64,
+    pub coverage: f64,
+    pub rarity: f64,
+    /// The threshold it was judged against.
+    pub threshold: f64,
+}
+
+/// Scores records as they arrive and flags the anomalous ones.
+pub struct OnlineDetector {
+    scanner: StreamScanner,
+    window: usize,
+    /// Uses of each back-referenced pattern, by content hash.
+    uses: HashMap<u64, u32>,
+    /// Bytes pushed since `uses` was last halved.
+    since_decay: usize,
+    threshold: OnlineThreshold,
+    method: DetectionMethod,
+    warmup: usize,
+    records: usize,
+}
+
+impl OnlineDetector {
+    /// `method` and `threshold` as for batch detection; `None` for
+    /// [`DetectionMethod::Top`], which needs every score.
+    pub fn new(params: &ScanParams, method: DetectionMethod, threshold: Option<f64>) -> Option<Self> {
+        Some(Self {
+            scanner: StreamScanner::new(params),
+            window: params.window_size,
+            uses: HashMap::new(),
+            since_decay: 0,
+            threshold: OnlineThreshold::new(method, threshold)?,
+            method,
+            warmup: DEFAULT_WARMUP,
+            records: 0,
+        })
+    }
+
+    /// Score this many records before flagging any (default
+    /// [`DEFAULT_WARMUP`]).
+    pub fn with_warmup(mut self, records: usize) -> Self {
+        self.warmup = records;
+        self
+    }
+
+    /// Records pushed so far.
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// The current threshold (on coverage for [`DetectionMethod::Coverage`],
+    /// on score otherwise).
+    pub fn threshold(&self) -> f64 {
+        self.threshold.cut()
+    }
+
+    /// Score one record (a trailing newline is added if missing), judge it
+    /// against the records before it, then add it to the statistics.
+    pub fn push(&mut self, record: &[u8]) -> Option<Anomaly> {
+        let ops = if record.last() == Some(&b'\n') {
+            self.scanner.push(record)
+        } else {
+            self.scanner.push(&[record, b"\n"].concat())
+        };
+        let length = record.len() + (record.last() != Some(&b'\n')) as usize;
+
+        let mut backref_bytes = 0;
+        let mut rarity_sum = 0.0;
+        let mut refs = 0;
+        for op in ops.iter().filter(|o| o.kind == OpKind::Backref) {
+            backref_bytes += op.length;
+            // The pattern is identified by its bytes, which are the
+            // record's own at this position. The hash is saved with the
+            // state, so it must not change between builds.
+            let at = op.position.min(record.len());
+            let key = fnv1a64(&record[at..(op.position + op.length).min(record.len())]);
+            let uses = self.uses.entry(key).or_default();
+            rarity_sum += 1.0 / (1.0 + *uses as f64);
+            *uses = uses.saturating_add(1);
+            refs += 1;
+        }
+        self.since_decay += length;
+        if self.since_decay >= self.window {
+            self.decay();
+        }
+
+        let coverage = backref_bytes as f64 / length as f64;
+        let rarity = if refs == 0 { 1.0 } else { rarity_sum / refs as f64 };
+        let score = COVERAGE_WEIGHT * (1.0 - coverage) + (1.0 - COVERAGE_WEIGHT) * rarity;
+        let value = match self.method {
+            DetectionMethod::Coverage => coverage,
+            _ => score,
+        };
+
+        let index = self.records;
+        let flagged = index >= self.warmup && self.threshold.is_anomalous(value);
+        let anomaly = flagged.then(|| Anomaly { index, score, coverage, rarity, threshold: self.threshold.cut() });
+        self.threshold.add(value);
+        self.records += 1;
+        anomaly
+    }
+
+    /// Halve every use count, dropping patterns that reach zero.
+    fn decay(&mut self) {
+        self.since_decay = 0;
+        self.uses.retain(|_, n| {
+            *n /= 2;
+            *n > 0
+        });
+    }
+
+    /// Encoded as the scanner, `varint window, varint since_decay,
+    /// varint warmup, varint records, varint n, n x { u64 hash, varint
+    /// uses }` in hash order, then the threshold.
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        self.scanner.write(w)?;
+        for v in [self.window, self.since_decay, self.warmup, self.records, self.uses.len()] {
+            write_varint(w, v as u64)?;
+        }
+        let mut uses: Vec<(u64, u32)> = self.uses.iter().map(|(&h, &n)| (h, n)).collect();
+        uses.sort_unstable();
+        for (hash, uses) in uses {
+            w.write_all(&hash.to_le_bytes())?;
+            write_varint(w, uses as u64)?;
+        }
+        self.threshold.write(w)
+    }
+
+    pub(crate) fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let scanner = StreamScanner::read(r)?;
+        let mut v = [0usize; 5];
+        for x in &mut v {
+            *x = read_varint(r)? as usize;
+        }
+        let [window, since_decay, warmup, records, n] = v;
+        let mut uses = HashMap::new();
+        for _ in 0..n {
+            let hash = read_u64(r)?;
+            uses.insert(hash, read_varint(r)?.min(u32::MAX as u64) as u32);
+        }
+        let threshold = OnlineThreshold::read(r)?;
+        let method = threshold.method();
+        Ok(Self { scanner, window, uses, since_decay, threshold, method, warmup, records })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Alert throttling
+// ---------------------------------------------------------------------------
+
+/// An alert let through by [`AlertThrottle::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repeat {
+    /// Occurrences since the previous alert for the fingerprint, this one
+    /// included (1 for a first alert).
+    pub count: usize,
+    /// Occurrences of the fingerprint so far.
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Throttled {
+    last_alert: f64,
+    since_alert: usize,
+    total: usize,
+}
+
+/// At most one alert per record [`fingerprint`] per cooldown. Later
+/// occurrences within the cooldown are counted, and the next alert after it
+/// carries the count, so a repeating anomaly produces one alert with an
+/// escalating count instead of thousands.
+#[derive(Debug, Clone)]
+pub struct AlertThrottle {
+    cooldown: f64,
+    seen: HashMap<u64, Throttled>,
+}
+
+impl AlertThrottle {
+    /// `cooldown` in seconds.
+    pub fn new(cooldown: f64) -> Self {
+        Self { cooldown, seen: HashMap::new() }
+    }
+
+    /// Record an anomalous `record` at time `now` (seconds); `Some` if it
+    /// should be alerted.
+    pub fn check(&mut self, record: &[u8], now: f64) -> Option<Repeat> {
+        if self.seen.len() >= MAX_THROTTLED {
+            let cooldown = self.cooldown;
+            self.seen.retain(|_, t| t.since_alert > 0 || now - t.last_alert < cooldown);
+        }
+        let t = self.seen.entry(fingerprint(record)).or_insert(Throttled {
+            last_alert: f64::NEG_INFINITY,
+            since_alert: 0,
+            total: 0,
+        });
+        t.total += 1;
+        t.since_alert += 1;
+        if now - t.last_alert < self.cooldown {
+            return None;
+        }
+        let count = std::mem::take(&mut t.since_alert);
+        t.last_alert = now;
+        Some(Repeat { count, total: t.total })
+    }
+
+    /// Occurrences held back since their fingerprint's last alert.
+    pub fn suppressed(&self) -> usize {
+        self.seen.values().map(|t| t.since_alert).sum()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pushed_records_are_flagged_online() {
+        let mut d = OnlineDetector::new(&ScanParams::default(), DetectionMethod::Score, None).unwrap();
+        let mut flagged = Vec::new();
+        for i in 0..2000 {
+            let line = match i {
+                50 | 1500 => "KERNEL PANIC: fatal error 0xDEADBEEF segfault in worker".to_string(),
+                _ => format!("2026-02-16 app: request {} handled in {}ms", i % 10, i % 7),
+            };
+            if let Some(a) = d.push(line.as_bytes()) {
+                flagged.push(a);
+            }
+        }
+        assert_eq!(d.records(), 2000);
+        // Record 50 is within the warm-up; by 1500 the panic line is still
+        // unlike everything in the window.
+        let found: Vec<usize> = flagged.iter().map(|a| a.index).collect();
+        assert_eq!(found, [1500]);
+        assert!(flagged[0].coverage < 0.5 && flagged[0].score > flagged[0].threshold);
+        assert!(d.uses.len() < 1000);
+
+        assert!(OnlineDetector::new(&ScanParams::default(), DetectionMethod::Top, None).is_none());
+    }
+
+    #[test]
+    fn pattern_keys_are_stable() {
+        let record = b"GET /api/users 200 ok\n";
+        let detector = || {
+            let mut d = OnlineDetector::new(&ScanParams::default(), DetectionMethod::Score, None).unwrap();
+            for _ in 0..5 {
+                d.push(record);
+            }
+            d
+        };
+        let d = detector();
+        let patterns: std::collections::HashSet<u64> = (0..record.len())
+            .flat_map(|i| (i + 1..=record.len()).map(move |j| fnv1a64(&record[i..j])))
+            .collect();
+        assert!(!d.uses.is_empty());
+        assert!(d.uses.keys().all(|k| patterns.contains(k)));
+
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        d.write(&mut a).unwrap();
+        detector().write(&mut b).unwrap();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn repeats_are_held_back_and_counted() {
+        let mut t = AlertThrottle::new(300.0);
+        let timeout = |ms: u32| format!("upstream timeout after {ms}ms");
+        assert_eq!(t.check(timeout(3012).as_bytes(), 0.0), Some(Repeat { count: 1, total: 1 }));
+        // Same fingerprint up to numbers: held back for the cooldown.
+        for s in 1..200 {
+            assert_eq!(t.check(timeout(s).as_bytes(), s as f64), None);
+        }
+        assert_eq!(t.check(b"disk full", 250.0), Some(Repeat { count: 1, total: 1 }));
+        assert_eq!(t.suppressed(), 199);
+        assert_eq!(t.check(timeout(1).as_bytes(), 300.0), Some(Repeat { count: 200, total: 201 }));
+        assert_eq!(t.suppressed(), 0);
+    }
+}
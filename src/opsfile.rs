@@ -0,0 +1,457 @@
+//! On-disk format for scan operation streams.
+//!
+//! Lets the expensive LZ77 pass run once (`scan --save`) and be reused by
+//! later `dict`/`analyze`/`anomalies` runs (`--ops`). Ops are stored
+//! compactly: positions are implicit (ops tile the input), and lengths and
+//! offsets are LEB128 varints.
+//!
+//! `write_annotations` exports the same ops as JSON lines for external
+//! tools.
+//!
+//! Layout (all integers little-endian):
+//!
+//! ```text
+//! magic    8 bytes   "BGTZOPS\0"
+//! version  u16
+//! params   scan parameters (version 2+, see `SavedParams`)
+//! in_len   u64       length of the scanned input
+//! in_hash  u64       FNV-1a of the scanned input
+//! count    u64       number of ops
+//! ops      count x { varint (length << 1 | is_backref), [varint offset] }
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::scanner::{OpKind, ScanOp};
+
+pub const OPS_MAGIC: &[u8; 8] = b"BGTZOPS\0";
+pub const OPS_VERSION: u16 = 3;
+
+// ---------------------------------------------------------------------------
+// Saved parameters
+// ---------------------------------------------------------------------------
+
+/// Scan settings recorded in saved files (op streams and profiles) so that
+/// reuse under different settings is caught instead of silently producing
+/// skewed scores.
+///
+/// Encoded as `varint window, varint min_match, varint max_match,
+/// u8 delimiter, varint mask length, mask bytes`, then `u8 flags` (bit 0
+/// ignore_case, bit 1 normalize_space) in version 3+ files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedParams {
+    pub window_size: u64,
+    pub min_match: u64,
+    pub max_match: u64,
+    pub delimiter: u8,
+    /// Masking regex applied before scanning (empty if none).
+    pub mask: String,
+    /// ASCII case folded before scanning.
+    pub ignore_case: bool,
+    /// Space/tab runs collapsed before scanning.
+    pub normalize_space: bool,
+}
+
+impl SavedParams {
+    /// Differences that make saved data meaningless under `current`.
+    pub fn conflicts(&self, current: &SavedParams) -> Vec<String> {
+        let mut out = Vec::new();
+        if self.min_match != current.min_match {
+            out.push(format!("min_match {} (now {})", self.min_match, current.min_match));
+        }
+        if self.max_match != current.max_match {
+            out.push(format!("max_match {} (now {})", self.max_match, current.max_match));
+        }
+        if self.delimiter != current.delimiter {
+            out.push(format!(
+                "delimiter {:?} (now {:?})",
+                self.delimiter as char, current.delimiter as char
+            ));
+        }
+        if self.mask != current.mask {
+            out.push(format!("mask {:?} (now {:?})", self.mask, current.mask));
+        }
+        if self.ignore_case != current.ignore_case {
+            out.push(format!("ignore_case {} (now {})", self.ignore_case, current.ignore_case));
+        }
+        if self.normalize_space != current.normalize_space {
+            out.push(format!(
+                "normalize_space {} (now {})",
+                self.normalize_space, current.normalize_space
+            ));
+        }
+        out
+    }
+
+    /// Differences that change results but leave saved data usable.
+    pub fn warnings(&self, current: &SavedParams) -> Vec<String> {
+        let mut out = Vec::new();
+        if self.window_size != current.window_size {
+            out.push(format!("window {} (now {})", self.window_size, current.window_size));
+        }
+        out
+    }
+
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_varint(w, self.window_size)?;
+        write_varint(w, self.min_match)?;
+        write_varint(w, self.max_match)?;
+        w.write_all(&[self.delimiter])?;
+        write_varint(w, self.mask.len() as u64)?;
+        w.write_all(self.mask.as_bytes())?;
+        w.write_all(&[self.ignore_case as u8 | (self.normalize_space as u8) << 1])
+    }
+
+    /// Read params; `with_flags` is false for version 2 files, which
+    /// predate the flags byte.
+    pub(crate) fn read<R: Read>(r: &mut R, with_flags: bool) -> io::Result<SavedParams> {
+        let window_size = read_varint(r)?;
+        let min_match = read_varint(r)?;
+        let max_match = read_varint(r)?;
+        let mut delim = [0u8; 1];
+        r.read_exact(&mut delim)?;
+        let n = read_varint(r)?;
+        let mut mask = Vec::new();
+        r.take(n).read_to_end(&mut mask)?;
+        if mask.len() as u64 != n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let mask = String::from_utf8(mask).map_err(|_| invalid("invalid UTF-8 in mask"))?;
+        let mut flags = [0u8; 1];
+        if with_flags {
+            r.read_exact(&mut flags)?;
+        }
+        Ok(SavedParams {
+            window_size,
+            min_match,
+            max_match,
+            delimiter: delim[0],
+            mask,
+            ignore_case: flags[0] & 1 != 0,
+            normalize_space: flags[0] & 2 != 0,
+        })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+pub(crate) const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// 64-bit FNV-1a hash, used to tie a saved stream to its input.
+pub fn fnv1a64(data: &[u8]) -> u64 {
+    data.iter().fold(FNV_OFFSET, |h, &b| (h ^ b as u64).wrapping_mul(FNV_PRIME))
+}
+
+pub(crate) fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub(crate) fn write_varint<W: Write>(w: &mut W, mut v: u64) -> io::Result<()> {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            return w.write_all(&[byte]);
+        }
+        w.write_all(&[byte | 0x80])?;
+    }
+}
+
+pub(crate) fn read_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut v: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let mut b = [0u8; 1];
+        r.read_exact(&mut b)?;
+        v |= ((b[0] & 0x7f) as u64) << shift;
+        if b[0] & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+pub(crate) fn write_f64<W: Write>(w: &mut W, v: f64) -> io::Result<()> {
+    w.write_all(&v.to_le_bytes())
+}
+
+pub(crate) fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    read_u64(r).map(f64::from_bits)
+}
+
+// ---------------------------------------------------------------------------
+// Write / read
+// ---------------------------------------------------------------------------
+
+/// Serialize `ops` (produced by scanning `data` with `params`) to `w`.
+pub fn write_ops<W: Write>(
+    mut w: W,
+    data: &[u8],
+    ops: &[ScanOp],
+    params: &SavedParams,
+) -> io::Result<()> {
+    w.write_all(OPS_MAGIC)?;
+    w.write_all(&OPS_VERSION.to_le_bytes())?;
+    params.write(&mut w)?;
+    w.write_all(&(data.len() as u64).to_le_bytes())?;
+    w.write_all(&fnv1a64(data).to_le_bytes())?;
+    w.write_all(&(ops.len() as u64).to_le_bytes())?;
+    for op in ops {
+        let tag = (op.length as u64) << 1 | (op.kind == OpKind::Backref) as u64;
+        write_varint(&mut w, tag)?;
+        if op.kind == OpKind::Backref {
+            write_varint(&mut w, op.ref_offset as u64)?;
+        }
+    }
+    w.flush()
+}
+
+/// Deserialize an op stream and check that it belongs to `data`.
+///
+/// Fails if the header is wrong, the input length or hash differ, or the
+/// ops do not exactly tile the input with in-bounds back-references.
+/// Returns the recorded scan parameters, or `None` for version 1 files,
+/// which predate them.
+pub fn read_ops<R: Read>(mut r: R, data: &[u8]) -> io::Result<(Vec<ScanOp>, Option<SavedParams>)> {
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != OPS_MAGIC {
+        return Err(invalid("not a bgtzip ops file"));
+    }
+    let mut ver = [0u8; 2];
+    r.read_exact(&mut ver)?;
+    let version = u16::from_le_bytes(ver);
+    if version == 0 || version > OPS_VERSION {
+        return Err(invalid(format!(
+            "unsupported ops file version {version} (expected {OPS_VERSION})"
+        )));
+    }
+    let params = if version >= 2 { Some(SavedParams::read(&mut r, version >= 3)?) } else { None };
+    let in_len = read_u64(&mut r)?;
+    let in_hash = read_u64(&mut r)?;
+    if in_len != data.len() as u64 || in_hash != fnv1a64(data) {
+        return Err(invalid("ops file was produced from different input"));
+    }
+
+    let count = read_u64(&mut r)?;
+    let mut ops = Vec::with_capacity(count.min(1 << 20) as usize);
+    let mut pos: usize = 0;
+    for _ in 0..count {
+        let tag = read_varint(&mut r)?;
+        let length = (tag >> 1) as usize;
+        let (kind, ref_offset) = if tag & 1 == 1 {
+            (OpKind::Backref, read_varint(&mut r)? as usize)
+        } else {
+            (OpKind::Literal, 0)
+        };
+        if length == 0 || pos + length > data.len() {
+            return Err(invalid(format!("op at byte {pos} has invalid length {length}")));
+        }
+        if kind == OpKind::Backref && (ref_offset == 0 || ref_offset > pos) {
+            return Err(invalid(format!("backref at byte {pos} has invalid offset {ref_offset}")));
+        }
+        ops.push(ScanOp { position: pos, kind, length, ref_offset });
+        pos += length;
+    }
+    if pos != data.len() {
+        return Err(invalid(format!("ops cover {pos} of {} bytes", data.len())));
+    }
+    Ok((ops, params))
+}
+
+/// Write an op stream to `path`.
+pub fn save_ops(
+    path: impl AsRef<Path>,
+    data: &[u8],
+    ops: &[ScanOp],
+    params: &SavedParams,
+) -> io::Result<()> {
+    write_ops(BufWriter::new(File::create(path)?), data, ops, params)
+}
+
+/// Read an op stream from `path`, validated against `data`.
+pub fn load_ops(
+    path: impl AsRef<Path>,
+    data: &[u8],
+) -> io::Result<(Vec<ScanOp>, Option<SavedParams>)> {
+    read_ops(BufReader::new(File::open(path)?), data)
+}
+
+// ---------------------------------------------------------------------------
+// Annotation export
+// ---------------------------------------------------------------------------
+
+/// Write one JSON object per op, in input order:
+///
+/// ```text
+/// {"position":0,"length":12,"kind":"literal"}
+/// {"position":12,"length":9,"kind":"backref","offset":12,"source":0}
+/// ```
+///
+/// `source` is the absolute position the back-reference copies from
+/// (`position - offset`), so a viewer can link each span to its origin
+/// without re-deriving it.
+pub fn write_annotations<W: Write>(mut w: W, ops: &[ScanOp]) -> io::Result<()> {
+    for op in ops {
+        match op.kind {
+            OpKind::Literal => writeln!(
+                w,
+                r#"{{"position":{},"length":{},"kind":"literal"}}"#,
+                op.position, op.length
+            )?,
+            OpKind::Backref => writeln!(
+                w,
+                r#"{{"position":{},"length":{},"kind":"backref","offset":{},"source":{}}}"#,
+                op.position,
+                op.length,
+                op.ref_offset,
+                op.position - op.ref_offset
+            )?,
+        }
+    }
+    w.flush()
+}
+
+/// Write annotations for `ops` to `path`.
+pub fn save_annotations(path: impl AsRef<Path>, ops: &[ScanOp]) -> io::Result<()> {
+    write_annotations(BufWriter::new(File::create(path)?), ops)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{scan, ScanParams};
+
+    fn params() -> SavedParams {
+        SavedParams {
+            window_size: 32768,
+            min_match: 4,
+            max_match: 258,
+            delimiter: b'\n',
+            mask: String::new(),
+            ignore_case: false,
+            normalize_space: false,
+        }
+    }
+
+    fn encode(data: &[u8], ops: &[ScanOp]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_ops(&mut buf, data, ops, &params()).unwrap();
+        buf
+    }
+
+    #[test]
+    fn round_trip() {
+        let data: Vec<u8> = b"2026-02-16 app: request served in 12ms\n".repeat(50);
+        let ops = scan(&data, &ScanParams::default());
+        let (back, saved) = read_ops(encode(&data, &ops).as_slice(), &data).unwrap();
+        assert_eq!(saved, Some(params()));
+        assert_eq!(back.len(), ops.len());
+        for (a, b) in ops.iter().zip(&back) {
+            assert_eq!(
+                (a.position, a.kind, a.length, a.ref_offset),
+                (b.position, b.kind, b.length, b.ref_offset)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_different_input() {
+        let data = b"some input some input some input".to_vec();
+        let ops = scan(&data, &ScanParams::default());
+        let buf = encode(&data, &ops);
+        let mut other = data.clone();
+        other[0] = b'S';
+        assert!(read_ops(buf.as_slice(), &other).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_magic_and_truncation() {
+        let data = b"abcabcabcabc".to_vec();
+        let ops = scan(&data, &ScanParams::default());
+        let mut buf = encode(&data, &ops);
+        assert!(read_ops(&buf[..buf.len() - 1], &data).is_err());
+        buf[0] = b'X';
+        assert!(read_ops(buf.as_slice(), &data).is_err());
+    }
+
+    #[test]
+    fn params_compatibility() {
+        let saved = params();
+        assert!(saved.conflicts(&saved).is_empty());
+        let other = SavedParams { window_size: 1 << 20, min_match: 8, ..params() };
+        assert_eq!(saved.conflicts(&other).len(), 1);
+        assert_eq!(saved.warnings(&other).len(), 1);
+    }
+
+    #[test]
+    fn reads_version_one_without_params() {
+        let data = b"abcabcabcabc".to_vec();
+        let ops = scan(&data, &ScanParams::default());
+        let v2 = encode(&data, &ops);
+        let mut p = Vec::new();
+        params().write(&mut p).unwrap();
+        let mut v1 = v2[..8].to_vec();
+        v1.extend_from_slice(&1u16.to_le_bytes());
+        v1.extend_from_slice(&v2[10 + p.len()..]);
+        let (back, saved) = read_ops(v1.as_slice(), &data).unwrap();
+        assert_eq!(back.len(), ops.len());
+        assert!(saved.is_none());
+    }
+
+    #[test]
+    fn flags_round_trip_and_default_for_version_two() {
+        let data = b"abcabcabcabc".to_vec();
+        let ops = scan(&data, &ScanParams::default());
+        let folded = SavedParams { ignore_case: true, normalize_space: true, ..params() };
+        let mut v3 = Vec::new();
+        write_ops(&mut v3, &data, &ops, &folded).unwrap();
+        assert_eq!(read_ops(v3.as_slice(), &data).unwrap().1, Some(folded.clone()));
+        assert_eq!(params().conflicts(&folded).len(), 2);
+
+        let mut p = Vec::new();
+        folded.write(&mut p).unwrap();
+        let mut v2 = v3[..8].to_vec();
+        v2.extend_from_slice(&2u16.to_le_bytes());
+        v2.extend_from_slice(&p[..p.len() - 1]);
+        v2.extend_from_slice(&v3[10 + p.len()..]);
+        assert_eq!(read_ops(v2.as_slice(), &data).unwrap().1, Some(params()));
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn annotations_are_json_lines() {
+        let data = b"abcabcabcabc".to_vec();
+        let ops = scan(&data, &ScanParams::default());
+        let mut buf = Vec::new();
+        write_annotations(&mut buf, &ops).unwrap();
+        let lines: Vec<serde_json::Value> = buf
+            .split(|&b| b == b'\n')
+            .filter(|l| !l.is_empty())
+            .map(|l| serde_json::from_slice(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), ops.len());
+        for (v, op) in lines.iter().zip(&ops) {
+            assert_eq!(v["position"], op.position);
+            assert_eq!(v["length"], op.length);
+            if op.kind == OpKind::Backref {
+                assert_eq!(v["kind"], "backref");
+                assert_eq!(v["source"], op.position - op.ref_offset);
+            }
+        }
+    }
+}
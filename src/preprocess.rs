@@ -0,0 +1,138 @@
+//! Input preprocessing for the LZ77 path.
+//!
+//! Masks per-line volatile content (typically a leading timestamp) before
+//! scanning. Masked bytes are overwritten in place with [`MASK_BYTE`], so
+//! the masked buffer has the same length and record offsets as the original
+//! and callers can keep displaying the original bytes.
+//!
+//! [`normalize_space`] collapses runs of spaces and tabs, which changes
+//! lengths; [`Normalized`] maps offsets back to the original.
+
+use regex::bytes::Regex;
+
+use crate::records::RecordIndex;
+
+/// Byte written over masked content.
+pub const MASK_BYTE: u8 = b'#';
+
+/// Leading timestamps recognised by `--strip-timestamps`: ISO 8601 /
+/// RFC 3339, syslog (`Feb 16 12:00:01`), bracketed common log format, and
+/// Unix epoch seconds or milliseconds. Trailing whitespace is included.
+pub const TIMESTAMP_PATTERN: &str = concat!(
+    r"^(?:",
+    r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?",
+    r"|[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}",
+    r"|\[\d{2}/[A-Z][a-z]{2}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4}\]",
+    r"|\d{10}(?:\d{3})?(?:\.\d+)?",
+    r")\s*",
+);
+
+/// Regex for [`TIMESTAMP_PATTERN`].
+pub fn timestamp_regex() -> Regex {
+    Regex::new(TIMESTAMP_PATTERN).expect("valid timestamp pattern")
+}
+
+/// Return a copy of `data` with every match of `re` in each record replaced
+/// by [`MASK_BYTE`]. The regex is applied to each record separately
+/// (without its delimiter), so `^` anchors at the start of a record.
+pub fn mask_records(data: &[u8], re: &Regex, delimiter: u8) -> Vec<u8> {
+    let mut out = data.to_vec();
+    let index = RecordIndex::new(data, delimiter);
+    for i in 0..index.len() {
+        let r = index.content_range(data, i).unwrap();
+        for m in re.find_iter(&data[r.clone()]) {
+            out[r.start + m.start()..r.start + m.end()].fill(MASK_BYTE);
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Whitespace
+// ---------------------------------------------------------------------------
+
+/// Input with space/tab runs collapsed, and the way back to the original.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Normalized {
+    pub data: Vec<u8>,
+    /// `(offset in data, offset in the original)` after each collapsed run.
+    marks: Vec<(usize, usize)>,
+}
+
+impl Normalized {
+    /// Map an offset in `data` (or its end) back to the original.
+    pub fn orig_offset(&self, offset: usize) -> usize {
+        match self.marks.partition_point(|&(n, _)| n <= offset).checked_sub(1) {
+            Some(i) => self.marks[i].1 + (offset - self.marks[i].0),
+            None => offset,
+        }
+    }
+
+    /// Map `(offset, length)` in `data` back to the original.
+    pub fn orig_range(&self, offset: usize, length: usize) -> (usize, usize) {
+        let start = self.orig_offset(offset);
+        (start, self.orig_offset(offset + length) - start)
+    }
+}
+
+/// Replace every run of spaces and tabs in `data` with one space, so
+/// column padding does not break matches. Delimiters are untouched, so
+/// records keep their indices.
+pub fn normalize_space(data: &[u8]) -> Normalized {
+    let mut out = Normalized { data: Vec::with_capacity(data.len()), marks: Vec::new() };
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != b' ' && data[i] != b'\t' {
+            out.data.push(data[i]);
+            i += 1;
+            continue;
+        }
+        let run = data[i..].iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+        out.data.push(b' ');
+        i += run;
+        if run > 1 {
+            out.marks.push((out.data.len(), i));
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_leading_timestamps_preserving_offsets() {
+        let data = b"2026-02-16T12:00:01.123Z INFO ok\nFeb 16 12:00:02 host sshd: ok\n\n1771243203 x\n";
+        let out = mask_records(data, &timestamp_regex(), b'\n');
+        assert_eq!(out.len(), data.len());
+        let lines: Vec<&[u8]> = out.split(|&b| b == b'\n').collect();
+        assert_eq!(lines[0], b"#########################INFO ok");
+        assert_eq!(lines[1], b"################host sshd: ok");
+        assert_eq!(lines[2], b"");
+        assert_eq!(lines[3], b"###########x");
+    }
+
+    #[test]
+    fn custom_regex_and_untouched_lines() {
+        let data = b"a1 b2 keep\nnomatch\n";
+        let re = Regex::new(r"^\S+ \S+ ").unwrap();
+        let out = mask_records(data, &re, b'\n');
+        assert_eq!(out, b"######keep\nnomatch\n");
+    }
+
+    #[test]
+    fn normalizes_space_and_maps_offsets() {
+        let data = b"id  42\tok\nid \t 7    ok  \n";
+        let n = normalize_space(data);
+        assert_eq!(n.data, b"id 42 ok\nid 7 ok \n");
+        assert_eq!(n.orig_offset(3), 4);
+        assert_eq!(n.orig_range(9, 9), (10, 15));
+        assert_eq!(n.orig_offset(n.data.len()), data.len());
+        assert_eq!(&data[n.orig_offset(12)..n.orig_offset(12) + 1], b"7");
+    }
+}
@@ -0,0 +1,525 @@
+//! Saved baseline profiles.
+//!
+//! A profile captures what a baseline run learned so later runs can score
+//! new input against it (`--baseline profile.bgt`) without the original
+//! logs: the reference bytes LZ77 matching runs against, the dictionary
+//! built from them, and, for JSON-lines input, the schema. Profiles from
+//! several sources can be merged into one.
+//!
+//! Layout (integers little-endian, `varint` = LEB128, `str` = varint length
+//! followed by UTF-8 bytes):
+//!
+//! ```text
+//! magic      8 bytes   "BGTZPROF"
+//! version    u16
+//! params     scan parameters (version 2+, see `opsfile::SavedParams`)
+//! reference  varint length, bytes
+//! dict       varint n, n x { varint len, bytes, varint count,
+//!                            varint npos, npos x varint position delta }
+//! schema     u8 present; if 1:
+//!            varint total, varint valid,
+//!            varint nfields, nfields x { str name, varint present,
+//!                varint ntypes, ntypes x { u8 type, varint count },
+//!                varint nvalues, nvalues x { str value, varint count },
+//!                varint nenum, nenum x str (version 5+; 0 = not an enum) },
+//!            varint nsets, nsets x { varint nkeys, nkeys x str, varint count }
+//! ```
+//!
+//! Nulls are counted among a field's types but, from version 4, not among
+//! its values; older profiles have them migrated on load.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::dictionary::{decay_dictionary, merge_dictionaries, rank_entries, DictEntry};
+use crate::json_analyzer::{
+    decay_schema, finalize_schema, merge_schemas, FieldProfile, JsonType, Name, SchemaProfile,
+};
+use crate::opsfile::{invalid, read_varint, write_varint, SavedParams};
+
+pub const PROFILE_MAGIC: &[u8; 8] = b"BGTZPROF";
+pub const PROFILE_VERSION: u16 = 5;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// A trained baseline.
+#[derive(Debug)]
+pub struct Profile {
+    /// Scan settings the profile was trained with (`None` for version 1
+    /// files).
+    pub params: Option<SavedParams>,
+    /// Baseline bytes that LZ77 matching runs against.
+    pub reference: Vec<u8>,
+    /// Dictionary built from `reference`; positions index into it.
+    pub dictionary: Vec<DictEntry>,
+    /// Schema, if the baseline was JSON lines.
+    pub schema: Option<SchemaProfile>,
+}
+
+// ---------------------------------------------------------------------------
+// Merge
+// ---------------------------------------------------------------------------
+
+impl Profile {
+    /// Combine two profiles as if trained on `self`'s input followed by
+    /// `other`'s: references are concatenated (newline-separated),
+    /// dictionary counts are summed with positions merged, and schema
+    /// counts are summed. Fails if the profiles were trained with
+    /// conflicting scan parameters.
+    pub fn merge(&self, other: &Profile) -> io::Result<Profile> {
+        if let (Some(a), Some(b)) = (&self.params, &other.params) {
+            let conflicts = a.conflicts(b);
+            if !conflicts.is_empty() {
+                return Err(invalid(format!(
+                    "profiles trained with different parameters: {}",
+                    conflicts.join(", ")
+                )));
+            }
+        }
+
+        let mut reference = self.reference.clone();
+        if reference.last().is_some_and(|&b| b != b'\n') {
+            reference.push(b'\n');
+        }
+        let shift = reference.len();
+        reference.extend_from_slice(&other.reference);
+
+        let schema = match (&self.schema, &other.schema) {
+            (Some(a), Some(b)) => Some(merge_schemas(a, b)),
+            (Some(s), None) | (None, Some(s)) => Some(merge_schemas(s, &empty_schema())),
+            (None, None) => None,
+        };
+
+        Ok(Profile {
+            params: self.params.clone().or_else(|| other.params.clone()),
+            reference,
+            dictionary: merge_dictionaries(&self.dictionary, &other.dictionary, shift),
+            schema,
+        })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Decay
+// ---------------------------------------------------------------------------
+
+impl Profile {
+    /// Age the profile by `factor` (0..=1) before folding in new data, so
+    /// patterns that stop occurring fade out: dictionary and schema counts
+    /// are scaled by `factor`, and the reference keeps only its most recent
+    /// `factor` share of bytes (cut at a line start), so it stays bounded
+    /// across repeated updates.
+    pub fn decay(&self, factor: f64) -> Profile {
+        let factor = factor.clamp(0.0, 1.0);
+        let keep = (self.reference.len() as f64 * factor).ceil() as usize;
+        let mut cut = self.reference.len() - keep.min(self.reference.len());
+        if cut > 0 {
+            cut = match self.reference[cut - 1..].iter().position(|&b| b == b'\n') {
+                Some(i) => cut + i,
+                None => self.reference.len(),
+            };
+        }
+        Profile {
+            params: self.params.clone(),
+            reference: self.reference[cut..].to_vec(),
+            dictionary: decay_dictionary(&self.dictionary, factor, cut),
+            schema: self.schema.as_ref().map(|s| decay_schema(s, factor)),
+        }
+    }
+}
+
+fn empty_schema() -> SchemaProfile {
+    finalize_schema(0, 0, HashMap::new(), HashMap::new())
+}
+
+// ---------------------------------------------------------------------------
+// Helpers
+// ---------------------------------------------------------------------------
+
+fn type_code(t: JsonType) -> u8 {
+    match t {
+        JsonType::Null => 0,
+        JsonType::Bool => 1,
+        JsonType::Number => 2,
+        JsonType::String => 3,
+        JsonType::Array => 4,
+        JsonType::Object => 5,
+    }
+}
+
+fn code_type(c: u8) -> io::Result<JsonType> {
+    Ok(match c {
+        0 => JsonType::Null,
+        1 => JsonType::Bool,
+        2 => JsonType::Number,
+        3 => JsonType::String,
+        4 => JsonType::Array,
+        5 => JsonType::Object,
+        _ => return Err(invalid(format!("unknown JSON type code {c}"))),
+    })
+}
+
+fn write_bytes<W: Write>(w: &mut W, b: &[u8]) -> io::Result<()> {
+    write_varint(w, b.len() as u64)?;
+    w.write_all(b)
+}
+
+fn read_len<R: Read>(r: &mut R) -> io::Result<usize> {
+    let n = read_varint(r)?;
+    usize::try_from(n).map_err(|_| invalid("length out of range"))
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let n = read_len(r)?;
+    let mut buf = Vec::new();
+    r.take(n as u64).read_to_end(&mut buf)?;
+    if buf.len() != n {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+fn read_str<R: Read>(r: &mut R) -> io::Result<String> {
+    String::from_utf8(read_bytes(r)?).map_err(|_| invalid("invalid UTF-8 in profile"))
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+/// Whether `data` starts with the profile magic.
+pub fn is_profile(data: &[u8]) -> bool {
+    data.starts_with(PROFILE_MAGIC)
+}
+
+// ---------------------------------------------------------------------------
+// Write / read
+// ---------------------------------------------------------------------------
+
+pub fn write_profile<W: Write>(w: W, p: &Profile) -> io::Result<()> {
+    write_profile_version(w, p, PROFILE_VERSION)
+}
+
+/// [`write_profile`] in the layout of `version` (3 or later).
+fn write_profile_version<W: Write>(mut w: W, p: &Profile, version: u16) -> io::Result<()> {
+    w.write_all(PROFILE_MAGIC)?;
+    w.write_all(&version.to_le_bytes())?;
+    match &p.params {
+        Some(params) => params.write(&mut w)?,
+        None => return Err(invalid("profile has no scan parameters")),
+    }
+    write_bytes(&mut w, &p.reference)?;
+
+    write_varint(&mut w, p.dictionary.len() as u64)?;
+    for e in &p.dictionary {
+        write_bytes(&mut w, &e.content)?;
+        write_varint(&mut w, e.count as u64)?;
+        write_varint(&mut w, e.positions.len() as u64)?;
+        let mut last = 0;
+        for &pos in &e.positions {
+            write_varint(&mut w, (pos - last) as u64)?;
+            last = pos;
+        }
+    }
+
+    match &p.schema {
+        None => w.write_all(&[0])?,
+        Some(s) => {
+            w.write_all(&[1])?;
+            write_varint(&mut w, s.total_records as u64)?;
+            write_varint(&mut w, s.valid_records as u64)?;
+            write_varint(&mut w, s.fields.len() as u64)?;
+            for f in s.fields.values() {
+                write_bytes(&mut w, f.name.as_bytes())?;
+                write_varint(&mut w, f.present_count as u64)?;
+                write_varint(&mut w, f.type_counts.len() as u64)?;
+                for (&t, &n) in &f.type_counts {
+                    w.write_all(&[type_code(t)])?;
+                    write_varint(&mut w, n as u64)?;
+                }
+                write_varint(&mut w, f.value_counts.len() as u64)?;
+                for (v, &n) in &f.value_counts {
+                    write_bytes(&mut w, v.as_bytes())?;
+                    write_varint(&mut w, n as u64)?;
+                }
+                if version >= 5 {
+                    let set = f.enum_values.iter().flatten();
+                    write_varint(&mut w, set.clone().count() as u64)?;
+                    for v in set {
+                        write_bytes(&mut w, v.as_bytes())?;
+                    }
+                }
+            }
+            write_varint(&mut w, s.field_set_counts.len() as u64)?;
+            for (keys, &n) in &s.field_set_counts {
+                write_varint(&mut w, keys.len() as u64)?;
+                for k in keys {
+                    write_bytes(&mut w, k.as_bytes())?;
+                }
+                write_varint(&mut w, n as u64)?;
+            }
+        }
+    }
+    w.flush()
+}
+
+pub fn read_profile<R: Read>(mut r: R) -> io::Result<Profile> {
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if &magic != PROFILE_MAGIC {
+        return Err(invalid("not a bgtzip profile"));
+    }
+    let mut ver = [0u8; 2];
+    r.read_exact(&mut ver)?;
+    let version = u16::from_le_bytes(ver);
+    if version == 0 || version > PROFILE_VERSION {
+        return Err(invalid(format!(
+            "unsupported profile version {version} (expected {PROFILE_VERSION})"
+        )));
+    }
+    let params = if version >= 2 { Some(SavedParams::read(&mut r, version >= 3)?) } else { None };
+    let reference = read_bytes(&mut r)?;
+
+    let n = read_len(&mut r)?;
+    let mut dictionary = Vec::with_capacity(n.min(1 << 16));
+    for _ in 0..n {
+        let content = read_bytes(&mut r)?;
+        let count = read_len(&mut r)?;
+        let npos = read_len(&mut r)?;
+        let mut positions = Vec::with_capacity(npos.min(1 << 16));
+        let mut last = 0usize;
+        for _ in 0..npos {
+            last = last
+                .checked_add(read_len(&mut r)?)
+                .filter(|&p| p < reference.len())
+                .ok_or_else(|| invalid("dictionary position out of range"))?;
+            positions.push(last);
+        }
+        dictionary.push(DictEntry { entry_id: 0, content, count, positions });
+    }
+    rank_entries(&mut dictionary);
+
+    let schema = match read_u8(&mut r)? {
+        0 => None,
+        1 => {
+            let total = read_len(&mut r)?;
+            let valid = read_len(&mut r)?;
+            if valid > total {
+                return Err(invalid("schema has more valid records than records"));
+            }
+            let nfields = read_len(&mut r)?;
+            let mut fields = HashMap::new();
+            for _ in 0..nfields {
+                let name: Name = read_str(&mut r)?.into();
+                let present_count = read_len(&mut r)?;
+                let mut type_counts = HashMap::new();
+                for _ in 0..read_len(&mut r)? {
+                    let t = code_type(read_u8(&mut r)?)?;
+                    type_counts.insert(t, read_len(&mut r)?);
+                }
+                let mut value_counts: HashMap<Name, usize> = HashMap::new();
+                for _ in 0..read_len(&mut r)? {
+                    let v = read_str(&mut r)?;
+                    value_counts.insert(v.into(), read_len(&mut r)?);
+                }
+                if version < 4 {
+                    // Nulls shared the "null" value key with the string "null".
+                    let nulls = type_counts.get(&JsonType::Null).copied().unwrap_or(0);
+                    if let Some(n) = value_counts.get_mut("null") {
+                        *n = n.saturating_sub(nulls);
+                    }
+                    value_counts.retain(|_, n| *n > 0);
+                }
+                let mut enum_values = None;
+                if version >= 5 {
+                    let n = read_len(&mut r)?;
+                    let mut set = HashSet::with_capacity(n.min(1 << 10));
+                    for _ in 0..n {
+                        let v = read_str(&mut r)?;
+                        // Share the value count's key where there is one.
+                        set.insert(match value_counts.get_key_value(v.as_str()) {
+                            Some((k, _)) => Name::clone(k),
+                            None => v.into(),
+                        });
+                    }
+                    enum_values = (n > 0).then_some(set);
+                }
+                fields.insert(name.clone(), FieldProfile {
+                    name,
+                    present_count,
+                    presence_rate: 0.0,
+                    null_count: 0,
+                    empty_count: 0,
+                    type_counts,
+                    dominant_type: JsonType::Null,
+                    value_counts,
+                    unique_values: 0,
+                    is_low_cardinality: true,
+                    enum_values,
+                });
+            }
+            let mut sets = HashMap::new();
+            for _ in 0..read_len(&mut r)? {
+                let nkeys = read_len(&mut r)?;
+                let mut keys = Vec::with_capacity(nkeys);
+                for _ in 0..nkeys {
+                    let k = read_str(&mut r)?;
+                    // Share the field's name rather than allocating another.
+                    keys.push(match fields.get_key_value(k.as_str()) {
+                        Some((n, _)) => Name::clone(n),
+                        None => k.into(),
+                    });
+                }
+                sets.insert(keys, read_len(&mut r)?);
+            }
+            Some(finalize_schema(total, valid, fields, sets))
+        }
+        b => return Err(invalid(format!("bad schema flag {b}"))),
+    };
+
+    Ok(Profile { params, reference, dictionary, schema })
+}
+
+/// Write a profile to `path`.
+pub fn save_profile(path: impl AsRef<Path>, p: &Profile) -> io::Result<()> {
+    write_profile(BufWriter::new(File::create(path)?), p)
+}
+
+/// Read a profile from `path`.
+pub fn load_profile(path: impl AsRef<Path>) -> io::Result<Profile> {
+    read_profile(BufReader::new(File::open(path)?))
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dictionary::build_dictionary;
+    use crate::json_analyzer::{build_schema, parse_json_records};
+    use crate::scanner::{scan, ScanParams};
+
+    fn train(data: &[u8]) -> Profile {
+        let ops = scan(data, &ScanParams::default());
+        Profile {
+            params: Some(SavedParams {
+                window_size: 32768,
+                min_match: 4,
+                max_match: 258,
+                delimiter: b'\n',
+                mask: String::new(),
+                ignore_case: false,
+                normalize_space: false,
+            }),
+            reference: data.to_vec(),
+            dictionary: build_dictionary(data, &ops, 2),
+            schema: Some(build_schema(&parse_json_records(data, b'\n'))),
+        }
+    }
+
+    fn round_trip(p: &Profile) -> Profile {
+        let mut buf = Vec::new();
+        write_profile(&mut buf, p).unwrap();
+        assert!(is_profile(&buf));
+        read_profile(buf.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_preserves_profile() {
+        let data = b"{\"level\":\"INFO\",\"msg\":\"ok\"}\n".repeat(250);
+        let p = train(&data);
+        let back = round_trip(&p);
+        assert_eq!(back.params, p.params);
+        assert_eq!(back.reference, p.reference);
+        assert_eq!(back.dictionary.len(), p.dictionary.len());
+        for (a, b) in p.dictionary.iter().zip(&back.dictionary) {
+            assert_eq!((&a.content, a.count, &a.positions), (&b.content, b.count, &b.positions));
+        }
+        let (s, t) = (p.schema.unwrap(), back.schema.unwrap());
+        assert_eq!(s.total_records, t.total_records);
+        assert_eq!(s.field_set_counts, t.field_set_counts);
+        assert_eq!(s.fields["level"].value_counts, t.fields["level"].value_counts);
+        assert!(s.fields["level"].enum_values.is_some());
+        assert_eq!(s.fields["level"].enum_values, t.fields["level"].enum_values);
+    }
+
+    #[test]
+    fn version_3_nulls_are_moved_out_of_values() {
+        let mut p = train(b"{\"user\":\"a\"}\n{\"user\":null}\n{\"user\":\"null\"}\n");
+        let s = p.schema.as_mut().unwrap();
+        assert_eq!(s.fields["user"].value_counts.get("null"), Some(&1));
+        // Version 3 counted the null under the "null" value key as well.
+        *s.fields.get_mut("user").unwrap().value_counts.get_mut("null").unwrap() += 1;
+        let mut buf = Vec::new();
+        write_profile_version(&mut buf, &p, 3).unwrap();
+        let back = read_profile(buf.as_slice()).unwrap().schema.unwrap();
+        let user = &back.fields["user"];
+        assert_eq!((user.null_count, user.value_counts["null"]), (1, 1));
+        assert_eq!(user.unique_values, 2);
+    }
+
+    #[test]
+    fn merge_combines_sources() {
+        let a = train(&b"{\"host\":\"a\",\"msg\":\"ok\"}\n".repeat(10));
+        let b = train(&b"{\"host\":\"b\",\"msg\":\"ok\"}\n".repeat(30));
+        let m = round_trip(&a.merge(&b).unwrap());
+        assert_eq!(m.reference.len(), a.reference.len() + b.reference.len());
+        let s = m.schema.unwrap();
+        assert_eq!(s.total_records, 40);
+        assert_eq!(s.fields["host"].value_counts["b"], 30);
+        assert!(m.dictionary.iter().all(|e| e.positions.iter().all(|&p| p < m.reference.len())));
+    }
+
+    #[test]
+    fn merge_refuses_conflicting_params() {
+        let a = train(b"x\n");
+        let mut b = train(b"y\n");
+        b.params.as_mut().unwrap().min_match = 6;
+        assert!(a.merge(&b).is_err());
+        b.params.as_mut().unwrap().min_match = 4;
+        b.params.as_mut().unwrap().window_size = 1 << 20;
+        assert!(a.merge(&b).is_ok());
+    }
+
+    #[test]
+    fn decay_ages_out_old_patterns() {
+        let old = train(&b"{\"host\":\"a\",\"msg\":\"ok\"}\n".repeat(40));
+        let new = train(&b"{\"host\":\"b\",\"msg\":\"ok\"}\n".repeat(40));
+        let d = old.decay(0.5);
+        assert!(d.reference.len() <= old.reference.len() / 2 + 1);
+        assert!(d.reference.starts_with(b"{"));
+        assert!(d.dictionary.iter().all(|e| e.positions.iter().all(|&p| p < d.reference.len())));
+        assert_eq!(d.schema.as_ref().unwrap().total_records, 20);
+
+        let mut p = old;
+        for _ in 0..6 {
+            p = p.decay(0.5).merge(&new).unwrap();
+        }
+        let s = p.schema.unwrap();
+        assert_eq!(s.fields["host"].value_counts.get("a"), None);
+        assert!(s.fields["host"].value_counts["b"] > 40);
+        assert!(p.reference.len() < 3 * new.reference.len());
+        assert!(old_free(&p.reference));
+
+        fn old_free(r: &[u8]) -> bool {
+            !r.windows(10).any(|w| w == b"\"host\":\"a\"")
+        }
+    }
+
+    #[test]
+    fn rejects_bad_header_and_truncation() {
+        let mut buf = Vec::new();
+        write_profile(&mut buf, &train(b"x\nx\n")).unwrap();
+        assert!(read_profile(&buf[..buf.len() - 1]).is_err());
+        buf[0] = b'X';
+        assert!(read_profile(buf.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! Bounded-memory score statistics.
+//!
+//! Detection thresholds normally come from the full score vector. A
+//! long-running or streaming scorer cannot keep every score, so this module
+//! provides running estimates instead: [`RunningStats`] for the mean and
+//! standard deviation (Welford's method), and [`TDigest`] for quantiles.
+//! The t-digest keeps a few hundred weighted centroids, small near the
+//! extremes, so tail quantiles such as the 95th or 99th percentile stay
+//! accurate whatever the number of scores.
+
+use std::f64::consts::PI;
+use std::io::{self, Read, Write};
+
+use crate::opsfile::{invalid, read_f64, read_varint, write_f64, write_varint};
+
+/// Default [`TDigest`] compression: about this many centroids are kept.
+pub const DEFAULT_COMPRESSION: f64 = 100.0;
+
+// ---------------------------------------------------------------------------
+// Mean and deviation
+// ---------------------------------------------------------------------------
+
+/// Running count, mean and variance.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunningStats {
+    count: usize,
+    mean: f64,
+    /// Sum of squared differences from the mean.
+    m2: f64,
+}
+
+impl RunningStats {
+    pub fn add(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Sample standard deviation; 0 for fewer than two values.
+    pub fn stdev(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        (self.m2 / (self.count - 1) as f64).sqrt()
+    }
+
+    /// Encoded as `varint count, f64 mean, f64 m2`.
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_varint(w, self.count as u64)?;
+        write_f64(w, self.mean)?;
+        write_f64(w, self.m2)
+    }
+
+    pub(crate) fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        Ok(Self { count: read_varint(r)? as usize, mean: read_f64(r)?, m2: read_f64(r)? })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// t-digest
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// Quantile sketch (merging t-digest). Values are buffered and merged into
+/// the centroids when the buffer fills; memory stays proportional to the
+/// compression.
+#[derive(Debug, Clone)]
+pub struct TDigest {
+    compression: f64,
+    centroids: Vec<Centroid>,
+    buffer: Vec<f64>,
+    count: usize,
+    min: f64,
+    max: f64,
+}
+
+impl Default for TDigest {
+    fn default() -> Self {
+        Self::new(DEFAULT_COMPRESSION)
+    }
+}
+
+impl TDigest {
+    pub fn new(compression: f64) -> Self {
+        TDigest {
+            compression: compression.max(10.0),
+            centroids: Vec::new(),
+            buffer: Vec::new(),
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn add(&mut self, x: f64) {
+        if x.is_nan() {
+            return;
+        }
+        self.count += 1;
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.buffer.push(x);
+        if self.buffer.len() >= 5 * self.compression as usize {
+            self.centroids = self.merged();
+            self.buffer.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Estimated value at quantile `q` (0-1); `None` when empty.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let merged;
+        let centroids = if self.buffer.is_empty() {
+            &self.centroids
+        } else {
+            merged = self.merged();
+            &merged
+        };
+        let n = self.count as f64;
+        let target = q.clamp(0.0, 1.0) * n;
+        let mut cum = 0.0;
+        for (i, c) in centroids.iter().enumerate() {
+            let mid = cum + c.weight / 2.0;
+            if target < mid {
+                return Some(match i {
+                    0 => self.min + (c.mean - self.min) * (target / mid),
+                    _ => {
+                        let prev = centroids[i - 1];
+                        let prev_mid = cum - prev.weight / 2.0;
+                        prev.mean + (c.mean - prev.mean) * (target - prev_mid) / (mid - prev_mid)
+                    }
+                });
+            }
+            cum += c.weight;
+        }
+        let last = centroids[centroids.len() - 1];
+        let last_mid = n - last.weight / 2.0;
+        Some(last.mean + (self.max - last.mean) * (target - last_mid) / (n - last_mid))
+    }
+
+    /// Centroids with the buffer merged in. Neighbors are combined while the
+    /// scale function `k(q) = δ/2π · asin(2q - 1)` grows by at most 1 across
+    /// the combined centroid, which keeps centroids near q = 0 and 1 small.
+    fn merged(&self) -> Vec<Centroid> {
+        let mut all: Vec<Centroid> = self.centroids.clone();
+        all.extend(self.buffer.iter().map(|&x| Centroid { mean: x, weight: 1.0 }));
+        all.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+        let n: f64 = all.iter().map(|c| c.weight).sum();
+        let k = |q: f64| self.compression / (2.0 * PI) * (2.0 * q.clamp(0.0, 1.0) - 1.0).asin();
+
+        let mut out: Vec<Centroid> = Vec::with_capacity(self.compression as usize * 2);
+        let mut done = 0.0;
+        let mut cur = all[0];
+        for &c in &all[1..] {
+            if k((done + cur.weight + c.weight) / n) - k(done / n) <= 1.0 {
+                let w = cur.weight + c.weight;
+                cur.mean += (c.mean - cur.mean) * c.weight / w;
+                cur.weight = w;
+            } else {
+                done += cur.weight;
+                out.push(cur);
+                cur = c;
+            }
+        }
+        out.push(cur);
+        out
+    }
+
+    /// Encoded as `f64 compression, varint count, f64 min, f64 max,
+    /// varint n, n x { f64 mean, f64 weight }, varint m, m x f64` (the
+    /// unmerged buffer).
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_f64(w, self.compression)?;
+        write_varint(w, self.count as u64)?;
+        write_f64(w, self.min)?;
+        write_f64(w, self.max)?;
+        write_varint(w, self.centroids.len() as u64)?;
+        for c in &self.centroids {
+            write_f64(w, c.mean)?;
+            write_f64(w, c.weight)?;
+        }
+        write_varint(w, self.buffer.len() as u64)?;
+        self.buffer.iter().try_for_each(|&x| write_f64(w, x))
+    }
+
+    pub(crate) fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut d = TDigest::new(read_f64(r)?);
+        d.count = read_varint(r)? as usize;
+        d.min = read_f64(r)?;
+        d.max = read_f64(r)?;
+        let n = read_varint(r)? as usize;
+        if n > d.count {
+            return Err(invalid("more t-digest centroids than values"));
+        }
+        for _ in 0..n {
+            d.centroids.push(Centroid { mean: read_f64(r)?, weight: read_f64(r)? });
+        }
+        let m = read_varint(r)? as usize;
+        if m > d.count || m >= 5 * d.compression as usize {
+            return Err(invalid("t-digest buffer too long"));
+        }
+        for _ in 0..m {
+            d.buffer.push(read_f64(r)?);
+        }
+        if d.count > 0 && d.centroids.is_empty() && d.buffer.is_empty() {
+            return Err(invalid("t-digest has values but no centroids"));
+        }
+        Ok(d)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic, skewed values in [0, 1).
+    fn values(n: usize) -> Vec<f64> {
+        let mut x: u64 = 0x9E37_79B9_7F4A_7C15;
+        (0..n)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                ((x >> 11) as f64 / (1u64 << 53) as f64).powi(3)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn running_stats_match_batch() {
+        let vals = values(1000);
+        let mut s = RunningStats::default();
+        vals.iter().for_each(|&v| s.add(v));
+        let mean = vals.iter().sum::<f64>() / 1000.0;
+        let var = vals.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 999.0;
+        assert_eq!(s.count(), 1000);
+        assert!((s.mean() - mean).abs() < 1e-12);
+        assert!((s.stdev() - var.sqrt()).abs() < 1e-12);
+        assert_eq!(RunningStats::default().stdev(), 0.0);
+    }
+
+    #[test]
+    fn digest_tracks_quantiles_in_bounded_memory() {
+        let vals = values(100_000);
+        let mut d = TDigest::default();
+        vals.iter().for_each(|&v| d.add(v));
+        let mut sorted = vals.clone();
+        sorted.sort_by(f64::total_cmp);
+        for q in [0.01, 0.25, 0.5, 0.9, 0.95, 0.99, 0.999] {
+            let exact = sorted[(q * sorted.len() as f64) as usize];
+            let est = d.quantile(q).unwrap();
+            assert!((est - exact).abs() < 0.01, "q={q}: {est} vs {exact}");
+        }
+        assert_eq!(d.quantile(0.0), Some(sorted[0]));
+        assert_eq!(d.quantile(1.0), Some(sorted[sorted.len() - 1]));
+        assert!(d.centroids.len() + d.buffer.len() < 700);
+
+        let mut small = TDigest::default();
+        assert_eq!(small.quantile(0.5), None);
+        small.add(3.0);
+        assert_eq!(small.quantile(0.9), Some(3.0));
+    }
+}
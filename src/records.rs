@@ -0,0 +1,613 @@
+//! Record boundaries.
+//!
+//! Every record-oriented part of the crate splits input the same way: a
+//! record runs up to and including its delimiter, and a final unterminated
+//! record is kept. [`RecordIndex`] does the split once and answers lookups
+//! in both directions (record to byte range, byte offset to record).
+//! [`dedup_records`] collapses byte-identical records so each distinct one
+//! is scanned and scored once, [`fingerprint`] (re-exported from
+//! [`crate::fingerprint`]) identifies records up to their numbers,
+//! [`limit_records`] truncates or drops oversized ones, and
+//! [`sliding_windows`] pairs runs of records with the records before them
+//! for baselines that follow the input. [`Locator`] turns byte offsets
+//! back into `FILE:LINE:COLUMN` locations, in the file as it was read if
+//! the input was rewritten before analysis ([`Source`]).
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use memchr::memchr;
+
+pub use crate::fingerprint::fingerprint;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordIndex {
+    /// Start offset of each record.
+    starts: Vec<usize>,
+    /// Length of the indexed data.
+    end: usize,
+    delimiter: u8,
+}
+
+impl RecordIndex {
+    pub fn new(data: &[u8], delimiter: u8) -> Self {
+        let mut starts = Vec::new();
+        let mut start = 0;
+        while start < data.len() {
+            starts.push(start);
+            start = memchr(delimiter, &data[start..]).map_or(data.len(), |p| start + p + 1);
+        }
+        Self { starts, end: data.len(), delimiter }
+    }
+
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter
+    }
+
+    /// Byte range of `record`, delimiter included.
+    pub fn range_for(&self, record: usize) -> Option<Range<usize>> {
+        let start = *self.starts.get(record)?;
+        let end = self.starts.get(record + 1).copied().unwrap_or(self.end);
+        Some(start..end)
+    }
+
+    /// Byte range of `record` without its trailing delimiter.
+    pub fn content_range(&self, data: &[u8], record: usize) -> Option<Range<usize>> {
+        let r = self.range_for(record)?;
+        let end = if r.end > r.start && data[r.end - 1] == self.delimiter { r.end - 1 } else { r.end };
+        Some(r.start..end)
+    }
+
+    /// Record containing byte `offset`.
+    pub fn record_at(&self, offset: usize) -> Option<usize> {
+        if offset >= self.end {
+            return None;
+        }
+        Some(self.starts.partition_point(|&s| s <= offset) - 1)
+    }
+
+    /// Byte range spanned by `records`, clamped to the data.
+    pub fn byte_range(&self, records: Range<usize>) -> Range<usize> {
+        let at = |i: usize| self.starts.get(i).copied().unwrap_or(self.end);
+        let start = at(records.start);
+        start..at(records.end).max(start)
+    }
+
+    /// `(offset, length)` of each record, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..self.len()).map(|i| {
+            let r = self.range_for(i).unwrap();
+            (r.start, r.len())
+        })
+    }
+
+    /// [`iter`](Self::iter), collected.
+    pub fn bounds(&self) -> Vec<(usize, usize)> {
+        self.iter().collect()
+    }
+}
+
+/// Length of the first `n` records of `data`, delimiters included. Only
+/// that prefix is examined.
+pub fn head_len(data: &[u8], delimiter: u8, n: usize) -> usize {
+    let mut end = 0;
+    for _ in 0..n {
+        match memchr(delimiter, &data[end..]) {
+            Some(p) => end += p + 1,
+            None => return data.len(),
+        }
+    }
+    end
+}
+
+// ---------------------------------------------------------------------------
+// Subsets
+// ---------------------------------------------------------------------------
+
+/// Some records of an input (possibly shortened), copied into one buffer,
+/// with the mapping back to the original input.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordSubset {
+    pub data: Vec<u8>,
+    /// Records in the original input.
+    pub total: usize,
+    /// Original record index of each kept record.
+    pub index: Vec<usize>,
+    /// `(offset in data, offset in the original)` of each kept record.
+    starts: Vec<(usize, usize)>,
+}
+
+impl RecordSubset {
+    pub fn new(total: usize) -> Self {
+        Self { total, ..Default::default() }
+    }
+
+    /// Append `bytes` as original record `index`, found at `offset`.
+    pub fn push(&mut self, index: usize, offset: usize, bytes: &[u8]) {
+        self.starts.push((self.data.len(), offset));
+        self.index.push(index);
+        self.data.extend_from_slice(bytes);
+    }
+
+    /// Records not kept.
+    pub fn dropped(&self) -> usize {
+        self.total - self.index.len()
+    }
+
+    /// Original index of the kept record holding `offset` in `data`.
+    pub fn orig_index(&self, offset: usize) -> usize {
+        let i = self.starts.partition_point(|&(s, _)| s <= offset);
+        self.index[i.saturating_sub(1)]
+    }
+
+    /// Map an offset in `data` back to the original input.
+    pub fn orig_offset(&self, offset: usize) -> usize {
+        let i = self.starts.partition_point(|&(s, _)| s <= offset);
+        match i.checked_sub(1) {
+            Some(i) => self.starts[i].1 + (offset - self.starts[i].0),
+            None => offset,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Size limits
+// ---------------------------------------------------------------------------
+
+/// What to do with a record longer than the limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversize {
+    /// Keep the first `max_bytes` bytes.
+    Truncate,
+    /// Drop the record.
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordLimit {
+    /// Longest record content (delimiter excluded) kept whole.
+    pub max_bytes: usize,
+    pub policy: Oversize,
+}
+
+/// Number of records whose content exceeds `max_bytes`.
+pub fn count_oversized(data: &[u8], delimiter: u8, max_bytes: usize) -> usize {
+    let index = RecordIndex::new(data, delimiter);
+    (0..index.len()).filter(|&i| index.content_range(data, i).unwrap().len() > max_bytes).count()
+}
+
+/// Apply `limit` to every record. A truncated record keeps its delimiter.
+pub fn limit_records(data: &[u8], delimiter: u8, limit: RecordLimit) -> RecordSubset {
+    let index = RecordIndex::new(data, delimiter);
+    let mut out = RecordSubset::new(index.len());
+    for i in 0..index.len() {
+        let r = index.range_for(i).unwrap();
+        let content = index.content_range(data, i).unwrap();
+        if content.len() <= limit.max_bytes {
+            out.push(i, r.start, &data[r]);
+        } else if limit.policy == Oversize::Truncate {
+            out.push(i, r.start, &data[content.start..content.start + limit.max_bytes]);
+            if content.end < r.end {
+                out.data.push(delimiter);
+            }
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Duplicates
+// ---------------------------------------------------------------------------
+
+/// Input records collapsed to one copy per distinct content.
+///
+/// The first copy of a record scans as mostly literal while its repeats
+/// are one back-reference, so a record that repeats is kept twice: once as
+/// it first appears and once as a repeat. Scanning `data` thus gives every
+/// input record a stand-in with the coverage it would have had in place.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Deduped {
+    /// Distinct records in order of first appearance, each followed by a
+    /// second copy if it repeats; every copy ends with the delimiter.
+    pub data: Vec<u8>,
+    /// Record of `data` standing in for each input record.
+    pub of: Vec<usize>,
+    /// Input records per distinct record.
+    pub counts: Vec<usize>,
+}
+
+impl Deduped {
+    /// Number of distinct records.
+    pub fn distinct(&self) -> usize {
+        self.counts.len()
+    }
+}
+
+/// Collapse byte-identical records (compared without their delimiter, so
+/// an unterminated last record matches a terminated copy).
+pub fn dedup_records(data: &[u8], delimiter: u8) -> Deduped {
+    let index = RecordIndex::new(data, delimiter);
+    let content = |i: usize| &data[index.content_range(data, i).unwrap()];
+    let mut ids: HashMap<&[u8], usize> = HashMap::new();
+    let mut counts: Vec<usize> = Vec::new();
+    let distinct: Vec<usize> = (0..index.len())
+        .map(|i| {
+            let id = *ids.entry(content(i)).or_insert_with(|| {
+                counts.push(0);
+                counts.len() - 1
+            });
+            counts[id] += 1;
+            id
+        })
+        .collect();
+
+    // Stand-in record of each distinct record's first and repeat copies.
+    let mut first = vec![usize::MAX; counts.len()];
+    let mut repeat = vec![usize::MAX; counts.len()];
+    let mut out = Deduped { of: Vec::with_capacity(index.len()), ..Default::default() };
+    let mut copies = 0;
+    for (i, &id) in distinct.iter().enumerate() {
+        if first[id] == usize::MAX {
+            let n = if counts[id] > 1 { 2 } else { 1 };
+            for _ in 0..n {
+                out.data.extend_from_slice(content(i));
+                out.data.push(delimiter);
+            }
+            (first[id], repeat[id]) = (copies, copies + 1);
+            copies += n;
+            out.of.push(first[id]);
+        } else {
+            out.of.push(repeat[id]);
+        }
+    }
+    out.counts = counts;
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Sliding windows
+// ---------------------------------------------------------------------------
+
+/// Steps a sliding window advances in per window length.
+const WINDOW_STEPS: usize = 4;
+
+/// A run of records judged against a baseline of the records before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowBlock {
+    /// Records judged.
+    pub block: Range<usize>,
+    /// Records the baseline is built from.
+    pub window: Range<usize>,
+}
+
+/// Split `total` records into blocks of a quarter `window` (rounded up),
+/// each paired with up to `window` records before it: a window that slides
+/// in steps rather than per record, and never looks ahead of the block.
+/// The first block has nothing before it and is paired with itself.
+pub fn sliding_windows(total: usize, window: usize) -> Vec<WindowBlock> {
+    let window = window.max(1);
+    let step = window.div_ceil(WINDOW_STEPS);
+    (0..total)
+        .step_by(step)
+        .map(|start| {
+            let block = start..(start + step).min(total);
+            let window = if start == 0 { block.clone() } else { start.saturating_sub(window)..start };
+            WindowBlock { block, window }
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Locations
+// ---------------------------------------------------------------------------
+
+/// Offsets of rewritten bytes mapped back to the bytes they were rewritten
+/// from. Each mark starts a run copied unchanged; offsets before the first
+/// mark are their own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetMap {
+    /// `(rewritten offset, original offset)`, in order.
+    marks: Vec<(usize, usize)>,
+}
+
+impl OffsetMap {
+    /// Note that byte `at` of the rewritten bytes, and the run after it,
+    /// came from `orig`. Marks must be added in order; a later mark at the
+    /// same offset replaces the earlier one.
+    pub fn mark(&mut self, at: usize, orig: usize) {
+        if self.marks.last().is_some_and(|&(a, _)| a == at) {
+            self.marks.pop();
+        }
+        if self.orig_offset(at) != orig {
+            self.marks.push((at, orig));
+        }
+    }
+
+    /// Add `other`'s marks for bytes rewritten from `orig` onwards and
+    /// appended at `at`.
+    pub fn append(&mut self, other: &OffsetMap, at: usize, orig: usize) {
+        self.mark(at, orig);
+        for &(a, o) in &other.marks {
+            self.mark(at + a, orig + o);
+        }
+    }
+
+    /// Map an offset (or the end) of the rewritten bytes back.
+    pub fn orig_offset(&self, offset: usize) -> usize {
+        match self.marks.partition_point(|&(a, _)| a <= offset).checked_sub(1) {
+            Some(i) => self.marks[i].1 + (offset - self.marks[i].0),
+            None => offset,
+        }
+    }
+
+    /// Whether every offset maps to itself.
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+}
+
+/// How analysed input maps back to the file it was read from, when it was
+/// rewritten first: JSON reflowed one record per line (which drops
+/// whitespace and blank lines), text transcoded to UTF-8 or a byte order
+/// mark dropped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Source {
+    /// The text before reflowing, if it was reflowed; otherwise the
+    /// analysed input is the text.
+    pub text: Option<Vec<u8>>,
+    /// Offsets of the analysed input in the text.
+    pub to_text: OffsetMap,
+    /// Offsets of the text in the file; in input made of several files,
+    /// each file's count from where it starts in the input. Transcoded text
+    /// is marked at line starts and ends, so only those map exactly.
+    pub to_file: OffsetMap,
+}
+
+/// The place of a byte offset in the input, in the forms editors and pagers
+/// jump to: `FILE:LINE:COLUMN` (both 1-based, the column counted in UTF-8
+/// characters) or `FILE@OFFSET`.
+///
+/// Input made of several files (an archive's, concatenated) is located per
+/// file, each with its own name and line numbers. Rewritten input is
+/// located in the file it was read from ([`Locator::with_source`]).
+#[derive(Debug, Clone)]
+pub struct Locator<'a> {
+    input: &'a str,
+    /// The text lines and columns are counted in.
+    data: &'a [u8],
+    lines: RecordIndex,
+    /// Name and byte range of each file of the input.
+    files: Vec<(&'a str, Range<usize>)>,
+    by_offset: bool,
+    source: Option<&'a Source>,
+}
+
+impl<'a> Locator<'a> {
+    /// Locations in `data`, all of it named `input`.
+    pub fn new(input: &'a str, data: &'a [u8]) -> Self {
+        Locator {
+            input,
+            data,
+            lines: RecordIndex::new(data, b'\n'),
+            files: Vec::new(),
+            by_offset: false,
+            source: None,
+        }
+    }
+
+    /// Name the given byte ranges of the input after the files they came
+    /// from. Offsets outside them keep the input's name.
+    pub fn with_files(mut self, files: impl IntoIterator<Item = (&'a str, Range<usize>)>) -> Self {
+        self.files = files.into_iter().collect();
+        self
+    }
+
+    /// Locate by byte offset (`FILE@OFFSET`) instead of line and column.
+    pub fn by_offset(mut self, yes: bool) -> Self {
+        self.by_offset = yes;
+        self
+    }
+
+    /// Locate offsets of the input in the file it was rewritten from:
+    /// lines and columns in `source`'s text, byte offsets in the file.
+    pub fn with_source(mut self, source: &'a Source) -> Self {
+        if let Some(text) = &source.text {
+            self.data = text;
+            self.lines = RecordIndex::new(text, b'\n');
+        }
+        self.source = Some(source);
+        self
+    }
+
+    /// Location of byte `offset` of the input.
+    pub fn locate(&self, offset: usize) -> String {
+        let (name, start) = self.file_at(offset);
+        if self.by_offset {
+            return format!("{name}@{}", self.file_offset(offset) - start);
+        }
+        let (line, column) = self.line_column(offset);
+        format!("{name}:{line}:{column}")
+    }
+
+    /// Name of the file byte `offset` of the input came from, and where
+    /// that file starts in the input.
+    pub fn file_at(&self, offset: usize) -> (&'a str, usize) {
+        match self.files.iter().find(|(_, r)| r.contains(&offset)) {
+            Some((name, r)) => (name, r.start),
+            None => (self.input, 0),
+        }
+    }
+
+    /// Line and column (from 1) of byte `offset` within its file.
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        let start = self.text_offset(self.file_at(offset).1);
+        let offset = self.text_offset(offset);
+        let line = |o: usize| self.lines.record_at(o).unwrap_or(self.lines.len());
+        let n = line(offset);
+        let line_start = self.lines.range_for(n).map_or(offset, |r| r.start).max(start);
+        let first = if start > 0 { line(start) } else { 0 };
+        let end = offset.min(self.data.len());
+        let column = self.data[line_start.min(end)..end].iter().filter(|&&b| !is_utf8_continuation(b)).count();
+        (n - first + 1, column + 1)
+    }
+
+    /// Where `record`, found at byte `offset` of the input, is in the
+    /// file, as `(offset, length)`. Its delimiter stands for the one ending
+    /// its last line in the file, if the record ends that line.
+    pub fn file_range(&self, offset: usize, record: &[u8]) -> (usize, usize) {
+        let body = record.strip_suffix(b"\n").unwrap_or(record).len();
+        let start = self.file_offset(offset);
+        let mut end = self.text_offset(offset + body);
+        if body < record.len() && self.data.get(end) == Some(&b'\n') {
+            end += 1;
+        }
+        (start, self.to_file(end) - start)
+    }
+
+    fn text_offset(&self, offset: usize) -> usize {
+        self.source.map_or(offset, |s| s.to_text.orig_offset(offset))
+    }
+
+    fn to_file(&self, text_offset: usize) -> usize {
+        self.source.map_or(text_offset, |s| s.to_file.orig_offset(text_offset))
+    }
+
+    fn file_offset(&self, offset: usize) -> usize {
+        self.to_file(self.text_offset(offset))
+    }
+}
+
+fn is_utf8_continuation(b: u8) -> bool {
+    b & 0xC0 == 0x80
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_and_looks_up() {
+        let data = b"one\ntwo\n\nfour";
+        let idx = RecordIndex::new(data, b'\n');
+        assert_eq!(idx.bounds(), [(0, 4), (4, 4), (8, 1), (9, 4)]);
+        assert_eq!(idx.range_for(1), Some(4..8));
+        assert_eq!(idx.content_range(data, 1), Some(4..7));
+        assert_eq!(idx.content_range(data, 3), Some(9..13));
+        assert_eq!(idx.range_for(4), None);
+        assert_eq!(idx.record_at(0), Some(0));
+        assert_eq!(idx.record_at(7), Some(1));
+        assert_eq!(idx.record_at(8), Some(2));
+        assert_eq!(idx.record_at(13), None);
+        assert_eq!(idx.byte_range(1..3), 4..9);
+        assert_eq!(idx.byte_range(2..10), 8..13);
+        assert_eq!(idx.byte_range(7..9), 13..13);
+    }
+
+    #[test]
+    fn empty_and_trailing_delimiter() {
+        assert!(RecordIndex::new(b"", b'\n').is_empty());
+        let idx = RecordIndex::new(b"a\nb\n", b'\n');
+        assert_eq!(idx.len(), 2);
+        assert_eq!(idx.record_at(3), Some(1));
+    }
+
+    #[test]
+    fn head_len_counts_records() {
+        let data = b"one\ntwo\nthree";
+        assert_eq!([0, 1, 2, 3, 9].map(|n| head_len(data, b'\n', n)), [0, 4, 8, 13, 13]);
+    }
+
+    #[test]
+    fn dedup_collapses_identical_records() {
+        let d = dedup_records(b"a\nb\na\n\na\n\nb\nc", b'\n');
+        assert_eq!(d.data, b"a\na\nb\nb\n\n\nc\n");
+        assert_eq!(d.of, [0, 2, 1, 4, 1, 5, 3, 6]);
+        assert_eq!(d.counts, [3, 2, 2, 1]);
+        assert_eq!(d.distinct(), 4);
+    }
+
+    #[test]
+    fn windows_slide_over_the_records_before_each_block() {
+        let w = sliding_windows(10, 4);
+        let pairs: Vec<_> = w.iter().map(|b| (b.block.clone(), b.window.clone())).collect();
+        assert_eq!(pairs, [(0..1, 0..1), (1..2, 0..1), (2..3, 0..2), (3..4, 0..3),
+            (4..5, 0..4), (5..6, 1..5), (6..7, 2..6), (7..8, 3..7), (8..9, 4..8), (9..10, 5..9)]);
+        // No window reaches past the block it judges.
+        assert!(w.iter().all(|b| b.window.end <= b.block.end && !b.window.is_empty()));
+        let w = sliding_windows(7, 10);
+        assert_eq!((w[0].block.clone(), w[0].window.clone()), (0..3, 0..3));
+        assert_eq!((w[2].block.clone(), w[2].window.clone()), (6..7, 0..6));
+        assert!(sliding_windows(0, 5).is_empty());
+    }
+
+    #[test]
+    fn limit_truncates_or_skips_long_records() {
+        let data = b"short\nthis one is long\nok\nlong tail";
+        assert_eq!(count_oversized(data, b'\n', 5), 2);
+        let t = limit_records(data, b'\n', RecordLimit { max_bytes: 5, policy: Oversize::Truncate });
+        assert_eq!(t.data, b"short\nthis \nok\nlong ");
+        assert_eq!(t.index, [0, 1, 2, 3]);
+        assert_eq!((t.orig_offset(6), t.orig_offset(12), t.orig_offset(15)), (6, 23, 26));
+        let s = limit_records(data, b'\n', RecordLimit { max_bytes: 5, policy: Oversize::Skip });
+        assert_eq!(s.data, b"short\nok\n");
+        assert_eq!((s.index.as_slice(), s.dropped()), (&[0, 2][..], 2));
+        assert_eq!(s.orig_offset(7), 24);
+    }
+
+    #[test]
+    fn offset_maps_follow_marked_runs() {
+        let mut map = OffsetMap::default();
+        map.mark(0, 0);
+        assert!(map.is_empty());
+        map.mark(4, 6);
+        map.mark(4, 7);
+        map.mark(6, 9);
+        assert_eq!([0, 3, 4, 5, 6, 8].map(|o| map.orig_offset(o)), [0, 3, 7, 8, 9, 11]);
+        let mut joined = OffsetMap::default();
+        joined.append(&map, 10, 20);
+        assert_eq!([2, 10, 14, 16].map(|o| joined.orig_offset(o)), [2, 20, 27, 29]);
+    }
+
+    #[test]
+    fn locator_counts_lines_per_file() {
+        let data = b"a1\na2\nb1\nb2\nb3\ntail\n";
+        let loc = Locator::new("all.tar", data).with_files([("a.log", 0..6), ("b.log", 6..15)]);
+        assert_eq!(loc.locate(0), "a.log:1:1");
+        assert_eq!(loc.locate(4), "a.log:2:2");
+        assert_eq!(loc.locate(6), "b.log:1:1");
+        assert_eq!(loc.locate(13), "b.log:3:2");
+        assert_eq!(loc.locate(15), "all.tar:6:1");
+        let loc = loc.by_offset(true);
+        assert_eq!(loc.locate(4), "a.log@4");
+        assert_eq!(loc.locate(13), "b.log@7");
+        assert_eq!(loc.locate(16), "all.tar@16");
+    }
+
+    #[test]
+    fn locator_columns_count_characters() {
+        let data = "first\r\nx=\u{e9}t\u{e9} \u{1f600} y\r\nlast".as_bytes();
+        let loc = Locator::new("in.log", data);
+        assert_eq!(loc.locate(7), "in.log:2:1");
+        let y = data.iter().position(|&b| b == b'y').unwrap();
+        assert_eq!(loc.locate(y), "in.log:2:9");
+        let cr = data.iter().rposition(|&b| b == b'\r').unwrap();
+        assert_eq!(loc.locate(cr), "in.log:2:10");
+        assert_eq!(loc.locate(cr + 2), "in.log:3:1");
+    }
+}
@@ -0,0 +1,300 @@
+//! Rule-based tagging of detected anomalies.
+//!
+//! Statistics find what is unusual; teams know which of it is expected
+//! ("this timeout is the flaky payments dependency"). A rules file (YAML)
+//! encodes that knowledge: each rule matches records by a regex on the raw
+//! line and/or conditions on JSON fields, and tags them, gives them a
+//! severity, or suppresses them from the report.
+//!
+//! ```yaml
+//! rules:
+//!   - name: flaky-payments
+//!     regex: 'payments.*(timeout|ECONNRESET)'
+//!     fields:
+//!       service: payments          # value equals (as counted: "200", "true")
+//!       status: { regex: '^5' }    # value matches
+//!       retry: { present: true }   # field present (or absent)
+//!     tag: known-flaky
+//!     severity: low
+//!   - regex: 'healthcheck'
+//!     suppress: true
+//! ```
+//!
+//! Field conditions read the record as a JSON object; on other lines they
+//! do not match. All conditions of a rule must hold.
+
+use regex::bytes::Regex;
+use serde_yaml_ng::{Mapping, Value};
+
+use crate::json_value::{parse_record, JsonObject, JsonValue};
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// A condition on one top-level JSON field.
+#[derive(Debug, Clone)]
+pub enum FieldCondition {
+    /// The field's value key equals this.
+    Equals(String),
+    /// The field's value key matches this.
+    Matches(Regex),
+    /// The field is present (`true`) or absent (`false`).
+    Present(bool),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub regex: Option<Regex>,
+    pub fields: Vec<(String, FieldCondition)>,
+    pub tag: Option<String>,
+    pub severity: Option<String>,
+    pub suppress: bool,
+}
+
+/// Rules in file order.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+/// What the rules say about one record.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleMatch {
+    /// Names of the matching rules.
+    pub rules: Vec<String>,
+    /// Tags of the matching rules, deduplicated, in rule order.
+    pub tags: Vec<String>,
+    /// Severity of the first matching rule that sets one.
+    pub severity: Option<String>,
+    /// Whether any matching rule suppresses the record.
+    pub suppress: bool,
+}
+
+// ---------------------------------------------------------------------------
+// Parsing
+// ---------------------------------------------------------------------------
+
+impl RuleSet {
+    /// Parse a rules file: a `rules:` list, or a bare list of rules.
+    pub fn parse(yaml: &str) -> Result<Self, String> {
+        let doc: Value = serde_yaml_ng::from_str(yaml).map_err(|e| e.to_string())?;
+        let list = match &doc {
+            Value::Null => return Ok(Self::default()),
+            Value::Sequence(list) => list,
+            Value::Mapping(m) => match m.get("rules") {
+                Some(Value::Sequence(list)) => list,
+                Some(Value::Null) => return Ok(Self::default()),
+                _ => return Err("expected a 'rules' list".into()),
+            },
+            _ => return Err("expected a 'rules' list".into()),
+        };
+        let rules = list
+            .iter()
+            .enumerate()
+            .map(|(i, v)| match v {
+                Value::Mapping(m) => parse_rule(m, i + 1),
+                _ => Err(format!("rule {}: expected a mapping", i + 1)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+}
+
+fn parse_rule(m: &Mapping, n: usize) -> Result<Rule, String> {
+    let mut rule = Rule {
+        name: format!("rule {n}"),
+        regex: None,
+        fields: Vec::new(),
+        tag: None,
+        severity: None,
+        suppress: false,
+    };
+    let name = m.get("name").and_then(Value::as_str).unwrap_or(&rule.name).to_string();
+    let err = |msg: String| format!("{name}: {msg}");
+    for (k, v) in m {
+        let key = k.as_str().ok_or_else(|| err("keys must be strings".into()))?;
+        match key {
+            "name" => rule.name = scalar(v).ok_or_else(|| err("name must be a string".into()))?,
+            "regex" => {
+                let pattern = v.as_str().ok_or_else(|| err("regex must be a string".into()))?;
+                rule.regex = Some(Regex::new(pattern).map_err(|e| err(e.to_string()))?);
+            }
+            "fields" => {
+                let Value::Mapping(fields) = v else {
+                    return Err(err("fields must be a mapping".into()));
+                };
+                for (f, cond) in fields {
+                    let f = scalar(f).ok_or_else(|| err("field names must be strings".into()))?;
+                    let cond = parse_condition(cond).map_err(|e| err(format!("field '{f}': {e}")))?;
+                    rule.fields.push((f, cond));
+                }
+            }
+            "tag" => rule.tag = Some(scalar(v).ok_or_else(|| err("tag must be a string".into()))?),
+            "severity" => {
+                rule.severity = Some(scalar(v).ok_or_else(|| err("severity must be a string".into()))?)
+            }
+            "suppress" => {
+                rule.suppress = v.as_bool().ok_or_else(|| err("suppress must be true or false".into()))?
+            }
+            _ => return Err(err(format!("unknown key '{key}'"))),
+        }
+    }
+    if rule.regex.is_none() && rule.fields.is_empty() {
+        return Err(err("needs a regex or fields to match".into()));
+    }
+    if rule.tag.is_none() && rule.severity.is_none() && !rule.suppress {
+        return Err(err("needs a tag, severity, or suppress: true".into()));
+    }
+    Ok(rule)
+}
+
+fn parse_condition(v: &Value) -> Result<FieldCondition, String> {
+    if let Some(s) = scalar(v) {
+        return Ok(FieldCondition::Equals(s));
+    }
+    let Value::Mapping(m) = v else {
+        return Err("expected a value, {regex: ...} or {present: ...}".into());
+    };
+    match (m.len(), m.get("regex"), m.get("present")) {
+        (1, Some(Value::String(p)), None) => {
+            Ok(FieldCondition::Matches(Regex::new(p).map_err(|e| e.to_string())?))
+        }
+        (1, None, Some(Value::Bool(b))) => Ok(FieldCondition::Present(*b)),
+        _ => Err("expected a value, {regex: ...} or {present: ...}".into()),
+    }
+}
+
+/// A scalar as text, the way value keys print it (`200`, `true`, `null`).
+fn scalar(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Null => Some("null".into()),
+        _ => None,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Matching
+// ---------------------------------------------------------------------------
+
+impl Rule {
+    fn matches(&self, line: &[u8], object: Option<&JsonObject>) -> bool {
+        if self.regex.as_ref().is_some_and(|re| !re.is_match(line)) {
+            return false;
+        }
+        if self.fields.is_empty() {
+            return true;
+        }
+        let Some(object) = object else { return false };
+        self.fields.iter().all(|(name, cond)| {
+            let value = object
+                .binary_search_by(|(k, _)| (**k).cmp(name.as_str()))
+                .ok()
+                .map(|i| &object[i].1);
+            match (cond, value) {
+                (FieldCondition::Present(want), v) => v.is_some() == *want,
+                (_, None) => false,
+                (FieldCondition::Equals(s), Some(v)) => value_key(v) == *s,
+                (FieldCondition::Matches(re), Some(v)) => re.is_match(value_key(v).as_bytes()),
+            }
+        })
+    }
+}
+
+fn value_key(v: &JsonValue) -> String {
+    v.key().into_owned()
+}
+
+impl RuleSet {
+    /// Apply every rule to one record's raw line (without its delimiter).
+    pub fn apply(&self, line: &[u8]) -> RuleMatch {
+        let needs_fields = self.rules.iter().any(|r| !r.fields.is_empty());
+        let object = needs_fields
+            .then(|| std::str::from_utf8(line).ok())
+            .flatten()
+            .and_then(|s| parse_record(s.trim_end()).ok().flatten());
+        let mut out = RuleMatch::default();
+        for rule in self.rules.iter().filter(|r| r.matches(line, object.as_ref())) {
+            out.rules.push(rule.name.clone());
+            if let Some(t) = rule.tag.as_ref().filter(|t| !out.tags.contains(t)) {
+                out.tags.push(t.clone());
+            }
+            if out.severity.is_none() {
+                out.severity = rule.severity.clone();
+            }
+            out.suppress |= rule.suppress;
+        }
+        out
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+rules:
+  - name: flaky-payments
+    regex: 'timeout'
+    fields:
+      service: payments
+      status: { regex: '^5' }
+    tag: known-flaky
+    severity: low
+  - name: retries
+    fields:
+      retry: { present: true }
+      ok: true
+    tag: retry
+  - regex: 'healthcheck'
+    suppress: true
+    tag: noise
+"#;
+
+    #[test]
+    fn rules_tag_and_suppress() {
+        let rules = RuleSet::parse(RULES).unwrap();
+        assert_eq!(rules.rules.len(), 3);
+        assert_eq!(rules.rules[2].name, "rule 3");
+
+        let m = rules.apply(br#"{"service":"payments","status":503,"msg":"timeout","retry":1,"ok":true}"#);
+        assert_eq!(m.rules, ["flaky-payments", "retries"]);
+        assert_eq!(m.tags, ["known-flaky", "retry"]);
+        assert_eq!(m.severity.as_deref(), Some("low"));
+        assert!(!m.suppress);
+
+        // Fields must all hold; field conditions never match plain lines.
+        let m = rules.apply(br#"{"service":"payments","status":200,"msg":"timeout"}"#);
+        assert!(m.rules.is_empty());
+        assert_eq!(rules.apply(b"service=payments status=503 timeout"), RuleMatch::default());
+
+        let m = rules.apply(b"GET /healthcheck 200");
+        assert!(m.suppress);
+        assert_eq!(m.tags, ["noise"]);
+    }
+
+    #[test]
+    fn bad_rules_are_reported() {
+        assert!(RuleSet::parse("").unwrap().is_empty());
+        assert!(RuleSet::parse("- regex: x\n  tag: t\n").is_ok());
+        let err = |y: &str| RuleSet::parse(y).unwrap_err();
+        assert!(err("rules:\n  - regex: x\n    sevrity: low\n").contains("unknown key 'sevrity'"));
+        assert!(err("rules:\n  - name: a\n    tag: t\n").contains("a: needs a regex"));
+        assert!(err("rules:\n  - regex: x\n").contains("needs a tag"));
+        assert!(err("rules:\n  - regex: '('\n    tag: t\n").contains("rule 1"));
+        assert!(err("rules:\n  - fields: { a: [1] }\n    tag: t\n").contains("field 'a'"));
+        assert!(err("rules: 3\n").contains("'rules' list"));
+    }
+}
@@ -0,0 +1,138 @@
+//! Compression savings from a shared preset dictionary.
+//!
+//! Dictionary compression pays off when many small messages are compressed
+//! independently (one log record per request, per queue message, ...):
+//! each message alone has little internal redundancy, but shares a lot
+//! with the corpus. This module measures that directly by deflating each
+//! record on its own, with and without a preset dictionary built by
+//! [`crate::dictionary::zlib_preset`].
+
+use std::io;
+
+use flate2::{Compress, Compression, FlushCompress};
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// Per-record deflate sizes with and without the preset dictionary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SavingsEstimate {
+    /// Records compressed (a sample if the input had more).
+    pub records: usize,
+    /// Uncompressed bytes of those records.
+    pub raw_bytes: usize,
+    /// Sum of raw-deflate sizes, each record compressed alone.
+    pub plain_bytes: usize,
+    /// Same, with the preset dictionary loaded first.
+    pub preset_bytes: usize,
+    pub dict_bytes: usize,
+}
+
+impl SavingsEstimate {
+    /// Bytes saved by the dictionary over plain per-record deflate.
+    pub fn saved(&self) -> isize {
+        self.plain_bytes as isize - self.preset_bytes as isize
+    }
+
+    /// `saved` as a percentage of the plain compressed size.
+    pub fn saved_pct(&self) -> f64 {
+        if self.plain_bytes == 0 {
+            return 0.0;
+        }
+        self.saved() as f64 / self.plain_bytes as f64 * 100.0
+    }
+
+    /// Scale a sampled estimate to `total_records`.
+    pub fn projected_saved(&self, total_records: usize) -> f64 {
+        if self.records == 0 {
+            return 0.0;
+        }
+        self.saved() as f64 * total_records as f64 / self.records as f64
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Estimate
+// ---------------------------------------------------------------------------
+
+/// Raw-deflate `record` with a fresh stream on `c`, optionally primed with
+/// `dict`, and return the compressed size.
+pub(crate) fn deflate_len(c: &mut Compress, record: &[u8], dict: &[u8]) -> io::Result<usize> {
+    c.reset();
+    if !dict.is_empty() {
+        c.set_dictionary(dict).map_err(io::Error::other)?;
+    }
+    let mut out = Vec::with_capacity(record.len() + 64);
+    loop {
+        let before = c.total_in() as usize;
+        let status = c
+            .compress_vec(&record[before..], &mut out, FlushCompress::Finish)
+            .map_err(io::Error::other)?;
+        if status == flate2::Status::StreamEnd {
+            return Ok(out.len());
+        }
+        out.reserve(out.capacity().max(64));
+    }
+}
+
+/// Deflate each record (`(offset, length)` into `data`) alone, with and
+/// without `dict` as a preset dictionary. At most `sample` records are
+/// compressed, evenly spaced across the input.
+pub fn estimate_savings(
+    data: &[u8],
+    records: &[(usize, usize)],
+    dict: &[u8],
+    sample: usize,
+) -> io::Result<SavingsEstimate> {
+    let mut plain = Compress::new(Compression::default(), false);
+    let mut primed = Compress::new(Compression::default(), false);
+    let n = records.len().min(sample.max(1));
+    let mut est = SavingsEstimate { dict_bytes: dict.len(), ..Default::default() };
+    for i in 0..n {
+        let (off, len) = records[i * records.len() / n];
+        let rec = &data[off..off + len];
+        est.records += 1;
+        est.raw_bytes += len;
+        est.plain_bytes += deflate_len(&mut plain, rec, &[])?;
+        est.preset_bytes += deflate_len(&mut primed, rec, dict)?;
+    }
+    Ok(est)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scorer::split_records;
+
+    fn lines(data: &[u8]) -> Vec<(usize, usize)> {
+        split_records(data, b'\n')
+    }
+
+    #[test]
+    fn shared_dictionary_saves_bytes() {
+        let data: Vec<u8> = (0..50)
+            .map(|i| format!("2026-02-16 app[1000]: request GET /api/v1/items/{i} status=200\n"))
+            .collect::<String>()
+            .into_bytes();
+        let dict = b"app[1000]: request GET /api/v1/items/ status=200\n";
+        let est = estimate_savings(&data, &lines(&data), dict, 1000).unwrap();
+        assert_eq!(est.records, 50);
+        assert_eq!(est.raw_bytes, data.len());
+        assert!(est.preset_bytes < est.plain_bytes);
+        assert!(est.saved_pct() > 20.0);
+        assert!((est.projected_saved(100) - 2.0 * est.saved() as f64).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sampling_and_empty_dictionary() {
+        let data = b"one\ntwo\nthree\nfour\n";
+        let est = estimate_savings(data, &lines(data), b"", 2).unwrap();
+        assert_eq!(est.records, 2);
+        assert_eq!(est.saved(), 0);
+    }
+}
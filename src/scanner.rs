@@ -3,15 +3,51 @@
 //! Runs LZ77 matching on raw input data and produces a stream of operations
 //! (literal runs + back-references) for downstream analysis.
 
-const HASH_BITS: usize = 15;
-const HASH_SIZE: usize = 1 << HASH_BITS;
-const HASH_MASK: usize = HASH_SIZE - 1;
-const MAX_CHAIN: usize = 64;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::opsfile::{invalid, read_varint, write_varint};
+
 const NO_POS: u32 = u32::MAX;
 
 pub const DEFAULT_WINDOW: usize = 32 * 1024;
 pub const MIN_MATCH: usize = 4;
 pub const MAX_MATCH: usize = 258;
+pub const DEFAULT_HASH_BITS: u32 = 15;
+pub const DEFAULT_MAX_CHAIN: usize = 64;
+pub const DEFAULT_EFFORT: u8 = 5;
+
+/// Window size meaning "the whole input".
+pub const UNBOUNDED_WINDOW: usize = usize::MAX;
+
+/// Minimum length of matches found by the long-range table, which is
+/// enabled for windows larger than `DEFAULT_WINDOW`.
+const FAR_MATCH: usize = 16;
+
+/// Rolling-hash span of the long-distance matcher; also its minimum match.
+const LDM_MIN_LEN: usize = 64;
+/// Anchors are positions whose mixed rolling hash has this many leading
+/// zero bits, i.e. roughly one anchor per 2^LDM_RATE_BITS bytes.
+const LDM_RATE_BITS: u32 = 5;
+const LDM_BASE: u64 = 0x0100_0000_01b3;
+
+/// `(max_chain, hash_bits, skip_trigger)` for effort levels 1..=9. Level 5
+/// matches the historical fixed settings; only the fast levels skip.
+const EFFORT_TABLE: [(usize, u32, usize); 9] = [
+    (4, 12, 32),
+    (8, 13, 64),
+    (16, 14, 128),
+    (32, 15, 0),
+    (64, 15, 0),
+    (128, 16, 0),
+    (256, 16, 0),
+    (1024, 17, 0),
+    (4096, 18, 0),
+];
+
+/// Once skipping, the step grows by one every 2^SKIP_SHIFT further misses.
+const SKIP_SHIFT: u32 = 5;
+const MAX_SKIP_STEP: usize = 32;
 
 // ---------------------------------------------------------------------------
 // Public types
@@ -29,6 +65,9 @@ pub struct ScanOp {
     pub kind: OpKind,
     pub length: usize,
     /// Distance back to match source (0 for literals).
+    ///
+    /// May be smaller than `length`: the copy then overlaps its own output,
+    /// which is how runs such as `"------"` are encoded (offset 1).
     pub ref_offset: usize,
 }
 
@@ -38,6 +77,58 @@ impl ScanOp {
     pub fn content<'a>(&self, data: &'a [u8]) -> &'a [u8] {
         &data[self.position..self.position + self.length]
     }
+
+    /// True for a self-overlapping backref (`ref_offset < length`).
+    #[inline]
+    pub fn is_overlapping(&self) -> bool {
+        self.kind == OpKind::Backref && self.ref_offset < self.length
+    }
+}
+
+/// Match-finder and parser settings for [`scan`].
+#[derive(Debug, Clone)]
+pub struct ScanParams {
+    pub window_size: usize,
+    pub min_match: usize,
+    pub max_match: usize,
+    /// Maximum hash-chain candidates examined per position.
+    pub max_chain: usize,
+    /// log2 of the hash table size (clamped to 8..=24).
+    pub hash_bits: u32,
+    /// Consecutive unmatched bytes after which the greedy parser starts
+    /// stepping over input faster (0 disables skipping).
+    pub skip_trigger: usize,
+    pub parse: ParseMode,
+    pub matcher: Matcher,
+}
+
+impl Default for ScanParams {
+    fn default() -> Self {
+        Self {
+            window_size: DEFAULT_WINDOW,
+            min_match: MIN_MATCH,
+            max_match: MAX_MATCH,
+            max_chain: DEFAULT_MAX_CHAIN,
+            hash_bits: DEFAULT_HASH_BITS,
+            skip_trigger: 0,
+            parse: ParseMode::Greedy,
+            matcher: Matcher::HashChain,
+        }
+    }
+}
+
+impl ScanParams {
+    /// Default parameters with chain depth and hash size taken from an
+    /// effort level (1 = fastest triage, 9 = deepest search).
+    pub fn with_effort(effort: u8) -> Self {
+        let (max_chain, hash_bits, skip_trigger) = EFFORT_TABLE[effort.clamp(1, 9) as usize - 1];
+        Self {
+            max_chain,
+            hash_bits,
+            skip_trigger,
+            ..Self::default()
+        }
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -47,30 +138,78 @@ impl ScanOp {
 struct HashChain {
     window_size: usize,
     mask: usize,
+    hash_shift: u32,
+    max_chain: usize,
     head: Vec<u32>,
     prev: Vec<u32>,
+    far: Option<FarTable>,
+    /// Match sources may not extend past this position (see
+    /// [`scan_with_baseline`]).
+    src_end: usize,
+}
+
+/// Length of the common prefix of `data[a..]` and `data[b..]`, at most
+/// `limit` (both ranges must hold `limit` bytes). Compares eight bytes at a
+/// time; the first differing byte is the lowest set byte of the XOR.
+#[inline]
+fn match_length(data: &[u8], a: usize, b: usize, limit: usize) -> usize {
+    let (x, y) = (&data[a..a + limit], &data[b..b + limit]);
+    let word = |s: &[u8], i: usize| u64::from_le_bytes(s[i..i + 8].try_into().unwrap());
+    let mut len = 0;
+    while len + 8 <= limit {
+        let diff = word(x, len) ^ word(y, len);
+        if diff != 0 {
+            return len + (diff.trailing_zeros() / 8) as usize;
+        }
+        len += 8;
+    }
+    while len < limit && x[len] == y[len] {
+        len += 1;
+    }
+    len
+}
+
+/// Resolve a requested window against the input length.
+///
+/// The result is a power of two no larger than needed to span `len`, so
+/// `UNBOUNDED_WINDOW` (or any oversized request) costs memory
+/// proportional to the input rather than the request.
+fn effective_window(window_size: usize, len: usize) -> usize {
+    window_size.min(len).max(1).next_power_of_two()
 }
 
 impl HashChain {
-    fn new(window_size: usize) -> Self {
+    fn new(window_size: usize, hash_bits: u32, max_chain: usize) -> Self {
         debug_assert!(window_size.is_power_of_two());
+        let mut bits = hash_bits.clamp(8, 24);
+        let far = if window_size > DEFAULT_WINDOW {
+            // Scale the near table with the window so chains stay short.
+            bits = bits.max(window_size.trailing_zeros().saturating_sub(4)).min(24);
+            Some(FarTable::new(window_size))
+        } else {
+            None
+        };
         Self {
             window_size,
             mask: window_size - 1,
-            head: vec![NO_POS; HASH_SIZE],
+            hash_shift: 32 - bits,
+            max_chain: max_chain.max(1),
+            head: vec![NO_POS; 1 << bits],
             prev: vec![NO_POS; window_size],
+            far,
+            src_end: usize::MAX,
         }
     }
 
     #[inline]
-    fn hash4(data: &[u8], pos: usize) -> usize {
+    fn hash4(&self, data: &[u8], pos: usize) -> usize {
         let h = u32::from_le_bytes([
             data[pos],
             data[pos + 1],
             data[pos + 2],
             data[pos + 3],
         ]);
-        (h.wrapping_mul(2654435761) >> 17) as usize & HASH_MASK
+        (h.wrapping_mul(2654435761) >> self.hash_shift) as usize
     }
 
     #[inline]
@@ -82,10 +221,22 @@ impl HashChain {
         if pos + 4 > data.len() {
             return;
         }
-        let h = Self::hash4(data, pos);
+        let h = self.hash4(data, pos);
         let s = self.slot(pos);
         self.prev[s] = self.head[h];
         self.head[h] = pos as u32;
+        if let Some(far) = &mut self.far {
+            far.insert(data, pos);
+        }
+    }
+
+    /// Long-range candidate for `pos`, if the far table has one.
+    #[inline]
+    fn far_match(&self, data: &[u8], pos: usize, max_len: usize) -> Option<(usize, usize)> {
+        let far = self.far.as_ref()?;
+        let (off, len) = far.find(data, pos, max_len, pos.saturating_sub(self.window_size))?;
+        let len = len.min(self.src_end.saturating_sub(pos - off));
+        (len >= MIN_MATCH).then_some((off, len))
     }
 
     fn insert_range(&mut self, data: &[u8], start: usize, end: usize) {
@@ -105,14 +256,14 @@ impl HashChain {
             return None;
         }
 
-        let h = Self::hash4(data, pos);
+        let h = self.hash4(data, pos);
         let mut cp = self.head[h];
         let min_pos = pos.saturating_sub(self.window_size);
         let mut best_off: usize = 0;
         let mut best_len: usize = MIN_MATCH - 1;
         let mut steps: usize = 0;
 
-        while cp != NO_POS && (cp as usize) >= min_pos && steps < MAX_CHAIN {
+        while cp != NO_POS && (cp as usize) >= min_pos && steps < self.max_chain {
             let c = cp as usize;
             if c >= pos {
                 cp = self.prev[self.slot(c)];
@@ -120,12 +271,9 @@ impl HashChain {
                 continue;
             }
 
-            let limit = max_len.min(data.len() - pos).min(data.len() - c);
+            let limit = max_len.min(data.len() - pos).min(self.src_end.min(data.len()) - c);
             if limit > best_len && data[c + best_len] == data[pos + best_len] {
-                let mut len = 0;
-                while len < limit && data[c + len] == data[pos + len] {
-                    len += 1;
-                }
+                let len = match_length(data, c, pos, limit);
                 if len > best_len {
                     best_len = len;
                     best_off = pos - c;
@@ -139,12 +287,302 @@ impl HashChain {
             steps += 1;
         }
 
+        if best_len < max_len {
+            if let Some((off, len)) = self.far_match(data, pos, max_len) {
+                if len > best_len {
+                    best_len = len;
+                    best_off = off;
+                }
+            }
+        }
+
         if best_len >= MIN_MATCH && best_off > 0 {
             Some((best_off, best_len))
         } else {
             None
         }
     }
+
+    /// Collect every match along the chain that is longer than all closer
+    /// ones. Returned `(offset, length)` pairs have strictly increasing
+    /// length and offset, so each is the cheapest source for its lengths.
+    fn candidates(
+        &self,
+        data: &[u8],
+        pos: usize,
+        max_len: usize,
+    ) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        if pos + MIN_MATCH > data.len() {
+            return found;
+        }
+
+        let h = self.hash4(data, pos);
+        let mut cp = self.head[h];
+        let min_pos = pos.saturating_sub(self.window_size);
+        let mut best_len: usize = MIN_MATCH - 1;
+        let mut steps: usize = 0;
+
+        while cp != NO_POS && (cp as usize) >= min_pos && steps < self.max_chain {
+            let c = cp as usize;
+            if c < pos {
+                let limit = max_len.min(data.len() - pos);
+                if limit > best_len && data[c + best_len] == data[pos + best_len] {
+                    let len = match_length(data, c, pos, limit);
+                    if len > best_len {
+                        best_len = len;
+                        found.push((pos - c, len));
+                        if best_len >= max_len {
+                            break;
+                        }
+                    }
+                }
+            }
+            cp = self.prev[self.slot(c)];
+            steps += 1;
+        }
+
+        if best_len < max_len {
+            if let Some((off, len)) = self.far_match(data, pos, max_len) {
+                if len > best_len {
+                    found.push((off, len));
+                }
+            }
+        }
+
+        found
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Long-range table
+// ---------------------------------------------------------------------------
+
+/// Single-slot hash table over `FAR_MATCH`-byte prefixes.
+///
+/// Hash chains degrade on large windows: a bounded chain walk only ever
+/// reaches the most recent few candidates, so distant repeats are missed.
+/// This table remembers the latest position of each 16-byte prefix, which
+/// is enough to recover long, far-apart repeats in O(1) per position.
+struct FarTable {
+    shift: u32,
+    head: Vec<u32>,
+}
+
+impl FarTable {
+    fn new(window_size: usize) -> Self {
+        let bits = window_size.trailing_zeros().saturating_sub(2).clamp(16, 22);
+        Self {
+            shift: 64 - bits,
+            head: vec![NO_POS; 1 << bits],
+        }
+    }
+
+    #[inline]
+    fn hash16(&self, data: &[u8], pos: usize) -> usize {
+        let a = u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap());
+        let b = u64::from_le_bytes(data[pos + 8..pos + 16].try_into().unwrap());
+        ((a ^ b.rotate_left(29)).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> self.shift) as usize
+    }
+
+    #[inline]
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if pos + FAR_MATCH <= data.len() {
+            let h = self.hash16(data, pos);
+            self.head[h] = pos as u32;
+        }
+    }
+
+    fn find(
+        &self,
+        data: &[u8],
+        pos: usize,
+        max_len: usize,
+        min_pos: usize,
+    ) -> Option<(usize, usize)> {
+        if pos + FAR_MATCH > data.len() {
+            return None;
+        }
+        let c = self.head[self.hash16(data, pos)];
+        if c == NO_POS || (c as usize) >= pos || (c as usize) < min_pos {
+            return None;
+        }
+        let c = c as usize;
+        let limit = max_len.min(data.len() - pos);
+        let len = match_length(data, c, pos, limit);
+        (len >= FAR_MATCH.min(limit).max(MIN_MATCH)).then_some((pos - c, len))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Cost model
+// ---------------------------------------------------------------------------
+
+/// Approximate bit costs of LZ77 operations, used by optimal parsing.
+///
+/// The defaults loosely follow deflate's fixed Huffman tables: a literal
+/// costs about 9 bits, a match costs a fixed length/distance code plus
+/// extra bits that grow with the logarithm of its offset and length.
+#[derive(Debug, Clone, Copy)]
+pub struct CostModel {
+    pub literal_bits: f64,
+    pub match_base_bits: f64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self {
+            literal_bits: 9.0,
+            match_base_bits: 12.0,
+        }
+    }
+}
+
+impl CostModel {
+    #[inline]
+    pub fn literal_cost(&self, length: usize) -> f64 {
+        self.literal_bits * length as f64
+    }
+
+    #[inline]
+    pub fn match_cost(&self, offset: usize, length: usize) -> f64 {
+        self.match_base_bits + (offset as f64).log2() + (length as f64).log2()
+    }
+
+    /// Total estimated bits for an operation stream.
+    pub fn parse_cost(&self, ops: &[ScanOp]) -> f64 {
+        ops.iter()
+            .map(|op| match op.kind {
+                OpKind::Literal => self.literal_cost(op.length),
+                OpKind::Backref => self.match_cost(op.ref_offset, op.length),
+            })
+            .sum()
+    }
+}
+
+/// How the scanner chooses between overlapping match candidates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Take the longest match at each position (deflate-style lazy-free).
+    Greedy,
+    /// Minimize total `CostModel` bits via shortest path over candidates.
+    Optimal,
+}
+
+/// Which match finders feed the parser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Matcher {
+    /// Hash chains over the sliding window only.
+    HashChain,
+    /// Hash chains plus a content-defined long-distance matcher that finds
+    /// repeats of `LDM_MIN_LEN`+ bytes anywhere earlier in the input,
+    /// regardless of window size.
+    Ldm,
+}
+
+// ---------------------------------------------------------------------------
+// Long-distance matcher
+// ---------------------------------------------------------------------------
+
+/// A long repeat found by the long-distance matcher. Parsers split it into
+/// backrefs of at most `max_match` bytes.
+#[derive(Debug, Clone, Copy)]
+struct LongMatch {
+    start: usize,
+    offset: usize,
+    length: usize,
+}
+
+impl LongMatch {
+    #[inline]
+    fn end(&self) -> usize {
+        self.start + self.length
+    }
+}
+
+#[inline]
+fn ldm_is_anchor(h: u64) -> bool {
+    h.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - LDM_RATE_BITS) == 0
+}
+
+fn ldm_hash(window: &[u8]) -> u64 {
+    window
+        .iter()
+        .fold(0u64, |h, &b| h.wrapping_mul(LDM_BASE).wrapping_add(b as u64))
+}
+
+/// Find long, possibly very distant repeats with a Rabin-Karp rolling hash.
+///
+/// Anchors are chosen by content (hash value), not position, so the same
+/// text produces the same anchors wherever it appears. Each anchor's hash
+/// is remembered; when it recurs, the match is verified and extended in
+/// both directions. Returned matches are sorted and non-overlapping.
+fn find_long_matches(data: &[u8], min_len: usize) -> Vec<LongMatch> {
+    let n = data.len();
+    let span = LDM_MIN_LEN;
+    let mut out = Vec::new();
+    if n <= span {
+        return out;
+    }
+
+    let pow = (1..span).fold(1u64, |p, _| p.wrapping_mul(LDM_BASE));
+    let mut table: HashMap<u64, usize> = HashMap::new();
+    let mut pos = 0;
+    let mut last_end = 0;
+    let mut h = ldm_hash(&data[..span]);
+
+    loop {
+        if ldm_is_anchor(h) {
+            if let Some(&c) = table.get(&h) {
+                if data[c..c + span] == data[pos..pos + span] {
+                    let fwd = span + match_length(data, c + span, pos + span, n - pos - span);
+                    let mut back = 0;
+                    while back < pos - last_end && back < c && data[c - 1 - back] == data[pos - 1 - back] {
+                        back += 1;
+                    }
+                    if fwd + back >= min_len.max(span) {
+                        let m = LongMatch {
+                            start: pos - back,
+                            offset: pos - c,
+                            length: fwd + back,
+                        };
+                        out.push(m);
+                        table.insert(h, pos);
+                        last_end = m.end();
+                        pos = last_end;
+                        if pos + span > n {
+                            break;
+                        }
+                        h = ldm_hash(&data[pos..pos + span]);
+                        continue;
+                    }
+                }
+            }
+            table.insert(h, pos);
+        }
+        if pos + span >= n {
+            break;
+        }
+        h = h
+            .wrapping_sub((data[pos] as u64).wrapping_mul(pow))
+            .wrapping_mul(LDM_BASE)
+            .wrapping_add(data[pos + span] as u64);
+        pos += 1;
+    }
+
+    out
+}
+
+/// Length of the next backref to emit at `pos` inside long match `m`,
+/// chosen so the remainder never drops below `min_len`.
+#[inline]
+fn long_chunk(m: &LongMatch, pos: usize, max_match: usize, min_len: usize) -> usize {
+    let rem = m.end() - pos;
+    if rem <= max_match {
+        rem
+    } else {
+        max_match.min(rem - min_len)
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -155,20 +593,71 @@ impl HashChain {
 ///
 /// Each byte of the input is covered by exactly one `ScanOp`.
 /// Consecutive unmatched bytes are merged into a single literal `ScanOp`.
-pub fn scan(data: &[u8], window_size: usize, min_match: usize, max_match: usize) -> Vec<ScanOp> {
+/// `ParseMode::Optimal` dispatches to [`scan_optimal`] with the default
+/// cost model.
+pub fn scan(data: &[u8], params: &ScanParams) -> Vec<ScanOp> {
+    match params.parse {
+        ParseMode::Greedy => scan_greedy(data, params),
+        ParseMode::Optimal => scan_optimal(data, params, &CostModel::default()),
+    }
+}
+
+fn long_matches_for(data: &[u8], params: &ScanParams) -> Vec<LongMatch> {
+    match params.matcher {
+        Matcher::HashChain => Vec::new(),
+        Matcher::Ldm => find_long_matches(data, params.min_match),
+    }
+}
+
+fn scan_greedy(data: &[u8], params: &ScanParams) -> Vec<ScanOp> {
     if data.is_empty() {
         return Vec::new();
     }
 
-    let ws = window_size.next_power_of_two();
-    let mut chain = HashChain::new(ws);
+    let min_match = params.min_match;
+    let max_match = params.max_match;
+    let ws = effective_window(params.window_size, data.len());
+    let mut chain = HashChain::new(ws, params.hash_bits, params.max_chain);
+    let long = long_matches_for(data, params);
+    let mut li = 0;
     let mut ops = Vec::new();
     let mut pos: usize = 0;
     let mut lit_start: Option<usize> = None;
+    let mut misses: usize = 0;
 
     while pos < data.len() {
-        if pos + 4 <= data.len() {
-            if let Some((off, len)) = chain.longest_match(data, pos, max_match) {
+        // Long matches take priority; chain matches may not run into them.
+        let mut cap = max_match;
+        if let Some(m) = long.get(li) {
+            if m.start <= pos {
+                if let Some(s) = lit_start.take() {
+                    ops.push(ScanOp {
+                        position: s,
+                        kind: OpKind::Literal,
+                        length: pos - s,
+                        ref_offset: 0,
+                    });
+                }
+                let len = long_chunk(m, pos, max_match, min_match.max(MIN_MATCH));
+                ops.push(ScanOp {
+                    position: pos,
+                    kind: OpKind::Backref,
+                    length: len,
+                    ref_offset: m.offset,
+                });
+                chain.insert_range(data, pos, pos + len);
+                pos += len;
+                misses = 0;
+                if pos >= m.end() {
+                    li += 1;
+                }
+                continue;
+            }
+            cap = cap.min(m.start - pos);
+        }
+
+        if pos + 4 <= data.len() && cap >= MIN_MATCH {
+            if let Some((off, len)) = chain.longest_match(data, pos, cap) {
                 if len >= min_match {
                     // Flush pending literal run
                     if let Some(s) = lit_start.take() {
@@ -187,6 +676,7 @@ pub fn scan(data: &[u8], window_size: usize, min_match: usize, max_match: usize)
                     });
                     chain.insert_range(data, pos, pos + len);
                     pos += len;
+                    misses = 0;
                     continue;
                 }
             }
@@ -196,7 +686,19 @@ pub fn scan(data: &[u8], window_size: usize, min_match: usize, max_match: usize)
             lit_start = Some(pos);
         }
         chain.insert(data, pos);
-        pos += 1;
+
+        // Skip acceleration: in long match-free stretches (binary blobs,
+        // compressed payloads) only every `step`-th position is searched
+        // and hashed. Never step past the start of a pending long match.
+        misses += 1;
+        let mut step = 1;
+        if params.skip_trigger > 0 && misses > params.skip_trigger {
+            step = (1 + ((misses - params.skip_trigger) >> SKIP_SHIFT)).min(MAX_SKIP_STEP);
+            if let Some(m) = long.get(li) {
+                step = step.min(m.start - pos);
+            }
+        }
+        pos = (pos + step.max(1)).min(data.len());
     }
 
     // Flush final literal run
@@ -212,6 +714,314 @@ pub fn scan(data: &[u8], window_size: usize, min_match: usize, max_match: usize)
     ops
 }
 
+/// Scan `data[..baseline]` normally, then scan `data[baseline..]` using
+/// only the baseline as match source.
+///
+/// Backrefs in the tail never point at tail bytes, so tail coverage
+/// measures how well each byte is explained by the baseline alone, at any
+/// distance (the window does not apply to the tail). The tail is parsed
+/// greedily with the hash-chain matcher regardless of `parse`/`matcher`.
+/// Ops tile the whole of `data` with absolute positions.
+pub fn scan_with_baseline(data: &[u8], baseline: usize, params: &ScanParams) -> Vec<ScanOp> {
+    let baseline = baseline.min(data.len());
+    let mut ops = scan(&data[..baseline], params);
+    if baseline == data.len() {
+        return ops;
+    }
+
+    let ws = effective_window(UNBOUNDED_WINDOW, data.len());
+    let mut chain = HashChain::new(ws, params.hash_bits, params.max_chain);
+    chain.src_end = baseline;
+    chain.insert_range(data, 0, baseline);
+
+    let mut pos = baseline;
+    let mut lit_start: Option<usize> = None;
+    while pos < data.len() {
+        if let Some((off, len)) = chain.longest_match(data, pos, params.max_match) {
+            if len >= params.min_match {
+                if let Some(s) = lit_start.take() {
+                    ops.push(ScanOp {
+                        position: s,
+                        kind: OpKind::Literal,
+                        length: pos - s,
+                        ref_offset: 0,
+                    });
+                }
+                ops.push(ScanOp {
+                    position: pos,
+                    kind: OpKind::Backref,
+                    length: len,
+                    ref_offset: off,
+                });
+                pos += len;
+                continue;
+            }
+        }
+        lit_start.get_or_insert(pos);
+        pos += 1;
+    }
+    if let Some(s) = lit_start {
+        ops.push(ScanOp {
+            position: s,
+            kind: OpKind::Literal,
+            length: data.len() - s,
+            ref_offset: 0,
+        });
+    }
+    ops
+}
+
+/// Literal runs of at least `min_len` bytes, as `(start, end)` ranges.
+///
+/// Long match-free stretches are where skip acceleration engages; they are
+/// typically embedded binary or compressed data rather than text.
+pub fn incompressible_regions(ops: &[ScanOp], min_len: usize) -> Vec<(usize, usize)> {
+    ops.iter()
+        .filter(|o| o.kind == OpKind::Literal && o.length >= min_len)
+        .map(|o| (o.position, o.position + o.length))
+        .collect()
+}
+
+/// Run LZ77 match-finding and return the minimal-cost operation sequence.
+///
+/// Every position is treated as a node in a shortest-path graph: a literal
+/// advances one byte, and each match candidate advances by any length
+/// between `min_match` and its full length. Costs come from `model`. This
+/// is considerably slower than [`scan`] on highly repetitive input, but its
+/// coverage reflects the best parse the match finder can see.
+pub fn scan_optimal(data: &[u8], params: &ScanParams, model: &CostModel) -> Vec<ScanOp> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let n = data.len();
+    let max_match = params.max_match;
+    let ws = effective_window(params.window_size, data.len());
+    let min_len = params.min_match.max(MIN_MATCH);
+    let mut chain = HashChain::new(ws, params.hash_bits, params.max_chain);
+    let long = long_matches_for(data, params);
+    let mut li = 0;
+
+    // cost[i] = cheapest bits to encode data[..i]; step[i] = (len, offset)
+    // of the final operation on that path (offset 0 = single literal byte).
+    let mut cost = vec![f64::INFINITY; n + 1];
+    let mut step = vec![(0u32, 0u32); n + 1];
+    cost[0] = 0.0;
+
+    for pos in 0..n {
+        let base = cost[pos];
+
+        let lit = base + model.literal_cost(1);
+        if lit < cost[pos + 1] {
+            cost[pos + 1] = lit;
+            step[pos + 1] = (1, 0);
+        }
+
+        if pos + 4 <= n {
+            let mut next_len = min_len;
+            for (off, len) in chain.candidates(data, pos, max_match) {
+                for l in next_len..=len {
+                    let c = base + model.match_cost(off, l);
+                    if c < cost[pos + l] {
+                        cost[pos + l] = c;
+                        step[pos + l] = (l as u32, off as u32);
+                    }
+                }
+                next_len = next_len.max(len + 1);
+            }
+
+            // A long match covering `pos` is a candidate at every offset
+            // inside it, not only at its start.
+            while li < long.len() && long[li].end() <= pos {
+                li += 1;
+            }
+            if let Some(m) = long.get(li).filter(|m| m.start <= pos) {
+                let len = (m.end() - pos).min(max_match);
+                for l in next_len..=len {
+                    let c = base + model.match_cost(m.offset, l);
+                    if c < cost[pos + l] {
+                        cost[pos + l] = c;
+                        step[pos + l] = (l as u32, m.offset as u32);
+                    }
+                }
+            }
+        }
+
+        chain.insert(data, pos);
+    }
+
+    // Walk the chosen path backwards, then emit ops front to back.
+    let mut path: Vec<(usize, usize)> = Vec::new();
+    let mut end = n;
+    while end > 0 {
+        let (len, off) = step[end];
+        let len = len as usize;
+        path.push((len, off as usize));
+        end -= len;
+    }
+    path.reverse();
+
+    let mut ops = Vec::new();
+    let mut pos = 0;
+    let mut lit_start: Option<usize> = None;
+    for (len, off) in path {
+        if off == 0 {
+            if lit_start.is_none() {
+                lit_start = Some(pos);
+            }
+        } else {
+            if let Some(s) = lit_start.take() {
+                ops.push(ScanOp {
+                    position: s,
+                    kind: OpKind::Literal,
+                    length: pos - s,
+                    ref_offset: 0,
+                });
+            }
+            ops.push(ScanOp {
+                position: pos,
+                kind: OpKind::Backref,
+                length: len,
+                ref_offset: off,
+            });
+        }
+        pos += len;
+    }
+    if let Some(s) = lit_start {
+        ops.push(ScanOp {
+            position: s,
+            kind: OpKind::Literal,
+            length: n - s,
+            ref_offset: 0,
+        });
+    }
+
+    ops
+}
+
+// ---------------------------------------------------------------------------
+// Incremental scanning
+// ---------------------------------------------------------------------------
+
+/// Largest window a [`StreamScanner`] keeps history for.
+pub const MAX_STREAM_WINDOW: usize = 1 << 24;
+
+/// Greedy hash-chain scanner fed a piece at a time (typically a record).
+/// Each piece is matched against everything before it within the window,
+/// itself included, as [`scan`] would see it; matches cannot run past the
+/// end of the piece. Between one and two windows of history are kept.
+pub struct StreamScanner {
+    params: ScanParams,
+    window: usize,
+    buf: Vec<u8>,
+    chain: HashChain,
+    /// Positions from here on were too close to the end to hash.
+    pending: usize,
+}
+
+impl StreamScanner {
+    /// `params.window_size` is capped at [`MAX_STREAM_WINDOW`]; parse mode,
+    /// matcher and skipping are ignored.
+    pub fn new(params: &ScanParams) -> Self {
+        let window = params.window_size.clamp(1, MAX_STREAM_WINDOW).next_power_of_two();
+        Self {
+            params: params.clone(),
+            window,
+            buf: Vec::new(),
+            chain: HashChain::new(window, params.hash_bits, params.max_chain),
+            pending: 0,
+        }
+    }
+
+    /// Scan `piece` and add it to the history. Op positions are relative to
+    /// the start of `piece`; `ref_offset` is the usual distance back.
+    pub fn push(&mut self, piece: &[u8]) -> Vec<ScanOp> {
+        if self.buf.len() + piece.len() > 2 * self.window {
+            self.slide();
+        }
+        let start = self.buf.len();
+        self.buf.extend_from_slice(piece);
+        let data = &self.buf[..];
+        let chain = &mut self.chain;
+        chain.insert_range(data, self.pending, start);
+
+        let mut ops = Vec::new();
+        let mut pos = start;
+        let mut lit_start: Option<usize> = None;
+        let literal = |s: usize, e: usize| ScanOp { position: s - start, kind: OpKind::Literal, length: e - s, ref_offset: 0 };
+        while pos < data.len() {
+            if let Some((off, len)) = chain.longest_match(data, pos, self.params.max_match) {
+                if len >= self.params.min_match {
+                    if let Some(s) = lit_start.take() {
+                        ops.push(literal(s, pos));
+                    }
+                    ops.push(ScanOp { position: pos - start, kind: OpKind::Backref, length: len, ref_offset: off });
+                    chain.insert_range(data, pos, pos + len);
+                    pos += len;
+                    continue;
+                }
+            }
+            lit_start.get_or_insert(pos);
+            chain.insert(data, pos);
+            pos += 1;
+        }
+        if let Some(s) = lit_start {
+            ops.push(literal(s, data.len()));
+        }
+        self.pending = data.len().saturating_sub(3).max(start);
+        ops
+    }
+
+    /// Drop all but the last window of history and rebuild the chain.
+    fn slide(&mut self) {
+        let drop = self.buf.len().saturating_sub(self.window);
+        self.buf.drain(..drop);
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        self.chain = HashChain::new(self.window, self.params.hash_bits, self.params.max_chain);
+        self.chain.insert_range(&self.buf, 0, self.buf.len());
+        self.pending = self.buf.len().saturating_sub(3);
+    }
+
+    /// Encoded as `varint window, varint min_match, varint max_match,
+    /// varint max_chain, varint hash_bits, varint length, history bytes`;
+    /// the chain is rebuilt on read.
+    pub(crate) fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for v in [self.window, self.params.min_match, self.params.max_match, self.params.max_chain] {
+            write_varint(w, v as u64)?;
+        }
+        write_varint(w, self.params.hash_bits as u64)?;
+        write_varint(w, self.buf.len() as u64)?;
+        w.write_all(&self.buf)
+    }
+
+    pub(crate) fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut v = [0usize; 4];
+        for x in &mut v {
+            *x = read_varint(r)? as usize;
+        }
+        let [window_size, min_match, max_match, max_chain] = v;
+        let hash_bits = read_varint(r)?.clamp(8, 24) as u32;
+        if window_size == 0 || window_size > MAX_STREAM_WINDOW || min_match == 0 || max_match < min_match {
+            return Err(invalid("invalid stream scanner parameters"));
+        }
+        let params = ScanParams { window_size, min_match, max_match, max_chain, hash_bits, ..ScanParams::default() };
+        let mut s = StreamScanner::new(&params);
+        let n = read_varint(r)?;
+        if n > 2 * s.window as u64 {
+            return Err(invalid("stream history longer than two windows"));
+        }
+        r.take(n).read_to_end(&mut s.buf)?;
+        if s.buf.len() as u64 != n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        s.rebuild();
+        Ok(s)
+    }
+}
+
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
@@ -220,15 +1030,26 @@ pub fn scan(data: &[u8], window_size: usize, min_match: usize, max_match: usize)
 mod tests {
     use super::*;
 
+    #[test]
+    fn match_length_compares_words_and_tail() {
+        let data = b"abcdefghijklmnopXabcdefghijklmnopY";
+        assert_eq!(match_length(data, 0, 17, 17), 16);
+        assert_eq!(match_length(data, 0, 17, 11), 11);
+        assert_eq!(match_length(data, 3, 20, 5), 5);
+        assert_eq!(match_length(data, 0, 1, 20), 0);
+        // Self-overlapping run.
+        assert_eq!(match_length(b"aaaaaaaaaaaaaaaaaaaaab", 0, 1, 21), 20);
+    }
+
     #[test]
     fn empty_input() {
-        assert!(scan(b"", DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH).is_empty());
+        assert!(scan(b"", &ScanParams::default()).is_empty());
     }
 
     #[test]
     fn all_literal() {
         let data = b"abcdefgh";
-        let ops = scan(data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(data, &ScanParams::default());
         assert_eq!(ops.len(), 1);
         assert_eq!(ops[0].kind, OpKind::Literal);
         assert_eq!(ops[0].length, data.len());
@@ -237,7 +1058,7 @@ mod tests {
     #[test]
     fn full_coverage() {
         let data = b"hello world, hello world, hello world again!\n";
-        let ops = scan(data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(data, &ScanParams::default());
         let covered: usize = ops.iter().map(|o| o.length).sum();
         assert_eq!(covered, data.len());
     }
@@ -245,7 +1066,7 @@ mod tests {
     #[test]
     fn no_gaps() {
         let data = b"test line one\ntest line two\ntest line three\n";
-        let ops = scan(data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(data, &ScanParams::default());
         let mut pos = 0;
         for op in &ops {
             assert_eq!(op.position, pos, "gap at byte {pos}");
@@ -260,7 +1081,7 @@ mod tests {
         let mut data = Vec::new();
         data.extend_from_slice(chunk);
         data.extend_from_slice(chunk);
-        let ops = scan(&data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(&data, &ScanParams::default());
         let br_bytes: usize = ops
             .iter()
             .filter(|o| o.kind == OpKind::Backref)
@@ -272,7 +1093,7 @@ mod tests {
     #[test]
     fn backref_content_matches_source() {
         let data = b"pattern1234 pattern1234 pattern1234";
-        let ops = scan(data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(data, &ScanParams::default());
         for op in &ops {
             if op.kind == OpKind::Backref {
                 let src = op.position - op.ref_offset;
@@ -288,7 +1109,7 @@ mod tests {
     fn large_repetition_high_coverage() {
         let line = b"2026-02-16 08:31:02 myapp[1423]: Connection established from 10.0.0.5\n";
         let data: Vec<u8> = line.repeat(100);
-        let ops = scan(&data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(&data, &ScanParams::default());
         let br_bytes: usize = ops
             .iter()
             .filter(|o| o.kind == OpKind::Backref)
@@ -297,4 +1118,279 @@ mod tests {
         let coverage = br_bytes as f64 / data.len() as f64;
         assert!(coverage > 0.8, "expected >80% coverage, got {coverage:.1}%");
     }
+
+    #[test]
+    fn byte_run_is_overlapping_backref() {
+        let data = format!("pad={} end", "-".repeat(200)).into_bytes();
+        let ops = scan(&data, &ScanParams::default());
+        let run = ops
+            .iter()
+            .find(|o| o.kind == OpKind::Backref && o.ref_offset == 1)
+            .expect("run should be a single offset-1 backref");
+        assert!(run.is_overlapping());
+        assert_eq!(run.length, 199);
+    }
+
+    #[test]
+    fn periodic_run_is_overlapping_backref() {
+        let data = format!("sep:{}|", "=-".repeat(100)).into_bytes();
+        for parse in [ParseMode::Greedy, ParseMode::Optimal] {
+            let ops = scan(&data, &ScanParams { parse, ..ScanParams::default() });
+            let lit: usize = ops
+                .iter()
+                .filter(|o| o.kind == OpKind::Literal)
+                .map(|o| o.length)
+                .sum();
+            assert!(lit <= 8, "{parse:?}: {lit} literal bytes");
+            assert!(ops.iter().any(|o| o.is_overlapping() && o.ref_offset == 2));
+        }
+    }
+
+    #[test]
+    fn fast_skip_flags_blob_and_recovers() {
+        let text = b"2026-02-16 worker: job finished ok\n".repeat(20);
+        let mut data = text.clone();
+        data.extend(noise(16 * 1024, 3));
+        let after = data.len();
+        data.extend_from_slice(&text);
+
+        let params = ScanParams {
+            skip_trigger: 64,
+            ..ScanParams::default()
+        };
+        let ops = scan(&data, &params);
+        let covered: usize = ops.iter().map(|o| o.length).sum();
+        assert_eq!(covered, data.len());
+
+        let regions = incompressible_regions(&ops, 1024);
+        assert_eq!(regions.len(), 1);
+        assert!(regions[0].1 - regions[0].0 >= 15 * 1024);
+
+        let tail_refs: usize = ops
+            .iter()
+            .filter(|o| o.kind == OpKind::Backref && o.position >= after)
+            .map(|o| o.length)
+            .sum();
+        assert!(tail_refs as f64 > 0.9 * text.len() as f64);
+    }
+
+    #[test]
+    fn effort_five_is_default() {
+        let p = ScanParams::with_effort(DEFAULT_EFFORT);
+        assert_eq!(p.max_chain, DEFAULT_MAX_CHAIN);
+        assert_eq!(p.hash_bits, DEFAULT_HASH_BITS);
+        assert_eq!(p.skip_trigger, 0);
+    }
+
+    #[test]
+    fn all_efforts_cover_input() {
+        let data: Vec<u8> = (0..100)
+            .map(|i| format!("user{} logged in from 10.0.{}.{}\n", i % 7, i % 4, i))
+            .collect::<String>()
+            .into_bytes();
+        for effort in 1..=9 {
+            let ops = scan(&data, &ScanParams::with_effort(effort));
+            let covered: usize = ops.iter().map(|o| o.length).sum();
+            assert_eq!(covered, data.len(), "effort {effort}");
+        }
+    }
+
+    /// Deterministic incompressible filler (xorshift64).
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unbounded_window_finds_distant_repeat() {
+        let block = b"=== service startup banner v1.2.3 build 9f8e7d loaded modules: a b c d e f ===\n";
+        let mut data = block.to_vec();
+        data.extend(noise(200 * 1024, 7));
+        let repeat_at = data.len();
+        data.extend_from_slice(block);
+
+        let covered = |params: &ScanParams| -> usize {
+            scan(&data, params)
+                .iter()
+                .filter(|o| o.kind == OpKind::Backref && o.position >= repeat_at)
+                .map(|o| o.length)
+                .sum()
+        };
+        let near = covered(&ScanParams::default());
+        let far = covered(&ScanParams {
+            window_size: UNBOUNDED_WINDOW,
+            ..ScanParams::default()
+        });
+        assert_eq!(near, 0);
+        assert_eq!(far, block.len());
+    }
+
+    fn stack_trace() -> Vec<u8> {
+        (0..40)
+            .map(|i| format!("    at com.example.service.Handler{i}.process(Handler{i}.java:{})\n", 100 + i))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn ldm_finds_distant_multi_kb_repeat() {
+        let trace = stack_trace();
+        let mut data = trace.clone();
+        data.extend(noise(300 * 1024, 11));
+        let repeat_at = data.len();
+        data.extend_from_slice(&trace);
+
+        let params = ScanParams {
+            matcher: Matcher::Ldm,
+            ..ScanParams::default()
+        };
+        let ops = scan(&data, &params);
+        let covered: usize = ops
+            .iter()
+            .filter(|o| o.kind == OpKind::Backref && o.position >= repeat_at)
+            .map(|o| o.length)
+            .sum();
+        assert!(covered >= trace.len() - LDM_MIN_LEN, "covered {covered} of {}", trace.len());
+
+        let mut pos = 0;
+        for op in &ops {
+            assert_eq!(op.position, pos);
+            if op.kind == OpKind::Backref {
+                assert!((MIN_MATCH..=MAX_MATCH).contains(&op.length));
+                let src = op.position - op.ref_offset;
+                assert_eq!(&data[src..src + op.length], op.content(&data));
+            }
+            pos += op.length;
+        }
+        assert_eq!(pos, data.len());
+    }
+
+    #[test]
+    fn ldm_optimal_covers_repeat() {
+        let trace = stack_trace();
+        let mut data = trace.clone();
+        data.extend(noise(100 * 1024, 5));
+        data.extend_from_slice(&trace);
+        let params = ScanParams {
+            matcher: Matcher::Ldm,
+            parse: ParseMode::Optimal,
+            ..ScanParams::default()
+        };
+        let ops = scan(&data, &params);
+        let covered: usize = ops.iter().map(|o| o.length).sum();
+        assert_eq!(covered, data.len());
+        let far = ops
+            .iter()
+            .filter(|o| o.kind == OpKind::Backref && o.ref_offset > DEFAULT_WINDOW)
+            .count();
+        assert!(far > 0);
+    }
+
+    #[test]
+    fn baseline_tail_only_references_baseline() {
+        let mut data: Vec<u8> = b"GET /api/items status=200\n".repeat(20);
+        let base = data.len();
+        data.extend_from_slice(b"GET /api/items status=200\n");
+        data.extend_from_slice(b"zz novel novel novel zz\n");
+        let ops = scan_with_baseline(&data, base, &ScanParams::default());
+
+        let mut pos = 0;
+        for op in &ops {
+            assert_eq!(op.position, pos);
+            pos += op.length;
+            if op.kind == OpKind::Backref && op.position >= base {
+                assert!(op.position - op.ref_offset + op.length <= base);
+            }
+        }
+        assert_eq!(pos, data.len());
+
+        // The repeated tail line is covered; the novel one stays literal
+        // even though it repeats "novel " within itself.
+        let novel = base + 26;
+        let tail_ref: usize = ops.iter()
+            .filter(|o| o.kind == OpKind::Backref && o.position >= novel)
+            .map(|o| o.length)
+            .sum();
+        assert!(tail_ref < 8);
+        assert!(ops.iter().any(|o| o.kind == OpKind::Backref && o.position == base));
+    }
+
+    #[test]
+    fn stream_scanner_matches_earlier_pieces() {
+        let params = ScanParams { window_size: 256, ..ScanParams::default() };
+        let mut s = StreamScanner::new(&params);
+        let mut history: Vec<u8> = Vec::new();
+        for i in 0..200 {
+            let piece = if i == 150 {
+                b"zq9 unseen payload x7\n".to_vec()
+            } else {
+                format!("GET /api/items/{} status=200\n", i % 3).into_bytes()
+            };
+            let ops = s.push(&piece);
+            let base = history.len();
+            history.extend_from_slice(&piece);
+
+            let mut pos = 0;
+            for op in &ops {
+                assert_eq!(op.position, pos);
+                pos += op.length;
+                if op.kind == OpKind::Backref {
+                    let (at, src) = (base + op.position, base + op.position - op.ref_offset);
+                    assert!(op.ref_offset <= 256);
+                    assert_eq!(history[at..at + op.length], history[src..src + op.length]);
+                }
+            }
+            assert_eq!(pos, piece.len());
+            let covered: usize = ops.iter().filter(|o| o.kind == OpKind::Backref).map(|o| o.length).sum();
+            match i {
+                0 => assert_eq!(covered, 0),
+                150 => assert!(covered < 4),
+                i if i > 3 => assert_eq!(covered, piece.len(), "piece {i}"),
+                _ => {}
+            }
+        }
+        assert!(s.buf.len() <= 512);
+    }
+
+    #[test]
+    fn optimal_no_gaps() {
+        let data = b"abcabcabcd xyzxyz abcabcabcd xyzxyz abcd\n".repeat(5);
+        let ops = scan_optimal(&data, &ScanParams::default(), &CostModel::default());
+        let mut pos = 0;
+        for op in &ops {
+            assert_eq!(op.position, pos, "gap at byte {pos}");
+            assert!(op.kind == OpKind::Literal || op.length >= MIN_MATCH);
+            pos += op.length;
+        }
+        assert_eq!(pos, data.len());
+    }
+
+    #[test]
+    fn optimal_backrefs_match_source() {
+        let data = b"pattern1234 pattern1234 pattern1234 pat1234";
+        let ops = scan_optimal(data, &ScanParams::default(), &CostModel::default());
+        for op in ops.iter().filter(|o| o.kind == OpKind::Backref) {
+            let src = op.position - op.ref_offset;
+            assert_eq!(&data[src..src + op.length], op.content(data));
+        }
+    }
+
+    #[test]
+    fn optimal_cost_not_worse_than_greedy() {
+        let model = CostModel::default();
+        let data: Vec<u8> = (0..200)
+            .map(|i| format!("req id={} path=/api/v{} status={}\n", i * 7, i % 3, 200 + i % 5))
+            .collect::<String>()
+            .into_bytes();
+        let greedy = scan(&data, &ScanParams::default());
+        let optimal = scan_optimal(&data, &ScanParams::default(), &model);
+        assert!(model.parse_cost(&optimal) <= model.parse_cost(&greedy) + 1e-9);
+    }
 }
@@ -0,0 +1,176 @@
+//! Scoring one record against a saved profile.
+//!
+//! `score-line` answers "is this line weird?" for a single record: the
+//! record is LZ77-matched against the profile's reference bytes and ranked
+//! by the profile's dictionary, as `--baseline profile.bgt` would score it,
+//! and its score is then placed among the scores of the profile's own
+//! records ([`Verdict`]).
+
+use crate::anomaly::{detect_indices, DetectionMethod};
+use crate::dictionary::build_dictionary;
+use crate::entropy::{record_bits_per_byte, EntropyModel};
+use crate::profile::Profile;
+use crate::scanner::{scan, scan_with_baseline, ScanOp, ScanParams};
+use crate::scorer::{record_spans, score_records, set_bits_per_byte, RecordAnalysis, Span};
+
+// ---------------------------------------------------------------------------
+// Verdict
+// ---------------------------------------------------------------------------
+
+/// Where one score falls among a reference set of scores.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Verdict {
+    pub score: f64,
+    /// Threshold the score method would use on the reference scores.
+    pub threshold: f64,
+    /// Percentage of reference scores below `score`.
+    pub percentile: f64,
+    pub reference_records: usize,
+}
+
+impl Verdict {
+    pub fn new(score: f64, reference: &[f64]) -> Self {
+        let (threshold, _) = detect_indices(reference, None, DetectionMethod::Score, None, None);
+        let below = reference.iter().filter(|&&s| s < score).count();
+        let percentile = if reference.is_empty() { 0.0 } else { below as f64 / reference.len() as f64 * 100.0 };
+        Verdict { score, threshold, percentile, reference_records: reference.len() }
+    }
+
+    /// Whether `anomalies` would flag the score among the reference's.
+    pub fn is_anomalous(&self) -> bool {
+        self.score > self.threshold
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Scoring
+// ---------------------------------------------------------------------------
+
+/// One record scored against a profile.
+#[derive(Debug, Clone)]
+pub struct LineScore {
+    /// The record's analysis; offsets are relative to the line.
+    pub record: RecordAnalysis,
+    /// Op spans covering the record, relative to the line. Entry IDs refer
+    /// to the profile's dictionary.
+    pub spans: Vec<Span>,
+    pub verdict: Verdict,
+}
+
+/// `text` as the one record to score, without its line ending (`\n` or
+/// `\r\n`). Errors with the number of lines if there are more than one.
+pub fn one_record(text: &[u8]) -> Result<&[u8], usize> {
+    let line = text.strip_suffix(b"\n").unwrap_or(text);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    match line.iter().filter(|&&b| b == b'\n').count() {
+        0 => Ok(line),
+        n => Err(n + 1),
+    }
+}
+
+/// Anomaly scores of the profile's reference records scored among
+/// themselves, as `anomalies` would score its training input.
+pub fn reference_scores(profile: &Profile, params: &ScanParams, min_count: usize) -> Vec<f64> {
+    let data = &profile.reference;
+    let ops = scan(data, params);
+    let dict = build_dictionary(data, &ops, min_count);
+    score_records(data, &ops, &dict, b'\n').iter().map(|r| r.anomaly_score).collect()
+}
+
+/// Score `line`, one record preprocessed as the profile's input was,
+/// against `profile`: matched only against the reference bytes, ranked by
+/// the profile's dictionary, and judged against [`reference_scores`].
+///
+/// Returns `None` if `line` is empty.
+pub fn score_line(profile: &Profile, line: &[u8], params: &ScanParams, min_count: usize) -> Option<LineScore> {
+    if line.is_empty() {
+        return None;
+    }
+    let mut buf = profile.reference.clone();
+    if buf.last().is_some_and(|&c| c != b'\n') {
+        buf.push(b'\n');
+    }
+    let split = buf.len();
+    buf.extend_from_slice(line);
+
+    let ops = scan_with_baseline(&buf, split, params);
+    let k = ops.partition_point(|o| o.position < split);
+    let dict = &profile.dictionary;
+    let mut record = score_records(&buf, &ops, dict, b'\n').into_iter().find(|r| r.offset >= split)?;
+    let bounds = [(record.offset, record.length)];
+    let model = EntropyModel::from_ops(&buf, &ops[..k]);
+    set_bits_per_byte(std::slice::from_mut(&mut record), &record_bits_per_byte(&buf, &ops, &model, &bounds));
+
+    let own: Vec<ScanOp> = ops[k..].iter().map(|o| ScanOp { position: o.position - split, ..o.clone() }).collect();
+    record.index = 0;
+    record.offset -= split;
+    let spans = record_spans(line, &own, dict, &[(record.offset, record.length)]).remove(0);
+    let verdict = Verdict::new(record.anomaly_score, &reference_scores(profile, params, min_count));
+    Some(LineScore { record, spans, verdict })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::OpKind;
+
+    fn profile(reference: &[u8]) -> Profile {
+        let ops = scan(reference, &ScanParams::default());
+        Profile {
+            params: None,
+            reference: reference.to_vec(),
+            dictionary: build_dictionary(reference, &ops, 2),
+            schema: None,
+        }
+    }
+
+    #[test]
+    fn verdict_places_score_among_reference() {
+        let reference: Vec<f64> = (0..100).map(|i| i as f64 / 100.0).collect();
+        let v = Verdict::new(0.505, &reference);
+        assert_eq!(v.percentile, 51.0);
+        assert_eq!(v.reference_records, 100);
+        assert!(!v.is_anomalous());
+        let v = Verdict::new(5.0, &reference);
+        assert_eq!(v.percentile, 100.0);
+        assert!(v.is_anomalous());
+        assert_eq!(Verdict::new(1.0, &[]).percentile, 0.0);
+    }
+
+    #[test]
+    fn one_record_drops_its_line_ending() {
+        assert_eq!(one_record(b"GET / 200\r\n"), Ok(&b"GET / 200"[..]));
+        assert_eq!(one_record(b"GET / 200\n"), Ok(&b"GET / 200"[..]));
+        assert_eq!(one_record(b"GET / 200"), Ok(&b"GET / 200"[..]));
+        assert_eq!(one_record(b"\n"), Ok(&b""[..]));
+        assert_eq!(one_record(b"a\nb\nc\n"), Err(3));
+    }
+
+    #[test]
+    fn familiar_line_is_normal_and_novel_line_anomalous() {
+        let reference: String = (0..300).map(|i| format!("GET /api/users/{} 200 ok\n", i % 17)).collect();
+        let p = profile(reference.as_bytes());
+        let params = ScanParams::default();
+
+        let line = b"GET /api/users/4 200 ok";
+        let s = score_line(&p, line, &params, 2).unwrap();
+        assert_eq!((s.record.index, s.record.offset, s.record.length), (0, 0, line.len()));
+        assert!(!s.verdict.is_anomalous());
+        assert_eq!(s.verdict.reference_records, 300);
+        assert_eq!(s.spans.first().map(|sp| sp.start), Some(0));
+        assert_eq!(s.spans.last().map(|sp| sp.end), Some(line.len()));
+        assert!(s.spans.iter().all(|sp| sp.entry_id.is_none_or(|id| id < p.dictionary.len())));
+
+        let odd = b"kernel: BUG unable to handle page fault at 0xffff8800deadbeef";
+        let s = score_line(&p, odd, &params, 2).unwrap();
+        assert!(s.verdict.is_anomalous());
+        assert!(s.spans.iter().any(|sp| sp.kind == OpKind::Literal));
+        assert!(s.record.literal_bytes > odd.len() / 2);
+
+        assert!(score_line(&p, b"", &params, 2).is_none());
+    }
+}
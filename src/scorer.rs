@@ -4,10 +4,20 @@
 //! each record, and computes coverage and profile statistics.
 
 use std::collections::HashMap;
+use std::ops::Range;
 
-use crate::dictionary::DictEntry;
+use memchr::memchr;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use crate::dictionary::{build_dictionary, decay_dictionary, merge_dictionaries, rank_entries, DictEntry};
+use crate::records::{sliding_windows, RecordIndex, WindowBlock};
 use crate::scanner::{OpKind, ScanOp};
 
+/// Share of a record's anomaly score taken by its compressibility term
+/// (coverage, or bits per byte); the rest is dictionary rarity.
+pub const COVERAGE_WEIGHT: f64 = 0.7;
+
 // ---------------------------------------------------------------------------
 // Public types
 // ---------------------------------------------------------------------------
@@ -23,6 +33,11 @@ pub struct RecordAnalysis {
     pub coverage: f64,
     /// Dictionary entry IDs referenced by this record (sorted, deduplicated).
     pub ref_entries: Vec<usize>,
+    /// Mean rank of referenced entries, scaled to 0..1 (1.0 if none).
+    pub rarity_score: f64,
+    /// Estimated compressed bits per byte (0 until set by
+    /// [`set_bits_per_byte`]).
+    pub bits_per_byte: f64,
     /// Anomaly score — higher means more anomalous.
     pub anomaly_score: f64,
 }
@@ -39,6 +54,13 @@ impl RecordAnalysis {
 // Scorer
 // ---------------------------------------------------------------------------
 
+/// Split `data` into `(offset, length)` records, each including its
+/// trailing delimiter; a final unterminated record is kept. Shorthand for
+/// [`RecordIndex::bounds`].
+pub fn split_records(data: &[u8], delimiter: u8) -> Vec<(usize, usize)> {
+    RecordIndex::new(data, delimiter).bounds()
+}
+
 /// Score each record in `data` using scan operations and the dictionary.
 ///
 /// Returns one `RecordAnalysis` per record with coverage stats and a
@@ -53,18 +75,7 @@ pub fn score_records(
         return Vec::new();
     }
 
-    // --- Split input into records ---
-    let mut records: Vec<(usize, usize)> = Vec::new(); // (offset, length)
-    let mut start = 0;
-    for (i, &b) in data.iter().enumerate() {
-        if b == delimiter {
-            records.push((start, i + 1 - start));
-            start = i + 1;
-        }
-    }
-    if start < data.len() {
-        records.push((start, data.len() - start));
-    }
+    let records = split_records(data, delimiter);
     if records.is_empty() {
         return Vec::new();
     }
@@ -89,9 +100,7 @@ pub fn score_records(
     for op in ops {
         if op.kind == OpKind::Backref {
             let end = (op.position + op.length).min(data.len());
-            for i in op.position..end {
-                covered[i] = true;
-            }
+            covered[op.position..end].fill(true);
             let eid = content_to_entry.get(op.content(data)).copied();
             br_infos.push(BrInfo {
                 start: op.position,
@@ -102,10 +111,12 @@ pub fn score_records(
     }
 
     // --- Score each record ---
-    let mut analyses = Vec::with_capacity(records.len());
-    let mut br_cursor: usize = 0;
-
-    for (rec_idx, &(rec_off, rec_len)) in records.iter().enumerate() {
+    // Records are independent once `covered` is built; collect keeps order.
+    #[cfg(feature = "parallel")]
+    let iter = records.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let iter = records.iter();
+    iter.enumerate().map(|(rec_idx, &(rec_off, rec_len))| {
         let rec_end = rec_off + rec_len;
 
         // Count backref bytes
@@ -117,10 +128,9 @@ pub fn score_records(
             0.0
         };
 
-        // Advance cursor past ops that end before this record
-        while br_cursor < br_infos.len() && br_infos[br_cursor].end <= rec_off {
-            br_cursor += 1;
-        }
+        // Skip ops that end before this record (ops are ordered and
+        // non-overlapping, so ends are sorted too)
+        let br_cursor = br_infos.partition_point(|b| b.end <= rec_off);
 
         // Collect dictionary entries referenced within this record
         let mut ref_entries: Vec<usize> = Vec::new();
@@ -144,9 +154,9 @@ pub fn score_records(
             ref_entries.iter().map(|&eid| eid as f64 / dict_size as f64).sum::<f64>()
                 / ref_entries.len() as f64
         };
-        let anomaly_score = 0.7 * coverage_score + 0.3 * rarity_score;
+        let anomaly_score = COVERAGE_WEIGHT * coverage_score + (1.0 - COVERAGE_WEIGHT) * rarity_score;
 
-        analyses.push(RecordAnalysis {
+        RecordAnalysis {
             index: rec_idx,
             offset: rec_off,
             length: rec_len,
@@ -154,11 +164,484 @@ pub fn score_records(
             literal_bytes,
             coverage: cov,
             ref_entries,
+            rarity_score,
+            bits_per_byte: 0.0,
             anomaly_score,
-        });
+        }
+    }).collect()
+}
+
+/// Like [`score_records`], with each block of records ranking the entries
+/// it references in a dictionary built from the `window` records before
+/// it ([`sliding_windows`]) rather than in `dictionary`, which covers the
+/// whole input. Coverage is unchanged, and `ref_entries` still holds IDs
+/// of `dictionary`. Ops crossing a block boundary are clipped to it.
+pub fn score_records_windowed(
+    data: &[u8],
+    ops: &[ScanOp],
+    dictionary: &[DictEntry],
+    delimiter: u8,
+    min_count: usize,
+    window: usize,
+) -> Vec<RecordAnalysis> {
+    let index = RecordIndex::new(data, delimiter);
+    let mut out = Vec::with_capacity(index.len());
+    for WindowBlock { block, window } in sliding_windows(index.len(), window) {
+        let w = index.byte_range(window);
+        let dict = build_dictionary(data, ops_within(ops, w), min_count);
+        out.extend(score_block(data, ops, &index, block, &dict, dictionary, delimiter));
+    }
+    out
+}
+
+/// Like [`score_records_windowed`], for chunks of `chunk` records, each
+/// ranking its references in a running dictionary: the previous chunk's
+/// with counts scaled by `decay` (0 rebuilds it from scratch), plus the
+/// entries of the chunk itself. Patterns that stop occurring fade out, so
+/// late records are judged by what is common lately.
+pub fn score_records_chunked(
+    data: &[u8],
+    ops: &[ScanOp],
+    dictionary: &[DictEntry],
+    delimiter: u8,
+    min_count: usize,
+    chunk: usize,
+    decay: f64,
+) -> Vec<RecordAnalysis> {
+    let index = RecordIndex::new(data, delimiter);
+    let chunk = chunk.max(1);
+    let mut running: Vec<DictEntry> = Vec::new();
+    let mut out = Vec::with_capacity(index.len());
+    for start in (0..index.len()).step_by(chunk) {
+        let block = start..(start + chunk).min(index.len());
+        let own = build_dictionary(data, ops_within(ops, index.byte_range(block.clone())), 1);
+        running = merge_dictionaries(&decay_dictionary(&running, decay, 0), &own, 0);
+        let mut dict: Vec<DictEntry> = running.iter().filter(|e| e.count >= min_count).cloned().collect();
+        rank_entries(&mut dict);
+        out.extend(score_block(data, ops, &index, block, &dict, dictionary, delimiter));
+    }
+    out
+}
+
+/// Ops starting within byte range `r`.
+fn ops_within(ops: &[ScanOp], r: Range<usize>) -> &[ScanOp] {
+    &ops[ops.partition_point(|o| o.position < r.start)..ops.partition_point(|o| o.position < r.end)]
+}
+
+/// Score records `block` of `data` with `dict`, clipping ops to the block,
+/// and map their entries to IDs of `global`.
+fn score_block(
+    data: &[u8],
+    ops: &[ScanOp],
+    index: &RecordIndex,
+    block: Range<usize>,
+    dict: &[DictEntry],
+    global: &[DictEntry],
+    delimiter: u8,
+) -> Vec<RecordAnalysis> {
+    let b = index.byte_range(block.clone());
+    let first = ops.partition_point(|o| o.position + o.length <= b.start);
+    let last = ops.partition_point(|o| o.position < b.end);
+    let b_ops: Vec<ScanOp> = ops[first..last]
+        .iter()
+        .map(|o| {
+            let start = o.position.max(b.start);
+            let end = (o.position + o.length).min(b.end);
+            ScanOp { position: start - b.start, length: end - start, ..o.clone() }
+        })
+        .collect();
+
+    let global_ids: HashMap<&[u8], usize> =
+        global.iter().map(|e| (e.content.as_slice(), e.entry_id)).collect();
+    let mut scored = score_records(&data[b.clone()], &b_ops, dict, delimiter);
+    for r in &mut scored {
+        r.index += block.start;
+        r.offset += b.start;
+        r.ref_entries = r
+            .ref_entries
+            .iter()
+            .filter_map(|&id| global_ids.get(dict[id].content.as_slice()).copied())
+            .collect();
+        r.ref_entries.sort_unstable();
+    }
+    scored
+}
+
+/// Replace each record's rarity term (e.g. with a cosine distance from
+/// [`crate::cosine`]), adjusting its anomaly score to match.
+pub fn set_rarity(records: &mut [RecordAnalysis], rarity: &[f64]) {
+    for (r, &new) in records.iter_mut().zip(rarity) {
+        r.anomaly_score += (1.0 - COVERAGE_WEIGHT) * (new - r.rarity_score);
+        r.rarity_score = new;
+    }
+}
+
+/// How broadly one dictionary entry is used across records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryUsage {
+    /// Distinct records referencing the entry.
+    pub records: usize,
+    /// First few referencing record indices.
+    pub examples: Vec<usize>,
+}
+
+/// Per-entry usage (indexed by entry ID, `dict_size` long), keeping up to
+/// `max_examples` record indices each.
+pub fn entry_usage(
+    records: &[RecordAnalysis],
+    dict_size: usize,
+    max_examples: usize,
+) -> Vec<EntryUsage> {
+    let mut usage = vec![EntryUsage::default(); dict_size];
+    for r in records {
+        for &e in &r.ref_entries {
+            if let Some(u) = usage.get_mut(e) {
+                u.records += 1;
+                if u.examples.len() < max_examples {
+                    u.examples.push(r.index);
+                }
+            }
+        }
+    }
+    usage
+}
+
+/// Count-based rarity: the mean over a record's referenced entries of
+/// `1 - ln(count) / ln(total)`, where `count` is the entry's backref count
+/// and `total` the count summed over the dictionary. Unlike the rank-based
+/// default this moves smoothly with counts, so near-ties that swap rank
+/// between runs barely change scores. Records referencing nothing get 1.0.
+pub fn count_rarity(records: &[RecordAnalysis], dict: &[DictEntry]) -> Vec<f64> {
+    let mut counts = vec![0usize; dict.iter().map(|e| e.entry_id + 1).max().unwrap_or(0)];
+    for e in dict {
+        counts[e.entry_id] = e.count;
+    }
+    let ln_total = (dict.iter().map(|e| e.count).sum::<usize>() as f64).ln();
+    records
+        .iter()
+        .map(|r| {
+            if r.ref_entries.is_empty() {
+                return 1.0;
+            }
+            if ln_total <= 0.0 {
+                return 0.0;
+            }
+            let sum: f64 = r
+                .ref_entries
+                .iter()
+                .map(|&e| {
+                    let c = counts.get(e).copied().unwrap_or(1).max(1) as f64;
+                    1.0 - c.ln() / ln_total
+                })
+                .sum();
+            sum / r.ref_entries.len() as f64
+        })
+        .collect()
+}
+
+/// Inverse-document-frequency rarity: the mean over a record's referenced
+/// entries of `ln(N / df) / ln(N)`, where `df` is how many of the `N`
+/// records reference the entry. An entry every record uses contributes 0,
+/// one used by a single record contributes 1. Records referencing nothing
+/// get 1.0, as in [`score_records`].
+pub fn idf_rarity(records: &[RecordAnalysis]) -> Vec<f64> {
+    let dims = records.iter().flat_map(|r| r.ref_entries.iter().map(|&e| e + 1)).max();
+    let mut df = vec![0usize; dims.unwrap_or(0)];
+    for r in records {
+        r.ref_entries.iter().for_each(|&e| df[e] += 1);
+    }
+    let ln_n = (records.len() as f64).ln();
+    records
+        .iter()
+        .map(|r| {
+            if r.ref_entries.is_empty() {
+                return 1.0;
+            }
+            if ln_n <= 0.0 {
+                return 0.0;
+            }
+            let sum: f64 = r.ref_entries.iter().map(|&e| (ln_n - (df[e] as f64).ln()) / ln_n).sum();
+            sum / r.ref_entries.len() as f64
+        })
+        .collect()
+}
+
+/// Store per-record bits per byte (see [`crate::entropy`]).
+pub fn set_bits_per_byte(records: &mut [RecordAnalysis], bits_per_byte: &[f64]) {
+    for (r, &bpb) in records.iter_mut().zip(bits_per_byte) {
+        r.bits_per_byte = bpb;
+    }
+}
+
+/// Replace the coverage term of each record's score with its bits per
+/// byte, scaled so 8 bits/byte (incompressible) counts as fully uncovered.
+/// The rarity term is kept.
+pub fn rescore_with_bits(records: &mut [RecordAnalysis]) {
+    for r in records {
+        let cost_score = (r.bits_per_byte / 8.0).min(1.0);
+        r.anomaly_score = COVERAGE_WEIGHT * cost_score + (1.0 - COVERAGE_WEIGHT) * r.rarity_score;
+    }
+}
+
+/// Fan scores of deduplicated records (see
+/// [`crate::records::dedup_records`]) back out to the input: record `i`
+/// gets a copy of `scored[of[i]]` with its own index and `bounds[i]` offset
+/// and length.
+pub fn expand_duplicates(
+    scored: &[RecordAnalysis],
+    of: &[usize],
+    bounds: &[(usize, usize)],
+) -> Vec<RecordAnalysis> {
+    of.iter()
+        .zip(bounds)
+        .enumerate()
+        .map(|(index, (&d, &(offset, length)))| RecordAnalysis {
+            index,
+            offset,
+            length,
+            ..scored[d].clone()
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Robust rescoring
+// ---------------------------------------------------------------------------
+
+/// Input reordered for a second, robust pass: the records not flagged by a
+/// first pass, then the flagged ones. Scanning it with
+/// [`crate::scanner::scan_with_baseline`] at `split` scans the clean records
+/// as usual and matches the flagged ones against them alone, so a large
+/// incident no longer explains itself, and a dictionary built from the
+/// ops before `split` holds none of its patterns.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CleanSplit {
+    /// Clean records, then flagged ones, each ending in the delimiter.
+    pub data: Vec<u8>,
+    /// Where the flagged records start in `data`.
+    pub split: usize,
+    /// Length of the original input.
+    input_len: usize,
+    /// `(start in data, original index, original offset)` of each record,
+    /// in `data` order.
+    starts: Vec<(usize, usize, usize)>,
+}
+
+/// Reorder `data` with the records at `flagged` indices moved to the end.
+pub fn clean_split(data: &[u8], delimiter: u8, flagged: &[usize]) -> CleanSplit {
+    let index = RecordIndex::new(data, delimiter);
+    let mut is_flagged = vec![false; index.len()];
+    for &i in flagged.iter().filter(|&&i| i < index.len()) {
+        is_flagged[i] = true;
+    }
+    let mut out = CleanSplit { input_len: data.len(), ..Default::default() };
+    for pass in [false, true] {
+        if pass {
+            out.split = out.data.len();
+        }
+        for (i, (offset, len)) in index.iter().enumerate().filter(|&(i, _)| is_flagged[i] == pass) {
+            out.starts.push((out.data.len(), i, offset));
+            out.data.extend_from_slice(&data[offset..offset + len]);
+            if data[offset..offset + len].last() != Some(&delimiter) {
+                out.data.push(delimiter);
+            }
+        }
+    }
+    out
+}
+
+impl CleanSplit {
+    /// `(start in data, original index, original offset)` of the record
+    /// holding `offset` in `data`.
+    fn record_at(&self, offset: usize) -> (usize, usize, usize) {
+        self.starts[self.starts.partition_point(|s| s.0 <= offset) - 1]
+    }
+
+    /// Records scored over `data`, back in input order with input indices
+    /// and offsets.
+    pub fn restore_records(&self, records: &mut [RecordAnalysis]) {
+        for r in records.iter_mut() {
+            let (start, index, offset) = self.record_at(r.offset);
+            r.index = index;
+            r.offset = offset + (r.offset - start);
+            r.length = r.length.min(self.input_len - r.offset);
+        }
+        records.sort_by_key(|r| r.index);
+    }
+
+    /// Ops over `data` mapped onto the input: split at record boundaries,
+    /// moved to their records' original offsets and sorted. Back-reference
+    /// distances are left as scanned.
+    pub fn restore_ops(&self, ops: &[ScanOp]) -> Vec<ScanOp> {
+        let mut out = Vec::with_capacity(ops.len() + self.starts.len());
+        for (k, &(start, _, offset)) in self.starts.iter().enumerate() {
+            let end = self.starts.get(k + 1).map_or(self.data.len(), |s| s.0);
+            let first = ops.partition_point(|o| o.position + o.length <= start);
+            for o in ops[first..].iter().take_while(|o| o.position < end) {
+                let a = o.position.max(start);
+                let b = (o.position + o.length).min(end);
+                let position = offset + (a - start);
+                let length = (b - a).min(self.input_len.saturating_sub(position));
+                if length > 0 {
+                    out.push(ScanOp { position, length, ..o.clone() });
+                }
+            }
+        }
+        out.sort_by_key(|o| o.position);
+        out
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Record spans
+// ---------------------------------------------------------------------------
+
+/// The part of one scan op that falls inside a record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub kind: OpKind,
+    /// Dictionary entry the whole back-reference resolves to, if any.
+    pub entry_id: Option<usize>,
+    /// True if the op began before this record (the span is a tail).
+    pub continued: bool,
+}
+
+/// Bytes each dictionary entry covers among one record's spans, largest
+/// first (ties by entry ID).
+pub fn entry_bytes(spans: &[Span]) -> Vec<(usize, usize)> {
+    let mut bytes: HashMap<usize, usize> = HashMap::new();
+    for sp in spans {
+        if let Some(id) = sp.entry_id {
+            *bytes.entry(id).or_default() += sp.end - sp.start;
+        }
+    }
+    let mut out: Vec<(usize, usize)> = bytes.into_iter().collect();
+    out.sort_by_key(|&(id, n)| (std::cmp::Reverse(n), id));
+    out
+}
+
+/// The literal runs among one record's spans, as ranges relative to
+/// `start`, the record's first byte: the bytes no back-reference explains.
+pub fn literal_runs(spans: &[Span], start: usize) -> Vec<Range<usize>> {
+    spans
+        .iter()
+        .filter(|sp| sp.kind == OpKind::Literal)
+        .map(|sp| sp.start - start..sp.end - start)
+        .collect()
+}
+
+/// How a record's bytes are explained, in percent of its length. The
+/// parts add up to 100.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageBreakdown {
+    /// `(entry_id, pct)` for each entry asked about.
+    pub entries: Vec<(usize, f64)>,
+    /// Back-references to entries not listed.
+    pub other_backref_pct: f64,
+    pub literal_pct: f64,
+}
+
+/// Break `r`'s coverage down by `top`, `(entry_id, bytes)` pairs as from
+/// [`entry_bytes`].
+pub fn coverage_breakdown(r: &RecordAnalysis, top: &[(usize, usize)]) -> CoverageBreakdown {
+    let pct = |n: usize| if r.length == 0 { 0.0 } else { n as f64 / r.length as f64 * 100.0 };
+    let shown: usize = top.iter().map(|e| e.1).sum();
+    CoverageBreakdown {
+        entries: top.iter().map(|&(id, n)| (id, pct(n))).collect(),
+        other_backref_pct: pct(r.backref_bytes.saturating_sub(shown)),
+        literal_pct: pct(r.literal_bytes),
+    }
+}
+
+/// Break each `(offset, length)` record into the op spans covering it.
+///
+/// Back-references are resolved to dictionary entries by content, as in
+/// `score_records`; ops crossing a record boundary are clipped.
+pub fn record_spans(
+    data: &[u8],
+    ops: &[ScanOp],
+    dictionary: &[DictEntry],
+    records: &[(usize, usize)],
+) -> Vec<Vec<Span>> {
+    let content_to_entry: HashMap<&[u8], usize> = dictionary
+        .iter()
+        .map(|e| (e.content.as_slice(), e.entry_id))
+        .collect();
+
+    records
+        .iter()
+        .map(|&(off, len)| {
+            let end = off + len;
+            let first = ops.partition_point(|o| o.position + o.length <= off);
+            ops[first..]
+                .iter()
+                .take_while(|o| o.position < end)
+                .map(|o| Span {
+                    start: o.position.max(off),
+                    end: (o.position + o.length).min(end),
+                    kind: o.kind,
+                    entry_id: match o.kind {
+                        OpKind::Backref => content_to_entry.get(o.content(data)).copied(),
+                        OpKind::Literal => None,
+                    },
+                    continued: o.position < off,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Corpus statistics
+// ---------------------------------------------------------------------------
+
+/// Per-record back-reference coverage, without dictionary lookups or
+/// scoring. Records are split as in `score_records`, so the result matches
+/// its `coverage` field; this is the cheap path for corpus summaries.
+pub fn record_coverage(data: &[u8], ops: &[ScanOp], delimiter: u8) -> Vec<f64> {
+    let mut backrefs = ops.iter().filter(|o| o.kind == OpKind::Backref).peekable();
+    let mut out = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = match memchr(delimiter, &data[start..]) {
+            Some(p) => start + p + 1,
+            None => data.len(),
+        };
+        let mut covered = 0;
+        while let Some(op) = backrefs.peek() {
+            let op_end = (op.position + op.length).min(data.len());
+            if op.position >= end {
+                break;
+            }
+            covered += op_end.min(end) - op.position.max(start);
+            if op_end > end {
+                break;
+            }
+            backrefs.next();
+        }
+        out.push(covered as f64 / (end - start) as f64);
+        start = end;
     }
+    out
+}
 
-    analyses
+/// Shannon entropy of the byte distribution, in bits per byte (0-8).
+pub fn byte_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    let n = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / n;
+            -p * p.log2()
+        })
+        .sum()
 }
 
 // ---------------------------------------------------------------------------
@@ -168,11 +651,10 @@ pub fn score_records(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::dictionary::build_dictionary;
-    use crate::scanner::{scan, DEFAULT_WINDOW, MAX_MATCH, MIN_MATCH};
+    use crate::scanner::{scan, scan_with_baseline, ScanParams};
 
     fn pipeline(data: &[u8]) -> Vec<RecordAnalysis> {
-        let ops = scan(data, DEFAULT_WINDOW, MIN_MATCH, MAX_MATCH);
+        let ops = scan(data, &ScanParams::default());
         let dict = build_dictionary(data, &ops, 1);
         score_records(data, &ops, &dict, b'\n')
     }
@@ -241,4 +723,205 @@ mod tests {
             assert_eq!(r.content(data), &data[r.offset..r.offset + r.length]);
         }
     }
+
+    #[test]
+    fn record_coverage_matches_scorer() {
+        let mut data: Vec<u8> = b"GET /api/users 200 12ms\n".repeat(30);
+        data.extend_from_slice(b"zq8#unexpected xk!\nGET /api/users 200 12ms");
+        let ops = scan(&data, &ScanParams::default());
+        let dict = build_dictionary(&data, &ops, 1);
+        let full = score_records(&data, &ops, &dict, b'\n');
+        let fast = record_coverage(&data, &ops, b'\n');
+        assert_eq!(fast.len(), full.len());
+        for (c, r) in fast.iter().zip(&full) {
+            assert!((c - r.coverage).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn entropy_bounds() {
+        assert_eq!(byte_entropy(b""), 0.0);
+        assert_eq!(byte_entropy(b"aaaa"), 0.0);
+        assert!((byte_entropy(b"abab") - 1.0).abs() < 1e-12);
+        let all: Vec<u8> = (0..=255).collect();
+        assert!((byte_entropy(&all) - 8.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn spans_tile_records() {
+        let data: Vec<u8> = b"GET /api/users 200\n".repeat(10);
+        let ops = scan(&data, &ScanParams::default());
+        let dict = build_dictionary(&data, &ops, 1);
+        let recs = [(0, 19), (19, 19), (171, 19)];
+        let spans = record_spans(&data, &ops, &dict, &recs);
+        for (&(off, len), s) in recs.iter().zip(&spans) {
+            assert_eq!(s.first().unwrap().start, off);
+            assert_eq!(s.last().unwrap().end, off + len);
+            assert!(s.windows(2).all(|w| w[0].end == w[1].start));
+        }
+        assert!(spans[0].iter().all(|s| s.kind == OpKind::Literal));
+        assert!(spans[2].iter().any(|s| s.kind == OpKind::Backref && s.entry_id.is_some()));
+        assert!(entry_bytes(&spans[0]).is_empty());
+        let top = entry_bytes(&spans[2]);
+        assert!(!top.is_empty() && top.iter().map(|e| e.1).sum::<usize>() <= 19);
+        assert!(top.windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+
+    #[test]
+    fn duplicates_share_distinct_scores() {
+        let data = b"status ok\nfail\nstatus ok\nstatus ok";
+        let d = crate::records::dedup_records(data, b'\n');
+        let scored = pipeline(&d.data);
+        let recs = expand_duplicates(&scored, &d.of, &split_records(data, b'\n'));
+        assert_eq!(recs.len(), 4);
+        assert_eq!((recs[2].index, recs[2].offset, recs[2].length), (2, 15, 10));
+        assert_eq!(recs[3].length, 9);
+        assert_eq!(recs[0].coverage, 0.0);
+        assert_eq!(recs[2].coverage, 1.0);
+        assert_eq!(recs[3].anomaly_score, recs[2].anomaly_score);
+        assert_eq!(recs[1].anomaly_score, scored[2].anomaly_score);
+    }
+
+    #[test]
+    fn windowed_scores_keep_records_and_coverage() {
+        let data: Vec<u8> = (0..200)
+            .map(|i| if i < 100 { format!("GET /api/users/{i} 200\n") } else { format!("job {i} state=done\n") })
+            .collect::<String>()
+            .into_bytes();
+        let ops = scan(&data, &ScanParams::default());
+        let dict = build_dictionary(&data, &ops, 2);
+        let whole = score_records(&data, &ops, &dict, b'\n');
+        let windowed = score_records_windowed(&data, &ops, &dict, b'\n', 2, 40);
+        assert_eq!(windowed.len(), whole.len());
+        for (w, r) in windowed.iter().zip(&whole) {
+            assert_eq!((w.index, w.offset, w.length), (r.index, r.offset, r.length));
+            assert_eq!(w.backref_bytes, r.backref_bytes);
+            assert!(w.ref_entries.iter().all(|&id| id < dict.len()));
+        }
+        // Late records rank their references among their own format's.
+        assert!(windowed[190].rarity_score < whole[190].rarity_score);
+    }
+
+    #[test]
+    fn chunked_dictionary_forgets_old_patterns() {
+        let data: Vec<u8> = (0..300)
+            .map(|i| if i < 150 { format!("GET /api/users/{i} 200\n") } else { format!("job {i} state=done\n") })
+            .collect::<String>()
+            .into_bytes();
+        let ops = scan(&data, &ScanParams::default());
+        let dict = build_dictionary(&data, &ops, 2);
+        let whole = score_records(&data, &ops, &dict, b'\n');
+        let kept = score_records_chunked(&data, &ops, &dict, b'\n', 2, 50, 1.0);
+        let rebuilt = score_records_chunked(&data, &ops, &dict, b'\n', 2, 50, 0.0);
+        assert_eq!(rebuilt.len(), whole.len());
+        for (c, r) in rebuilt.iter().zip(&whole) {
+            assert_eq!((c.index, c.offset, c.backref_bytes), (r.index, r.offset, r.backref_bytes));
+            assert!(c.ref_entries.iter().all(|&id| id < dict.len()));
+        }
+        // Records of the new format rank their references among recent
+        // patterns, the more so the faster old ones fade.
+        assert!(rebuilt[160].rarity_score < kept[160].rarity_score);
+        assert!(rebuilt[160].rarity_score < whole[160].rarity_score);
+    }
+
+    #[test]
+    fn idf_discounts_common_entries() {
+        let mut recs = pipeline(&b"GET /api/users 200\n".repeat(10));
+        for (i, r) in recs.iter_mut().enumerate() {
+            r.ref_entries = if i < 9 { vec![0] } else { vec![0, 1] };
+        }
+        recs.push(RecordAnalysis { ref_entries: vec![], ..recs[0].clone() });
+        let idf = idf_rarity(&recs);
+        assert!(idf[0] < 0.05);
+        assert!((idf[9] - (idf[0] + 1.0) / 2.0).abs() < 1e-12);
+        assert_eq!(idf[10], 1.0);
+
+        let before = recs[9].anomaly_score;
+        set_rarity(&mut recs, &idf);
+        assert_eq!(recs[9].rarity_score, idf[9]);
+        assert!((recs[9].anomaly_score - before).abs() <= 1.0 - COVERAGE_WEIGHT);
+    }
+
+    #[test]
+    fn clean_split_keeps_an_incident_from_explaining_itself() {
+        let mut data = String::new();
+        for i in 0..400 {
+            data += &match i {
+                200..=259 => "KERNEL PANIC: fatal error 0xDEADBEEF segfault in worker\n".to_string(),
+                _ => format!("2026-02-16 app: request {} handled in {}ms\n", i % 10, i % 7),
+            };
+        }
+        data += "tail without newline";
+        let data = data.as_bytes();
+        let first = pipeline(data);
+        assert!(first[230].coverage > 0.9);
+
+        let flagged: Vec<usize> = (200..260).chain([400]).collect();
+        let split = clean_split(data, b'\n', &flagged);
+        assert_eq!(split.data.len(), data.len() + 1);
+        assert!(split.data[split.split..].starts_with(b"KERNEL PANIC"));
+        let ops = scan_with_baseline(&split.data, split.split, &ScanParams::default());
+        let k = ops.partition_point(|o| o.position < split.split);
+        let dict = build_dictionary(&split.data, &ops[..k], 1);
+        let mut second = score_records(&split.data, &ops, &dict, b'\n');
+        split.restore_records(&mut second);
+
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!((a.index, a.offset, a.length), (b.index, b.offset, b.length));
+        }
+        assert!(second[230].coverage < 0.5 && second[230].anomaly_score > first[230].anomaly_score);
+        assert!(second[100].coverage > 0.9);
+
+        let restored = split.restore_ops(&ops);
+        let mut at = 0;
+        for o in &restored {
+            assert_eq!(o.position, at);
+            at += o.length;
+        }
+        assert_eq!(at, data.len());
+    }
+
+    #[test]
+    fn count_rarity_is_smooth_in_counts() {
+        let data = b"GET /api/users 200\n".repeat(10);
+        let ops = scan(&data, &ScanParams::default());
+        let mut dict = build_dictionary(&data, &ops, 1);
+        let mut recs = score_records(&data, &ops, &dict, b'\n');
+        recs[1].ref_entries = vec![dict[0].entry_id];
+        recs[2].ref_entries = vec![];
+        let usage = entry_usage(&recs, dict.len(), 2);
+        assert_eq!(usage[dict[0].entry_id].examples.first(), Some(&1));
+        assert!(usage.iter().all(|u| u.examples.len() <= 2 && u.records <= recs.len()));
+        let before = count_rarity(&recs, &dict);
+        assert_eq!(before[2], 1.0);
+        assert!((0.0..1.0).contains(&before[1]));
+        dict[0].count += 1;
+        let after = count_rarity(&recs, &dict);
+        assert!(after[1] <= before[1] && before[1] - after[1] < 0.1);
+    }
+
+    #[test]
+    fn literal_run_shows_in_spans_and_breakdown() {
+        let mut data = b"GET /api/users 200 ok\n".repeat(20);
+        let start = data.len();
+        data.extend_from_slice(b"GET /api/users ZQXJ 200 ok\n");
+        let ops = scan(&data, &ScanParams::default());
+        let dict = build_dictionary(&data, &ops, 1);
+        let recs = score_records(&data, &ops, &dict, b'\n');
+        let r = recs.last().unwrap();
+        let spans = record_spans(&data, &ops, &dict, &[(r.offset, r.length)]).remove(0);
+        assert_eq!(literal_runs(&spans, start), vec![15..19]);
+        assert_eq!(&data[start + 15..start + 19], b"ZQXJ");
+
+        // 27 bytes: 4 literal, the rest split between two entries.
+        let top = entry_bytes(&spans);
+        assert_eq!(top.iter().map(|e| e.1).collect::<Vec<_>>(), vec![15, 8]);
+        let b = coverage_breakdown(r, &top[..1]);
+        let of = |n: f64| n / 27.0 * 100.0;
+        assert_eq!(b.entries, vec![(top[0].0, of(15.0))]);
+        assert_eq!((b.other_backref_pct, b.literal_pct), (of(8.0), of(4.0)));
+        assert!((b.entries[0].1 + b.other_backref_pct + b.literal_pct - 100.0).abs() < 1e-9);
+        assert_eq!(coverage_breakdown(r, &top).entries.len(), 2);
+        assert_eq!(coverage_breakdown(r, &top).other_backref_pct, 0.0);
+    }
 }
@@ -0,0 +1,148 @@
+//! Hunting with known-bad examples.
+//!
+//! Unsupervised scores only say what is unusual. When an analyst has
+//! already confirmed a few bad lines, the rest of the input should be
+//! searched for more like them. The seeds are reduced to tokens (words of a
+//! plain line; field names and `field=value` pairs of a JSON record), each
+//! weighted by how rare it is in the input, so the tokens a seed shares with
+//! every record count for little and the ones characteristic of it count
+//! most. A record's similarity to the seeds is the cosine between its tokens
+//! and theirs, and blends into its anomaly score.
+
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "json")]
+use crate::json_analyzer::JsonRecord;
+
+// ---------------------------------------------------------------------------
+// Tokens
+// ---------------------------------------------------------------------------
+
+/// Words of a plain line: runs of letters, digits and `_`, lower-cased.
+/// Numbers alone (times, counters, addresses) are not words.
+pub fn line_tokens(line: &[u8]) -> Vec<String> {
+    line.split(|b| !(b.is_ascii_alphanumeric() || *b == b'_'))
+        .filter(|w| !w.is_empty() && !w.iter().all(u8::is_ascii_digit))
+        .map(|w| String::from_utf8_lossy(w).to_ascii_lowercase())
+        .collect()
+}
+
+/// Top-level field names of a JSON record and its `field=value` pairs; an
+/// unparseable record has none.
+#[cfg(feature = "json")]
+pub fn record_tokens(rec: &JsonRecord) -> Vec<String> {
+    let Some(fields) = &rec.fields else { return Vec::new() };
+    fields
+        .iter()
+        .flat_map(|(name, value)| [name.to_string(), format!("{name}={}", value.key())])
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// Similarity
+// ---------------------------------------------------------------------------
+
+/// Seed tokens weighted by their rarity in the input.
+#[derive(Debug, Clone, Default)]
+pub struct SeedProfile {
+    weights: HashMap<String, f64>,
+    norm: f64,
+    /// Inverse document frequency of every input token.
+    idf: HashMap<String, f64>,
+}
+
+impl SeedProfile {
+    /// Profile of `seeds` among the input's `records`, each given as its
+    /// tokens. Seed tokens that never occur in the input cannot match and
+    /// are dropped.
+    pub fn new(seeds: &[Vec<String>], records: &[Vec<String>]) -> Self {
+        let mut df: HashMap<&str, usize> = HashMap::new();
+        for r in records {
+            let distinct: HashSet<&str> = r.iter().map(String::as_str).collect();
+            for t in distinct {
+                *df.entry(t).or_default() += 1;
+            }
+        }
+        let n = records.len() as f64;
+        let idf: HashMap<String, f64> =
+            df.into_iter().map(|(t, d)| (t.to_string(), (1.0 + n / d as f64).ln())).collect();
+
+        let mut weights: HashMap<String, f64> = HashMap::new();
+        for t in seeds.iter().flat_map(|s| s.iter().collect::<HashSet<_>>()) {
+            if let Some(&w) = idf.get(t) {
+                *weights.entry(t.clone()).or_default() += w;
+            }
+        }
+        let norm = weights.values().map(|w| w * w).sum::<f64>().sqrt();
+        Self { weights, norm, idf }
+    }
+
+    /// Whether any seed token occurs in the input.
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Cosine similarity, 0-1, of a record's tokens to the seeds.
+    pub fn similarity(&self, tokens: &[String]) -> f64 {
+        if self.norm == 0.0 {
+            return 0.0;
+        }
+        let distinct: HashSet<&String> = tokens.iter().collect();
+        let (mut dot, mut norm) = (0.0, 0.0);
+        for t in distinct {
+            let w = self.idf.get(t).copied().unwrap_or(0.0);
+            norm += w * w;
+            dot += w * self.weights.get(t).copied().unwrap_or(0.0);
+        }
+        if norm == 0.0 {
+            return 0.0;
+        }
+        (dot / (norm.sqrt() * self.norm)).clamp(0.0, 1.0)
+    }
+}
+
+/// Blend `similarity` into `scores`: `(1 - weight) * score + weight *
+/// similarity`, so records like the seeds rise and are flagged first.
+pub fn blend_seed_scores(scores: &mut [f64], similarity: &[f64], weight: f64) {
+    for (s, &sim) in scores.iter_mut().zip(similarity) {
+        *s = (1.0 - weight) * *s + weight * sim;
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_like_the_seed_come_first() {
+        let lines: Vec<String> = (0..200)
+            .map(|i| match i {
+                50 => "2026-02-16 12:00:01 worker 3: disk quota exceeded on /var/data".to_string(),
+                120 => "2026-02-16 12:09:44 worker 7: disk quota exceeded on /srv/cache".to_string(),
+                150 => "2026-02-16 12:11:02 worker 1: certificate expired for api.internal".to_string(),
+                _ => format!("2026-02-16 12:{:02}:00 worker {}: request handled in {}ms", i % 60, i % 8, i % 13),
+            })
+            .collect();
+        let records: Vec<Vec<String>> = lines.iter().map(|l| line_tokens(l.as_bytes())).collect();
+        assert_eq!(records[0][..3], ["worker", "request", "handled"]);
+
+        let seed = line_tokens(b"Feb 16 worker 9: disk quota exceeded on /home");
+        let p = SeedProfile::new(&[seed], &records);
+        assert!(!p.is_empty());
+        let sim: Vec<f64> = records.iter().map(|r| p.similarity(r)).collect();
+        assert!(sim[50] > 0.6 && sim[120] > 0.6);
+        assert!(sim[150] < 0.2 && sim[0] < 0.1);
+
+        let mut scores = vec![0.5; 200];
+        scores[150] = 0.9;
+        blend_seed_scores(&mut scores, &sim, 0.5);
+        assert!(scores[50] > scores[150] && scores[150] > scores[0]);
+
+        let none = SeedProfile::new(&[line_tokens(b"zzz qqq")], &records);
+        assert!(none.is_empty() && none.similarity(&records[50]) == 0.0);
+    }
+}
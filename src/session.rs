@@ -0,0 +1,191 @@
+//! Session-level scoring.
+//!
+//! Records that share a correlation key (a `trace_id`, `request_id`, ...)
+//! form a session. Sessions are scored as units on three signals: unusual
+//! length, unusual template sequence, and anomalous members. Line-level
+//! scoring misses a request that is made of ordinary lines in an odd order
+//! or that stops halfway.
+
+use std::collections::HashMap;
+
+use crate::anomaly::median_of;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Robust z-score at which the length signal saturates at 1.0.
+const LENGTH_Z_SATURATION: f64 = 3.0;
+
+/// Pseudo-template marking the start and end of a session in transitions.
+const BOUNDARY: &str = "";
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// Score for one session.
+#[derive(Debug, Clone)]
+pub struct SessionScore {
+    pub key: String,
+    /// Member record indices, in input order.
+    pub members: Vec<usize>,
+    /// Robust z-score of the session length, scaled to 0-1.
+    pub length_score: f64,
+    /// Mean rarity `1 - P(next | prev)` of template transitions, including
+    /// the session start and end.
+    pub sequence_score: f64,
+    /// Highest member anomaly score.
+    pub member_score: f64,
+    /// `0.3 * length + 0.4 * sequence + 0.3 * member`.
+    pub score: f64,
+}
+
+// ---------------------------------------------------------------------------
+// Scoring
+// ---------------------------------------------------------------------------
+
+/// Group record indices by key, in order of first appearance. Records with
+/// no key are left out.
+pub fn sessionize(keys: &[Option<String>]) -> Vec<(String, Vec<usize>)> {
+    let mut order: Vec<(String, Vec<usize>)> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (i, k) in keys.iter().enumerate() {
+        let Some(k) = k else { continue };
+        let slot = *index.entry(k.as_str()).or_insert_with(|| {
+            order.push((k.clone(), Vec::new()));
+            order.len() - 1
+        });
+        order[slot].1.push(i);
+    }
+    order
+}
+
+/// Score sessions, most anomalous first.
+///
+/// `keys`, `templates` and `member_scores` are aligned per record.
+/// `templates` labels each record's kind (e.g. its field set); transition
+/// probabilities between consecutive templates are estimated over all
+/// sessions.
+pub fn score_sessions(
+    keys: &[Option<String>],
+    templates: &[String],
+    member_scores: &[f64],
+) -> Vec<SessionScore> {
+    let sessions = sessionize(keys);
+    if sessions.is_empty() {
+        return Vec::new();
+    }
+
+    let seq = |members: &[usize]| -> Vec<&str> {
+        std::iter::once(BOUNDARY)
+            .chain(members.iter().map(|&i| templates[i].as_str()))
+            .chain(std::iter::once(BOUNDARY))
+            .collect()
+    };
+
+    let mut from_counts: HashMap<&str, usize> = HashMap::new();
+    let mut pair_counts: HashMap<(&str, &str), usize> = HashMap::new();
+    for (_, members) in &sessions {
+        for w in seq(members).windows(2) {
+            *from_counts.entry(w[0]).or_default() += 1;
+            *pair_counts.entry((w[0], w[1])).or_default() += 1;
+        }
+    }
+
+    let lengths: Vec<f64> = sessions.iter().map(|(_, m)| m.len() as f64).collect();
+    let median = median_of(&lengths);
+    let deviations: Vec<f64> = lengths.iter().map(|l| (l - median).abs()).collect();
+    let mad = (1.4826 * median_of(&deviations)).max(1.0);
+
+    let mut out: Vec<SessionScore> = sessions
+        .into_iter()
+        .map(|(key, members)| {
+            let z = (members.len() as f64 - median).abs() / mad;
+            let length_score = (z / LENGTH_Z_SATURATION).min(1.0);
+
+            let s = seq(&members);
+            let rarity: f64 = s
+                .windows(2)
+                .map(|w| 1.0 - pair_counts[&(w[0], w[1])] as f64 / from_counts[w[0]] as f64)
+                .sum();
+            let sequence_score = rarity / (s.len() - 1) as f64;
+
+            let member_score = members
+                .iter()
+                .map(|&i| member_scores[i])
+                .fold(0.0, f64::max);
+
+            SessionScore {
+                key,
+                members,
+                length_score,
+                sequence_score,
+                member_score,
+                score: 0.3 * length_score + 0.4 * sequence_score + 0.3 * member_score,
+            }
+        })
+        .collect();
+    out.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(sessions: &[(&str, &[&str])]) -> (Vec<Option<String>>, Vec<String>) {
+        let mut keys = Vec::new();
+        let mut templates = Vec::new();
+        for (k, seq) in sessions {
+            for t in *seq {
+                keys.push(Some(k.to_string()));
+                templates.push(t.to_string());
+            }
+        }
+        (keys, templates)
+    }
+
+    #[test]
+    fn sessionize_keeps_first_seen_order_and_skips_unkeyed() {
+        let keys = vec![Some("b".into()), None, Some("a".into()), Some("b".into())];
+        let s = sessionize(&keys);
+        assert_eq!(s, vec![("b".into(), vec![0, 3]), ("a".into(), vec![2])]);
+    }
+
+    #[test]
+    fn odd_order_and_truncated_sessions_rank_first() {
+        let normal: &[&str] = &["start", "auth", "query", "done"];
+        let mut spec: Vec<(String, &[&str])> =
+            (0..30).map(|i| (format!("t{i}"), normal)).collect();
+        spec.push(("swapped".into(), &["start", "query", "auth", "done"]));
+        spec.push(("cut".into(), &["start", "auth"]));
+        let spec: Vec<(&str, &[&str])> = spec.iter().map(|(k, s)| (k.as_str(), *s)).collect();
+        let (keys, templates) = build(&spec);
+        let scores = vec![0.1; keys.len()];
+
+        let ranked = score_sessions(&keys, &templates, &scores);
+        let top: Vec<&str> = ranked[..2].iter().map(|s| s.key.as_str()).collect();
+        assert!(top.contains(&"swapped") && top.contains(&"cut"));
+        let cut = ranked.iter().find(|s| s.key == "cut").unwrap();
+        assert!(cut.length_score > 0.0);
+        assert!(ranked.last().unwrap().sequence_score < cut.sequence_score);
+    }
+
+    #[test]
+    fn anomalous_member_raises_session() {
+        let normal: &[&str] = &["a", "b"];
+        let spec: Vec<(String, &[&str])> = (0..10).map(|i| (format!("s{i}"), normal)).collect();
+        let spec: Vec<(&str, &[&str])> = spec.iter().map(|(k, s)| (k.as_str(), *s)).collect();
+        let (keys, templates) = build(&spec);
+        let mut scores = vec![0.0; keys.len()];
+        scores[7] = 0.9;
+        let ranked = score_sessions(&keys, &templates, &scores);
+        assert_eq!(ranked[0].key, "s3");
+        assert_eq!(ranked[0].member_score, 0.9);
+    }
+}
@@ -0,0 +1,128 @@
+//! Log severity detection and level-aware ordering.
+//!
+//! Two records can be equally unusual while only one of them matters: an
+//! odd ERROR line deserves attention before an odd DEBUG line. Severity is
+//! read from a level field in JSON records, or from an upper-case level
+//! token (`ERROR`, `WARN`, ...) in plain lines, and used to boost scores
+//! when ordering the report.
+
+use crate::json_analyzer::JsonRecord;
+use crate::json_value::JsonValue;
+
+/// JSON field names that hold a record's level, in lookup order.
+pub const LEVEL_FIELDS: [&str; 6] = ["level", "severity", "lvl", "log_level", "loglevel", "levelname"];
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl Severity {
+    /// The severity a level name stands for, ignoring case.
+    pub fn from_name(name: &str) -> Option<Severity> {
+        Some(match name.to_ascii_uppercase().as_str() {
+            "FATAL" | "CRITICAL" | "CRIT" | "EMERG" | "EMERGENCY" | "ALERT" | "PANIC" => Severity::Fatal,
+            "ERROR" | "ERR" | "SEVERE" => Severity::Error,
+            "WARN" | "WARNING" => Severity::Warn,
+            "INFO" | "NOTICE" | "INFORMATION" => Severity::Info,
+            "DEBUG" | "TRACE" | "FINE" | "FINER" | "FINEST" | "VERBOSE" => Severity::Debug,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Debug => "debug",
+            Severity::Info => "info",
+            Severity::Warn => "warn",
+            Severity::Error => "error",
+            Severity::Fatal => "fatal",
+        }
+    }
+
+    /// Score multiplier for `factor`: errors and worse get all of it,
+    /// warnings its square root, everything else none.
+    pub fn boost(self, factor: f64) -> f64 {
+        match self {
+            Severity::Fatal | Severity::Error => factor,
+            Severity::Warn => factor.sqrt(),
+            Severity::Info | Severity::Debug => 1.0,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Detection
+// ---------------------------------------------------------------------------
+
+/// Severity of a plain log line: the first whole-word, upper-case level
+/// token (`[ERROR]`, `WARN:`, ...). Lower-case words are ignored, since
+/// "error" in a message is not a level.
+pub fn line_severity(line: &[u8]) -> Option<Severity> {
+    line.split(|b| !b.is_ascii_alphanumeric())
+        .filter(|w| (3..=9).contains(&w.len()) && w.iter().all(u8::is_ascii_uppercase))
+        .find_map(|w| Severity::from_name(std::str::from_utf8(w).ok()?))
+}
+
+/// Severity of a JSON record, from the first of [`LEVEL_FIELDS`] it has
+/// with a string value naming a level.
+pub fn record_severity(rec: &JsonRecord) -> Option<Severity> {
+    LEVEL_FIELDS.iter().find_map(|f| match rec.get(f)? {
+        JsonValue::String(s) => Severity::from_name(s),
+        _ => None,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Ordering
+// ---------------------------------------------------------------------------
+
+/// Reorder `indices` by `scores[i]` times the boost for the record's
+/// severity, descending. Ties keep their order.
+pub fn boost_order(indices: &mut [usize], scores: &[f64], severities: &[Option<Severity>], factor: f64) {
+    let boosted = |i: usize| scores[i] * severities[i].map_or(1.0, |s| s.boost(factor));
+    indices.sort_by(|&a, &b| boosted(b).partial_cmp(&boosted(a)).unwrap());
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_analyzer::parse_json_records;
+
+    #[test]
+    fn levels_from_lines_and_fields() {
+        assert_eq!(line_severity(b"2026-02-16 12:00:01 [ERROR] disk full"), Some(Severity::Error));
+        assert_eq!(line_severity(b"Feb 16 host app: WARNING: slow"), Some(Severity::Warn));
+        assert_eq!(line_severity(b"request failed with error 500"), None);
+        assert_eq!(line_severity(b"GET /api OK"), None);
+
+        let data = b"{\"severity\":\"Fatal\"}\n{\"level\":\"debug\",\"severity\":\"ERROR\"}\n{\"level\":30}\n";
+        let recs = parse_json_records(data, b'\n');
+        let found: Vec<_> = recs.iter().map(record_severity).collect();
+        assert_eq!(found, [Some(Severity::Fatal), Some(Severity::Debug), None]);
+    }
+
+    #[test]
+    fn error_outranks_equally_anomalous_debug() {
+        let scores = [0.5, 0.5, 0.6, 0.4];
+        let sev = [Some(Severity::Debug), Some(Severity::Error), None, Some(Severity::Warn)];
+        let mut idx = vec![0, 1, 2, 3];
+        boost_order(&mut idx, &scores, &sev, 2.0);
+        assert_eq!(idx, [1, 2, 3, 0]);
+        let mut idx = vec![2, 0, 1, 3];
+        boost_order(&mut idx, &scores, &sev, 1.0);
+        assert_eq!(idx, [2, 0, 1, 3]);
+    }
+}
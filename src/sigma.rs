@@ -0,0 +1,606 @@
+//! Sigma rule matching on structured records (`sigma` feature).
+//!
+//! Statistical scoring finds what is unusual; Sigma rules describe what is
+//! known to be bad. This module loads the common subset of the Sigma format
+//! and evaluates it against JSON records, or `key=value` (logfmt) lines, so
+//! both kinds of finding come out of one pass.
+//!
+//! Supported: `title`, `id`, `level`, and a `detection` block of named
+//! selections plus a `condition`.
+//!
+//! - A selection is a map of `field|modifier...` to a value or a list of
+//!   values (any of them; all with `|all`). All fields must match. A list of
+//!   such maps matches if any does. A list of plain values is a keyword
+//!   search over the whole line.
+//! - Modifiers: `contains`, `startswith`, `endswith`, `re`, `all`. Values
+//!   match case-insensitively and may use `*` and `?` wildcards; `null`
+//!   matches a missing or null field.
+//! - Conditions: selection names, `and`, `or`, `not`, parentheses, and
+//!   `1 of`/`all of` a name pattern (`sel*`) or `them`.
+//!
+//! Fields are top-level names; `a.b` also reaches field `b` of an object
+//! field `a`. Aggregations (`| count() by ...`) and other modifiers are
+//! rejected when loading.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use regex::{Regex, RegexBuilder};
+use serde_yaml_ng::{Mapping, Value};
+
+use crate::json_value::{parse_record, JsonObject, JsonValue};
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// One Sigma rule.
+#[derive(Debug, Clone)]
+pub struct SigmaRule {
+    pub title: String,
+    pub id: Option<String>,
+    /// `informational`, `low`, `medium`, `high` or `critical`, as written.
+    pub level: Option<String>,
+    selections: BTreeMap<String, Selection>,
+    condition: Condition,
+}
+
+/// Rules in load order.
+#[derive(Debug, Clone, Default)]
+pub struct SigmaRules {
+    pub rules: Vec<SigmaRule>,
+}
+
+/// A record matched by a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigmaHit {
+    /// Position of the record in the input, as passed to [`SigmaRules::hits`].
+    pub record: usize,
+    /// Index of the rule in [`SigmaRules::rules`].
+    pub rule: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Selection {
+    /// Any of these maps; each needs all of its fields to match.
+    Maps(Vec<Vec<FieldMatch>>),
+    /// Any of these patterns somewhere in the line.
+    Keywords(Vec<Regex>),
+}
+
+#[derive(Debug, Clone)]
+struct FieldMatch {
+    field: String,
+    /// `None` matches a missing or null field.
+    patterns: Vec<Option<Regex>>,
+    all: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Selection(String),
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    /// `1 of` (`all == false`) or `all of` these selections.
+    Of { all: bool, names: Vec<String> },
+}
+
+// ---------------------------------------------------------------------------
+// Loading
+// ---------------------------------------------------------------------------
+
+impl SigmaRules {
+    /// Parse one or more rules (YAML documents separated by `---`).
+    pub fn parse(yaml: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for doc in documents(yaml) {
+            match serde_yaml_ng::from_str(&doc).map_err(|e| e.to_string())? {
+                Value::Null => {}
+                Value::Mapping(m) => rules.push(parse_rule(&m)?),
+                _ => return Err("expected a rule mapping".into()),
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    /// Load a rule file, or every `.yml`/`.yaml` file in a directory (by
+    /// name). Errors name the file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let files = if path.is_dir() {
+            let mut files: Vec<_> = fs::read_dir(path)
+                .map_err(|e| format!("{}: {e}", path.display()))?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|x| x == "yml" || x == "yaml"))
+                .collect();
+            files.sort();
+            files
+        } else {
+            vec![path.to_path_buf()]
+        };
+        let mut all = Self::default();
+        for f in files {
+            let text = fs::read_to_string(&f).map_err(|e| format!("{}: {e}", f.display()))?;
+            let rules = Self::parse(&text).map_err(|e| format!("{}: {e}", f.display()))?;
+            all.rules.extend(rules.rules);
+        }
+        Ok(all)
+    }
+}
+
+/// The YAML documents of a file, split at `---` lines.
+fn documents(yaml: &str) -> Vec<String> {
+    let mut docs = vec![String::new()];
+    for line in yaml.lines() {
+        if line.trim_end() == "---" {
+            docs.push(String::new());
+        } else {
+            let doc = docs.last_mut().unwrap();
+            doc.push_str(line);
+            doc.push('\n');
+        }
+    }
+    docs
+}
+
+fn parse_rule(m: &Mapping) -> Result<SigmaRule, String> {
+    let title = m.get("title").and_then(Value::as_str).unwrap_or("untitled").to_string();
+    let err = |msg: String| format!("rule '{title}': {msg}");
+    let text = |key: &str| m.get(key).and_then(Value::as_str).map(str::to_string);
+    let Some(Value::Mapping(detection)) = m.get("detection") else {
+        return Err(err("missing detection".into()));
+    };
+
+    let mut selections = BTreeMap::new();
+    let mut condition = None;
+    for (k, v) in detection {
+        let name = k.as_str().ok_or_else(|| err("detection keys must be strings".into()))?;
+        if name == "condition" {
+            let c = match v {
+                Value::String(c) => c.as_str(),
+                Value::Sequence(list) if list.len() == 1 => list[0].as_str().unwrap_or(""),
+                _ => return Err(err("condition must be one string".into())),
+            };
+            condition = Some(c.to_string());
+        } else if name != "timeframe" {
+            let sel = parse_selection(v).map_err(|e| err(format!("{name}: {e}")))?;
+            selections.insert(name.to_string(), sel);
+        }
+    }
+    let condition = condition.ok_or_else(|| err("missing condition".into()))?;
+    let names: Vec<&str> = selections.keys().map(String::as_str).collect();
+    let condition = parse_condition(&condition, &names).map_err(|e| err(format!("condition: {e}")))?;
+    Ok(SigmaRule { title: title.clone(), id: text("id"), level: text("level"), selections, condition })
+}
+
+fn parse_selection(v: &Value) -> Result<Selection, String> {
+    match v {
+        Value::Mapping(m) => Ok(Selection::Maps(vec![parse_field_map(m)?])),
+        Value::Sequence(list) if list.iter().all(|v| matches!(v, Value::Mapping(_))) => list
+            .iter()
+            .map(|v| match v {
+                Value::Mapping(m) => parse_field_map(m),
+                _ => unreachable!(),
+            })
+            .collect::<Result<_, _>>()
+            .map(Selection::Maps),
+        Value::Sequence(list) => list
+            .iter()
+            .map(|v| {
+                let s = scalar(v).ok_or("keywords must be scalars")?;
+                value_regex(&s, "contains")
+            })
+            .collect::<Result<_, _>>()
+            .map(Selection::Keywords),
+        _ => Err("expected a map or a list".into()),
+    }
+}
+
+fn parse_field_map(m: &Mapping) -> Result<Vec<FieldMatch>, String> {
+    let mut out = Vec::new();
+    for (k, v) in m {
+        let key = k.as_str().ok_or("field names must be strings")?;
+        let mut parts = key.split('|');
+        let field = parts.next().unwrap_or_default().to_string();
+        let mut how = "equals";
+        let mut all = false;
+        for modifier in parts {
+            match modifier {
+                "contains" | "startswith" | "endswith" | "re" => how = modifier,
+                "all" => all = true,
+                _ => return Err(format!("unsupported modifier '{modifier}'")),
+            }
+        }
+        let values = match v {
+            Value::Sequence(list) => list.iter().collect(),
+            v => vec![v],
+        };
+        let patterns = values
+            .into_iter()
+            .map(|v| match v {
+                Value::Null => Ok(None),
+                v => {
+                    let s = scalar(v).ok_or_else(|| format!("{field}: values must be scalars"))?;
+                    value_regex(&s, how).map(Some)
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        out.push(FieldMatch { field, patterns, all });
+    }
+    Ok(out)
+}
+
+/// A Sigma value as an anchored, case-insensitive regex (`re` values are
+/// used as written).
+fn value_regex(value: &str, how: &str) -> Result<Regex, String> {
+    if how == "re" {
+        return Regex::new(value).map_err(|e| e.to_string());
+    }
+    let mut pattern = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            '\\' => match chars.next() {
+                Some(e @ ('*' | '?' | '\\')) => pattern.push_str(&regex::escape(&e.to_string())),
+                Some(e) => pattern.push_str(&regex::escape(&format!("\\{e}"))),
+                None => pattern.push_str(r"\\"),
+            },
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    let pattern = match how {
+        "contains" => format!(".*{pattern}.*"),
+        "startswith" => format!("{pattern}.*"),
+        "endswith" => format!(".*{pattern}"),
+        _ => pattern,
+    };
+    RegexBuilder::new(&format!("^(?:{pattern})$"))
+        .case_insensitive(true)
+        .dot_matches_new_line(true)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+fn scalar(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Conditions
+// ---------------------------------------------------------------------------
+
+fn parse_condition(text: &str, names: &[&str]) -> Result<Condition, String> {
+    if text.contains('|') {
+        return Err("aggregations are not supported".into());
+    }
+    let spaced = text.replace('(', " ( ").replace(')', " ) ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    let mut p = ConditionParser { tokens: &tokens, pos: 0, names };
+    let c = p.or()?;
+    match p.tokens.get(p.pos) {
+        None => Ok(c),
+        Some(t) => Err(format!("unexpected '{t}'")),
+    }
+}
+
+struct ConditionParser<'a> {
+    tokens: &'a [&'a str],
+    pos: usize,
+    names: &'a [&'a str],
+}
+
+impl ConditionParser<'_> {
+    fn peek_is(&self, word: &str) -> bool {
+        self.tokens.get(self.pos).is_some_and(|t| t.eq_ignore_ascii_case(word))
+    }
+
+    fn next(&mut self) -> Result<&str, String> {
+        let t = self.tokens.get(self.pos).ok_or("unexpected end")?;
+        self.pos += 1;
+        Ok(t)
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        let mut terms = vec![self.and()?];
+        while self.peek_is("or") {
+            self.pos += 1;
+            terms.push(self.and()?);
+        }
+        Ok(if terms.len() == 1 { terms.pop().unwrap() } else { Condition::Or(terms) })
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        let mut terms = vec![self.not()?];
+        while self.peek_is("and") {
+            self.pos += 1;
+            terms.push(self.not()?);
+        }
+        Ok(if terms.len() == 1 { terms.pop().unwrap() } else { Condition::And(terms) })
+    }
+
+    fn not(&mut self) -> Result<Condition, String> {
+        if self.peek_is("not") {
+            self.pos += 1;
+            return Ok(Condition::Not(Box::new(self.not()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Condition, String> {
+        let t = self.next()?.to_string();
+        if t == "(" {
+            let c = self.or()?;
+            return match self.next()? {
+                ")" => Ok(c),
+                t => Err(format!("expected ')', found '{t}'")),
+            };
+        }
+        if self.peek_is("of") {
+            let all = match t.to_ascii_lowercase().as_str() {
+                "all" => true,
+                "1" | "any" => false,
+                _ => return Err(format!("'{t} of' is not supported; use '1 of' or 'all of'")),
+            };
+            self.pos += 1;
+            let pattern = self.next()?.to_string();
+            let names: Vec<String> = self
+                .names
+                .iter()
+                .filter(|n| pattern == "them" || glob_match(&pattern, n))
+                .map(|n| n.to_string())
+                .collect();
+            if names.is_empty() {
+                return Err(format!("no selection matches '{pattern}'"));
+            }
+            return Ok(Condition::Of { all, names });
+        }
+        if !self.names.contains(&t.as_str()) {
+            return Err(format!("unknown selection '{t}'"));
+        }
+        Ok(Condition::Selection(t))
+    }
+}
+
+/// `*` wildcard match for selection name patterns.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((head, tail)) => {
+            let Some(rest) = name.strip_prefix(head) else { return false };
+            (0..=rest.len()).any(|i| rest.is_char_boundary(i) && glob_match(tail, &rest[i..]))
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Matching
+// ---------------------------------------------------------------------------
+
+/// A record's fields as text (`None` for null), from a JSON object or a
+/// logfmt line, plus the line itself for keyword searches.
+struct Event<'a> {
+    line: &'a str,
+    fields: HashMap<String, Option<String>>,
+}
+
+impl<'a> Event<'a> {
+    fn new(line: &'a str) -> Self {
+        let fields = match parse_record(line) {
+            Ok(Some(object)) => object_fields(&object, ""),
+            _ => parse_logfmt(line),
+        };
+        Event { line, fields }
+    }
+}
+
+/// Fields of a JSON object, with nested objects also reachable by dotted
+/// names.
+fn object_fields(object: &JsonObject, prefix: &str) -> HashMap<String, Option<String>> {
+    let mut out = HashMap::new();
+    for (k, v) in object {
+        let name = format!("{prefix}{k}");
+        if let JsonValue::Object(raw) = v {
+            if let Ok(Some(inner)) = parse_record(raw) {
+                out.extend(object_fields(&inner, &format!("{name}.")));
+            }
+        }
+        let text = match v {
+            JsonValue::Null => None,
+            v => Some(v.key().into_owned()),
+        };
+        out.insert(name, text);
+    }
+    out
+}
+
+/// `key=value` and `key="quoted value"` pairs of a logfmt line; bare words
+/// are skipped.
+fn parse_logfmt(line: &str) -> HashMap<String, Option<String>> {
+    let mut out = HashMap::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let end = rest.find([' ', '=']).unwrap_or(rest.len());
+        let (key, after) = rest.split_at(end);
+        let Some(after) = after.strip_prefix('=') else {
+            rest = after.trim_start();
+            continue;
+        };
+        let (value, next) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut close = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            close = i + 1;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                (value, &quoted[close..])
+            }
+            None => {
+                let end = after.find(' ').unwrap_or(after.len());
+                (after[..end].to_string(), &after[end..])
+            }
+        };
+        if !key.is_empty() {
+            out.insert(key.to_string(), Some(value));
+        }
+        rest = next.trim_start();
+    }
+    out
+}
+
+impl FieldMatch {
+    fn matches(&self, event: &Event) -> bool {
+        let value = event.fields.get(&self.field).and_then(Option::as_deref);
+        let one = |p: &Option<Regex>| match (p, value) {
+            (None, v) => v.is_none(),
+            (Some(re), Some(v)) => re.is_match(v),
+            (Some(_), None) => false,
+        };
+        if self.all {
+            self.patterns.iter().all(one)
+        } else {
+            self.patterns.iter().any(one)
+        }
+    }
+}
+
+impl Selection {
+    fn matches(&self, event: &Event) -> bool {
+        match self {
+            Selection::Maps(maps) => maps.iter().any(|m| m.iter().all(|f| f.matches(event))),
+            Selection::Keywords(words) => words.iter().any(|w| w.is_match(event.line)),
+        }
+    }
+}
+
+impl SigmaRule {
+    fn eval(&self, c: &Condition, event: &Event) -> bool {
+        let sel = |name: &String| self.selections[name].matches(event);
+        match c {
+            Condition::Selection(name) => sel(name),
+            Condition::Not(c) => !self.eval(c, event),
+            Condition::And(cs) => cs.iter().all(|c| self.eval(c, event)),
+            Condition::Or(cs) => cs.iter().any(|c| self.eval(c, event)),
+            Condition::Of { all: true, names } => names.iter().all(sel),
+            Condition::Of { all: false, names } => names.iter().any(sel),
+        }
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        self.eval(&self.condition, event)
+    }
+}
+
+impl SigmaRules {
+    /// Indices of the rules matching one record's raw line.
+    pub fn matching(&self, line: &[u8]) -> Vec<usize> {
+        let line = String::from_utf8_lossy(line);
+        let event = Event::new(line.trim_end());
+        (0..self.rules.len()).filter(|&i| self.rules[i].matches(&event)).collect()
+    }
+
+    /// Every rule hit over a sequence of records, in record order.
+    pub fn hits<'d>(&self, records: impl IntoIterator<Item = &'d [u8]>) -> Vec<SigmaHit> {
+        records
+            .into_iter()
+            .enumerate()
+            .flat_map(|(record, line)| {
+                self.matching(line).into_iter().map(move |rule| SigmaHit { record, rule })
+            })
+            .collect()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+title: Failed logon burst source
+id: 5a1b
+level: high
+detection:
+  selection:
+    EventID: 4625
+    LogonType:
+      - 3
+      - 10
+  filter:
+    src.ip|startswith: '10.'
+  condition: selection and not filter
+---
+title: Suspicious shell
+level: medium
+detection:
+  sel_cmd:
+    cmd|contains|all:
+      - curl
+      - '| sh'
+  sel_user:
+    - user: root
+    - user|endswith: '$'
+  keywords:
+    - 'mimikatz'
+  condition: all of sel_* or keywords
+"#;
+
+    fn titles(rules: &SigmaRules, line: &str) -> Vec<String> {
+        rules.matching(line.as_bytes()).into_iter().map(|i| rules.rules[i].title.clone()).collect()
+    }
+
+    #[test]
+    fn json_and_logfmt_records_match() {
+        let rules = SigmaRules::parse(RULES).unwrap();
+        assert_eq!(rules.rules.len(), 2);
+        assert_eq!((rules.rules[0].id.as_deref(), rules.rules[0].level.as_deref()), (Some("5a1b"), Some("high")));
+
+        assert_eq!(titles(&rules, r#"{"EventID":4625,"LogonType":3,"src":{"ip":"203.0.113.9"}}"#),
+            ["Failed logon burst source"]);
+        assert!(titles(&rules, r#"{"EventID":4625,"LogonType":3,"src":{"ip":"10.0.0.1"}}"#).is_empty());
+        assert!(titles(&rules, r#"{"EventID":4625,"LogonType":2}"#).is_empty());
+
+        assert_eq!(titles(&rules, r#"user=ROOT cmd="CURL http://x | sh" pid=1"#), ["Suspicious shell"]);
+        assert!(titles(&rules, r#"user=root cmd="curl http://x""#).is_empty());
+        assert_eq!(titles(&rules, "plain line running Mimikatz.exe"), ["Suspicious shell"]);
+
+        let lines: Vec<&[u8]> = vec![b"x=1", b"host$ ran mimikatz", br#"{"EventID":"4625","LogonType":10}"#];
+        let hits = rules.hits(lines);
+        assert_eq!(hits, [SigmaHit { record: 1, rule: 1 }, SigmaHit { record: 2, rule: 0 }]);
+    }
+
+    #[test]
+    fn wildcards_nulls_and_errors() {
+        let rules = SigmaRules::parse(
+            "title: t\ndetection:\n  a:\n    path: 'c:\\w*\\cmd.ex?'\n    parent: null\n  condition: a\n",
+        )
+        .unwrap();
+        assert_eq!(titles(&rules, r#"{"path":"c:\\Windows\\cmd.exe"}"#), ["t"]);
+        assert!(titles(&rules, r#"{"path":"c:\\Windows\\cmd.exe","parent":"x"}"#).is_empty());
+        assert_eq!(titles(&rules, r#"{"path":"c:\\Windows\\cmd.exe","parent":null}"#), ["t"]);
+
+        let err = |y: &str| SigmaRules::parse(y).unwrap_err();
+        assert!(err("title: t\ndetection:\n  a: {x: 1}\n  condition: a | count() > 5\n").contains("aggregations"));
+        assert!(err("title: t\ndetection:\n  a: {x: 1}\n  condition: b\n").contains("unknown selection 'b'"));
+        assert!(err("title: t\ndetection:\n  a: {x|base64: 1}\n  condition: a\n").contains("'base64'"));
+        assert!(err("title: t\ndetection:\n  a: {x: 1}\n").contains("missing condition"));
+    }
+}
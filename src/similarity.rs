@@ -0,0 +1,128 @@
+//! Normalized compression distance between records.
+//!
+//! `NCD(x, y) = (C(xy) - min(C(x), C(y))) / max(C(x), C(y))`, where `C` is
+//! the deflate size with the corpus preset dictionary loaded
+//! ([`crate::dictionary::zlib_preset`]). Priming with the dictionary means
+//! boilerplate shared by every record is already "known" and costs nearly
+//! nothing, so the distance reflects what two records share beyond the
+//! corpus norm. Values are near 0 for near-duplicates and near 1 (slightly
+//! above, in practice) for unrelated records.
+
+use std::io;
+
+use flate2::{Compress, Compression};
+
+use crate::savings::deflate_len;
+
+// ---------------------------------------------------------------------------
+// Distance
+// ---------------------------------------------------------------------------
+
+/// Compressed sizes with a fixed preset dictionary.
+pub struct NcdCompressor {
+    c: Compress,
+    dict: Vec<u8>,
+    buf: Vec<u8>,
+}
+
+impl NcdCompressor {
+    pub fn new(dict: &[u8]) -> Self {
+        Self {
+            c: Compress::new(Compression::best(), false),
+            dict: dict.to_vec(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Compressed size of `x`.
+    pub fn size(&mut self, x: &[u8]) -> io::Result<usize> {
+        deflate_len(&mut self.c, x, &self.dict)
+    }
+
+    /// Compressed size of `x` followed by `y`.
+    pub fn pair_size(&mut self, x: &[u8], y: &[u8]) -> io::Result<usize> {
+        self.buf.clear();
+        self.buf.extend_from_slice(x);
+        self.buf.extend_from_slice(y);
+        deflate_len(&mut self.c, &self.buf, &self.dict)
+    }
+
+    /// NCD of `x` and `y`.
+    pub fn distance(&mut self, x: &[u8], y: &[u8]) -> io::Result<f64> {
+        let (cx, cy) = (self.size(x)?, self.size(y)?);
+        Ok(ncd(cx, cy, self.pair_size(x, y)?))
+    }
+}
+
+/// NCD from the three compressed sizes.
+pub fn ncd(cx: usize, cy: usize, cxy: usize) -> f64 {
+    let (lo, hi) = (cx.min(cy), cx.max(cy));
+    if hi == 0 {
+        return 0.0;
+    }
+    cxy.saturating_sub(lo) as f64 / hi as f64
+}
+
+/// The `k` records nearest to record `target` by NCD, closest first, as
+/// `(record index, distance)`. Records are `(offset, length)` into `data`;
+/// the target itself is excluded.
+pub fn nearest_records(
+    data: &[u8],
+    records: &[(usize, usize)],
+    target: usize,
+    dict: &[u8],
+    k: usize,
+) -> io::Result<Vec<(usize, f64)>> {
+    let mut comp = NcdCompressor::new(dict);
+    let (toff, tlen) = records[target];
+    let x = &data[toff..toff + tlen];
+    let cx = comp.size(x)?;
+    let mut out = Vec::with_capacity(records.len().saturating_sub(1));
+    for (i, &(off, len)) in records.iter().enumerate() {
+        if i == target {
+            continue;
+        }
+        let y = &data[off..off + len];
+        let cy = comp.size(y)?;
+        out.push((i, ncd(cx, cy, comp.pair_size(x, y)?)));
+    }
+    out.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.cmp(&b.0)));
+    out.truncate(k);
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scorer::split_records;
+
+    #[test]
+    fn ncd_formula() {
+        assert_eq!(ncd(0, 0, 0), 0.0);
+        assert_eq!(ncd(10, 20, 25), 0.75);
+        assert_eq!(ncd(10, 10, 5), 0.0);
+    }
+
+    #[test]
+    fn nearest_finds_near_duplicates() {
+        let data = b"user alice logged in from 10.0.0.1\n\
+            disk /dev/sda1 at 91% capacity on host db-3\n\
+            user alice logged in from 10.0.0.2\n\
+            cron job backup finished in 42s\n\
+            disk /dev/sda1 at 93% capacity on host db-3\n";
+        let recs = split_records(data, b'\n');
+        let near = nearest_records(data, &recs, 1, b"", 10).unwrap();
+        assert_eq!(near.len(), 4);
+        assert_eq!(near[0].0, 4);
+        assert!(near.windows(2).all(|w| w[0].1 <= w[1].1));
+
+        let mut comp = NcdCompressor::new(b"");
+        let same = comp.distance(&data[..35], &data[..35]).unwrap();
+        let diff = comp.distance(&data[..35], &data[recs[3].0..]).unwrap();
+        assert!(same < diff);
+    }
+}
@@ -0,0 +1,153 @@
+//! Value-transition anomalies for a state field.
+//!
+//! A field such as `status` moves through states (`PENDING`, `RUNNING`,
+//! `TERMINATED`). Each value can be common while a step between two of
+//! them is not: `TERMINATED` followed by `RUNNING` is made of ordinary
+//! records. Transition probabilities between consecutive values are
+//! learned, per session or over the whole input, and improbable steps are
+//! flagged.
+
+use std::collections::HashMap;
+
+use crate::session::sessionize;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Transitions less likely than this, given the state they leave, are
+/// improbable.
+const MAX_TRANSITION_PROBABILITY: f64 = 0.01;
+
+/// States left fewer times than this have no reliable transition
+/// probabilities and are not judged.
+const MIN_FROM_COUNT: usize = 50;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// An improbable step between consecutive values of the state field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition {
+    /// Record holding the new state, and the one holding the previous.
+    pub index: usize,
+    pub prev_index: usize,
+    pub from: String,
+    pub to: String,
+    /// `P(to | from)` over all learned transitions.
+    pub probability: f64,
+    /// Times `from` was followed by any state.
+    pub from_count: usize,
+}
+
+// ---------------------------------------------------------------------------
+// Detection
+// ---------------------------------------------------------------------------
+
+/// Flag improbable transitions between consecutive values of a state field.
+///
+/// `states` holds the field's value per record (`None` where absent). With
+/// `sessions` (correlation keys aligned per record), transitions are taken
+/// between consecutive records of the same session, and records without a
+/// key are skipped; otherwise between consecutive records that have the
+/// field. Sorted by probability, then record index.
+pub fn improbable_transitions(
+    states: &[Option<String>],
+    sessions: Option<&[Option<String>]>,
+) -> Vec<Transition> {
+    let sequences: Vec<Vec<usize>> = match sessions {
+        Some(keys) => sessionize(keys).into_iter().map(|(_, members)| members).collect(),
+        None => vec![(0..states.len()).collect()],
+    };
+    let steps: Vec<(usize, usize)> = sequences
+        .iter()
+        .flat_map(|members| {
+            let present: Vec<usize> =
+                members.iter().copied().filter(|&i| states[i].is_some()).collect();
+            present.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>()
+        })
+        .collect();
+    let state = |i: usize| states[i].as_deref().unwrap();
+
+    let mut from_counts: HashMap<&str, usize> = HashMap::new();
+    let mut pair_counts: HashMap<(&str, &str), usize> = HashMap::new();
+    for &(a, b) in &steps {
+        *from_counts.entry(state(a)).or_default() += 1;
+        *pair_counts.entry((state(a), state(b))).or_default() += 1;
+    }
+
+    let mut out: Vec<Transition> = steps
+        .iter()
+        .filter_map(|&(a, b)| {
+            let from_count = from_counts[state(a)];
+            let probability = pair_counts[&(state(a), state(b))] as f64 / from_count as f64;
+            (from_count >= MIN_FROM_COUNT && probability < MAX_TRANSITION_PROBABILITY).then(|| {
+                Transition {
+                    index: b,
+                    prev_index: a,
+                    from: state(a).to_string(),
+                    to: state(b).to_string(),
+                    probability,
+                    from_count,
+                }
+            })
+        })
+        .collect();
+    out.sort_by(|a, b| {
+        a.probability
+            .partial_cmp(&b.probability)
+            .unwrap()
+            .then_with(|| a.index.cmp(&b.index))
+    });
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn some(v: &str) -> Option<String> {
+        Some(v.to_string())
+    }
+
+    #[test]
+    fn global_order_flags_rare_step() {
+        let cycle = ["PENDING", "RUNNING", "TERMINATED"];
+        let mut states: Vec<Option<String>> = (0..600).map(|i| some(cycle[i % 3])).collect();
+        // A skipped PENDING: TERMINATED -> RUNNING. Records without the
+        // field do not break the sequence.
+        states.remove(300);
+        states.insert(150, None);
+
+        let t = improbable_transitions(&states, None);
+        assert_eq!(t.len(), 1);
+        assert_eq!((t[0].from.as_str(), t[0].to.as_str()), ("TERMINATED", "RUNNING"));
+        assert_eq!((t[0].prev_index, t[0].index), (300, 301));
+        assert_eq!(t[0].from_count, 199);
+        assert!((t[0].probability - 1.0 / 199.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sessions_are_followed_separately() {
+        // Two interleaved jobs, each alternating A and B; the input as a
+        // whole reads A A B B A A ...
+        let mut states = Vec::new();
+        let mut keys = Vec::new();
+        for i in 0..400 {
+            states.push(some(if (i / 2) % 2 == 0 { "A" } else { "B" }));
+            keys.push(some(if i % 2 == 0 { "job1" } else { "job2" }));
+        }
+        assert!(improbable_transitions(&states, Some(&keys)).is_empty());
+        // job1 goes B -> C; C is too rare to judge what follows it.
+        states[200] = some("C");
+        let t = improbable_transitions(&states, Some(&keys));
+        assert_eq!(t.len(), 1);
+        assert_eq!((t[0].from.as_str(), t[0].to.as_str()), ("B", "C"));
+        assert_eq!((t[0].prev_index, t[0].index), (198, 200));
+    }
+}
@@ -0,0 +1,225 @@
+//! Schema evolution through the input.
+//!
+//! A deploy that adds a field, or a code path that stops logging one,
+//! shows up as a point in the file where the schema changed: "`retry_count`
+//! first appears at record 190233" is often the whole diagnosis. The first
+//! and last record carrying each field and each value are tracked, and
+//! those that start or stop partway through the input, and are common
+//! while they last, are reported.
+
+use std::collections::HashMap;
+
+use crate::json_analyzer::JsonRecord;
+
+// ---------------------------------------------------------------------------
+// Constants
+// ---------------------------------------------------------------------------
+
+/// Starting or stopping within this share of either end of the input (of
+/// the field's occurrences, for values) counts as present throughout.
+const EDGE_SHARE: f64 = 0.05;
+
+/// Share of records between the first and last sighting that must carry
+/// the field (of records carrying the field, for values) for a change to
+/// be reported; sporadic fields are not schema changes.
+const MIN_DENSITY: f64 = 0.5;
+
+/// Fields and values seen fewer times than this are not judged.
+const MIN_OCCURRENCES: usize = 20;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+/// Where a field or value was seen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Indices of the first and last records carrying it.
+    pub first: usize,
+    pub last: usize,
+    pub count: usize,
+    /// Positions of the first and last sighting among all records (for a
+    /// field) or among the records carrying the field (for a value).
+    first_rank: usize,
+    last_rank: usize,
+}
+
+impl Span {
+    fn new(index: usize, rank: usize) -> Self {
+        Span { first: index, last: index, count: 0, first_rank: rank, last_rank: rank }
+    }
+
+    fn see(&mut self, index: usize, rank: usize) {
+        self.last = index;
+        self.last_rank = rank;
+        self.count += 1;
+    }
+
+    /// Share of the records it spans that carry it.
+    pub fn density(&self) -> f64 {
+        self.count as f64 / (self.last_rank - self.first_rank + 1) as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Appeared,
+    Disappeared,
+}
+
+impl ChangeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeKind::Appeared => "appeared",
+            ChangeKind::Disappeared => "disappeared",
+        }
+    }
+}
+
+/// A field or value that starts or stops partway through the input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaChange {
+    pub field: String,
+    /// `None` when the field itself changed.
+    pub value: Option<String>,
+    pub kind: ChangeKind,
+    /// First record carrying it (appeared) or last (disappeared).
+    pub index: usize,
+    pub span: Span,
+}
+
+/// First and last sightings of every field and value.
+#[derive(Debug, Default)]
+pub struct SchemaTimeline {
+    pub total_records: usize,
+    pub fields: HashMap<String, Span>,
+    /// Per field, its values by value key.
+    pub values: HashMap<String, HashMap<String, Span>>,
+}
+
+// ---------------------------------------------------------------------------
+// Tracking
+// ---------------------------------------------------------------------------
+
+/// Track the first and last record index of every top-level field and
+/// value key in `records`.
+pub fn schema_timeline(records: &[JsonRecord]) -> SchemaTimeline {
+    let mut t = SchemaTimeline { total_records: records.len(), ..Default::default() };
+    for (i, rec) in records.iter().enumerate() {
+        let Some(fields) = &rec.fields else { continue };
+        for (name, value) in fields {
+            let field = match t.fields.get_mut(&**name) {
+                Some(span) => span,
+                None => t.fields.entry(name.to_string()).or_insert(Span::new(i, i)),
+            };
+            let rank = field.count;
+            field.see(i, i);
+            let values = match t.values.get_mut(&**name) {
+                Some(v) => v,
+                None => t.values.entry(name.to_string()).or_default(),
+            };
+            let key = value.key();
+            let span = match values.get_mut(&*key) {
+                Some(span) => span,
+                None => values.entry(key.into_owned()).or_insert(Span::new(i, rank)),
+            };
+            span.see(i, rank);
+        }
+    }
+    t
+}
+
+impl SchemaTimeline {
+    /// Fields and values that start or stop away from the ends of the
+    /// input and are common while they last, sorted by record index.
+    pub fn changes(&self) -> Vec<SchemaChange> {
+        let mut out = Vec::new();
+        for (field, fspan) in &self.fields {
+            out.extend(span_changes(field, None, fspan, self.total_records));
+            if fspan.count < MIN_OCCURRENCES {
+                continue;
+            }
+            for (value, vspan) in &self.values[field] {
+                out.extend(span_changes(field, Some(value), vspan, fspan.count));
+            }
+        }
+        out.sort_by(|a, b| {
+            a.index
+                .cmp(&b.index)
+                .then_with(|| a.field.cmp(&b.field))
+                .then_with(|| a.value.cmp(&b.value))
+        });
+        out
+    }
+}
+
+/// Changes for one span out of `total` ranks.
+fn span_changes(field: &str, value: Option<&String>, span: &Span, total: usize) -> Vec<SchemaChange> {
+    if span.count < MIN_OCCURRENCES || span.density() < MIN_DENSITY {
+        return Vec::new();
+    }
+    let edge = (total as f64 * EDGE_SHARE).ceil() as usize;
+    let change = |kind, index| SchemaChange {
+        field: field.to_string(),
+        value: value.cloned(),
+        kind,
+        index,
+        span: *span,
+    };
+    let mut out = Vec::new();
+    if span.first_rank >= edge {
+        out.push(change(ChangeKind::Appeared, span.first));
+    }
+    if span.last_rank + edge < total {
+        out.push(change(ChangeKind::Disappeared, span.last));
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_analyzer::parse_json_records;
+
+    #[test]
+    fn late_field_and_retired_value_are_reported() {
+        let mut data = String::new();
+        for i in 0..1000 {
+            let version = if i < 600 { "1.0" } else { "1.1" };
+            data += &format!("{{\"id\":{i},\"version\":\"{version}\"");
+            if i >= 700 {
+                data += ",\"retry_count\":0";
+            }
+            // Sporadic through the input: no schema change.
+            if i % 7 == 0 || i == 300 {
+                data += ",\"debug\":true";
+            }
+            data += "}\n";
+        }
+        let records = parse_json_records(data.as_bytes(), b'\n');
+        let t = schema_timeline(&records);
+        // A value is placed among its field's records: retry_count is 0
+        // from its first appearance, which is no change of its own.
+        let retry = t.fields["retry_count"];
+        assert_eq!((retry.first, retry.last, retry.count), (700, 999, 300));
+        assert_eq!(t.values["version"]["1.0"].last, 599);
+
+        let found: Vec<_> = t
+            .changes()
+            .into_iter()
+            .map(|c| (c.field, c.value, c.kind, c.index))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("version".into(), Some("1.0".into()), ChangeKind::Disappeared, 599),
+                ("version".into(), Some("1.1".into()), ChangeKind::Appeared, 600),
+                ("retry_count".into(), None, ChangeKind::Appeared, 700),
+            ]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Choosing scan parameters and a threshold.
+//!
+//! `--window-size`, `--min-match` and the detection threshold interact, and
+//! the right values depend on the log. `tune` tries a grid of them and
+//! judges each trial by the records it flags. With labels (records known to
+//! be anomalous) a trial is as good as its F1 score against them. Without,
+//! two heuristics stand in: a good setting flags a small share of the input
+//! ([`MIN_RATE`]-[`MAX_RATE`]), and it is stable, flagging much the same
+//! records as its neighbors on the grid, so the result does not hinge on
+//! the exact value chosen.
+
+// ---------------------------------------------------------------------------
+// Trials
+// ---------------------------------------------------------------------------
+
+/// Flag rates outside this range count against a trial without labels.
+pub const MIN_RATE: f64 = 0.001;
+pub const MAX_RATE: f64 = 0.05;
+
+/// One point of the grid and the records it flagged.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial {
+    /// Position along each axis of the grid (window, min-match,
+    /// threshold).
+    pub at: [usize; 3],
+    /// Flagged record indices, sorted.
+    pub flagged: Vec<usize>,
+}
+
+/// How a [`Trial`] did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Assessment {
+    /// Share of the records flagged.
+    pub rate: f64,
+    /// Mean Jaccard similarity of the flagged set to those of the trials one
+    /// step away along one axis (1 with no neighbors).
+    pub stability: f64,
+    /// Precision, recall and F1 against the labels, if given.
+    pub labeled: Option<(f64, f64, f64)>,
+    /// What trials are ranked by: F1 with labels, otherwise stability
+    /// scaled by [`rate_fit`].
+    pub quality: f64,
+}
+
+/// Jaccard similarity of two sorted index sets (1 when both are empty).
+pub fn jaccard(a: &[usize], b: &[usize]) -> f64 {
+    let (mut i, mut j, mut both) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                both += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    let union = a.len() + b.len() - both;
+    if union == 0 { 1.0 } else { both as f64 / union as f64 }
+}
+
+/// Precision, recall and F1 of sorted `flagged` against sorted `labels`.
+pub fn f1(flagged: &[usize], labels: &[usize]) -> (f64, f64, f64) {
+    let hits = flagged.iter().filter(|i| labels.binary_search(i).is_ok()).count() as f64;
+    let precision = if flagged.is_empty() { 0.0 } else { hits / flagged.len() as f64 };
+    let recall = if labels.is_empty() { 0.0 } else { hits / labels.len() as f64 };
+    let f = if hits == 0.0 { 0.0 } else { 2.0 * precision * recall / (precision + recall) };
+    (precision, recall, f)
+}
+
+/// 1 for a flag rate within [`MIN_RATE`]-[`MAX_RATE`], falling off with
+/// the number of factors of ten outside it (0 for no flags).
+pub fn rate_fit(rate: f64) -> f64 {
+    if rate <= 0.0 {
+        return 0.0;
+    }
+    let off = if rate < MIN_RATE {
+        (MIN_RATE / rate).log10()
+    } else if rate > MAX_RATE {
+        (rate / MAX_RATE).log10()
+    } else {
+        0.0
+    };
+    1.0 / (1.0 + 2.0 * off)
+}
+
+/// Assess every trial over `records` records, against sorted `labels` if
+/// given.
+pub fn assess(trials: &[Trial], records: usize, labels: Option<&[usize]>) -> Vec<Assessment> {
+    trials
+        .iter()
+        .map(|t| {
+            let neighbors: Vec<f64> = trials
+                .iter()
+                .filter(|o| t.at.iter().zip(&o.at).map(|(a, b)| a.abs_diff(*b)).sum::<usize>() == 1)
+                .map(|o| jaccard(&t.flagged, &o.flagged))
+                .collect();
+            let stability = if neighbors.is_empty() {
+                1.0
+            } else {
+                neighbors.iter().sum::<f64>() / neighbors.len() as f64
+            };
+            let rate = if records == 0 { 0.0 } else { t.flagged.len() as f64 / records as f64 };
+            let labeled = labels.map(|l| f1(&t.flagged, l));
+            let quality = match labeled {
+                Some((_, _, f)) => f,
+                None => stability * rate_fit(rate),
+            };
+            Assessment { rate, stability, labeled, quality }
+        })
+        .collect()
+}
+
+/// Index of the best assessment: highest quality, then stability; the
+/// earliest wins ties. `None` if there are none.
+pub fn best(assessments: &[Assessment]) -> Option<usize> {
+    (0..assessments.len()).rev().max_by(|&a, &b| {
+        let (x, y) = (&assessments[a], &assessments[b]);
+        x.quality.total_cmp(&y.quality).then(x.stability.total_cmp(&y.stability))
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_and_labeled_trials_win() {
+        assert_eq!(jaccard(&[1, 2, 3], &[2, 3, 4]), 0.5);
+        assert_eq!(jaccard(&[], &[]), 1.0);
+        assert_eq!(f1(&[1, 2, 3, 4], &[2, 4]), (0.5, 1.0, 2.0 / 3.0));
+        assert_eq!(f1(&[], &[2]), (0.0, 0.0, 0.0));
+        assert_eq!(rate_fit(0.01), 1.0);
+        assert!(rate_fit(0.5) < 0.5 && rate_fit(0.0) == 0.0);
+
+        // Along the threshold axis: the first trial flags a fifth of the
+        // input, the rest agree more or less on a few records.
+        let trial = |k: usize, flagged: Vec<usize>| Trial { at: [0, 0, k], flagged };
+        let trials = [
+            trial(0, (100..300).collect()),
+            trial(1, vec![10, 500, 900]),
+            trial(2, vec![500, 900]),
+            trial(3, vec![900]),
+        ];
+        let a = assess(&trials, 1000, None);
+        assert!(a[0].labeled.is_none() && a[0].quality < 0.5);
+        assert_eq!(a[1].stability, (0.0 + 2.0 / 3.0) / 2.0);
+        assert_eq!(best(&a), Some(2));
+
+        // Labels override the heuristics.
+        let a = assess(&trials, 1000, Some(&[10, 500, 900]));
+        assert_eq!(a[1].labeled, Some((1.0, 1.0, 1.0)));
+        assert_eq!(best(&a), Some(1));
+        let a = assess(&trials, 1000, Some(&[100, 101, 102]));
+        assert_eq!(best(&a), Some(0));
+        assert_eq!(best(&[]), None);
+    }
+}
@@ -0,0 +1,265 @@
+//! Zstandard frame and block structure (RFC 8878).
+//!
+//! Reports what the zstd compressor decided for a file: per frame the
+//! window size, dictionary ID, content size, and the raw / RLE /
+//! compressed block breakdown. Only headers are parsed; compressed block
+//! contents are not decoded, so a frame's ratio is known only when its
+//! header records the content size (the zstd CLI writes it by default).
+
+use std::io;
+
+use crate::opsfile::invalid;
+
+pub const ZSTD_MAGIC: u32 = 0xFD2F_B528;
+/// Skippable frames use magics `0x184D2A50..=0x184D2A5F`.
+const SKIPPABLE_MASK: u32 = 0xFFFF_FFF0;
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+
+// ---------------------------------------------------------------------------
+// Public types
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockType {
+    Raw,
+    Rle,
+    Compressed,
+}
+
+impl BlockType {
+    pub fn name(self) -> &'static str {
+        match self {
+            BlockType::Raw => "raw",
+            BlockType::Rle => "rle",
+            BlockType::Compressed => "compressed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZstdBlock {
+    /// Offset of the block header in the file.
+    pub offset: usize,
+    pub block_type: BlockType,
+    /// Bytes of block content in the file.
+    pub compressed_len: usize,
+    /// Decompressed size, when known without decoding (raw and RLE).
+    pub decompressed_len: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZstdFrame {
+    pub offset: usize,
+    /// Total frame size in the file, header and checksum included.
+    pub len: usize,
+    /// True for skippable (user data) frames, which have no blocks.
+    pub skippable: bool,
+    pub window_size: Option<u64>,
+    pub dictionary_id: Option<u32>,
+    pub content_size: Option<u64>,
+    pub checksum: bool,
+    pub blocks: Vec<ZstdBlock>,
+}
+
+impl ZstdFrame {
+    /// Content size over frame size, if the content size is recorded.
+    pub fn ratio(&self) -> Option<f64> {
+        let n = self.content_size?;
+        (self.len > 0).then(|| n as f64 / self.len as f64)
+    }
+
+    pub fn block_count(&self, t: BlockType) -> usize {
+        self.blocks.iter().filter(|b| b.block_type == t).count()
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parse
+// ---------------------------------------------------------------------------
+
+fn le(data: &[u8], pos: usize, n: usize) -> io::Result<u64> {
+    let bytes = data
+        .get(pos..pos + n)
+        .ok_or_else(|| invalid(format!("truncated zstd data at byte {pos}")))?;
+    Ok(bytes.iter().rev().fold(0u64, |v, &b| v << 8 | b as u64))
+}
+
+pub fn is_zstd(data: &[u8]) -> bool {
+    data.len() >= 4 && {
+        let m = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        m == ZSTD_MAGIC || m & SKIPPABLE_MASK == SKIPPABLE_MAGIC
+    }
+}
+
+/// Parse every frame in `data`.
+pub fn parse_frames(data: &[u8]) -> io::Result<Vec<ZstdFrame>> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let frame = parse_frame(data, pos)?;
+        pos += frame.len;
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+fn parse_frame(data: &[u8], start: usize) -> io::Result<ZstdFrame> {
+    let magic = le(data, start, 4)? as u32;
+    if magic & SKIPPABLE_MASK == SKIPPABLE_MAGIC {
+        let size = le(data, start + 4, 4)? as usize;
+        if start + 8 + size > data.len() {
+            return Err(invalid(format!("truncated skippable frame at byte {start}")));
+        }
+        return Ok(ZstdFrame {
+            offset: start,
+            len: 8 + size,
+            skippable: true,
+            window_size: None,
+            dictionary_id: None,
+            content_size: None,
+            checksum: false,
+            blocks: Vec::new(),
+        });
+    }
+    if magic != ZSTD_MAGIC {
+        return Err(invalid(format!("no zstd frame at byte {start}")));
+    }
+
+    let fhd = le(data, start + 4, 1)? as u8;
+    if fhd & 0x08 != 0 {
+        return Err(invalid(format!("reserved header bit set in frame at byte {start}")));
+    }
+    let single_segment = fhd & 0x20 != 0;
+    let checksum = fhd & 0x04 != 0;
+    let mut pos = start + 5;
+
+    let mut window_size = None;
+    if !single_segment {
+        let wd = le(data, pos, 1)?;
+        let base = 1u64 << (10 + (wd >> 3));
+        window_size = Some(base + base / 8 * (wd & 7));
+        pos += 1;
+    }
+    let did_len = [0, 1, 2, 4][(fhd & 3) as usize];
+    let dictionary_id = match did_len {
+        0 => None,
+        n => Some(le(data, pos, n)? as u32),
+    };
+    pos += did_len;
+    let fcs_len = match fhd >> 6 {
+        0 if single_segment => 1,
+        0 => 0,
+        1 => 2,
+        2 => 4,
+        _ => 8,
+    };
+    let content_size = match fcs_len {
+        0 => None,
+        2 => Some(le(data, pos, 2)? + 256),
+        n => Some(le(data, pos, n)?),
+    };
+    pos += fcs_len;
+    if single_segment {
+        window_size = content_size;
+    }
+
+    let mut blocks = Vec::new();
+    loop {
+        let header = le(data, pos, 3)?;
+        let last = header & 1 == 1;
+        let size = (header >> 3) as usize;
+        let (block_type, stored, decompressed) = match (header >> 1) & 3 {
+            0 => (BlockType::Raw, size, Some(size)),
+            1 => (BlockType::Rle, 1, Some(size)),
+            2 => (BlockType::Compressed, size, None),
+            _ => return Err(invalid(format!("reserved block type at byte {pos}"))),
+        };
+        if pos + 3 + stored > data.len() {
+            return Err(invalid(format!("truncated block at byte {pos}")));
+        }
+        blocks.push(ZstdBlock {
+            offset: pos,
+            block_type,
+            compressed_len: stored,
+            decompressed_len: decompressed,
+        });
+        pos += 3 + stored;
+        if last {
+            break;
+        }
+    }
+    if checksum {
+        le(data, pos, 4)?;
+        pos += 4;
+    }
+
+    Ok(ZstdFrame {
+        offset: start,
+        len: pos - start,
+        skippable: false,
+        window_size,
+        dictionary_id,
+        content_size,
+        checksum,
+        blocks,
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Tests
+// ---------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame with a window descriptor, 2-byte dictionary ID, 4-byte content
+    /// size, a raw block and a final RLE block, and a checksum.
+    fn sample_frame() -> Vec<u8> {
+        let mut f = ZSTD_MAGIC.to_le_bytes().to_vec();
+        f.push(0b1000_0110); // FCS 4 bytes, checksum, DID 2 bytes
+        f.push(0x18); // window 2^(10+3) = 8 KB
+        f.extend_from_slice(&0x1234u16.to_le_bytes());
+        f.extend_from_slice(&105u32.to_le_bytes());
+        let raw = (5u32 << 3).to_le_bytes(); // raw, 5 bytes, not last
+        f.extend_from_slice(&raw[..3]);
+        f.extend_from_slice(b"hello");
+        let rle = (100u32 << 3 | 1 << 1 | 1).to_le_bytes(); // RLE x100, last
+        f.extend_from_slice(&rle[..3]);
+        f.push(b'-');
+        f.extend_from_slice(&[0; 4]);
+        f
+    }
+
+    #[test]
+    fn parses_headers_and_blocks() {
+        let data = sample_frame();
+        assert!(is_zstd(&data));
+        let frames = parse_frames(&data).unwrap();
+        assert_eq!(frames.len(), 1);
+        let f = &frames[0];
+        assert_eq!(f.len, data.len());
+        assert_eq!(f.window_size, Some(8192));
+        assert_eq!(f.dictionary_id, Some(0x1234));
+        assert_eq!(f.content_size, Some(105));
+        assert!(f.checksum);
+        assert_eq!(f.block_count(BlockType::Raw), 1);
+        assert_eq!(f.block_count(BlockType::Rle), 1);
+        assert_eq!(f.blocks[1].decompressed_len, Some(100));
+        assert!(f.ratio().unwrap() > 3.0);
+    }
+
+    #[test]
+    fn skippable_frames_and_truncation() {
+        let mut data = 0x184D_2A53u32.to_le_bytes().to_vec();
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(b"abc");
+        data.extend_from_slice(&sample_frame());
+        let frames = parse_frames(&data).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].skippable && !frames[1].skippable);
+        assert_eq!(frames[1].offset, 11);
+        assert!(parse_frames(&data[..data.len() - 2]).is_err());
+        assert!(!is_zstd(b"plain text"));
+    }
+}